@@ -28,6 +28,7 @@ check-features:
 	cargo check --no-default-features -F tls-aws-lc
 	cargo check --no-default-features -F tls-openssl
 	cargo check -F jemalloc
+	cargo check -F otel
 	(cd fuzz; RUSTFLAGS="--cfg fuzzing" cargo check)
 
 # target in common/Makefile.common.mk doesn't handle our third party vendored files; only check golang and rust codes
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
@@ -21,10 +22,13 @@ use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 use hickory_resolver::config::{ResolverConfig, ResolverOpts};
 use pprof::criterion::{Output, PProfProfiler};
 use prometheus_client::registry::Registry;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::runtime::Runtime;
+use ztunnel::identity::Identity;
 use ztunnel::state::workload::Workload;
 use ztunnel::state::{DemandProxyState, ProxyState, ServiceResolutionMode};
 use ztunnel::strng;
+use ztunnel::tls::mock::generate_test_certs;
 use ztunnel::xds::ProxyStateUpdateMutator;
 use ztunnel::xds::istio::workload::LoadBalancing;
 use ztunnel::xds::istio::workload::Port;
@@ -125,6 +129,54 @@ pub fn load_balance(c: &mut Criterion) {
     run("locality-10000", 10000, locality.clone());
 }
 
+/// tls_handshake measures establishing an mTLS connection over loopback, with and without session
+/// resumption.
+pub fn tls_handshake(c: &mut Criterion) {
+    let mut c = c.benchmark_group("tls_handshake");
+    c.throughput(Throughput::Elements(1));
+    c.measurement_time(Duration::from_secs(5));
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/bench").unwrap();
+    let certs = Arc::new(generate_test_certs(
+        &id.clone().into(),
+        Duration::from_secs(0),
+        Duration::from_secs(60 * 60),
+    ));
+    let listener = Arc::new(
+        rt.block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap(),
+    );
+    let addr = listener.local_addr().unwrap();
+    for (name, cache_size) in [("full", 0), ("resumed", 256)] {
+        c.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| async {
+                let connector = certs
+                    .outbound_connector(vec![id.clone()], cache_size)
+                    .unwrap();
+                let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(
+                    certs.server_config(&[], cache_size).unwrap(),
+                ));
+                let client = async {
+                    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                    let mut tls = connector.connect(stream).await.unwrap();
+                    // Read past the handshake so session tickets are processed.
+                    tls.read_u8().await.unwrap();
+                };
+                let server = async {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let mut tls = acceptor.accept(stream).await.unwrap();
+                    tls.write_u8(1).await.unwrap();
+                    tls.flush().await.unwrap();
+                };
+                tokio::join!(client, server);
+            })
+        });
+    }
+}
+
 fn build_load_balancer(
     wl_count: usize,
     load_balancing: Option<LoadBalancing>,
@@ -196,7 +248,7 @@ criterion_group! {
     config = Criterion::default()
         .with_profiler(PProfProfiler::new(100, Output::Protobuf))
         .warm_up_time(Duration::from_millis(1));
-    targets = xds, load_balance
+    targets = xds, load_balance, tls_handshake
 }
 
 criterion_main!(benches);
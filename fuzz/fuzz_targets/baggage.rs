@@ -16,18 +16,29 @@
 
 use hyper::{HeaderMap, http::HeaderValue};
 use libfuzzer_sys::fuzz_target;
-use ztunnel::baggage::parse_baggage_header;
-use ztunnel::proxy::BAGGAGE_HEADER;
+use ztunnel::baggage::{parse_baggage_header, parse_baggage_header_with_labels};
+use ztunnel::proxy::{BAGGAGE_HEADER, TraceParent};
 
 fuzz_target!(|data: &[u8]| {
     let _ = run_baggage_header_parser(data);
     let _ = run_forwarded_header_parser(data);
+    let _ = run_traceparent_parser(data);
 });
 
 fn run_baggage_header_parser(data: &[u8]) -> anyhow::Result<()> {
     let mut hm = HeaderMap::new();
     hm.append(BAGGAGE_HEADER, HeaderValue::from_bytes(data)?);
     parse_baggage_header(hm.get_all(BAGGAGE_HEADER))?;
+    parse_baggage_header_with_labels(
+        hm.get_all(BAGGAGE_HEADER),
+        &["app".to_string(), "version".to_string()],
+    )?;
+    Ok(())
+}
+
+fn run_traceparent_parser(data: &[u8]) -> anyhow::Result<()> {
+    let s = std::str::from_utf8(data)?;
+    TraceParent::try_from(s)?;
     Ok(())
 }
 
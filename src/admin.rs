@@ -14,16 +14,19 @@
 
 use crate::config::Config;
 use crate::hyper_util::{Server, empty_response, plaintext_response};
-use crate::identity::SecretManager;
-use crate::state::DemandProxyState;
+use crate::identity::{Identity, SecretManager};
+use crate::proxy::connection_manager::ConnectionManager;
+use crate::state::workload::network_addr;
+use crate::state::{DemandProxyState, ProxyRbacContext};
+use crate::strng::Strng;
 use crate::tls::Certificate;
 use crate::version::BuildInfo;
 use crate::xds::LocalConfig;
-use crate::{signal, telemetry};
+use crate::{rbac, signal, strng, telemetry};
 
 use base64::engine::general_purpose::STANDARD;
 use bytes::Bytes;
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
 use hyper::{Request, Response, header::CONTENT_TYPE, header::HeaderValue};
 use std::borrow::Borrow;
@@ -53,6 +56,7 @@ struct State {
     shutdown_trigger: signal::ShutdownTrigger,
     cert_manager: Arc<SecretManager>,
     handlers: Vec<Arc<dyn AdminHandler>>,
+    connections: Option<ConnectionManager>,
 }
 
 pub struct Service {
@@ -106,6 +110,7 @@ impl Service {
                 shutdown_trigger,
                 cert_manager,
                 handlers: vec![],
+                connections: None,
             },
         )
         .await
@@ -120,6 +125,12 @@ impl Service {
         self.s.state_mut().handlers.push(handler);
     }
 
+    /// set_connection_manager sets the connection manager used to serve the live connection list on
+    /// /connections, and to close connections on /drain_identity.
+    pub fn set_connection_manager(&mut self, cm: ConnectionManager) {
+        self.s.state_mut().connections = Some(cm);
+    }
+
     pub fn spawn(self) {
         self.s.spawn(|state, req| async move {
             match req.uri().path() {
@@ -146,6 +157,13 @@ impl Service {
                     )
                     .await
                 }
+                "/connections" => {
+                    handle_connections(state.connections.as_ref().map(|cm| cm as &dyn AdminHandler))
+                }
+                "/drain_identity" => {
+                    Ok(handle_drain_identity(state.connections.as_ref(), req).await)
+                }
+                "/check_policy" => Ok(handle_check_policy(&state.proxy_state, req).await),
                 "/logging" => Ok(handle_logging(req).await),
                 "/" => Ok(handle_dashboard(req).await),
                 _ => Ok(empty_response(hyper::StatusCode::NOT_FOUND)),
@@ -166,6 +184,10 @@ async fn handle_dashboard(_req: Request<Incoming>) -> Response<Full<Bytes>> {
         ),
         ("quitquitquit", "shut down the server"),
         ("config_dump", "dump the current Ztunnel configuration"),
+        (
+            "connections",
+            "list the connections currently open through Ztunnel",
+        ),
         ("logging", "query/changing logging levels"),
     ];
 
@@ -310,6 +332,169 @@ async fn handle_config_dump(
         .expect("builder with known status code should not fail"))
 }
 
+fn handle_connections(handler: Option<&dyn AdminHandler>) -> anyhow::Result<Response<Full<Bytes>>> {
+    let Some(handler) = handler else {
+        return Ok(empty_response(hyper::StatusCode::NOT_FOUND));
+    };
+    let body = serde_json::to_string_pretty(&handler.handle()?)?;
+    Ok(Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("builder with known status code should not fail"))
+}
+
+// handle_drain_identity closes all tracked connections to or from the identity given as
+// ?identity=spiffe://..., for cutting off a compromised workload without restarting.
+async fn handle_drain_identity(
+    cm: Option<&ConnectionManager>,
+    req: Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    if req.method() != hyper::Method::POST {
+        return empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let Some(cm) = cm else {
+        return empty_response(hyper::StatusCode::NOT_FOUND);
+    };
+    let identity = req.uri().query().and_then(|q| {
+        url::form_urlencoded::parse(q.as_bytes())
+            .find(|(k, _)| k == "identity")
+            .map(|(_, v)| v.into_owned())
+    });
+    let Some(identity) = identity else {
+        return plaintext_response(
+            hyper::StatusCode::BAD_REQUEST,
+            "usage: POST /drain_identity?identity=spiffe://<trust domain>/ns/<namespace>/sa/<service account>\n".into(),
+        );
+    };
+    let identity = match Identity::from_str(&identity) {
+        Ok(id) => id,
+        Err(e) => {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("invalid identity: {e}\n"),
+            );
+        }
+    };
+    let closed = cm.drain_identity(&identity).await;
+    plaintext_response(
+        hyper::StatusCode::OK,
+        format!("closed {closed} connections\n"),
+    )
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PolicyCheckRequest {
+    src: SocketAddr,
+    dst: SocketAddr,
+    #[serde(default)]
+    src_identity: Option<String>,
+    #[serde(default)]
+    dst_network: Strng,
+}
+
+#[derive(serde::Serialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct PolicyCheckResponse {
+    allowed: bool,
+    // The policy (namespace/name) that decided the outcome, if a specific one did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<Strng>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+static CHECK_POLICY_HELP: &str = "usage: POST /check_policy with a JSON body: \
+{\"src\": \"<ip:port>\", \"dst\": \"<ip:port>\", \"srcIdentity\": \"spiffe://...\", \"dstNetwork\": \"<network>\"}\n";
+
+// handle_check_policy evaluates authorization policies against a synthetic connection without
+// opening it, so policy changes can be validated before they apply to live traffic.
+async fn handle_check_policy(
+    proxy_state: &DemandProxyState,
+    req: Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    if req.method() != hyper::Method::POST {
+        return empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let body = match req.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("failed to read body: {e}\n"),
+            );
+        }
+    };
+    let check: PolicyCheckRequest = match serde_json::from_slice(&body) {
+        Ok(check) => check,
+        Err(e) => {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("invalid request: {e}\n{CHECK_POLICY_HELP}"),
+            );
+        }
+    };
+    let resp = match check_policy(proxy_state, check).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            return plaintext_response(hyper::StatusCode::BAD_REQUEST, format!("{e}\n"));
+        }
+    };
+    match serde_json::to_string_pretty(&resp) {
+        Ok(body) => Response::builder()
+            .status(hyper::StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .expect("builder with known status code should not fail"),
+        Err(e) => plaintext_response(
+            hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to serialize response: {e}\n"),
+        ),
+    }
+}
+
+async fn check_policy(
+    proxy_state: &DemandProxyState,
+    check: PolicyCheckRequest,
+) -> anyhow::Result<PolicyCheckResponse> {
+    let src_identity = check
+        .src_identity
+        .as_deref()
+        .map(Identity::from_str)
+        .transpose()?;
+    let dst = network_addr(check.dst_network.clone(), check.dst.ip());
+    let Some(dest_workload) = proxy_state.fetch_workload_by_address(&dst).await else {
+        anyhow::bail!("no workload found for destination {dst}");
+    };
+    let ctx = ProxyRbacContext {
+        conn: rbac::Connection {
+            src: check.src,
+            dst: check.dst,
+            src_identity,
+            dst_network: check.dst_network,
+        },
+        dest_workload,
+    };
+    Ok(match proxy_state.evaluate_rbac(&ctx).await {
+        Ok(policy) => PolicyCheckResponse {
+            allowed: true,
+            policy,
+            reason: None,
+        },
+        Err(e) => PolicyCheckResponse {
+            allowed: false,
+            policy: match &e {
+                crate::proxy::AuthorizationRejectionError::ExplicitlyDenied(ns, name) => {
+                    Some(strng::format!("{ns}/{name}"))
+                }
+                _ => None,
+            },
+            reason: Some(e.to_string()),
+        },
+    })
+}
+
 //mirror envoy's behavior: https://www.envoyproxy.io/docs/envoy/latest/operations/admin#post--logging
 //NOTE: multiple query parameters is not supported, for example
 //curl -X POST http://127.0.0.1:15000/logging?"tap=debug&router=debug"
@@ -786,6 +971,72 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_check_policy() {
+        let wl = XdsWorkload {
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+            uid: "uid".to_string(),
+            name: "name".to_string(),
+            namespace: "ns".to_string(),
+            trust_domain: "cluster.local".to_string(),
+            service_account: "default".to_string(),
+            ..Default::default()
+        };
+        let policy = |name: &str, action: i32, service_account: &str| XdsAuthorization {
+            name: name.to_string(),
+            namespace: "ns".to_string(),
+            scope: 1,
+            action,
+            rules: vec![XdsRule {
+                clauses: vec![XdsClause {
+                    matches: vec![XdsMatch {
+                        service_accounts: vec![XdsServiceAccountMatch {
+                            namespace: "client".into(),
+                            service_account: service_account.into(),
+                        }],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+        };
+        let proxy_state = new_proxy_state(
+            &[wl],
+            &[],
+            &[policy("allow", 0, "allowed"), policy("deny", 1, "denied")],
+        );
+        let check = |sa: &str| PolicyCheckRequest {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst: "127.0.0.2:8080".parse().unwrap(),
+            src_identity: Some(format!("spiffe://cluster.local/ns/client/sa/{sa}")),
+            dst_network: strng::EMPTY,
+        };
+
+        assert_eq!(
+            check_policy(&proxy_state, check("allowed")).await.unwrap(),
+            PolicyCheckResponse {
+                allowed: true,
+                policy: Some(strng::new("ns/allow")),
+                reason: None,
+            }
+        );
+        assert_eq!(
+            check_policy(&proxy_state, check("denied")).await.unwrap(),
+            PolicyCheckResponse {
+                allowed: false,
+                policy: Some(strng::new("ns/deny")),
+                reason: Some("explicitly denied by: ns/deny".to_string()),
+            }
+        );
+        let resp = check_policy(&proxy_state, check("other")).await.unwrap();
+        assert!(!resp.allowed);
+        assert_eq!(resp.policy, None);
+
+        // Unknown destinations are an error, rather than a decision.
+        let mut unknown = check("allowed");
+        unknown.dst = "127.0.0.3:8080".parse().unwrap();
+        assert!(check_policy(&proxy_state, unknown).await.is_err());
+    }
+
     // each of these tests assert that we can change the log level and the
     // appropriate response string is returned.
     //
@@ -27,15 +27,20 @@ use std::thread;
 use tokio::task::JoinSet;
 use tracing::{Instrument, warn};
 
-use crate::identity::SecretManager;
+use crate::identity::{CertCacheCollector, CertExpiryCollector, SecretManager};
 use crate::state::ProxyStateManager;
-use crate::{admin, config, metrics, proxy, readiness, signal};
+use crate::{admin, config, metrics, proxy, readiness, signal, socket};
 use crate::{dns, xds};
 
 pub async fn build_with_cert(
     config: Arc<config::Config>,
     cert_manager: Arc<SecretManager>,
 ) -> anyhow::Result<Bound> {
+    // Check for MPTCP support up front, so a missing kernel feature is reported at startup.
+    if config.socket_config.mptcp_enabled && socket::mptcp_supported() {
+        tracing::info!("using MPTCP for outbound connections");
+    }
+
     // Start the data plane worker pool.
     let data_plane_pool = new_data_plane_pool(config.num_worker_threads);
 
@@ -77,10 +82,18 @@ pub async fn build_with_cert(
 
     // Register metrics.
     let mut registry = Registry::default();
+    registry.register_collector(Box::new(metrics::process::ProcessCollector));
     let istio_registry = metrics::sub_registry(&mut registry);
     let _ = metrics::meta::Metrics::new(istio_registry);
     let xds_metrics = xds::Metrics::new(istio_registry);
-    let proxy_metrics = Arc::new(proxy::Metrics::new(istio_registry));
+    let proxy_metrics = Arc::new(
+        proxy::Metrics::new(istio_registry)
+            .with_coarse_locality(config.coarse_locality_metrics)
+            .with_access_log_sampling(config.access_log_sampling)
+            .with_baggage_labels(config.baggage_labels.keys().cloned().collect()),
+    );
+    istio_registry.register_collector(Box::new(CertExpiryCollector(cert_manager.clone())));
+    istio_registry.register_collector(Box::new(CertCacheCollector(cert_manager.clone())));
     let dns_metrics = if config.dns_proxy {
         Some(dns::Metrics::new(istio_registry))
     } else {
@@ -161,6 +174,9 @@ pub async fn build_with_cert(
             .clone()
             .expect("proxy_workload_information is required for dedicated mode");
         let proxies = proxy_gen.new_proxies_for_dedicated(wli).await?;
+        if let Some(cm) = proxies.connection_manager.clone() {
+            admin_server.set_connection_manager(cm);
+        }
         match proxies.proxy {
             Some(proxy) => {
                 proxy_addresses = Some(proxy.addresses());
@@ -363,9 +379,12 @@ impl Bound {
 
         // Start a drain; this will attempt to end all connections
         // or itself be interrupted by a stronger TERM signal, whichever comes first.
+        tracing::info!("draining all components");
+        let start = std::time::Instant::now();
         self.drain_tx
             .start_drain_and_wait(drain::DrainMode::Graceful)
             .await;
+        tracing::info!(elapsed = ?start.elapsed(), "drain complete, exiting");
 
         Ok(())
     }
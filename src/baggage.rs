@@ -12,49 +12,177 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
 use crate::strng::Strng;
 use hyper::{
     header::{GetAll, ToStrError},
     http::HeaderValue,
 };
 
-#[derive(Default)]
+// Baggage is limited to 180 entries and 8192 bytes in total, the limits from the W3C baggage
+// specification; anything beyond that is rejected rather than parsed.
+// https://www.w3.org/TR/baggage/#limits
+const MAX_BAGGAGE_LEN: usize = 8192;
+const MAX_BAGGAGE_ENTRIES: usize = 180;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BaggageError {
+    #[error("invalid baggage header: {0}")]
+    Invalid(#[from] ToStrError),
+    #[error("baggage header too large: {0} bytes")]
+    TooLarge(usize),
+    #[error("baggage header has too many entries: {0}")]
+    TooManyEntries(usize),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Baggage {
     pub cluster_id: Option<Strng>,
     pub namespace: Option<Strng>,
+    // The kind of workload, as in k8s.<workload_type>.name. Defaults to deployment when encoding.
+    pub workload_type: Option<Strng>,
     pub workload_name: Option<Strng>,
     pub service_name: Option<Strng>,
     pub revision: Option<Strng>,
     pub region: Option<Strng>,
     pub zone: Option<Strng>,
+    // Any additional keys, restricted to the keys and values allowed when parsing.
+    pub labels: BTreeMap<Strng, Strng>,
+}
+
+impl Baggage {
+    /// encode serializes the baggage into a header value that parse_baggage_header can read back.
+    /// The well-known fields are always written, even when empty. Values are percent-encoded.
+    pub fn encode(&self) -> String {
+        let field = |f: &Option<Strng>| encode_value(f.as_deref().unwrap_or_default());
+        let mut out = format!(
+            "k8s.cluster.name={cluster},k8s.namespace.name={namespace},k8s.{workload_type}.name={workload_name},service.name={name},service.version={version},cloud.region={region},cloud.availability_zone={zone}",
+            cluster = field(&self.cluster_id),
+            namespace = field(&self.namespace),
+            workload_type = self.workload_type.as_deref().unwrap_or("deployment"),
+            workload_name = field(&self.workload_name),
+            name = field(&self.service_name),
+            version = field(&self.revision),
+            region = field(&self.region),
+            zone = field(&self.zone),
+        );
+        for (k, v) in &self.labels {
+            let _ = write!(out, ",{k}={}", encode_value(v));
+        }
+        out
+    }
+}
+
+// encode_value percent-encodes anything outside the characters the baggage specification allows
+// in a value, as well as '%' itself.
+fn encode_value(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for b in v.bytes() {
+        match b {
+            b'!' | b'#'..=b'$' | b'&'..=b'+' | b'-'..=b':' | b'<'..=b'[' | b']'..=b'~' => {
+                out.push(b as char)
+            }
+            _ => {
+                let _ = write!(out, "%{b:02X}");
+            }
+        }
+    }
+    out
+}
+
+// decode_value reverses encode_value. Malformed escapes are kept as they are.
+fn decode_value(v: &str) -> String {
+    let bytes = v.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| v.to_string())
+}
+
+pub fn parse_baggage_header(headers: GetAll<HeaderValue>) -> Result<Baggage, BaggageError> {
+    parse_baggage_header_with_labels(headers, &BTreeMap::new())
 }
 
-pub fn parse_baggage_header(headers: GetAll<HeaderValue>) -> Result<Baggage, ToStrError> {
+/// parse_baggage_header_with_labels parses the baggage header like parse_baggage_header, and
+/// additionally collects any keys in `allowed_labels` into `Baggage::labels`, provided their value
+/// is one of the values allowed for that key.
+pub fn parse_baggage_header_with_labels(
+    headers: GetAll<HeaderValue>,
+    allowed_labels: &BTreeMap<Strng, Vec<Strng>>,
+) -> Result<Baggage, BaggageError> {
     let mut baggage = Baggage {
         ..Default::default()
     };
+    // The header comes straight from the peer, so bound the work we do on it up front.
+    let mut len = 0;
+    let mut entries = 0;
+    for hv in headers.iter() {
+        len += hv.len();
+        if len > MAX_BAGGAGE_LEN {
+            return Err(BaggageError::TooLarge(len));
+        }
+        entries += hv.as_bytes().iter().filter(|b| **b == b',').count() + 1;
+        if entries > MAX_BAGGAGE_ENTRIES {
+            return Err(BaggageError::TooManyEntries(entries));
+        }
+    }
     for hv in headers.iter() {
         let v = hv.to_str()?;
         v.split(',').for_each(|s| {
-            let parts: Vec<&str> = s.split('=').collect();
-            if parts.len() > 1 {
-                let val = match parts[1] {
+            if let Some((key, rest)) = s.split_once('=') {
+                // Anything after a second '=' is not part of the value.
+                let val = match rest.split_once('=').map_or(rest, |(v, _)| v).trim() {
                     "" => None,
-                    s => Some(s.into()),
+                    s => Some(decode_value(s).into()),
                 };
-                match parts[0] {
+                match key.trim() {
                     "k8s.cluster.name" => baggage.cluster_id = val,
                     "k8s.namespace.name" => baggage.namespace = val,
-                    "k8s.deployment.name"
+                    k @ ("k8s.deployment.name"
                     | "k8s.cronjob.name"
                     | "k8s.pod.name"
-                    | "k8s.job.name" => baggage.workload_name = val,
+                    | "k8s.job.name") => {
+                        baggage.workload_type = k
+                            .strip_prefix("k8s.")
+                            .and_then(|k| k.strip_suffix(".name"))
+                            .map(Into::into);
+                        baggage.workload_name = val
+                    }
                     "service.name" => baggage.service_name = val,
                     "service.version" => baggage.revision = val,
                     // https://opentelemetry.io/docs/specs/semconv/attributes-registry/cloud/
                     "cloud.region" => baggage.region = val,
                     "cloud.availability_zone" => baggage.zone = val,
-                    _ => {}
+                    k => {
+                        let Some((k, allowed)) = allowed_labels.get_key_value(k) else {
+                            return;
+                        };
+                        // Values become metric labels, so only allowed values are kept.
+                        if let Some(val) = val.filter(|v| allowed.contains(v)) {
+                            baggage.labels.insert(k.clone(), val);
+                        }
+                    }
                 }
             }
         });
@@ -64,11 +192,17 @@ pub fn parse_baggage_header(headers: GetAll<HeaderValue>) -> Result<Baggage, ToS
 
 #[cfg(test)]
 pub mod tests {
+    use std::collections::BTreeMap;
+
     use hyper::{HeaderMap, http::HeaderValue};
 
     use crate::proxy::BAGGAGE_HEADER;
+    use crate::strng::Strng;
 
-    use super::parse_baggage_header;
+    use super::{
+        Baggage, BaggageError, MAX_BAGGAGE_LEN, parse_baggage_header,
+        parse_baggage_header_with_labels,
+    };
 
     #[test]
     fn baggage_parser() -> anyhow::Result<()> {
@@ -136,4 +270,184 @@ pub mod tests {
         assert_eq!(baggage.revision, None);
         Ok(())
     }
+
+    // allow builds an allowlist of baggage keys, each with its allowed values.
+    fn allow(labels: &[(&str, &[&str])]) -> BTreeMap<Strng, Vec<Strng>> {
+        labels
+            .iter()
+            .map(|(k, vs)| {
+                (
+                    Strng::from(*k),
+                    vs.iter().map(|v| Strng::from(*v)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn baggage_parser_labels() -> anyhow::Result<()> {
+        let mut hm = HeaderMap::new();
+        let baggage_str = "k8s.namespace.name=NS1,app=reviews,version=v2,team=payments,tier=,stage=attacker-chosen";
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(baggage_str)?);
+        let allowed = allow(&[
+            ("app", &["reviews", "ratings"]),
+            ("version", &["v1", "v2"]),
+            ("tier", &["frontend"]),
+            ("stage", &["prod"]),
+        ]);
+        let baggage = parse_baggage_header_with_labels(hm.get_all(BAGGAGE_HEADER), &allowed)?;
+        assert_eq!(baggage.namespace, Some("NS1".into()));
+        // Keys outside the allowlist, values outside their key's allowlist, and empty values, are
+        // dropped.
+        assert_eq!(
+            baggage.labels,
+            [
+                ("app".into(), "reviews".into()),
+                ("version".into(), "v2".into())
+            ]
+            .into()
+        );
+
+        // Without an allowlist, no extra keys are kept.
+        let baggage = parse_baggage_header(hm.get_all(BAGGAGE_HEADER))?;
+        assert!(baggage.labels.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_round_trip() -> anyhow::Result<()> {
+        let baggage = Baggage {
+            cluster_id: Some("K1".into()),
+            namespace: Some("NS1".into()),
+            workload_type: Some("cronjob".into()),
+            workload_name: Some("N1".into()),
+            service_name: Some("N2".into()),
+            revision: Some("V1".into()),
+            region: Some("R1".into()),
+            zone: Some("Z1".into()),
+            labels: [
+                ("app".into(), "reviews".into()),
+                ("version".into(), "v2".into()),
+            ]
+            .into(),
+        };
+        let mut hm = HeaderMap::new();
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(&baggage.encode())?);
+        let allowed = allow(&[("app", &["reviews"]), ("version", &["v2"])]);
+        let parsed = parse_baggage_header_with_labels(hm.get_all(BAGGAGE_HEADER), &allowed)?;
+        assert_eq!(parsed, baggage);
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_encoding() -> anyhow::Result<()> {
+        let baggage = Baggage {
+            namespace: Some("a,b=c d%".into()),
+            labels: [("app".into(), "ré;view".into())].into(),
+            ..Default::default()
+        };
+        let encoded = baggage.encode();
+        assert!(
+            encoded.contains("k8s.namespace.name=a%2Cb%3Dc%20d%25,"),
+            "{encoded}"
+        );
+        assert!(encoded.ends_with(",app=r%C3%A9%3Bview"), "{encoded}");
+
+        let mut hm = HeaderMap::new();
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(&encoded)?);
+        let parsed = parse_baggage_header_with_labels(
+            hm.get_all(BAGGAGE_HEADER),
+            &allow(&[("app", &["ré;view"])]),
+        )?;
+        assert_eq!(parsed.namespace, baggage.namespace);
+        assert_eq!(parsed.labels, baggage.labels);
+
+        // Malformed escapes are kept as sent.
+        let mut hm = HeaderMap::new();
+        hm.append(
+            BAGGAGE_HEADER,
+            HeaderValue::from_str("k8s.namespace.name=a%2,k8s.cluster.name=%zz")?,
+        );
+        let parsed = parse_baggage_header(hm.get_all(BAGGAGE_HEADER))?;
+        assert_eq!(parsed.namespace, Some("a%2".into()));
+        assert_eq!(parsed.cluster_id, Some("%zz".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_parser_malformed() -> anyhow::Result<()> {
+        let mut hm = HeaderMap::new();
+        let baggage_str =
+            "=,,k8s.namespace.name,=NS0,k8s.cluster.name==K1, k8s.namespace.name = NS1 ,app=a=b";
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(baggage_str)?);
+        let baggage = parse_baggage_header_with_labels(
+            hm.get_all(BAGGAGE_HEADER),
+            &allow(&[("app", &["a"])]),
+        )?;
+        assert_eq!(baggage.cluster_id, None);
+        assert_eq!(baggage.namespace, Some("NS1".into()));
+        assert_eq!(baggage.labels, [("app".into(), "a".into())].into());
+
+        // Obsolete text is valid in a header value, but is not a valid string.
+        let mut hm = HeaderMap::new();
+        hm.append(
+            BAGGAGE_HEADER,
+            HeaderValue::from_bytes(b"k8s.cluster.name=\xff")?,
+        );
+        assert!(matches!(
+            parse_baggage_header(hm.get_all(BAGGAGE_HEADER)),
+            Err(BaggageError::Invalid(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_parser_limits() -> anyhow::Result<()> {
+        let mut hm = HeaderMap::new();
+        let entry = format!("k8s.cluster.name={}", "a".repeat(1000));
+        for _ in 0..(MAX_BAGGAGE_LEN / entry.len() + 1) {
+            hm.append(BAGGAGE_HEADER, HeaderValue::from_str(&entry)?);
+        }
+        assert!(matches!(
+            parse_baggage_header(hm.get_all(BAGGAGE_HEADER)),
+            Err(BaggageError::TooLarge(_))
+        ));
+
+        let mut hm = HeaderMap::new();
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(&",".repeat(1000))?);
+        assert!(matches!(
+            parse_baggage_header(hm.get_all(BAGGAGE_HEADER)),
+            Err(BaggageError::TooManyEntries(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_parser_random_input() {
+        use rand::Rng;
+        let mut rng = rand::rng();
+        let allowed = allow(&[("app", &["a"])]);
+        for _ in 0..10_000 {
+            let len = rng.random_range(0..512);
+            // Bias towards the separators so we exercise the splitting.
+            let data: Vec<u8> = (0..len)
+                .map(|_| match rng.random_range(0..8) {
+                    0 => b',',
+                    1 => b'=',
+                    _ => rng.random(),
+                })
+                .collect();
+            let Ok(hv) = HeaderValue::from_bytes(&data) else {
+                continue;
+            };
+            let mut hm = HeaderMap::new();
+            hm.append(BAGGAGE_HEADER, hv);
+            if let Ok(baggage) =
+                parse_baggage_header_with_labels(hm.get_all(BAGGAGE_HEADER), &allowed)
+            {
+                // Only allowlisted keys are retained.
+                assert!(baggage.labels.len() <= allowed.len());
+            }
+        }
+    }
 }
@@ -16,6 +16,8 @@ use crate::config;
 use crate::config::ProxyMode;
 use crate::identity::Priority::Warmup;
 use crate::identity::{Identity, Request, SecretManager};
+use crate::proxy;
+use crate::proxy::metrics::{CertPrefetchLabels, PrefetchResult};
 use crate::state::workload::{Protocol, Workload};
 use std::sync::Arc;
 use tokio::sync::mpsc;
@@ -36,10 +38,14 @@ impl CertFetcher for NoCertFetcher {
 }
 
 /// Constructs an appropriate [CertFetcher] for the proxy config.
-pub fn new(cfg: &config::Config, cert_manager: Arc<SecretManager>) -> Arc<dyn CertFetcher> {
+pub fn new(
+    cfg: &config::Config,
+    cert_manager: Arc<SecretManager>,
+    metrics: Arc<proxy::Metrics>,
+) -> Arc<dyn CertFetcher> {
     match cfg.proxy_mode {
         ProxyMode::Dedicated => Arc::new(NoCertFetcher()),
-        ProxyMode::Shared => Arc::new(CertFetcherImpl::new(cfg, cert_manager)),
+        ProxyMode::Shared => Arc::new(CertFetcherImpl::new(cfg, cert_manager, metrics)),
     }
 }
 
@@ -51,7 +57,11 @@ struct CertFetcherImpl {
 }
 
 impl CertFetcherImpl {
-    fn new(cfg: &config::Config, cert_manager: Arc<SecretManager>) -> Self {
+    fn new(
+        cfg: &config::Config,
+        cert_manager: Arc<SecretManager>,
+        metrics: Arc<proxy::Metrics>,
+    ) -> Self {
         let (tx, mut rx) = mpsc::channel::<Request>(256);
 
         // Spawn a task for handling the pre-fetch requests asynchronously.
@@ -59,19 +69,27 @@ impl CertFetcherImpl {
             while let Some(req) = rx.recv().await {
                 match req {
                     Request::Fetch(workload_identity, priority) => {
-                        match cert_manager
+                        let result = match cert_manager
                             .fetch_certificate_pri(&workload_identity, priority)
                             .await
                         {
                             Ok(_) => {
-                                debug!("prefetched cert for {:?}", workload_identity.to_string())
+                                debug!("prefetched cert for {:?}", workload_identity.to_string());
+                                PrefetchResult::success
                             }
-                            Err(e) => error!(
-                                "unable to prefetch cert for {:?}, skipping, {:?}",
-                                workload_identity.to_string(),
-                                e
-                            ),
-                        }
+                            Err(e) => {
+                                error!(
+                                    "unable to prefetch cert for {:?}, skipping, {:?}",
+                                    workload_identity.to_string(),
+                                    e
+                                );
+                                PrefetchResult::failure
+                            }
+                        };
+                        metrics
+                            .cert_prefetch
+                            .get_or_create(&CertPrefetchLabels { result })
+                            .inc();
                     }
                     Request::Forget(workload_identity) => {
                         cert_manager.forget_certificate(&workload_identity).await;
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use serde::ser::SerializeSeq;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
@@ -28,6 +28,7 @@ use bytes::Bytes;
 use hickory_resolver::config::{LookupIpStrategy, ResolverConfig, ResolverOpts};
 use hyper::Uri;
 use hyper::http::uri::InvalidUri;
+use ipnet::IpNet;
 
 use crate::strng::Strng;
 use crate::{identity, state};
@@ -46,6 +47,9 @@ const KEEPALIVE_INTERVAL: &str = "KEEPALIVE_INTERVAL";
 const KEEPALIVE_RETRIES: &str = "KEEPALIVE_RETRIES";
 const KEEPALIVE_ENABLED: &str = "KEEPALIVE_ENABLED";
 const USER_TIMEOUT_ENABLED: &str = "USER_TIMEOUT_ENABLED";
+const TCP_USER_TIMEOUT: &str = "TCP_USER_TIMEOUT";
+const LISTEN_BACKLOG: &str = "LISTEN_BACKLOG";
+const MPTCP_ENABLED: &str = "MPTCP_ENABLED";
 const INPOD_UDS: &str = "INPOD_UDS";
 const INPOD_PORT_REUSE: &str = "INPOD_PORT_REUSE";
 const CLUSTER_ID: &str = "CLUSTER_ID";
@@ -54,12 +58,33 @@ const LOCAL_XDS_PATH: &str = "LOCAL_XDS_PATH";
 const LOCAL_XDS: &str = "LOCAL_XDS";
 const XDS_ON_DEMAND: &str = "XDS_ON_DEMAND";
 const XDS_ADDRESS: &str = "XDS_ADDRESS";
+const XDS_RECONNECT_BACKOFF_BASE: &str = "XDS_RECONNECT_BACKOFF_BASE";
+const XDS_RECONNECT_BACKOFF_MAX: &str = "XDS_RECONNECT_BACKOFF_MAX";
 const CA_ADDRESS: &str = "CA_ADDRESS";
 const SECRET_TTL: &str = "SECRET_TTL";
+const CERT_CACHE_MAX_IDENTITIES: &str = "CERT_CACHE_MAX_IDENTITIES";
+const CERT_ROTATION_THRESHOLD: &str = "CERT_ROTATION_THRESHOLD";
 const FAKE_CA: &str = "FAKE_CA";
 const ZTUNNEL_WORKER_THREADS: &str = "ZTUNNEL_WORKER_THREADS";
 const POOL_MAX_STREAMS_PER_CONNECTION: &str = "POOL_MAX_STREAMS_PER_CONNECTION";
 const POOL_UNUSED_RELEASE_TIMEOUT: &str = "POOL_UNUSED_RELEASE_TIMEOUT";
+const HBONE_MAX_CONCURRENT_STREAMS: &str = "HBONE_MAX_CONCURRENT_STREAMS";
+const HBONE_MAX_HEADER_LIST_SIZE: &str = "HBONE_MAX_HEADER_LIST_SIZE";
+const CONNECTION_RATE_LIMIT: &str = "CONNECTION_RATE_LIMIT";
+const SERVICE_CONNECTION_RATE_LIMIT: &str = "SERVICE_CONNECTION_RATE_LIMIT";
+const COPY_BUFFER_MAX_SIZE: &str = "COPY_BUFFER_MAX_SIZE";
+const CONNECT_TIMEOUT: &str = "CONNECT_TIMEOUT";
+const HBONE_CONNECT_RETRIES: &str = "HBONE_CONNECT_RETRIES";
+const TLS_SESSION_CACHE_SIZE: &str = "TLS_SESSION_CACHE_SIZE";
+const HBONE_CONNECT_RETRY_BACKOFF: &str = "HBONE_CONNECT_RETRY_BACKOFF";
+const IDLE_TIMEOUT: &str = "IDLE_TIMEOUT";
+const HALF_CLOSE_TIMEOUT: &str = "HALF_CLOSE_TIMEOUT";
+const MAX_CONNECTIONS_PER_IDENTITY: &str = "MAX_CONNECTIONS_PER_IDENTITY";
+const MAX_INBOUND_CONNECTIONS: &str = "MAX_INBOUND_CONNECTIONS";
+const CIRCUIT_BREAKER_THRESHOLD: &str = "CIRCUIT_BREAKER_THRESHOLD";
+const CIRCUIT_BREAKER_COOLDOWN: &str = "CIRCUIT_BREAKER_COOLDOWN";
+const HTTP2_KEEPALIVE_INTERVAL: &str = "HTTP2_KEEPALIVE_INTERVAL";
+const HTTP2_KEEPALIVE_TIMEOUT: &str = "HTTP2_KEEPALIVE_TIMEOUT";
 // CONNECTION_TERMINATION_DEADLINE configures an explicit deadline
 const CONNECTION_TERMINATION_DEADLINE: &str = "CONNECTION_TERMINATION_DEADLINE";
 // TERMINATION_GRACE_PERIOD_SECONDS configures the Kubernetes terminationGracePeriodSeconds configuration.
@@ -67,10 +92,47 @@ const CONNECTION_TERMINATION_DEADLINE: &str = "CONNECTION_TERMINATION_DEADLINE";
 // (Our forceful shutdown is more graceful than a SIGKILL, as we can close connections cleanly).
 const TERMINATION_GRACE_PERIOD_SECONDS: &str = "TERMINATION_GRACE_PERIOD_SECONDS";
 const ENABLE_ORIG_SRC: &str = "ENABLE_ORIG_SRC";
+const ORIG_SRC_PRESERVE_PORT: &str = "ORIG_SRC_PRESERVE_PORT";
+const INBOUND_PROXY_PROTOCOL_TRUSTED_RANGES: &str = "INBOUND_PROXY_PROTOCOL_TRUSTED_RANGES";
+const OUTBOUND_PROXY_PROTOCOL: &str = "OUTBOUND_PROXY_PROTOCOL";
+const ENABLE_HTTP1_CONNECT: &str = "ENABLE_HTTP1_CONNECT";
+const REQUIRE_HBONE_ALPN: &str = "REQUIRE_HBONE_ALPN";
+const PROBE_SOURCE_RANGES: &str = "PROBE_SOURCE_RANGES";
+const INBOUND_INTERFACE: &str = "INBOUND_INTERFACE";
+const OUTBOUND_INTERFACE: &str = "OUTBOUND_INTERFACE";
+const OUTBOUND_UDS: &str = "OUTBOUND_UDS";
+const OUTBOUND_UDP_ADDR: &str = "OUTBOUND_UDP_ADDR";
+const UDP_IDLE_TIMEOUT: &str = "UDP_IDLE_TIMEOUT";
+const ALLOW_OUTBOUND_PASSTHROUGH: &str = "ALLOW_OUTBOUND_PASSTHROUGH";
+const ENDPOINT_SLOW_START_WINDOW: &str = "ENDPOINT_SLOW_START_WINDOW";
+const ENDPOINT_HEALTH_CHECK_INTERVAL: &str = "ENDPOINT_HEALTH_CHECK_INTERVAL";
+const ENDPOINT_HEALTH_CHECK_TIMEOUT: &str = "ENDPOINT_HEALTH_CHECK_TIMEOUT";
+const ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD: &str = "ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD";
+const ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD: &str = "ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD";
+const BAGGAGE_LABELS: &str = "BAGGAGE_LABELS";
+const CONNECTION_TAG_HEADER: &str = "CONNECTION_TAG_HEADER";
+const COARSE_LOCALITY_METRICS: &str = "COARSE_LOCALITY_METRICS";
+const ACCESS_LOG_SAMPLING: &str = "ACCESS_LOG_SAMPLING";
+const DSCP: &str = "DSCP";
+const SERVICE_DSCP: &str = "SERVICE_DSCP";
+const PROTOCOL_OVERRIDES: &str = "PROTOCOL_OVERRIDES";
+const SERVICE_WINDOW_SIZES: &str = "SERVICE_WINDOW_SIZES";
+const WORKLOAD_WINDOW_SIZES: &str = "WORKLOAD_WINDOW_SIZES";
+const LOCALITY_WINDOW_SIZES: &str = "LOCALITY_WINDOW_SIZES";
+const PLAINTEXT_FALLBACK: &str = "PLAINTEXT_FALLBACK";
+const SANDWICH_PORT_MAPPINGS: &str = "SANDWICH_PORT_MAPPINGS";
+const TRACING_ENDPOINT: &str = "TRACING_ENDPOINT";
+const TRUST_DOMAIN_ALIASES: &str = "TRUST_DOMAIN_ALIASES";
+const TRUSTED_AUTHORITY_IDENTITIES: &str = "TRUSTED_AUTHORITY_IDENTITIES";
+const DNS_RESOLV_CONF: &str = "DNS_RESOLV_CONF";
+const DNS_SEARCH_DOMAINS: &str = "DNS_SEARCH_DOMAINS";
+const DNS_NDOTS: &str = "DNS_NDOTS";
+const DNS_RESOLVER_TIMEOUT: &str = "DNS_RESOLVER_TIMEOUT";
 const PROXY_CONFIG: &str = "PROXY_CONFIG";
 const IPV6_ENABLED: &str = "IPV6_ENABLED";
 
 const UNSTABLE_ENABLE_SOCKS5: &str = "UNSTABLE_ENABLE_SOCKS5";
+const UNSTABLE_PLAINTEXT_HBONE_ADDR: &str = "UNSTABLE_PLAINTEXT_HBONE_ADDR";
 
 const DEFAULT_WORKER_THREADS: u16 = 2;
 const DEFAULT_ADMIN_PORT: u16 = 15000;
@@ -82,7 +144,22 @@ const DEFAULT_CLUSTER_ID: &str = "Kubernetes";
 const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
 const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24); // 24 hours
 const DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT: Duration = Duration::from_secs(60 * 5); // 5 minutes
+const DEFAULT_HBONE_MAX_CONCURRENT_STREAMS: u32 = 200; // hyper's default
+const DEFAULT_HBONE_MAX_HEADER_LIST_SIZE: u32 = 64 * 1024;
 const DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION: u16 = 100; //Go: 100, Hyper: 200, Envoy: 2147483647 (lol), Spec recommended minimum 100
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_HBONE_CONNECT_RETRIES: u32 = 0;
+const DEFAULT_TLS_SESSION_CACHE_SIZE: usize = 0;
+const DEFAULT_HBONE_CONNECT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(5);
+const DEFAULT_ENDPOINT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD: u32 = 3;
+const DEFAULT_ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD: u32 = 2;
+const DEFAULT_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_HTTP2_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_HTTP2_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+const DEFAULT_XDS_RECONNECT_BACKOFF_BASE: Duration = Duration::from_millis(10);
+const DEFAULT_XDS_RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(15);
 
 const DEFAULT_INPOD_MARK: u32 = 1337;
 
@@ -141,6 +218,41 @@ pub enum ProxyMode {
     Dedicated,
 }
 
+/// PlaintextFallback selects the destination workloads that outbound traffic may reach over
+/// plaintext when an mTLS tunnel to them cannot be established.
+#[derive(serde::Serialize, Default, Clone, Debug, PartialEq, Eq)]
+pub enum PlaintextFallback {
+    #[default]
+    Disabled,
+    All,
+    /// Workloads keyed by `namespace/name`.
+    Workloads(HashSet<Strng>),
+}
+
+impl PlaintextFallback {
+    pub fn allows(&self, wl: &state::workload::Workload) -> bool {
+        match self {
+            PlaintextFallback::Disabled => false,
+            PlaintextFallback::All => true,
+            PlaintextFallback::Workloads(workloads) => {
+                workloads.contains(&crate::strng::format!("{}/{}", wl.namespace, wl.name))
+            }
+        }
+    }
+}
+
+/// LOCALITY_RELATIONS are the keys of `locality_window_sizes`, describing where a destination
+/// workload is relative to us.
+pub const LOCALITY_RELATIONS: [&str; 3] = ["same-zone", "cross-zone", "cross-region"];
+
+/// WindowSizes are the HTTP/2 flow control windows of an HBONE connection.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowSizes {
+    pub window_size: u32,
+    pub connection_window_size: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct MetadataVector {
     pub vec: Vec<(AsciiMetadataKey, AsciiMetadataValue)>,
@@ -184,6 +296,19 @@ pub struct Config {
     pub connection_window_size: u32,
     pub frame_size: u32,
 
+    /// How often to send HTTP/2 PINGs on HBONE connections, in both directions. Besides detecting
+    /// dead peers, this keeps NAT and firewall mappings alive for long-lived, idle HBONE tunnels.
+    /// Setting HTTP2_KEEPALIVE_INTERVAL to 0 disables keepalive.
+    pub http2_keepalive_interval: Option<Duration>,
+    /// How long to wait for a PING acknowledgement before closing the HBONE connection.
+    pub http2_keepalive_timeout: Duration,
+    /// The maximum number of concurrent streams a client may open on a single inbound HBONE connection.
+    /// Additional streams are queued by the client until existing ones complete.
+    pub max_concurrent_streams: u32,
+    /// The maximum size, in bytes, of the headers of an inbound HBONE request, as defined for
+    /// SETTINGS_MAX_HEADER_LIST_SIZE. Requests with larger headers are rejected.
+    pub max_header_list_size: u32,
+
     // The limit of how many streams a single HBONE pool connection will be limited to, before
     // spawning a new conn rather than reusing an existing one, even to a dest that already has an open connection.
     //
@@ -199,13 +324,89 @@ pub struct Config {
 
     pub pool_unused_release_timeout: Duration,
 
+    /// How long to wait for an upstream TCP connection to be established before giving up.
+    pub connect_timeout: Duration,
+    /// How many times to retry establishing an outbound HBONE tunnel if the connection, handshake,
+    /// or CONNECT request fails. Retries only happen before any client data has been forwarded.
+    /// Defaults to 0, which disables retries.
+    pub hbone_connect_retries: u32,
+    /// The delay before the first HBONE connect retry; each further retry waits one more multiple.
+    pub hbone_connect_retry_backoff: Duration,
+    /// If set, proxied connections that have not transferred any bytes in either direction for
+    /// this long are closed. If unset, idle connections are never reaped.
+    pub idle_timeout: Option<Duration>,
+    /// If set, once one side of a proxied connection half-closes, the connection is closed if the
+    /// other direction transfers nothing for this long. If unset, it may stay open indefinitely.
+    pub half_close_timeout: Option<Duration>,
+    /// If set, each direction of a proxied connection is limited to this many bytes per second.
+    pub connection_rate_limit: Option<u64>,
+    /// Per-service byte rate limits, keyed by destination service hostname, overriding
+    /// `connection_rate_limit`.
+    pub service_connection_rate_limit: HashMap<Strng, u64>,
+    /// If set, caps the size in bytes that each connection's copy buffers grow to under sustained
+    /// traffic. Smaller buffers bound the memory held per connection at the cost of throughput.
+    pub copy_buffer_max_size: Option<usize>,
+    /// If set, inbound connections from a source identity that already has this many open
+    /// connections are rejected.
+    pub max_connections_per_identity: Option<usize>,
+    /// If set, at most this many inbound connections are served at once across all proxies; further
+    /// connections are not accepted until one finishes.
+    pub max_inbound_connections: Option<usize>,
+    /// If set, inbound connections to an endpoint of a destination service are rejected with a 503
+    /// once this many consecutive upstream connection attempts to it have failed.
+    pub circuit_breaker_threshold: Option<u32>,
+    /// How long a tripped circuit breaker rejects connections before letting a probe through.
+    pub circuit_breaker_cooldown: Duration,
+
     pub socks5_addr: Option<SocketAddr>,
     pub admin_addr: Address,
     pub stats_addr: Address,
     pub readiness_addr: Address,
     pub inbound_addr: SocketAddr,
     pub inbound_plaintext_addr: SocketAddr,
+    /// If set, an additional HBONE listener is served on this address without mTLS. This is
+    /// intended for use behind an external TLS terminator; all requests are treated as unauthenticated.
+    /// Wildcard addresses are rejected, so the listener is only reachable where explicitly configured.
+    pub inbound_plaintext_hbone_addr: Option<SocketAddr>,
+    /// Connections accepted on the plaintext inbound listeners from these ranges, typically the
+    /// PROXY protocol speaking load balancers fronting ztunnel, must begin with a PROXY protocol
+    /// header, which is used as the connection source. Connections from other peers are never
+    /// parsed for a header, so they cannot spoof their source. Empty (the default) disables PROXY
+    /// protocol.
+    pub inbound_proxy_protocol_trusted_ranges: Vec<IpNet>,
+    /// If true, connections accepted on the outbound listener must begin with a PROXY protocol
+    /// header, whose destination is used as the original destination. This allows connections that
+    /// were not redirected, such as from local testing, to name their destination. The header's
+    /// source is ignored.
+    pub outbound_proxy_protocol: bool,
+    /// If true, the inbound HBONE listener also accepts HTTP/1.1 CONNECT from peers that negotiate
+    /// `http/1.1` over ALPN, for interop with gateways that cannot tunnel over HTTP/2.
+    pub http1_connect: bool,
+    /// If true, the inbound HBONE listener rejects peers that do not negotiate one of its ALPN
+    /// protocols, including peers that offer no ALPN at all.
+    pub require_hbone_alpn: bool,
+    /// Number of TLS sessions cached for resuming HBONE connections, on each of the client and
+    /// server side. Resumption skips the full handshake but does not re-verify the peer
+    /// certificate, so it is disabled (0) by default.
+    pub tls_session_cache_size: usize,
+    /// Plaintext inbound connections from these ranges, such as kubelet health probes from the
+    /// node, are passed to the application without authorization policy checks. Empty by default.
+    pub probe_source_ranges: Vec<IpNet>,
+    /// If set, the inbound listeners only accept connections arriving on this network interface.
+    /// The interface is looked up in the listener's network namespace, which in in-pod mode is the
+    /// pod's, so a missing interface fails that pod's listeners rather than startup.
+    pub inbound_iface: Option<String>,
     pub outbound_addr: SocketAddr,
+    /// If set, the outbound listener only accepts connections arriving on this network interface.
+    pub outbound_iface: Option<String>,
+    /// If set, outbound traffic is also accepted on a Unix domain socket at this path. Clients
+    /// name the destination with a SOCKS5 CONNECT, as there is no original destination to recover.
+    pub outbound_uds: Option<PathBuf>,
+    /// If set, outbound UDP is accepted on this address and carried over HBONE. Clients name the
+    /// destination of each datagram with a SOCKS5 UDP request header.
+    pub outbound_udp_addr: Option<SocketAddr>,
+    /// How long a UDP association may go without a datagram in either direction before it is closed.
+    pub udp_idle_timeout: Duration,
     /// The socket address for the DNS proxy. Only applies if `dns_proxy` is true.
     pub dns_proxy_addr: Address,
     /// Populated with the internal ports of all the proxy handlers defined above.
@@ -222,6 +423,9 @@ pub struct Config {
     pub cluster_id: String,
     /// The domain of the cluster that this ztunnel belongs to
     pub cluster_domain: String,
+    /// Trust domains that are accepted as equivalent to a workload's own trust domain, for
+    /// federated meshes. Configured as a comma-separated list.
+    pub trust_domain_aliases: Vec<Strng>,
 
     /// CA address to use. If fake_ca is set, this will be None.
     /// Note: we do not implicitly use None when set to "" since using the fake_ca is not secure.
@@ -239,11 +443,24 @@ pub struct Config {
 
     /// TTL for CSR requests
     pub secret_ttl: Duration,
+    /// If set, at most this many identities have certificates cached; the least recently used
+    /// certificate is evicted to make room for a new identity. Certificates of workloads running
+    /// on this node are kept until the workload is removed, even if that exceeds the limit.
+    /// Unbounded by default.
+    pub cert_cache_max_identities: Option<usize>,
+    /// If set, certificates are rotated at least this long before they expire, and pooled
+    /// outbound connections established with a certificate that close to expiry are no longer
+    /// reused, so they drain and new connections handshake with the fresh certificate.
+    pub cert_rotation_threshold: Option<Duration>,
     /// YAML config for local XDS workloads
     #[serde(skip_serializing)]
     pub local_xds_config: Option<ConfigSource>,
     /// If true, on-demand XDS will be used
     pub xds_on_demand: bool,
+    /// The delay before reconnecting to XDS after the first failure. Each consecutive failure doubles
+    /// it, up to xds_reconnect_backoff_max; the actual delay is jittered.
+    pub xds_reconnect_backoff_base: Duration,
+    pub xds_reconnect_backoff_max: Duration,
 
     /// If true, then use builtin fake CA with self-signed certificates.
     pub fake_ca: bool,
@@ -263,8 +480,89 @@ pub struct Config {
 
     // If set, explicitly configure whether to use original source.
     // If unset (recommended), this is automatically detected based on permissions.
+    // Outbound plaintext connections only preserve the original source when this is explicitly true.
     pub require_original_source: Option<bool>,
 
+    /// If true, outbound plaintext connections that preserve the original source IP also try to
+    /// reuse the client's source port. This only applies when the original source is in use. The
+    /// port is best effort: if it is already bound on this host (for instance when the client shares
+    /// ztunnel's network namespace), or the resulting connection would collide with an existing one,
+    /// an ephemeral port is used instead. Upstreams may also see a reused port before an earlier
+    /// connection on it has left TIME_WAIT, which can cause connection resets.
+    pub preserve_source_port: bool,
+
+    /// If false, outbound traffic to destinations that are not known to ztunnel is rejected rather
+    /// than sent directly as plaintext TCP.
+    pub allow_outbound_passthrough: bool,
+    /// If set, endpoints that ztunnel has only recently discovered receive a share of new
+    /// connections that ramps up linearly over this window, rather than their full share at once.
+    pub endpoint_slow_start_window: Option<Duration>,
+    /// If set, ztunnel probes service endpoints outside the mesh with a TCP connect on their target
+    /// port at this interval, and ejects endpoints that keep failing from load balancing for that
+    /// service until they recover.
+    pub endpoint_health_check_interval: Option<Duration>,
+    /// How long an endpoint health check connect may take before it counts as a failure.
+    pub endpoint_health_check_timeout: Duration,
+    /// Consecutive failed health checks after which an endpoint is ejected.
+    pub endpoint_health_check_unhealthy_threshold: u32,
+    /// Consecutive successful health checks after which an ejected endpoint is readmitted.
+    pub endpoint_health_check_healthy_threshold: u32,
+
+    /// Additional baggage keys, beyond the well-known workload fields, that are read from inbound
+    /// requests and reported as source labels on metrics, each with the values that may be
+    /// reported. Configured as a comma-separated list of `key=value1|value2` entries. Every key is
+    /// always reported, as unknown when the peer did not send one of its allowed values.
+    pub baggage_labels: BTreeMap<Strng, Vec<Strng>>,
+    /// If set, inbound CONNECT requests may carry opaque tags in this header, for example
+    /// `x-ztunnel-tag`. Tags are validated and reported in access logs, but not used for routing.
+    pub connection_tag_header: Option<String>,
+    /// If true, traffic metrics are not labeled with the source and destination region and zone;
+    /// whether traffic crossed zones or regions is still reported by tcp_connections_locality.
+    pub coarse_locality_metrics: bool,
+    /// If set, only 1 in this many connections are access logged, chosen by a hash of the connection's
+    /// addresses. Connections that fail or are denied are always logged.
+    pub access_log_sampling: Option<u32>,
+    /// DSCP value (0-63) to mark outbound connections originated by ztunnel with, for QoS on the
+    /// underlay network.
+    pub dscp: Option<u8>,
+    /// Per-service DSCP values, keyed by service hostname, overriding `dscp`.
+    pub service_dscp: HashMap<Strng, u8>,
+    /// Protocols to reach specific destination workloads with, keyed by `namespace/name`, overriding
+    /// the protocol the workload declares. For example, `ns/legacy=TCP` sends plaintext to a mesh
+    /// workload during a migration. Overrides to TCP are ignored for workloads whose authorization
+    /// policies would deny unauthenticated clients.
+    pub protocol_overrides: HashMap<Strng, state::workload::Protocol>,
+    /// HTTP/2 windows for outbound HBONE connections to specific services, keyed by service
+    /// hostname, overriding `window_size` and `connection_window_size`. Configured as a
+    /// comma-separated list of `hostname=window_size:connection_window_size`.
+    pub service_window_sizes: HashMap<Strng, WindowSizes>,
+    /// HTTP/2 windows for inbound HBONE connections to specific workloads, keyed by
+    /// `namespace/name`, overriding `window_size` and `connection_window_size`. Configured the same
+    /// way as `service_window_sizes`.
+    pub workload_window_sizes: HashMap<Strng, WindowSizes>,
+    /// HTTP/2 windows for outbound HBONE connections, keyed by where the destination workload is
+    /// relative to us: `same-zone`, `cross-zone` or `cross-region`. Overridden by
+    /// `service_window_sizes`, and configured the same way. Workloads without a known locality use
+    /// the global windows.
+    pub locality_window_sizes: HashMap<Strng, WindowSizes>,
+    /// Destination workloads that outbound traffic falls back to plaintext for when establishing
+    /// an mTLS tunnel to them fails, for use while onboarding workloads to the mesh. Configured as
+    /// `*` for all workloads or a comma-separated list of `namespace/name`. Disabled by default.
+    pub plaintext_fallback: PlaintextFallback,
+    /// If set, per-connection spans are exported over plaintext OTLP/gRPC to this endpoint, for
+    /// example `http://otel-collector:4317`. Requires the `otel` feature.
+    pub tracing_endpoint: Option<String>,
+    /// Source identities allowed to send an HBONE authority whose IP differs from the connection's
+    /// destination, as long as it is another IP of the destination workload, such as a secondary pod
+    /// IP. Configured as a comma-separated list of SPIFFE IDs. Empty by default, which enforces that
+    /// the two match.
+    pub trusted_authority_identities: Vec<identity::Identity>,
+    /// Ports to forward waypoint sandwich traffic to, keyed by the port in the HBONE authority. When
+    /// the authority addresses a destination other than the waypoint itself, its port is that of the
+    /// final destination, which the waypoint may listen for on a different port. Configured as a
+    /// comma-separated list of `authority_port=waypoint_port` pairs. Unmapped ports are unchanged.
+    pub sandwich_port_mappings: HashMap<u16, u16>,
+
     // CLI args passed to ztunnel at runtime
     pub proxy_args: String,
 
@@ -303,6 +601,13 @@ pub struct SocketConfig {
     pub keepalive_retries: u32,
     pub keepalive_enabled: bool,
     pub user_timeout_enabled: bool,
+    /// If set, TCP_USER_TIMEOUT for proxied connections, bounding how long unacknowledged data is
+    /// retransmitted before the connection is dropped. Takes precedence over `user_timeout_enabled`.
+    pub user_timeout: Option<Duration>,
+    /// The maximum number of pending connections queued on each TCP listener.
+    pub listen_backlog: u32,
+    /// If true, outbound connections use Multipath TCP when the kernel supports it.
+    pub mptcp_enabled: bool,
 }
 
 impl Default for SocketConfig {
@@ -314,6 +619,9 @@ impl Default for SocketConfig {
             keepalive_enabled: true,
             // Might be a good idea but for now we haven't proven this out enough.
             user_timeout_enabled: false,
+            user_timeout: None,
+            listen_backlog: 128,
+            mptcp_enabled: false,
         }
     }
 }
@@ -372,6 +680,319 @@ fn parse_duration_default(env: &str, default: Duration) -> Result<Duration, Erro
     parse_duration(env).map(|v| v.unwrap_or(default))
 }
 
+/// parse_ip_ranges parses a comma separated list of CIDRs, which is empty if unset.
+fn parse_ip_ranges(env: &str) -> Result<Vec<IpNet>, Error> {
+    parse::<String>(env)?
+        .map(|raw| {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    s.parse::<IpNet>()
+                        .map_err(|e| Error::EnvVar(env.to_string(), raw.clone(), e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// DnsResolverOverrides are resolver settings set explicitly in the environment, which take
+/// precedence over those in resolv.conf.
+#[derive(Default)]
+struct DnsResolverOverrides {
+    search: Option<Vec<String>>,
+    ndots: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+/// parse_dns_resolver_config builds the resolver configuration from `resolv_conf`, or the system
+/// resolv.conf if unset, so hostname lookups apply the same search domains and ndots as the
+/// application would.
+fn parse_dns_resolver_config(
+    resolv_conf: Option<&[u8]>,
+    overrides: DnsResolverOverrides,
+) -> Result<(ResolverConfig, ResolverOpts), Error> {
+    use hickory_resolver::Name;
+    use hickory_resolver::system_conf::{parse_resolv_conf, read_system_conf};
+
+    let (mut cfg, mut opts) = match resolv_conf {
+        Some(data) => parse_resolv_conf(data),
+        None => read_system_conf(),
+    }
+    .map_err(|e| Error::InvalidState(format!("failed to load resolv.conf: {e}")))?;
+    if let Some(search) = overrides.search {
+        let search = search
+            .iter()
+            .map(|s| {
+                Name::from_str_relaxed(s).map_err(|e| {
+                    Error::EnvVar(DNS_SEARCH_DOMAINS.to_string(), s.clone(), e.to_string())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cfg =
+            ResolverConfig::from_parts(cfg.domain().cloned(), search, cfg.name_servers().to_vec());
+    }
+    if let Some(ndots) = overrides.ndots {
+        opts.ndots = ndots;
+    }
+    if let Some(timeout) = overrides.timeout {
+        opts.timeout = timeout;
+    }
+    Ok((cfg, opts))
+}
+
+fn parse_dscp(env: &str, raw: Option<&str>) -> Result<Option<u8>, Error> {
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+    match raw.trim().parse::<u8>() {
+        Ok(dscp) if dscp < 64 => Ok(Some(dscp)),
+        Ok(_) => Err(Error::EnvVar(
+            env.to_string(),
+            raw.to_string(),
+            "DSCP must be between 0 and 63".to_string(),
+        )),
+        Err(e) => Err(Error::EnvVar(
+            env.to_string(),
+            raw.to_string(),
+            e.to_string(),
+        )),
+    }
+}
+
+/// parse_plaintext_hbone_addr parses the address for the plaintext HBONE listener. Connections to
+/// it are not authenticated, so it must be a specific address rather than a wildcard.
+fn parse_plaintext_hbone_addr(env: &str, raw: Option<&str>) -> Result<Option<SocketAddr>, Error> {
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+    match raw.trim().parse::<SocketAddr>() {
+        Ok(addr) if addr.ip().is_unspecified() => Err(Error::EnvVar(
+            env.to_string(),
+            raw.to_string(),
+            "must be a specific address, not a wildcard".to_string(),
+        )),
+        Ok(addr) => Ok(Some(addr)),
+        Err(e) => Err(Error::EnvVar(
+            env.to_string(),
+            raw.to_string(),
+            e.to_string(),
+        )),
+    }
+}
+
+/// parse_service_dscp parses a comma separated list of `hostname=dscp` pairs.
+fn parse_service_dscp(raw: Option<&str>) -> Result<HashMap<Strng, u8>, Error> {
+    let Some(raw) = raw else {
+        return Ok(HashMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let Some((host, dscp)) = entry.split_once('=') else {
+                return Err(Error::EnvVar(
+                    SERVICE_DSCP.to_string(),
+                    raw.to_string(),
+                    format!("expected hostname=dscp, got {entry}"),
+                ));
+            };
+            let dscp = parse_dscp(SERVICE_DSCP, Some(dscp))?.expect("value is set");
+            Ok((Strng::from(host.trim()), dscp))
+        })
+        .collect()
+}
+
+/// parse_service_connection_rate_limit parses a comma separated list of `hostname=bytes_per_second`
+/// pairs.
+fn parse_service_connection_rate_limit(raw: Option<&str>) -> Result<HashMap<Strng, u64>, Error> {
+    let Some(raw) = raw else {
+        return Ok(HashMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let Some((host, limit)) = entry.split_once('=') else {
+                return Err(Error::EnvVar(
+                    SERVICE_CONNECTION_RATE_LIMIT.to_string(),
+                    raw.to_string(),
+                    format!("expected hostname=bytes_per_second, got {entry}"),
+                ));
+            };
+            let limit = limit.trim().parse::<u64>().map_err(|e| {
+                Error::EnvVar(
+                    SERVICE_CONNECTION_RATE_LIMIT.to_string(),
+                    raw.to_string(),
+                    e.to_string(),
+                )
+            })?;
+            Ok((Strng::from(host.trim()), limit))
+        })
+        .collect()
+}
+
+/// parse_plaintext_fallback parses either `*` or a comma separated list of `namespace/name`.
+fn parse_plaintext_fallback(raw: Option<&str>) -> Result<PlaintextFallback, Error> {
+    let Some(raw) = raw.map(str::trim).filter(|s| !s.is_empty()) else {
+        return Ok(PlaintextFallback::Disabled);
+    };
+    if raw == "*" {
+        return Ok(PlaintextFallback::All);
+    }
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|workload| {
+            if workload.split_once('/').is_none() {
+                return Err(Error::EnvVar(
+                    PLAINTEXT_FALLBACK.to_string(),
+                    raw.to_string(),
+                    format!("expected * or namespace/name, got {workload}"),
+                ));
+            }
+            Ok(Strng::from(workload))
+        })
+        .collect::<Result<_, _>>()
+        .map(PlaintextFallback::Workloads)
+}
+
+/// parse_protocol_overrides parses a comma separated list of `namespace/name=protocol` pairs, where
+/// protocol is `TCP` or `HBONE`.
+fn parse_protocol_overrides(
+    raw: Option<&str>,
+) -> Result<HashMap<Strng, state::workload::Protocol>, Error> {
+    let Some(raw) = raw else {
+        return Ok(HashMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let invalid = |reason: String| {
+                Error::EnvVar(PROTOCOL_OVERRIDES.to_string(), raw.to_string(), reason)
+            };
+            let Some((workload, protocol)) = entry.split_once('=') else {
+                return Err(invalid(format!(
+                    "expected namespace/name=protocol, got {entry}"
+                )));
+            };
+            let workload = workload.trim();
+            if workload.split_once('/').is_none() {
+                return Err(invalid(format!("expected namespace/name, got {workload}")));
+            }
+            let protocol = match protocol.trim().to_ascii_uppercase().as_str() {
+                "TCP" => state::workload::Protocol::TCP,
+                "HBONE" => state::workload::Protocol::HBONE,
+                p => return Err(invalid(format!("unknown protocol {p}"))),
+            };
+            Ok((Strng::from(workload), protocol))
+        })
+        .collect()
+}
+
+/// parse_window_sizes parses a comma separated list of `key=window_size:connection_window_size`
+/// pairs.
+fn parse_window_sizes(env: &str, raw: Option<&str>) -> Result<HashMap<Strng, WindowSizes>, Error> {
+    let Some(raw) = raw else {
+        return Ok(HashMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let invalid = |reason: String| Error::EnvVar(env.to_string(), raw.to_string(), reason);
+            let Some((key, (window, connection_window))) = entry
+                .split_once('=')
+                .and_then(|(k, v)| Some((k, v.split_once(':')?)))
+            else {
+                return Err(invalid(format!(
+                    "expected key=window_size:connection_window_size, got {entry}"
+                )));
+            };
+            // HTTP/2 windows are 31 bits
+            let size = |s: &str| match s.trim().parse::<u32>() {
+                Ok(size) if size > 0 && size < 1 << 31 => Ok(size),
+                _ => Err(invalid(format!("invalid window size {s}"))),
+            };
+            Ok((
+                Strng::from(key.trim()),
+                WindowSizes {
+                    window_size: size(window)?,
+                    connection_window_size: size(connection_window)?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// parse_baggage_labels parses a comma separated list of `key=value1|value2` entries, giving the
+/// values allowed for each baggage key.
+fn parse_baggage_labels(raw: Option<&str>) -> Result<BTreeMap<Strng, Vec<Strng>>, Error> {
+    let Some(raw) = raw else {
+        return Ok(BTreeMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let invalid =
+                |reason: String| Error::EnvVar(BAGGAGE_LABELS.to_string(), raw.to_string(), reason);
+            let Some((key, values)) = entry.split_once('=') else {
+                return Err(invalid(format!("expected key=value1|value2, got {entry}")));
+            };
+            let values: Vec<Strng> = values
+                .split('|')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(Strng::from)
+                .collect();
+            if values.is_empty() {
+                return Err(invalid(format!("no allowed values for {key}")));
+            }
+            Ok((Strng::from(key.trim()), values))
+        })
+        .collect()
+}
+
+/// parse_port_mappings parses a comma separated list of `from=to` port pairs.
+fn parse_port_mappings(env: &str, raw: Option<&str>) -> Result<HashMap<u16, u16>, Error> {
+    let Some(raw) = raw else {
+        return Ok(HashMap::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let invalid = |reason: String| Error::EnvVar(env.to_string(), raw.to_string(), reason);
+            let Some((from, to)) = entry.split_once('=') else {
+                return Err(invalid(format!("expected from=to, got {entry}")));
+            };
+            let port = |p: &str| {
+                p.trim()
+                    .parse::<u16>()
+                    .map_err(|e| invalid(format!("invalid port {p}: {e}")))
+            };
+            Ok((port(from)?, port(to)?))
+        })
+        .collect()
+}
+
+fn parse_identities(env: &str, raw: Option<&str>) -> Result<Vec<identity::Identity>, Error> {
+    let Some(raw) = raw else {
+        return Ok(Vec::new());
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|e| Error::EnvVar(env.to_string(), raw.to_string(), format!("{e}")))
+        })
+        .collect()
+}
+
 fn parse_args() -> String {
     let cli_args: Vec<String> = env::args().collect();
     cli_args[1..].join(" ")
@@ -496,9 +1117,32 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         }
     };
 
-    use hickory_resolver::system_conf::read_system_conf;
     use tracing::warn;
-    let (dns_resolver_cfg, mut dns_resolver_opts) = read_system_conf().unwrap();
+    let resolv_conf = parse::<PathBuf>(DNS_RESOLV_CONF)?
+        .map(|path| {
+            fs::read(&path).map_err(|e| {
+                Error::EnvVar(
+                    DNS_RESOLV_CONF.to_string(),
+                    path.display().to_string(),
+                    e.to_string(),
+                )
+            })
+        })
+        .transpose()?;
+    let (dns_resolver_cfg, mut dns_resolver_opts) = parse_dns_resolver_config(
+        resolv_conf.as_deref(),
+        DnsResolverOverrides {
+            search: parse::<String>(DNS_SEARCH_DOMAINS)?.map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+            ndots: parse(DNS_NDOTS)?,
+            timeout: parse_duration(DNS_RESOLVER_TIMEOUT)?,
+        },
+    )?;
     // Increase some defaults. Note these are NOT coming from /etc/resolv.conf (only some fields do, we don't override those),
     // but rather hickory's hardcoded defaults
     dns_resolver_opts.cache_size = 4096;
@@ -533,6 +1177,12 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
     let inbound_addr = SocketAddr::new(bind_wildcard, 15008);
     let inbound_plaintext_addr = SocketAddr::new(bind_wildcard, 15006);
     let outbound_addr = SocketAddr::new(bind_wildcard, 15001);
+    let probe_source_ranges = parse_ip_ranges(PROBE_SOURCE_RANGES)?;
+
+    let inbound_plaintext_hbone_addr = parse_plaintext_hbone_addr(
+        UNSTABLE_PLAINTEXT_HBONE_ADDR,
+        parse::<String>(UNSTABLE_PLAINTEXT_HBONE_ADDR)?.as_deref(),
+    )?;
 
     let mut illegal_ports = HashSet::from([
         // HBONE doesn't have redirection, so we cannot have loops, but this would allow multiple layers of HBONE.
@@ -545,6 +1195,9 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
     if let Some(addr) = socks5_addr {
         illegal_ports.insert(addr.port());
     }
+    if let Some(addr) = inbound_plaintext_hbone_addr {
+        illegal_ports.insert(addr.port());
+    }
 
     let proxy_mode = match parse::<String>(PROXY_MODE)? {
         Some(proxy_mode) => match proxy_mode.as_str() {
@@ -619,9 +1272,47 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT,
         )?,
 
+        connect_timeout: parse_duration_default(CONNECT_TIMEOUT, DEFAULT_CONNECT_TIMEOUT)?,
+        hbone_connect_retries: parse_default(HBONE_CONNECT_RETRIES, DEFAULT_HBONE_CONNECT_RETRIES)?,
+        hbone_connect_retry_backoff: parse_duration_default(
+            HBONE_CONNECT_RETRY_BACKOFF,
+            DEFAULT_HBONE_CONNECT_RETRY_BACKOFF,
+        )?,
+        idle_timeout: parse_duration(IDLE_TIMEOUT)?,
+        half_close_timeout: parse_duration(HALF_CLOSE_TIMEOUT)?,
+        connection_rate_limit: parse(CONNECTION_RATE_LIMIT)?,
+        service_connection_rate_limit: parse_service_connection_rate_limit(
+            parse::<String>(SERVICE_CONNECTION_RATE_LIMIT)?.as_deref(),
+        )?,
+        copy_buffer_max_size: parse(COPY_BUFFER_MAX_SIZE)?,
+        max_connections_per_identity: parse(MAX_CONNECTIONS_PER_IDENTITY)?,
+        max_inbound_connections: parse(MAX_INBOUND_CONNECTIONS)?,
+        circuit_breaker_threshold: parse(CIRCUIT_BREAKER_THRESHOLD)?,
+        circuit_breaker_cooldown: parse_duration_default(
+            CIRCUIT_BREAKER_COOLDOWN,
+            DEFAULT_CIRCUIT_BREAKER_COOLDOWN,
+        )?,
+
         window_size: 4 * 1024 * 1024,
         connection_window_size: 4 * 1024 * 1024,
         frame_size: 1024 * 1024,
+        max_concurrent_streams: parse_default(
+            HBONE_MAX_CONCURRENT_STREAMS,
+            DEFAULT_HBONE_MAX_CONCURRENT_STREAMS,
+        )?,
+        max_header_list_size: parse_default(
+            HBONE_MAX_HEADER_LIST_SIZE,
+            DEFAULT_HBONE_MAX_HEADER_LIST_SIZE,
+        )?,
+        http2_keepalive_interval: Some(parse_duration_default(
+            HTTP2_KEEPALIVE_INTERVAL,
+            DEFAULT_HTTP2_KEEPALIVE_INTERVAL,
+        )?)
+        .filter(|d| !d.is_zero()),
+        http2_keepalive_timeout: parse_duration_default(
+            HTTP2_KEEPALIVE_TIMEOUT,
+            DEFAULT_HTTP2_KEEPALIVE_TIMEOUT,
+        )?,
 
         self_termination_deadline: match parse_duration(CONNECTION_TERMINATION_DEADLINE)? {
             Some(period) => period,
@@ -661,7 +1352,24 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         socks5_addr,
         inbound_addr,
         inbound_plaintext_addr,
+        inbound_plaintext_hbone_addr,
+        inbound_proxy_protocol_trusted_ranges: parse_ip_ranges(
+            INBOUND_PROXY_PROTOCOL_TRUSTED_RANGES,
+        )?,
+        outbound_proxy_protocol: parse_default(OUTBOUND_PROXY_PROTOCOL, false)?,
+        http1_connect: parse_default(ENABLE_HTTP1_CONNECT, false)?,
+        require_hbone_alpn: parse_default(REQUIRE_HBONE_ALPN, false)?,
+        tls_session_cache_size: parse_default(
+            TLS_SESSION_CACHE_SIZE,
+            DEFAULT_TLS_SESSION_CACHE_SIZE,
+        )?,
+        probe_source_ranges,
+        inbound_iface: parse(INBOUND_INTERFACE)?,
         outbound_addr,
+        outbound_iface: parse(OUTBOUND_INTERFACE)?,
+        outbound_uds: parse(OUTBOUND_UDS)?,
+        outbound_udp_addr: parse(OUTBOUND_UDP_ADDR)?,
+        udp_idle_timeout: parse_duration_default(UDP_IDLE_TIMEOUT, DEFAULT_UDP_IDLE_TIMEOUT)?,
         dns_proxy_addr,
 
         illegal_ports,
@@ -681,8 +1389,18 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         alt_ca_hostname: parse(ALT_CA_HOSTNAME)?,
 
         secret_ttl: parse_duration_default(SECRET_TTL, DEFAULT_TTL)?,
+        cert_cache_max_identities: parse(CERT_CACHE_MAX_IDENTITIES)?,
+        cert_rotation_threshold: parse_duration(CERT_ROTATION_THRESHOLD)?,
         local_xds_config,
         xds_on_demand: parse_default(XDS_ON_DEMAND, false)?,
+        xds_reconnect_backoff_base: parse_duration_default(
+            XDS_RECONNECT_BACKOFF_BASE,
+            DEFAULT_XDS_RECONNECT_BACKOFF_BASE,
+        )?,
+        xds_reconnect_backoff_max: parse_duration_default(
+            XDS_RECONNECT_BACKOFF_MAX,
+            DEFAULT_XDS_RECONNECT_BACKOFF_MAX,
+        )?,
         proxy_metadata: pc.proxy_metadata,
 
         fake_ca,
@@ -694,6 +1412,66 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         )?,
 
         require_original_source: parse(ENABLE_ORIG_SRC)?,
+        preserve_source_port: parse_default(ORIG_SRC_PRESERVE_PORT, false)?,
+        allow_outbound_passthrough: parse_default(ALLOW_OUTBOUND_PASSTHROUGH, true)?,
+        endpoint_slow_start_window: parse_duration(ENDPOINT_SLOW_START_WINDOW)?,
+        endpoint_health_check_interval: parse_duration(ENDPOINT_HEALTH_CHECK_INTERVAL)?,
+        endpoint_health_check_timeout: parse_duration_default(
+            ENDPOINT_HEALTH_CHECK_TIMEOUT,
+            DEFAULT_ENDPOINT_HEALTH_CHECK_TIMEOUT,
+        )?,
+        endpoint_health_check_unhealthy_threshold: parse_default(
+            ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD,
+            DEFAULT_ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD,
+        )?,
+        endpoint_health_check_healthy_threshold: parse_default(
+            ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD,
+            DEFAULT_ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD,
+        )?,
+        baggage_labels: parse_baggage_labels(parse::<String>(BAGGAGE_LABELS)?.as_deref())?,
+        connection_tag_header: parse::<String>(CONNECTION_TAG_HEADER)?
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty()),
+        coarse_locality_metrics: parse_default(COARSE_LOCALITY_METRICS, false)?,
+        access_log_sampling: parse(ACCESS_LOG_SAMPLING)?,
+        dscp: parse_dscp(DSCP, parse::<String>(DSCP)?.as_deref())?,
+        service_dscp: parse_service_dscp(parse::<String>(SERVICE_DSCP)?.as_deref())?,
+        protocol_overrides: parse_protocol_overrides(
+            parse::<String>(PROTOCOL_OVERRIDES)?.as_deref(),
+        )?,
+        service_window_sizes: parse_window_sizes(
+            SERVICE_WINDOW_SIZES,
+            parse::<String>(SERVICE_WINDOW_SIZES)?.as_deref(),
+        )?,
+        workload_window_sizes: parse_window_sizes(
+            WORKLOAD_WINDOW_SIZES,
+            parse::<String>(WORKLOAD_WINDOW_SIZES)?.as_deref(),
+        )?,
+        locality_window_sizes: parse_window_sizes(
+            LOCALITY_WINDOW_SIZES,
+            parse::<String>(LOCALITY_WINDOW_SIZES)?.as_deref(),
+        )?,
+        plaintext_fallback: parse_plaintext_fallback(
+            parse::<String>(PLAINTEXT_FALLBACK)?.as_deref(),
+        )?,
+        tracing_endpoint: parse(TRACING_ENDPOINT)?,
+        trusted_authority_identities: parse_identities(
+            TRUSTED_AUTHORITY_IDENTITIES,
+            parse::<String>(TRUSTED_AUTHORITY_IDENTITIES)?.as_deref(),
+        )?,
+        sandwich_port_mappings: parse_port_mappings(
+            SANDWICH_PORT_MAPPINGS,
+            parse::<String>(SANDWICH_PORT_MAPPINGS)?.as_deref(),
+        )?,
+        trust_domain_aliases: parse::<String>(TRUST_DOMAIN_ALIASES)?
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(Strng::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
         proxy_args: parse_args(),
         dns_resolver_cfg,
         dns_resolver_opts,
@@ -739,6 +1517,9 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
                 USER_TIMEOUT_ENABLED,
                 socket_config_defaults.user_timeout_enabled,
             )?,
+            user_timeout: parse_duration(TCP_USER_TIMEOUT)?,
+            listen_backlog: parse_default(LISTEN_BACKLOG, socket_config_defaults.listen_backlog)?,
+            mptcp_enabled: parse_default(MPTCP_ENABLED, socket_config_defaults.mptcp_enabled)?,
         },
         packet_mark: parse(PACKET_MARK)?.or_else(|| {
             if proxy_mode == ProxyMode::Shared {
@@ -763,6 +1544,82 @@ fn validate_config(cfg: Config) -> Result<Config, Error> {
         )));
     }
 
+    if cfg.xds_reconnect_backoff_base.is_zero()
+        || cfg.xds_reconnect_backoff_base > cfg.xds_reconnect_backoff_max
+    {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{XDS_RECONNECT_BACKOFF_BASE} must be non-zero and at most {XDS_RECONNECT_BACKOFF_MAX}"
+        )));
+    }
+
+    if let Some(header) = &cfg.connection_tag_header {
+        hyper::http::HeaderName::from_bytes(header.as_bytes()).map_err(|e| {
+            Error::EnvVar(
+                CONNECTION_TAG_HEADER.to_string(),
+                header.clone(),
+                e.to_string(),
+            )
+        })?;
+    }
+
+    if let Some(workload) = cfg
+        .workload_window_sizes
+        .keys()
+        .find(|k| k.split_once('/').is_none())
+    {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{WORKLOAD_WINDOW_SIZES} must be keyed by namespace/name, got {workload}"
+        )));
+    }
+
+    if let Some(locality) = cfg
+        .locality_window_sizes
+        .keys()
+        .find(|k| !LOCALITY_RELATIONS.contains(&k.as_str()))
+    {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{LOCALITY_WINDOW_SIZES} must be keyed by one of {}, got {locality}",
+            LOCALITY_RELATIONS.join(", ")
+        )));
+    }
+
+    if cfg.max_header_list_size == 0 {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{HBONE_MAX_HEADER_LIST_SIZE} must be non-zero"
+        )));
+    }
+
+    if cfg.cert_cache_max_identities == Some(0) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{CERT_CACHE_MAX_IDENTITIES} must be non-zero"
+        )));
+    }
+
+    if cfg.socket_config.user_timeout == Some(Duration::ZERO) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{TCP_USER_TIMEOUT} must be non-zero"
+        )));
+    }
+
+    if cfg.endpoint_health_check_interval == Some(Duration::ZERO) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{ENDPOINT_HEALTH_CHECK_INTERVAL} must be non-zero"
+        )));
+    }
+    if cfg.endpoint_health_check_unhealthy_threshold == 0
+        || cfg.endpoint_health_check_healthy_threshold == 0
+    {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{ENDPOINT_HEALTH_CHECK_UNHEALTHY_THRESHOLD} and {ENDPOINT_HEALTH_CHECK_HEALTHY_THRESHOLD} must be non-zero"
+        )));
+    }
+
+    if cfg.copy_buffer_max_size == Some(0) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "{COPY_BUFFER_MAX_SIZE} must be non-zero"
+        )));
+    }
+
     if !cfg.proxy && !cfg.dns_proxy {
         return Err(Error::ProxyConfig(anyhow!(
             "ztunnel run without any servers enabled"
@@ -1048,6 +1905,256 @@ pub mod tests {
         validate_metadata_vector(&cfg.ca_headers, expected_ca_headers.clone());
     }
 
+    #[test]
+    fn service_dscp() {
+        assert!(parse_service_dscp(None).unwrap().is_empty());
+        assert_eq!(
+            parse_service_dscp(Some(
+                "a.ns.svc.cluster.local=46, b.ns.svc.cluster.local = 10,"
+            ))
+            .unwrap(),
+            HashMap::from([
+                (Strng::from("a.ns.svc.cluster.local"), 46),
+                (Strng::from("b.ns.svc.cluster.local"), 10),
+            ])
+        );
+        assert!(parse_service_dscp(Some("a.ns.svc.cluster.local")).is_err());
+        assert!(parse_service_dscp(Some("a.ns.svc.cluster.local=64")).is_err());
+        assert_eq!(parse_dscp(DSCP, Some("0")).unwrap(), Some(0));
+        assert!(parse_dscp(DSCP, Some("-1")).is_err());
+    }
+
+    #[test]
+    fn service_connection_rate_limit() {
+        assert!(
+            parse_service_connection_rate_limit(None)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            parse_service_connection_rate_limit(Some(
+                "a.ns.svc.cluster.local=1000, b.ns.svc.cluster.local = 2000,"
+            ))
+            .unwrap(),
+            HashMap::from([
+                (Strng::from("a.ns.svc.cluster.local"), 1000),
+                (Strng::from("b.ns.svc.cluster.local"), 2000),
+            ])
+        );
+        assert!(parse_service_connection_rate_limit(Some("a.ns.svc.cluster.local")).is_err());
+        assert!(parse_service_connection_rate_limit(Some("a.ns.svc.cluster.local=-1")).is_err());
+    }
+
+    #[test]
+    fn plaintext_hbone_addr() {
+        let env = UNSTABLE_PLAINTEXT_HBONE_ADDR;
+        assert_eq!(parse_plaintext_hbone_addr(env, None).unwrap(), None);
+        assert_eq!(
+            parse_plaintext_hbone_addr(env, Some("10.0.0.1:15009")).unwrap(),
+            Some("10.0.0.1:15009".parse().unwrap())
+        );
+        assert!(parse_plaintext_hbone_addr(env, Some("0.0.0.0:15009")).is_err());
+        assert!(parse_plaintext_hbone_addr(env, Some("[::]:15009")).is_err());
+        assert!(parse_plaintext_hbone_addr(env, Some("10.0.0.1")).is_err());
+    }
+
+    #[test]
+    fn protocol_overrides() {
+        use crate::state::workload::Protocol;
+        assert!(parse_protocol_overrides(None).unwrap().is_empty());
+        assert_eq!(
+            parse_protocol_overrides(Some("ns/legacy=TCP, ns/other = hbone,")).unwrap(),
+            HashMap::from([
+                (Strng::from("ns/legacy"), Protocol::TCP),
+                (Strng::from("ns/other"), Protocol::HBONE),
+            ])
+        );
+        assert!(parse_protocol_overrides(Some("ns/legacy")).is_err());
+        assert!(parse_protocol_overrides(Some("legacy=TCP")).is_err());
+        assert!(parse_protocol_overrides(Some("ns/legacy=UDP")).is_err());
+    }
+
+    #[test]
+    fn baggage_labels() {
+        assert!(parse_baggage_labels(None).unwrap().is_empty());
+        assert_eq!(
+            parse_baggage_labels(Some("tier=frontend|backend, team = payments,")).unwrap(),
+            BTreeMap::from([
+                (Strng::from("team"), vec![Strng::from("payments")]),
+                (
+                    Strng::from("tier"),
+                    vec![Strng::from("frontend"), Strng::from("backend")]
+                ),
+            ])
+        );
+        // Every key needs an allowlist, so the values reported are bounded.
+        assert!(parse_baggage_labels(Some("tier")).is_err());
+        assert!(parse_baggage_labels(Some("tier=")).is_err());
+    }
+
+    #[test]
+    fn window_sizes() {
+        assert!(
+            parse_window_sizes(SERVICE_WINDOW_SIZES, None)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            parse_window_sizes(
+                SERVICE_WINDOW_SIZES,
+                Some("a.ns.svc.cluster.local=8388608:16777216, ns/b = 65535:65535,")
+            )
+            .unwrap(),
+            HashMap::from([
+                (
+                    Strng::from("a.ns.svc.cluster.local"),
+                    WindowSizes {
+                        window_size: 8388608,
+                        connection_window_size: 16777216,
+                    }
+                ),
+                (
+                    Strng::from("ns/b"),
+                    WindowSizes {
+                        window_size: 65535,
+                        connection_window_size: 65535,
+                    }
+                ),
+            ])
+        );
+        for invalid in ["a=1", "a=0:1", "a=1:2147483648", "a=x:1", "a"] {
+            assert!(
+                parse_window_sizes(SERVICE_WINDOW_SIZES, Some(invalid)).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn plaintext_fallback() {
+        assert_eq!(
+            parse_plaintext_fallback(None).unwrap(),
+            PlaintextFallback::Disabled
+        );
+        assert_eq!(
+            parse_plaintext_fallback(Some(" ")).unwrap(),
+            PlaintextFallback::Disabled
+        );
+        assert_eq!(
+            parse_plaintext_fallback(Some("*")).unwrap(),
+            PlaintextFallback::All
+        );
+        assert_eq!(
+            parse_plaintext_fallback(Some("ns/legacy, ns/other,")).unwrap(),
+            PlaintextFallback::Workloads(HashSet::from([
+                Strng::from("ns/legacy"),
+                Strng::from("ns/other"),
+            ]))
+        );
+        assert!(parse_plaintext_fallback(Some("legacy")).is_err());
+    }
+
+    #[test]
+    fn sandwich_port_mappings() {
+        assert!(
+            parse_port_mappings(SANDWICH_PORT_MAPPINGS, None)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            parse_port_mappings(SANDWICH_PORT_MAPPINGS, Some("80=8080, 443 = 8443,")).unwrap(),
+            HashMap::from([(80, 8080), (443, 8443)])
+        );
+        assert!(parse_port_mappings(SANDWICH_PORT_MAPPINGS, Some("80")).is_err());
+        assert!(parse_port_mappings(SANDWICH_PORT_MAPPINGS, Some("80=http")).is_err());
+        assert!(parse_port_mappings(SANDWICH_PORT_MAPPINGS, Some("80=70000")).is_err());
+    }
+
+    #[test]
+    fn trusted_authority_identities() {
+        assert!(
+            parse_identities(TRUSTED_AUTHORITY_IDENTITIES, None)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            parse_identities(
+                TRUSTED_AUTHORITY_IDENTITIES,
+                Some("spiffe://cluster.local/ns/a/sa/b, spiffe://cluster.local/ns/c/sa/d,")
+            )
+            .unwrap(),
+            vec![
+                identity::Identity::from_str("spiffe://cluster.local/ns/a/sa/b").unwrap(),
+                identity::Identity::from_str("spiffe://cluster.local/ns/c/sa/d").unwrap(),
+            ]
+        );
+        assert!(parse_identities(TRUSTED_AUTHORITY_IDENTITIES, Some("not-an-identity")).is_err());
+    }
+
+    #[test]
+    fn dns_resolver_config() {
+        use hickory_resolver::Name;
+
+        let resolv_conf = b"nameserver 10.96.0.10
+search default.svc.cluster.local svc.cluster.local cluster.local
+options ndots:5 timeout:3
+";
+        let (cfg, opts) =
+            parse_dns_resolver_config(Some(resolv_conf), DnsResolverOverrides::default()).unwrap();
+        assert_eq!(
+            cfg.search(),
+            &[
+                Name::from_str_relaxed("default.svc.cluster.local").unwrap(),
+                Name::from_str_relaxed("svc.cluster.local").unwrap(),
+                Name::from_str_relaxed("cluster.local").unwrap(),
+            ]
+        );
+        assert_eq!(
+            cfg.name_servers()[0].socket_addr.ip().to_string(),
+            "10.96.0.10"
+        );
+        assert_eq!(opts.ndots, 5);
+        assert_eq!(opts.timeout, Duration::from_secs(3));
+
+        // Explicit settings take precedence, while the name servers are kept
+        let (cfg, opts) = parse_dns_resolver_config(
+            Some(resolv_conf),
+            DnsResolverOverrides {
+                search: Some(vec![
+                    "ns.svc.example.com".to_string(),
+                    "example.com".to_string(),
+                ]),
+                ndots: Some(2),
+                timeout: Some(Duration::from_millis(500)),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.search(),
+            &[
+                Name::from_str_relaxed("ns.svc.example.com").unwrap(),
+                Name::from_str_relaxed("example.com").unwrap(),
+            ]
+        );
+        assert_eq!(
+            cfg.name_servers()[0].socket_addr.ip().to_string(),
+            "10.96.0.10"
+        );
+        assert_eq!(opts.ndots, 2);
+        assert_eq!(opts.timeout, Duration::from_millis(500));
+
+        // An empty override clears the search domains
+        let (cfg, _) = parse_dns_resolver_config(
+            Some(resolv_conf),
+            DnsResolverOverrides {
+                search: Some(vec![]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(cfg.search().is_empty());
+    }
+
     fn validate_metadata_vector(metadata: &MetadataVector, header_map: HashMap<String, String>) {
         for (k, v) in header_map {
             let key: AsciiMetadataKey = AsciiMetadataKey::from_str(&k).unwrap();
@@ -15,17 +15,20 @@
 use crate::proxy;
 use crate::proxy::ConnectionResult;
 use crate::proxy::Error::{BackendDisconnected, ClientDisconnected, ReceiveError, SendError};
-use bytes::{Buf, Bytes, BytesMut};
+use crate::proxy::metrics::CopyDirection;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use pin_project_lite::pin_project;
 use std::future::Future;
 use std::io::Error;
 use std::marker::PhantomPinned;
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
+use std::time::Duration;
 use tokio::io;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::{Instant, Sleep};
 use tracing::trace;
 
 // BufferedSplitter is a trait to expose splitting an IO object into a buffered reader and a writer
@@ -139,17 +142,28 @@ const RESIZE_THRESHOLD_LARGE: u64 = 128 * 1024;
 // After 10Mb of data we will trigger a resize from LARGE to JUMBO
 const RESIZE_THRESHOLD_JUMBO: u64 = 10 * 1024 * 1024;
 
+// copy_bidirectional copies data in both directions until both sides are complete.
+// If `rate_limit` is set, each direction is limited to that many bytes per second.
+// If `max_buffer_size` is set, the copy buffers do not grow beyond that many bytes.
+// If `half_close_timeout` is set, once one direction completes the connection is closed if the other
+// transfers nothing for that long.
 pub async fn copy_bidirectional<A, B>(
     downstream: A,
     upstream: B,
     stats: &ConnectionResult,
+    rate_limit: Option<u64>,
+    half_close_timeout: Option<Duration>,
+    max_buffer_size: Option<usize>,
 ) -> Result<(), crate::proxy::Error>
 where
     A: BufferedSplitter,
     B: BufferedSplitter,
 {
-    let (mut rd, mut wd) = downstream.split_into_buffered_reader();
-    let (mut ru, mut wu) = upstream.split_into_buffered_reader();
+    let max_buffer_size = max_buffer_size.unwrap_or(JUMBO_BUFFER_SIZE);
+    let (rd, mut wd) = downstream.split_into_buffered_reader();
+    let (ru, mut wu) = upstream.split_into_buffered_reader();
+    let mut rd = RateLimitedReader::new(rd, rate_limit);
+    let mut ru = RateLimitedReader::new(ru, rate_limit);
     let downstream_to_upstream = async {
         let translate_error = |e: io::Error| {
             SendError(Box::new(match e.kind() {
@@ -159,7 +173,7 @@ where
                 _ => e.into(),
             }))
         };
-        let res = ignore_io_errors(copy_buf(&mut rd, &mut wu, stats, false).await)
+        let res = ignore_io_errors(copy_buf(&mut rd, &mut wu, stats, false, max_buffer_size).await)
             .map_err(translate_error);
         trace!(?res, "send");
         ignore_shutdown_errors(shutdown(&mut wu).await)
@@ -176,7 +190,7 @@ where
                 _ => e.into(),
             }))
         };
-        let res = ignore_io_errors(copy_buf(&mut ru, &mut wd, stats, true).await)
+        let res = ignore_io_errors(copy_buf(&mut ru, &mut wd, stats, true, max_buffer_size).await)
             .map_err(translate_error);
         trace!(?res, "receive");
         ignore_shutdown_errors(shutdown(&mut wd).await)
@@ -186,7 +200,22 @@ where
     };
 
     // join!() them rather than try_join!() so that we keep complete either end once one side is complete.
-    let (sent, received) = tokio::join!(downstream_to_upstream, upstream_to_downstream);
+    // This allows half-closed connections, where one side is done sending but still receiving.
+    let (sent, received) = match half_close_timeout {
+        None => tokio::join!(downstream_to_upstream, upstream_to_downstream),
+        Some(timeout) => {
+            // Once one direction completes, the other may continue as long as it stays active.
+            let mut downstream_to_upstream = std::pin::pin!(downstream_to_upstream);
+            let mut upstream_to_downstream = std::pin::pin!(upstream_to_downstream);
+            tokio::select! {
+                sent = &mut downstream_to_upstream => linger(stats, timeout, sent, upstream_to_downstream).await,
+                received = &mut upstream_to_downstream => {
+                    let (received, sent) = linger(stats, timeout, received, downstream_to_upstream).await;
+                    (sent, received)
+                }
+            }
+        }
+    };
 
     // Convert some error messages to easier to understand
     let sent = sent?;
@@ -195,6 +224,78 @@ where
     Ok(())
 }
 
+// linger waits for the remaining direction of a half-closed connection, for as long as it keeps
+// transferring data. The timeout restarts on activity, sampled as in wait_for_idle.
+async fn linger(
+    stats: &ConnectionResult,
+    timeout: Duration,
+    first: Result<u64, proxy::Error>,
+    second: impl Future<Output = Result<u64, proxy::Error>>,
+) -> (Result<u64, proxy::Error>, Result<u64, proxy::Error>) {
+    tokio::select! {
+        second = second => (first, second),
+        _ = wait_for_idle(stats, timeout) => {
+            // If the first direction failed, that is the more useful error to report.
+            if first.is_err() {
+                return (first, Ok(0));
+            }
+            trace!(?timeout, "half-closed connection went idle, closing");
+            (first, Err(proxy::Error::HalfCloseTimeout(timeout)))
+        }
+    }
+}
+
+// copy_bidirectional_with_limits is copy_bidirectional, but additionally closes the connection once
+// no bytes have been transferred in either direction for `idle_timeout`, if set.
+pub async fn copy_bidirectional_with_limits<A, B>(
+    downstream: A,
+    upstream: B,
+    stats: &ConnectionResult,
+    idle_timeout: Option<Duration>,
+    half_close_timeout: Option<Duration>,
+    rate_limit: Option<u64>,
+    max_buffer_size: Option<usize>,
+) -> Result<(), crate::proxy::Error>
+where
+    A: BufferedSplitter,
+    B: BufferedSplitter,
+{
+    let copy = copy_bidirectional(
+        downstream,
+        upstream,
+        stats,
+        rate_limit,
+        half_close_timeout,
+        max_buffer_size,
+    );
+    let Some(idle_timeout) = idle_timeout else {
+        return copy.await;
+    };
+    // Boxed to avoid growing the per-connection future when idle timeouts are disabled.
+    let idle = Box::pin(wait_for_idle(stats, idle_timeout));
+    tokio::select! {
+        res = copy => res,
+        _ = idle => {
+            trace!(?idle_timeout, "connection idle, closing");
+            Err(proxy::Error::IdleTimeout(idle_timeout))
+        }
+    }
+}
+
+// wait_for_idle completes once a full `idle_timeout` period passes without any bytes being transferred.
+// Activity is sampled once per period, so a connection may stay open for up to twice the timeout.
+pub(crate) async fn wait_for_idle(stats: &ConnectionResult, idle_timeout: Duration) {
+    let mut last = stats.bytes_transferred();
+    loop {
+        tokio::time::sleep(idle_timeout).await;
+        let current = stats.bytes_transferred();
+        if current == last {
+            return;
+        }
+        last = current;
+    }
+}
+
 // During copying, we may encounter errors from either side closing their connection. Typically, we
 // get a fully graceful shutdown with no errors on either end, but can if one end sends a RST directly,
 // or if we have other non-graceful behavior, we may see errors. This is generally ok - a TCP connection
@@ -240,6 +341,9 @@ struct CopyBuf<'a, R: ?Sized, W: ?Sized> {
     buf: Option<Bytes>,
     metrics: &'a ConnectionResult,
     amt: u64,
+    max_buffer_size: usize,
+    // Whether the last write could not make progress, so each stall is only recorded once.
+    stalled: bool,
 }
 
 async fn copy_buf<'a, R, W>(
@@ -247,11 +351,15 @@ async fn copy_buf<'a, R, W>(
     writer: &'a mut W,
     metrics: &ConnectionResult,
     is_send: bool,
+    max_buffer_size: usize,
 ) -> std::io::Result<u64>
 where
     R: ResizeBufRead + Unpin + ?Sized,
     W: AsyncWriteBuf + Unpin + ?Sized,
 {
+    if max_buffer_size < INITIAL_BUFFER_SIZE {
+        Pin::new(&mut *reader).resize(max_buffer_size);
+    }
     CopyBuf {
         send: is_send,
         reader,
@@ -259,6 +367,8 @@ where
         buf: None,
         metrics,
         amt: 0,
+        max_buffer_size,
+        stalled: false,
     }
     .await
 }
@@ -288,8 +398,19 @@ where
             // This is just a reference counter. Hold onto it in case the write() is not complete.
             let mut our_copy = buffer.clone();
             let i = match Pin::new(&mut *me.writer).poll_write_buf(cx, buffer) {
-                Poll::Ready(written) => written?,
+                Poll::Ready(written) => {
+                    me.stalled = false;
+                    written?
+                }
                 Poll::Pending => {
+                    if !me.stalled {
+                        me.stalled = true;
+                        me.metrics.increment_write_stall(if me.send {
+                            CopyDirection::send
+                        } else {
+                            CopyDirection::receive
+                        });
+                    }
                     me.buf = Some(our_copy);
                     return Poll::Pending;
                 }
@@ -312,10 +433,12 @@ where
 
             // If we were below the resize threshold before but are now above it, trigger the buffer to resize
             if old < RESIZE_THRESHOLD_LARGE && RESIZE_THRESHOLD_LARGE <= self.amt {
-                Pin::new(&mut *self.reader).resize(LARGE_BUFFER_SIZE);
+                let size = LARGE_BUFFER_SIZE.min(self.max_buffer_size);
+                Pin::new(&mut *self.reader).resize(size);
             }
             if old < RESIZE_THRESHOLD_JUMBO && RESIZE_THRESHOLD_JUMBO <= self.amt {
-                Pin::new(&mut *self.reader).resize(JUMBO_BUFFER_SIZE);
+                let size = JUMBO_BUFFER_SIZE.min(self.max_buffer_size);
+                Pin::new(&mut *self.reader).resize(size);
             }
         }
     }
@@ -346,9 +469,11 @@ impl<R: AsyncRead> ResizeBufRead for BufReader<R> {
     fn poll_bytes(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
         let me = self.project();
 
-        // Give us enough space to read a full chunk
+        // Give us enough space to read a full chunk, but read no more than that, as the buffer may
+        // have grown beyond it.
         me.buf.reserve(*me.buffer_size);
-        ready!(tokio_util::io::poll_read_buf(me.inner, cx, me.buf))?;
+        let mut buf = (&mut *me.buf).limit(*me.buffer_size);
+        ready!(tokio_util::io::poll_read_buf(me.inner, cx, &mut buf))?;
         Poll::Ready(Ok(me.buf.split().freeze()))
     }
 
@@ -358,6 +483,78 @@ impl<R: AsyncRead> ResizeBufRead for BufReader<R> {
     }
 }
 
+// Traffic allowed to accumulate while a rate limited connection is idle, expressed as time at the
+// configured rate.
+const RATE_LIMIT_BURST: Duration = Duration::from_secs(1);
+
+// RateLimitedReader wraps a ResizeBufRead, delaying reads so that on average no more than the
+// configured bytes per second pass through it.
+// Each direction of a connection is limited independently, so an idle direction never blocks the other.
+pub struct RateLimitedReader<R> {
+    inner: R,
+    // Boxed so unlimited connections, the common case, only pay for a pointer.
+    limiter: Option<Box<RateLimiter>>,
+}
+
+struct RateLimiter {
+    bytes_per_second: u64,
+    // The time at which all bytes read so far are paid for.
+    next: Instant,
+    sleep: Pin<Box<Sleep>>,
+    waiting: bool,
+}
+
+impl<R> RateLimitedReader<R> {
+    pub fn new(inner: R, bytes_per_second: Option<u64>) -> Self {
+        let limiter = bytes_per_second.filter(|b| *b > 0).map(|bytes_per_second| {
+            let now = Instant::now();
+            Box::new(RateLimiter {
+                bytes_per_second,
+                next: now,
+                sleep: Box::pin(tokio::time::sleep_until(now)),
+                waiting: false,
+            })
+        });
+        Self { inner, limiter }
+    }
+}
+
+impl RateLimiter {
+    fn consume(&mut self, bytes: usize) {
+        let now = Instant::now();
+        // Don't let an idle connection build up more than a burst's worth of credit.
+        let start = self
+            .next
+            .max(now.checked_sub(RATE_LIMIT_BURST).unwrap_or(now));
+        self.next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        if self.next > now {
+            self.sleep.as_mut().reset(self.next);
+            self.waiting = true;
+        }
+    }
+}
+
+impl<R: ResizeBufRead + Unpin> ResizeBufRead for RateLimitedReader<R> {
+    fn poll_bytes(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        let me = self.get_mut();
+        if let Some(limiter) = me.limiter.as_mut() {
+            if limiter.waiting {
+                ready!(limiter.sleep.as_mut().poll(cx));
+                limiter.waiting = false;
+            }
+        }
+        let bytes = ready!(Pin::new(&mut me.inner).poll_bytes(cx))?;
+        if let Some(limiter) = me.limiter.as_mut() {
+            limiter.consume(bytes.len());
+        }
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn resize(self: Pin<&mut Self>, new_size: usize) {
+        Pin::new(&mut self.get_mut().inner).resize(new_size)
+    }
+}
+
 pin_project! {
     /// A future used to shutdown an I/O object.
     ///
@@ -433,7 +630,7 @@ mod tests {
                 },
                 metrics.clone(),
             );
-            copy_bidirectional(ztunnel_downsteam, ztunnel_upsteam, &cr).await
+            copy_bidirectional(ztunnel_downsteam, ztunnel_upsteam, &cr, None, None, None).await
         });
         const ITERS: usize = 1000;
         const REPEATS: usize = 6400;
@@ -478,7 +675,15 @@ mod tests {
                 },
                 metrics.clone(),
             );
-            copy_bidirectional(WeirdIO(ztunnel_downsteam), WeirdIO(ztunnel_upsteam), &cr).await
+            copy_bidirectional(
+                WeirdIO(ztunnel_downsteam),
+                WeirdIO(ztunnel_upsteam),
+                &cr,
+                None,
+                None,
+                None,
+            )
+            .await
         });
         const WRITES: usize = 2560;
         // Do a bunch of writes of various size, and expect the other end to receive them
@@ -499,6 +704,260 @@ mod tests {
         tokio::try_join!(reader, writer).unwrap();
     }
 
+    #[tokio::test]
+    async fn write_stalls() {
+        // A peer that reads slowly: every write is first rejected, then accepted on the next poll.
+        #[derive(Default)]
+        struct SlowWriter {
+            ready: bool,
+            writes: u64,
+            largest_write: usize,
+            written: Vec<u8>,
+        }
+        impl AsyncWriteBuf for SlowWriter {
+            fn poll_write_buf(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: Bytes,
+            ) -> Poll<std::io::Result<usize>> {
+                self.ready = !self.ready;
+                if !self.ready {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                self.writes += 1;
+                self.largest_write = self.largest_write.max(buf.len());
+                self.written.extend_from_slice(&buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let (metrics, cr) = connection_result();
+        // Enough to pass the resize threshold, which must still respect the maximum buffer size.
+        let data: Vec<u8> = (0..2 * RESIZE_THRESHOLD_LARGE).map(|i| i as u8).collect();
+        let max_buffer_size = 4096;
+        let mut reader = BufReader::new(&data[..]);
+        let mut writer = SlowWriter::default();
+        let copied = copy_buf(&mut reader, &mut writer, &cr, false, max_buffer_size)
+            .await
+            .unwrap();
+
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(writer.written, data);
+        assert!(writer.largest_write <= max_buffer_size);
+        let stalls = |direction| {
+            metrics
+                .write_stalls
+                .get_or_create(&crate::proxy::metrics::WriteStallLabels { direction })
+                .get()
+        };
+        // Every write stalled exactly once before it was accepted.
+        assert_eq!(stalls(CopyDirection::receive), writer.writes);
+        assert_eq!(stalls(CopyDirection::send), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit() {
+        const RATE: u64 = 16 * 1024;
+        let data = vec![0u8; 4 * RATE as usize];
+        let mut reader = RateLimitedReader::new(BufReader::new(&data[..]), Some(RATE));
+
+        let start = tokio::time::Instant::now();
+        let mut total = 0;
+        loop {
+            let bytes = std::future::poll_fn(|cx| Pin::new(&mut reader).poll_bytes(cx))
+                .await
+                .unwrap();
+            if bytes.is_empty() {
+                break;
+            }
+            total += bytes.len();
+        }
+        assert_eq!(total, data.len());
+        // 4 seconds worth of data at the configured rate
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(3900) && elapsed <= Duration::from_millis(4100),
+            "took {elapsed:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        initialize_telemetry();
+        let (mut client, ztunnel_downsteam) = tokio::io::duplex(32000);
+        let (mut server, ztunnel_upsteam) = tokio::io::duplex(32000);
+
+        let copy = tokio::task::spawn(async move {
+            let (_, cr) = connection_result();
+            copy_bidirectional_with_limits(
+                ztunnel_downsteam,
+                ztunnel_upsteam,
+                &cr,
+                Some(Duration::from_secs(10)),
+                None,
+                None,
+                None,
+            )
+            .await
+        });
+
+        // Keep the connection active; it should not be closed
+        for _ in 0..5 {
+            client.write_all(b"hello").await.unwrap();
+            let mut res = [0; 5];
+            server.read_exact(&mut res).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        assert!(!copy.is_finished());
+
+        // Now go idle; the connection should be closed
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let res = copy.await.unwrap();
+        assert!(matches!(res, Err(proxy::Error::IdleTimeout(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_close() {
+        initialize_telemetry();
+        let (mut client, ztunnel_downsteam) = tokio::io::duplex(32000);
+        let (mut server, ztunnel_upsteam) = tokio::io::duplex(32000);
+
+        let copy = tokio::task::spawn(async move {
+            let (_, cr) = connection_result();
+            copy_bidirectional_with_limits(
+                ztunnel_downsteam,
+                ztunnel_upsteam,
+                &cr,
+                None,
+                Some(Duration::from_secs(10)),
+                None,
+                None,
+            )
+            .await
+        });
+
+        // The client finishes sending; the server should see EOF
+        client.write_all(b"request").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut req = Vec::new();
+        server.read_to_end(&mut req).await.unwrap();
+        assert_eq!(req, b"request");
+
+        // The client should still receive the response after half-closing
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        server.write_all(b"response").await.unwrap();
+        let mut res = [0; 8];
+        client.read_exact(&mut res).await.unwrap();
+        assert_eq!(&res, b"response");
+        assert!(!copy.is_finished());
+
+        // The server never closes its side; the connection is closed once the linger elapses
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let res = copy.await.unwrap();
+        assert!(matches!(res, Err(proxy::Error::HalfCloseTimeout(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_close_active() {
+        initialize_telemetry();
+        let (mut client, ztunnel_downsteam) = tokio::io::duplex(32000);
+        let (mut server, ztunnel_upsteam) = tokio::io::duplex(32000);
+
+        let copy = tokio::task::spawn(async move {
+            let (_, cr) = connection_result();
+            copy_bidirectional(
+                ztunnel_downsteam,
+                ztunnel_upsteam,
+                &cr,
+                None,
+                Some(Duration::from_secs(10)),
+                None,
+            )
+            .await
+        });
+
+        client.shutdown().await.unwrap();
+        let mut req = Vec::new();
+        server.read_to_end(&mut req).await.unwrap();
+
+        // The server keeps streaming well past the linger; the connection stays open while it does.
+        for _ in 0..10 {
+            server.write_all(b"chunk").await.unwrap();
+            let mut res = [0; 5];
+            client.read_exact(&mut res).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        assert!(!copy.is_finished());
+
+        // Once it goes quiet, the connection is closed.
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let res = copy.await.unwrap();
+        assert!(matches!(res, Err(proxy::Error::HalfCloseTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn half_close_both_complete() {
+        initialize_telemetry();
+        let (mut client, ztunnel_downsteam) = tokio::io::duplex(32000);
+        let (mut server, ztunnel_upsteam) = tokio::io::duplex(32000);
+
+        let copy = tokio::task::spawn(async move {
+            let (_, cr) = connection_result();
+            copy_bidirectional(
+                ztunnel_downsteam,
+                ztunnel_upsteam,
+                &cr,
+                None,
+                Some(Duration::from_secs(10)),
+                None,
+            )
+            .await
+        });
+
+        client.write_all(b"request").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut req = Vec::new();
+        server.read_to_end(&mut req).await.unwrap();
+        assert_eq!(req, b"request");
+
+        server.write_all(b"response").await.unwrap();
+        server.shutdown().await.unwrap();
+        let mut res = Vec::new();
+        client.read_to_end(&mut res).await.unwrap();
+        assert_eq!(res, b"response");
+        copy.await.unwrap().unwrap();
+    }
+
+    // connection_result returns a ConnectionResult for a test connection, and the metrics it records to.
+    fn connection_result() -> (std::sync::Arc<crate::proxy::Metrics>, ConnectionResult) {
+        let metrics = crate::test_helpers::helpers::test_proxy_metrics();
+        let cr = ConnectionResult::new(
+            "127.0.0.1:12345".parse().unwrap(),
+            "127.0.0.1:34567".parse().unwrap(),
+            None,
+            std::time::Instant::now(),
+            crate::proxy::metrics::ConnectionOpen {
+                reporter: crate::proxy::Reporter::destination,
+                source: None,
+                derived_source: None,
+                destination: None,
+                connection_security_policy: crate::proxy::metrics::SecurityPolicy::unknown,
+                destination_service: None,
+            },
+            metrics.clone(),
+        );
+        (metrics, cr)
+    }
+
     struct WeirdIO<I>(I);
     impl<I: AsyncWrite + std::marker::Unpin> AsyncWrite for WeirdIO<I> {
         fn poll_write(
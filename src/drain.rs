@@ -13,15 +13,20 @@
 // limitations under the License.
 use tracing::Instrument;
 
+use prometheus_client::metrics::counter::Counter;
 use std::time::Duration;
 use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
 pub use internal::DrainMode;
+pub use internal::InFlight;
 pub use internal::ReleaseShutdown as DrainBlocker;
 pub use internal::Signal as DrainTrigger;
 pub use internal::Watch as DrainWatcher;
 
+// How often to log the connections still pending while draining.
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
 /// New constructs a new pair for draining
 /// * DrainTrigger can be used to start a draining sequence and wait for it to complete.
 /// * DrainWatcher should be held by anything that wants to participate in the draining. This can be cloned,
@@ -38,16 +43,23 @@ pub fn new() -> (DrainTrigger, DrainWatcher) {
 /// * force_shutdown: when this is triggered, the future must forcefully shutdown any ongoing work ASAP.
 ///   This means the graceful drain exceeded the hard deadline, and all work must terminate now.
 ///   This is only required for spawned() tasks; otherwise, the future is dropped entirely, canceling all work.
+///
+/// Spawned connections should hold a guard from `drain.track()`, so that draining can report how
+/// many connections remain. Connections still open when the deadline expires are counted in
+/// `force_closed`.
 pub async fn run_with_drain<F, O>(
     component: String,
     drain: DrainWatcher,
     deadline: Duration,
+    force_closed: Counter,
     make_future: F,
 ) where
     F: AsyncFnOnce(DrainWatcher, watch::Receiver<()>) -> O,
     O: Send + 'static,
 {
     let (sub_drain_signal, sub_drain) = new();
+    let in_flight = sub_drain_signal.in_flight();
+    let pending = || in_flight.load(std::sync::atomic::Ordering::Relaxed);
     let (trigger_force_shutdown, force_shutdown) = watch::channel(());
     // Stop accepting once we drain.
     // We will then allow connections up to `deadline` to terminate on their own.
@@ -57,10 +69,31 @@ pub async fn run_with_drain<F, O>(
         _res = fut => {}
         res = drain.wait_for_drain() => {
             if res.mode() == DrainMode::Graceful {
-                debug!(component, "drain started, waiting {:?} for any connections to complete", deadline);
-                if tokio::time::timeout(deadline, sub_drain_signal.start_drain_and_wait(DrainMode::Graceful)).await.is_err() {
-                    // Not all connections completed within time, we will force shut them down
-                    warn!(component, "drain duration expired with pending connections, forcefully shutting down");
+                info!(component, pending = pending(), "drain started, waiting {:?} for any connections to complete", deadline);
+                let drained = sub_drain_signal.start_drain_and_wait(DrainMode::Graceful);
+                let expired = tokio::time::sleep(deadline);
+                tokio::pin!(drained, expired);
+                let mut progress = tokio::time::interval_at(
+                    tokio::time::Instant::now() + DRAIN_PROGRESS_INTERVAL,
+                    DRAIN_PROGRESS_INTERVAL,
+                );
+                loop {
+                    tokio::select! {
+                        _ = &mut drained => {
+                            info!(component, "all connections completed");
+                            break;
+                        }
+                        _ = &mut expired => {
+                            // Not all connections completed within time, we will force shut them down.
+                            let pending = pending();
+                            force_closed.inc_by(pending as u64);
+                            warn!(component, pending, "drain duration expired with pending connections, forcefully shutting down");
+                            break;
+                        }
+                        _ = progress.tick() => {
+                            info!(component, pending = pending(), "draining, waiting for connections to complete");
+                        }
+                    }
                 }
             } else {
                 debug!(component, "terminating");
@@ -76,6 +109,8 @@ pub async fn run_with_drain<F, O>(
 }
 
 mod internal {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tokio::sync::{mpsc, watch};
 
     /// Creates a drain channel.
@@ -85,14 +120,17 @@ mod internal {
     pub fn channel() -> (Signal, Watch) {
         let (signal_tx, signal_rx) = watch::channel(None);
         let (drained_tx, drained_rx) = mpsc::channel(1);
+        let in_flight = Arc::new(AtomicUsize::new(0));
 
         let signal = Signal {
             drained_rx,
             signal_tx,
+            in_flight: in_flight.clone(),
         };
         let watch = Watch {
             drained_tx,
             signal_rx,
+            in_flight,
         };
         (signal, watch)
     }
@@ -109,6 +147,7 @@ mod internal {
     pub struct Signal {
         drained_rx: mpsc::Receiver<Never>,
         signal_tx: watch::Sender<Option<DrainMode>>,
+        in_flight: Arc<AtomicUsize>,
     }
 
     /// Watch for a drain command.
@@ -119,6 +158,17 @@ mod internal {
     pub struct Watch {
         drained_tx: mpsc::Sender<Never>,
         signal_rx: watch::Receiver<Option<DrainMode>>,
+        in_flight: Arc<AtomicUsize>,
+    }
+
+    /// InFlight marks a connection as in flight until it is dropped.
+    #[must_use = "the connection is only counted while InFlight is held"]
+    pub struct InFlight(Arc<AtomicUsize>);
+
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 
     #[must_use = "ReleaseShutdown should be dropped explicitly to release the runtime"]
@@ -133,6 +183,11 @@ mod internal {
     }
 
     impl Signal {
+        /// Returns the count of connections tracked by the [`Watch`] instances.
+        pub fn in_flight(&self) -> Arc<AtomicUsize> {
+            self.in_flight.clone()
+        }
+
         /// Waits for all [`Watch`] instances to be dropped.
         pub async fn closed(&mut self) {
             self.signal_tx.closed().await;
@@ -154,6 +209,12 @@ mod internal {
     }
 
     impl Watch {
+        /// Marks a connection as in flight until the returned guard is dropped.
+        pub fn track(&self) -> InFlight {
+            self.in_flight.fetch_add(1, Ordering::Relaxed);
+            InFlight(self.in_flight.clone())
+        }
+
         /// Returns a `ReleaseShutdown` handle after the drain has been signaled. The
         /// handle must be dropped when a shutdown action has been completed to
         /// unblock graceful shutdown.
@@ -186,9 +247,57 @@ mod internal {
         }
     }
 
+    impl std::fmt::Debug for InFlight {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("InFlight").finish_non_exhaustive()
+        }
+    }
+
     impl std::fmt::Debug for ReleaseShutdown {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             f.debug_struct("ReleaseShutdown").finish_non_exhaustive()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn run_with_drain_counts_force_closed() {
+        let (trigger, watcher) = new();
+        let force_closed = Counter::default();
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(run_with_drain(
+            "test".to_string(),
+            watcher,
+            Duration::from_secs(10),
+            force_closed.clone(),
+            async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+                // This connection completes as soon as draining starts.
+                let in_flight = drain.track();
+                let graceful = drain.clone();
+                tokio::spawn(async move {
+                    let _release = graceful.wait_for_drain().await;
+                    drop(in_flight);
+                });
+                // This one only completes when it is forcefully shut down.
+                let in_flight = drain.track();
+                let stuck = drain.clone();
+                let mut force_shutdown = force_shutdown.clone();
+                tokio::spawn(async move {
+                    let _ = force_shutdown.changed().await;
+                    drop(stuck);
+                    drop(in_flight);
+                });
+                let _ = started_tx.send(());
+                std::future::pending::<()>().await
+            },
+        ));
+        started_rx.await.unwrap();
+        trigger.start_drain_and_wait(DrainMode::Graceful).await;
+        server.await.unwrap();
+        assert_eq!(force_closed.get(), 1);
+    }
+}
@@ -36,36 +36,114 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::Stream;
 use tracing::{Instrument, debug, info, warn};
 
-use crate::tls::ServerCertProvider;
+use crate::tls::{ServerCertProvider, TlsError};
 
 pub fn tls_server<T: ServerCertProvider + Clone + 'static>(
     cert_provider: T,
     listener: TcpListener,
+) -> impl Stream<Item = tokio_rustls::server::TlsStream<TcpStream>> {
+    tls_server_with_error_hook(cert_provider, listener, |_| {})
+}
+
+pub type TlsServerError = tls_listener::Error<std::io::Error, TlsError, SocketAddr>;
+
+/// tls_server_with_error_hook is like tls_server, but calls `on_error` for every failed accept or
+/// handshake before it is dropped.
+pub fn tls_server_with_error_hook<T: ServerCertProvider + Clone + 'static>(
+    cert_provider: T,
+    listener: TcpListener,
+    on_error: impl Fn(&TlsServerError) + 'static,
 ) -> impl Stream<Item = tokio_rustls::server::TlsStream<TcpStream>> {
     use tokio_stream::StreamExt;
 
-    tls_listener::builder(crate::tls::InboundAcceptor::new(cert_provider))
+    let stream = tls_listener::builder(crate::tls::InboundAcceptor::new(cert_provider))
         .listen(listener)
         .take_while(|item| {
             !matches!(item, Err(tls_listener::Error::ListenerError(e)) if proxy::util::is_runtime_shutdown(e))
-        })
-        .filter_map(|conn| {
-            // Avoid 'By default, if a client fails the TLS handshake, that is treated as an error, and the TlsListener will return an Err'
-            match conn {
-                Err(err) => {
-                    warn!("TLS handshake error: {}", err);
-                    None
-                }
-                Ok(s) => {
-                    debug!("TLS handshake succeeded");
-                    Some(s)
+        });
+    with_accept_backoff("inbound", stream, |e| match e {
+        tls_listener::Error::ListenerError(e) => Some(e),
+        _ => None,
+    })
+    .filter_map(move |conn| {
+        // Avoid 'By default, if a client fails the TLS handshake, that is treated as an error, and the TlsListener will return an Err'
+        match conn {
+            Err(err) => {
+                on_error(&err);
+                match &err {
+                    tls_listener::Error::TlsAcceptError {
+                        error: error @ TlsError::MissingClientCertificate,
+                        peer_addr,
+                        ..
+                    } => warn!(peer=%peer_addr, "rejected connection: {}", error),
+                    tls_listener::Error::TlsAcceptError { error, .. } => {
+                        warn!("TLS handshake error: {}", error)
+                    }
+                    // Already logged by the backoff
+                    tls_listener::Error::ListenerError(_) => {}
+                    _ => warn!("TLS handshake error: {}", err),
                 }
+                None
+            }
+            Ok(s) => {
+                debug!("TLS handshake succeeded");
+                Some(s)
+            }
+        }
+    })
+    .map(|(conn, _)| {
+        conn.get_ref().0.set_nodelay(true).unwrap();
+        conn
+    })
+}
+
+/// plaintext_server is like tls_server_with_error_hook, but serves connections without TLS.
+pub fn plaintext_server(
+    component: &'static str,
+    listener: TcpListener,
+    on_error: impl Fn(&std::io::Error) + 'static,
+) -> impl Stream<Item = TcpStream> {
+    use tokio_stream::StreamExt;
+
+    let stream = tokio_stream::wrappers::TcpListenerStream::new(listener)
+        .take_while(|item| !matches!(item, Err(e) if proxy::util::is_runtime_shutdown(e)));
+    with_accept_backoff(component, stream, |e| Some(e)).filter_map(move |conn| match conn {
+        Err(e) => {
+            on_error(&e);
+            None
+        }
+        Ok(s) => {
+            if let Err(e) = s.set_nodelay(true) {
+                debug!("failed to set nodelay: {e}");
+            }
+            Some(s)
+        }
+    })
+}
+
+// with_accept_backoff slows down `stream` if accepting keeps failing, rather than spinning on a
+// persistent error. `listener_error` returns the error from the listener itself, if any.
+fn with_accept_backoff<T, E>(
+    component: &'static str,
+    stream: impl Stream<Item = Result<T, E>>,
+    listener_error: impl Fn(&E) -> Option<&std::io::Error>,
+) -> impl Stream<Item = Result<T, E>> {
+    let mut backoff = proxy::util::AcceptBackoff::default();
+    futures_util::StreamExt::then(stream, move |conn| {
+        let delay = match conn.as_ref().err().and_then(&listener_error) {
+            Some(e) => backoff.failed(component, e),
+            None => {
+                backoff.reset();
+                Duration::ZERO
+            }
+        };
+        Box::pin(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
             }
-        })
-        .map(|(conn, _)| {
-            conn.get_ref().0.set_nodelay(true).unwrap();
             conn
         })
+    })
 }
 
 #[derive(Clone)]
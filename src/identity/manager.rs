@@ -19,11 +19,19 @@ use std::fmt::{Formatter, Write};
 use std::hash::{Hash, RandomState};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::SystemTime;
 
 use crate::config::ProxyMode;
 use async_trait::async_trait;
 
-use prometheus_client::encoding::{EncodeLabelValue, LabelValueEncoder};
+use prometheus_client::collector::Collector;
+use prometheus_client::encoding::{
+    DescriptorEncoder, EncodeLabelValue, EncodeMetric, LabelValueEncoder,
+};
+use prometheus_client::metrics::MetricType;
+use prometheus_client::metrics::counter::ConstCounter;
+use prometheus_client::metrics::gauge::ConstGauge;
 use tokio::sync::{Mutex, mpsc, watch};
 use tokio::time::{Duration, Instant, sleep_until};
 
@@ -125,6 +133,29 @@ impl Identity {
             Identity::Spiffe { trust_domain, .. } => trust_domain.clone(),
         }
     }
+
+    /// with_trust_domain returns the same identity in a different trust domain.
+    pub fn with_trust_domain(&self, td: Strng) -> Identity {
+        match self {
+            Identity::Spiffe {
+                namespace,
+                service_account,
+                ..
+            } => Identity::from_parts(td, namespace.clone(), service_account.clone()),
+        }
+    }
+
+    /// equivalent returns whether the identities are the same, treating all of `trust_domains`
+    /// as interchangeable.
+    pub fn equivalent(&self, other: &Identity, trust_domains: &[Strng]) -> bool {
+        if self == other {
+            return true;
+        }
+        let (a, b) = (self.trust_domain(), other.trust_domain());
+        trust_domains.contains(&a)
+            && trust_domains.contains(&b)
+            && self.with_trust_domain(b) == *other
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -183,6 +214,12 @@ struct CertChannel {
     // While this makes the code simpler, do note that it makes it impossible to use sender closure
     // as an indication of the background task failing.
     tx: watch::Sender<CertState>,
+    // When the certificate was last requested, used to pick which identity to evict.
+    last_used: Instant,
+    // Set for identities prefetched for workloads running on this node (Priority::Warmup). Those are
+    // forgotten explicitly once the workload goes away, so they are never evicted before that;
+    // evicting them would only make us fetch them again from the CA.
+    pinned: bool,
 }
 
 #[derive(Eq, PartialEq)]
@@ -217,6 +254,19 @@ struct Worker {
     certs: Mutex<HashMap<Identity, CertChannel>>,
     // How many concurrent fetch_certificate calls can be pending at a time.
     concurrency: u16,
+    // If set, the maximum number of identities kept in the `certs` map.
+    max_identities: Option<usize>,
+    // If set, certificates are refreshed no later than this long before they expire.
+    rotation_threshold: Option<Duration>,
+    // Expiry of each available certificate, mirrored from the `certs` map so that metrics can be
+    // read without waiting on the async lock. Only ever locked briefly, never across an await.
+    expiries: std::sync::Mutex<HashMap<Identity, SystemTime>>,
+    // Cache statistics, reported by CertCacheCollector.
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    // Number of entries in the `certs` map, updated under its lock so it can be read without it.
+    size: AtomicU64,
 }
 
 impl Worker {
@@ -232,7 +282,14 @@ impl Worker {
             client,
             time_conv: cfg.time_conv,
             concurrency: cfg.concurrency,
+            max_identities: cfg.max_identities,
+            rotation_threshold: cfg.rotation_threshold,
             certs: Default::default(),
+            expiries: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            evictions: Default::default(),
+            size: Default::default(),
         });
 
         // Process requests in the background. The task will terminate on its own when the
@@ -388,11 +445,17 @@ impl Worker {
                             (CertState::Unavailable(err), refresh_at)
                         },
                         Ok(certs) => {
-                             tracing::debug!(%id, "certificate fetch succeeded");
+                            let certs: tls::WorkloadCertificate = certs; // Type annotation.
+                            let expires_in = certs
+                                .cert
+                                .expiration()
+                                .not_after
+                                .duration_since(SystemTime::now())
+                                .unwrap_or_default();
+                            tracing::debug!(%id, ?expires_in, "certificate fetch succeeded");
                             // Reset (pop and drop) the backoff on success.
                             pending_backoffs_by_id.remove(&id);
-                            let certs: tls::WorkloadCertificate = certs; // Type annotation.
-                            let refresh_at = self.time_conv.system_time_to_instant(certs.refresh_at());
+                            let refresh_at = self.time_conv.system_time_to_instant(self.rotate_at(&certs));
                             let refresh_at = if let Some(t) = refresh_at {
                                 t.into()
                             } else {
@@ -428,6 +491,17 @@ impl Worker {
         while fetches.next().await.is_some() {}
     }
 
+    // Returns when a freshly fetched certificate should be refreshed: at its half life, or earlier
+    // if that would leave less than the rotation threshold before it expires. A threshold longer
+    // than the certificate lifetime is ignored, since refreshing would not yield a better one.
+    fn rotate_at(&self, certs: &tls::WorkloadCertificate) -> SystemTime {
+        let refresh_at = certs.refresh_at();
+        match self.rotation_threshold.and_then(|t| certs.rotate_at(t)) {
+            Some(rotate_at) => refresh_at.min(rotate_at),
+            None => refresh_at,
+        }
+    }
+
     // Returns whether the Identity is still managed.
     async fn update_certs(&self, id: &Identity, certs: CertState) -> bool {
         // Both errors (lack of entry in the `certs` map and a send error) are handled the same way
@@ -436,12 +510,25 @@ impl Worker {
         // finished just after the lock was released (but before certs was sent)
         match self.certs.lock().await.get(id) {
             Some(state) => {
+                let expiry = match certs {
+                    CertState::Available(ref c) => Some(c.cert.expiration().not_after),
+                    _ => None,
+                };
                 state.tx.send(certs).expect("state.rx cannot be gone");
+                self.set_expiry(id, expiry);
                 true
             }
             None => false,
         }
     }
+
+    fn set_expiry(&self, id: &Identity, expiry: Option<SystemTime>) {
+        let mut expiries = self.expiries.lock().unwrap();
+        match expiry {
+            Some(t) => expiries.insert(id.clone(), t),
+            None => expiries.remove(id),
+        };
+    }
 }
 
 // tokio::select evaluates each pattern before checking the (optional) associated condition. Work
@@ -464,6 +551,8 @@ pub enum Request {
 pub struct SecretManagerConfig {
     time_conv: crate::time::Converter,
     concurrency: u16,
+    max_identities: Option<usize>,
+    rotation_threshold: Option<Duration>,
 }
 
 // push_increase pushes an item onto the queue if its not present, otherwise updates the priority to the
@@ -510,7 +599,16 @@ impl SecretManager {
             cfg.ca_headers.vec.clone(),
         )
         .await?;
-        Ok(Self::new_with_client(caclient))
+        Ok(Self::new_internal(
+            Box::new(caclient),
+            SecretManagerConfig {
+                time_conv: crate::time::Converter::new(),
+                concurrency: 8,
+                max_identities: cfg.cert_cache_max_identities,
+                rotation_threshold: cfg.cert_rotation_threshold,
+            },
+        )
+        .0)
     }
 
     pub fn new_with_client<C: 'static + CaClientTrait>(client: C) -> Self {
@@ -519,6 +617,8 @@ impl SecretManager {
             SecretManagerConfig {
                 time_conv: crate::time::Converter::new(),
                 concurrency: 8,
+                max_identities: None,
+                rotation_threshold: None,
             },
         )
         .0
@@ -551,9 +651,12 @@ impl SecretManager {
         pri: Priority,
     ) -> Result<watch::Receiver<CertState>, Error> {
         let mut certs = self.worker.certs.lock().await;
-        match certs.get(id) {
+        match certs.get_mut(id) {
             // Identity found in cache and is already being refreshed. Bump the priority if needed.
             Some(st) => {
+                self.worker.hits.fetch_add(1, AtomicOrdering::Relaxed);
+                st.last_used = Instant::now();
+                st.pinned |= pri == Priority::Warmup;
                 let rx = st.rx.clone();
                 drop(certs);
 
@@ -566,9 +669,33 @@ impl SecretManager {
             }
             // New identity, start managing it and return the newly created channel.
             None => {
+                self.worker.misses.fetch_add(1, AtomicOrdering::Relaxed);
+                let evicted = match self.worker.max_identities {
+                    Some(max) => evict_least_recently_used(&mut certs, max.saturating_sub(1)),
+                    None => Vec::new(),
+                };
+                self.worker
+                    .evictions
+                    .fetch_add(evicted.len() as u64, AtomicOrdering::Relaxed);
                 let (tx, rx) = watch::channel(CertState::Initializing(pri));
-                certs.insert(id.to_owned(), CertChannel { rx: rx.clone(), tx });
+                certs.insert(
+                    id.to_owned(),
+                    CertChannel {
+                        rx: rx.clone(),
+                        tx,
+                        last_used: Instant::now(),
+                        pinned: pri == Priority::Warmup,
+                    },
+                );
+                self.worker
+                    .size
+                    .store(certs.len() as u64, AtomicOrdering::Relaxed);
                 drop(certs);
+                for evicted in evicted {
+                    self.worker.set_expiry(&evicted, None);
+                    tracing::debug!(id=%evicted, "evicting least recently used certificate");
+                    self.post(Request::Forget(evicted)).await;
+                }
                 // Notify the background worker to start refreshing the certificate.
                 self.post(Request::Fetch(id.to_owned(), pri)).await;
                 Ok(rx)
@@ -622,7 +749,13 @@ impl SecretManager {
     pub async fn forget_certificate(&self, id: &Identity) {
         // TODO: consider keeping the cert around for a minute or so to avoid churn
         // We would ideally drop any pending or new requests to rotate.
-        if self.worker.certs.lock().await.remove(id).is_some() {
+        let mut certs = self.worker.certs.lock().await;
+        if certs.remove(id).is_some() {
+            self.worker
+                .size
+                .store(certs.len() as u64, AtomicOrdering::Relaxed);
+            drop(certs);
+            self.worker.set_expiry(id, None);
             self.post(Request::Forget(id.clone())).await;
         }
     }
@@ -638,6 +771,105 @@ impl SecretManager {
     }
 }
 
+/// CertExpiryCollector reports the seconds remaining until each managed certificate expires.
+/// The value is computed at scrape time, so it stays accurate between refreshes.
+#[derive(Debug)]
+pub struct CertExpiryCollector(pub Arc<SecretManager>);
+
+impl Collector for CertExpiryCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), fmt::Error> {
+        let expiries: Vec<_> = {
+            let expiries = self.0.worker.expiries.lock().unwrap();
+            expiries.iter().map(|(id, t)| (id.clone(), *t)).collect()
+        };
+        let mut family = encoder.encode_descriptor(
+            "cert_expiry_seconds",
+            "Seconds until the workload certificate for an identity expires (unstable)",
+            None,
+            MetricType::Gauge,
+        )?;
+        let now = SystemTime::now();
+        for (id, not_after) in expiries {
+            let remaining = match not_after.duration_since(now) {
+                Ok(d) => d.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            let labels = [("identity", id)];
+            ConstGauge::new(remaining).encode(family.encode_family(&labels)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// CertCacheCollector reports the size of the certificate cache, and how often lookups hit it or
+/// evicted another identity.
+#[derive(Debug)]
+pub struct CertCacheCollector(pub Arc<SecretManager>);
+
+impl Collector for CertCacheCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), fmt::Error> {
+        let worker = &self.0.worker;
+        let size = ConstGauge::new(worker.size.load(AtomicOrdering::Relaxed) as i64);
+        size.encode(encoder.encode_descriptor(
+            "cert_cache_size",
+            "Number of identities with a cached certificate (unstable)",
+            None,
+            size.metric_type(),
+        )?)?;
+        for (name, help, value) in [
+            (
+                "cert_cache_hits",
+                "Certificate lookups for an identity that was already cached (unstable)",
+                &worker.hits,
+            ),
+            (
+                "cert_cache_misses",
+                "Certificate lookups for an identity that was not cached (unstable)",
+                &worker.misses,
+            ),
+            (
+                "cert_cache_evictions",
+                "Identities evicted from the certificate cache to stay within its limit (unstable)",
+                &worker.evictions,
+            ),
+        ] {
+            let counter = ConstCounter::new(value.load(AtomicOrdering::Relaxed));
+            counter.encode(encoder.encode_descriptor(
+                name,
+                help,
+                None,
+                counter.metric_type(),
+            )?)?;
+        }
+        Ok(())
+    }
+}
+
+// Removes the least recently used identities until at most `keep` remain, returning the removed
+// identities. Certificates that are still being fetched for the first time have callers waiting on
+// them, so they are never evicted; the cache may temporarily exceed its limit instead.
+// Connections using an evicted certificate are unaffected, as they hold their own reference.
+fn evict_least_recently_used(
+    certs: &mut HashMap<Identity, CertChannel>,
+    keep: usize,
+) -> Vec<Identity> {
+    let mut evicted = Vec::new();
+    while certs.len() > keep {
+        let lru = certs
+            .iter()
+            .filter(|(_, chan)| !chan.pinned)
+            .filter(|(_, chan)| !matches!(*chan.rx.borrow(), CertState::Initializing(_)))
+            .min_by_key(|(_, chan)| chan.last_used)
+            .map(|(id, _)| id.clone());
+        let Some(id) = lru else {
+            break;
+        };
+        certs.remove(&id);
+        evicted.push(id);
+    }
+    evicted
+}
+
 // Matches CertState::Initializing(pri) from a Receiver, wrapped in a function to make borrow
 // lifetimes more manageable.
 fn init_pri(rx: &watch::Receiver<CertState>) -> Option<Priority> {
@@ -687,6 +919,8 @@ pub mod mock {
                 super::SecretManagerConfig {
                     time_conv,
                     concurrency: 2,
+                    max_identities: None,
+                    rotation_threshold: None,
                 },
             )
             .0,
@@ -837,6 +1071,18 @@ mod tests {
     }
 
     fn setup(concurrency: u16) -> Test {
+        setup_with_limit(concurrency, None)
+    }
+
+    fn setup_with_limit(concurrency: u16, max_identities: Option<usize>) -> Test {
+        setup_with_config(concurrency, max_identities, None)
+    }
+
+    fn setup_with_config(
+        concurrency: u16,
+        max_identities: Option<usize>,
+        rotation_threshold: Option<Duration>,
+    ) -> Test {
         // Tests that use this function rely on Tokio's test time pause and auto-advance. It gets a
         // bit tricky so a few things to remember:
         //  - When *all* futures are blocked waiting for a specific time, the runtime will
@@ -857,6 +1103,8 @@ mod tests {
             SecretManagerConfig {
                 time_conv,
                 concurrency,
+                max_identities,
+                rotation_threshold,
             },
         );
         Test {
@@ -882,6 +1130,26 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_cert_expiry_collector() {
+        let sm = mock::new_secret_manager(Duration::from_secs(3600));
+        let id = identity::Identity::default();
+        sm.fetch_certificate(&id).await.unwrap();
+
+        let mut registry = prometheus_client::registry::Registry::default();
+        registry.register_collector(Box::new(CertExpiryCollector(sm.clone())));
+        let mut out = String::new();
+        prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+
+        let prefix = format!("cert_expiry_seconds{{identity=\"{id}\"}} ");
+        let line = out
+            .lines()
+            .find(|l| l.starts_with(&prefix))
+            .unwrap_or_else(|| panic!("missing expiry for {id}: {out}"));
+        let remaining: i64 = line[prefix.len()..].parse().unwrap();
+        assert!(remaining > 0 && remaining <= 3600, "{line}");
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_priority() {
         let test = setup(1);
@@ -1054,6 +1322,104 @@ mod tests {
         test.tear_down().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_lru_eviction() {
+        let test = setup_with_limit(1, Some(2));
+        let sm = test.secret_manager.clone();
+
+        let a = sm.fetch_certificate(&identity("a")).await.unwrap();
+        sm.fetch_certificate(&identity("b")).await.unwrap();
+        // Touch a, so b is the least recently used.
+        tokio::time::sleep(SEC).await;
+        sm.fetch_certificate(&identity("a")).await.unwrap();
+        sm.fetch_certificate(&identity("c")).await.unwrap();
+
+        let mut cached = sm.collect_certs(|id, _| id.clone()).await;
+        cached.sort();
+        assert_eq!(cached, vec![identity("a"), identity("c")]);
+        // Certificates already handed out remain usable.
+        assert!(!a.is_expired());
+
+        let out = {
+            let mut registry = prometheus_client::registry::Registry::default();
+            registry.register_collector(Box::new(CertCacheCollector(sm.clone())));
+            let mut out = String::new();
+            prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+            out
+        };
+        for want in [
+            "cert_cache_size 2",
+            "cert_cache_hits_total 1",
+            "cert_cache_misses_total 3",
+            "cert_cache_evictions_total 1",
+        ] {
+            assert!(out.lines().any(|l| l == want), "missing {want}: {out}");
+        }
+
+        // An evicted identity is fetched again on demand.
+        sm.fetch_certificate(&identity("b")).await.unwrap();
+        assert_eq!(sm.cache_len().await, 2);
+        drop(sm);
+        test.tear_down().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lru_eviction_keeps_local_workloads() {
+        let test = setup_with_limit(1, Some(2));
+        let sm = test.secret_manager.clone();
+
+        // a is prefetched for a workload on this node, so it is kept even though it is the least
+        // recently used.
+        sm.fetch_certificate_pri(&identity("a"), Priority::Warmup)
+            .await
+            .unwrap();
+        tokio::time::sleep(SEC).await;
+        sm.fetch_certificate(&identity("b")).await.unwrap();
+        sm.fetch_certificate(&identity("c")).await.unwrap();
+
+        let mut cached = sm.collect_certs(|id, _| id.clone()).await;
+        cached.sort();
+        assert_eq!(cached, vec![identity("a"), identity("c")]);
+
+        // Once the workload is gone its certificate is dropped like any other.
+        sm.forget_certificate(&identity("a")).await;
+        sm.fetch_certificate(&identity("d")).await.unwrap();
+        let mut cached = sm.collect_certs(|id, _| id.clone()).await;
+        cached.sort();
+        assert_eq!(cached, vec![identity("c"), identity("d")]);
+        drop(sm);
+        test.tear_down().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rotation_threshold() {
+        // Certificates live for 2 * CERT_HALFLIFE = 100s, so rotating with 80s left refreshes them
+        // about 20s after they are issued rather than at their half life.
+        let test = setup_with_config(1, None, Some(80 * SEC));
+        let start = Instant::now();
+        let id = identity("test");
+        test.secret_manager.fetch_certificate(&id).await.unwrap();
+        test.caclient.clear_fetches().await;
+
+        tokio::time::sleep_until(start + 20 * SEC - MILLISEC).await;
+        assert!(test.caclient.fetches().await.is_empty());
+        tokio::time::sleep_until(start + 23 * SEC).await;
+        assert_eq!(
+            collect_strings(test.caclient.fetches().await),
+            collect_strings([&id]),
+        );
+        test.tear_down().await;
+
+        // A threshold longer than the certificate lifetime falls back to the half life.
+        let test = setup_with_config(1, None, Some(200 * SEC));
+        let start = Instant::now();
+        test.secret_manager.fetch_certificate(&id).await.unwrap();
+        test.caclient.clear_fetches().await;
+        tokio::time::sleep_until(start + CERT_HALFLIFE - MILLISEC).await;
+        assert!(test.caclient.fetches().await.is_empty());
+        test.tear_down().await;
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_unused_cleanup() {
         setup(1).tear_down().await;
@@ -1146,4 +1512,20 @@ mod tests {
         assert_matches!(Identity::from_str("spiffe://td/ns/ns/sa/sa/"), Err(_));
         assert_matches!(Identity::from_str("spiffe://td/ns/ns/foobar/sa/"), Err(_));
     }
+
+    #[test]
+    fn identity_equivalent() {
+        let local = Identity::from_str("spiffe://cluster.local/ns/ns/sa/sa").unwrap();
+        let federated = Identity::from_str("spiffe://remote.td/ns/ns/sa/sa").unwrap();
+        let unknown = Identity::from_str("spiffe://unknown.td/ns/ns/sa/sa").unwrap();
+        let other_sa = Identity::from_str("spiffe://remote.td/ns/ns/sa/other").unwrap();
+        let tds = ["cluster.local".into(), "remote.td".into()];
+
+        assert!(local.equivalent(&local, &[]));
+        assert!(!local.equivalent(&federated, &[]));
+        assert!(local.equivalent(&federated, &tds));
+        assert!(federated.equivalent(&local, &tds));
+        assert!(!local.equivalent(&unknown, &tds));
+        assert!(!local.equivalent(&other_sa, &tds));
+    }
 }
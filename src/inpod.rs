@@ -94,6 +94,9 @@ pub fn init_and_new(
     WorkloadProxyManager::verify_syscalls()?;
     let admin_handler: Arc<admin::WorkloadManagerAdminHandler> = Default::default();
     admin_server.add_handler(admin_handler.clone());
+    admin_server.set_connections_handler(Arc::new(admin::WorkloadConnectionsAdminHandler(
+        admin_handler.clone(),
+    )));
     let inpod_config = crate::inpod::InPodConfig::new(cfg)?;
 
     let state_mgr = statemanager::WorkloadProxyManagerState::new(
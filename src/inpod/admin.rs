@@ -15,11 +15,11 @@
 use serde::{Deserialize, Serialize};
 use tracing::error;
 
-use crate::proxy::connection_manager::ConnectionManager;
+use crate::proxy::connection_manager::{ConnectionManager, ConnectionManagerDump};
 use crate::state::WorkloadInfo;
 use anyhow::anyhow;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum State {
@@ -147,6 +147,31 @@ impl crate::admin::AdminHandler for WorkloadManagerAdminHandler {
     }
 }
 
+/// WorkloadConnectionsAdminHandler serves the live connections of each workload proxy, keyed by
+/// workload UID.
+pub struct WorkloadConnectionsAdminHandler(pub Arc<WorkloadManagerAdminHandler>);
+
+impl crate::admin::AdminHandler for WorkloadConnectionsAdminHandler {
+    fn key(&self) -> &'static str {
+        "connections"
+    }
+
+    fn handle(&self) -> anyhow::Result<serde_json::Value> {
+        let snapshot: HashMap<crate::inpod::WorkloadUid, ConnectionManagerDump> = {
+            let state = self
+                .0
+                .state
+                .read()
+                .map_err(|_| anyhow!("Failed to read state"))?;
+            state
+                .iter()
+                .filter_map(|(uid, ps)| Some((uid.clone(), ps.connections.as_ref()?.snapshot())))
+                .collect()
+        };
+        Ok(serde_json::to_value(snapshot)?)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
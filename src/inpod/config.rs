@@ -105,9 +105,9 @@ impl crate::proxy::SocketFactory for InPodSocketFactory {
     }
 
     fn tcp_bind(&self, addr: std::net::SocketAddr) -> std::io::Result<socket::Listener> {
-        let std_sock = self.configure(|| std::net::TcpListener::bind(addr))?;
-        std_sock.set_nonblocking(true)?;
-        tokio::net::TcpListener::from_std(std_sock).map(socket::Listener::new)
+        let sock = self.configure(|| self.inner.new_listener_socket(addr))?;
+        sock.listen(self.inner.0.listen_backlog)
+            .map(socket::Listener::new)
     }
 
     fn udp_bind(&self, addr: std::net::SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
@@ -152,7 +152,8 @@ impl crate::proxy::SocketFactory for InPodSocketPortReuseFactory {
         }
 
         sock.bind(addr)?;
-        sock.listen(128).map(socket::Listener::new)
+        sock.listen(self.sf.inner.0.listen_backlog)
+            .map(socket::Listener::new)
     }
 
     fn udp_bind(&self, addr: std::net::SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
@@ -75,5 +75,16 @@ fn version() -> anyhow::Result<()> {
 async fn proxy(cfg: Arc<config::Config>) -> anyhow::Result<()> {
     info!("version: {}", version::BuildInfo::new());
     info!("running with config: {}", serde_yaml::to_string(&cfg)?);
-    app::build(cfg).await?.wait_termination().await
+    if let Some(endpoint) = &cfg.tracing_endpoint {
+        #[cfg(feature = "otel")]
+        telemetry::otel::setup(endpoint)?;
+        #[cfg(not(feature = "otel"))]
+        anyhow::bail!(
+            "span export to {endpoint} requires ztunnel to be built with the otel feature"
+        );
+    }
+    let res = app::build(cfg).await?.wait_termination().await;
+    #[cfg(feature = "otel")]
+    telemetry::otel::shutdown();
+    res
 }
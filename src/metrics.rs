@@ -24,6 +24,7 @@ use tracing_core::field::Value;
 use crate::identity::Identity;
 
 pub mod meta;
+pub mod process;
 pub mod server;
 
 use crate::strng::{RichStrng, Strng};
@@ -0,0 +1,212 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use prometheus_client::collector::Collector;
+use prometheus_client::encoding::{DescriptorEncoder, EncodeMetric};
+use prometheus_client::metrics::counter::ConstCounter;
+use prometheus_client::metrics::gauge::ConstGauge;
+
+/// ProcessCollector reports the standard `process_*` metrics (CPU, memory, file descriptors and
+/// start time) that Prometheus client libraries conventionally export.
+/// Values are sampled from the OS at scrape time; nothing is recorded on the data path.
+#[derive(Debug, Default)]
+pub struct ProcessCollector;
+
+#[derive(Debug, Default)]
+struct ProcessStats {
+    cpu_seconds: f64,
+    resident_memory_bytes: u64,
+    virtual_memory_bytes: u64,
+    start_time_seconds: f64,
+    open_fds: u64,
+    max_fds: u64,
+}
+
+impl Collector for ProcessCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), fmt::Error> {
+        // Sampling is best effort; if the OS does not expose the stats we report nothing rather
+        // than failing the whole scrape.
+        let Some(stats) = ProcessStats::read() else {
+            return Ok(());
+        };
+        let cpu = ConstCounter::new(stats.cpu_seconds);
+        cpu.encode(encoder.encode_descriptor(
+            "process_cpu_seconds",
+            "Total user and system CPU time spent in seconds.",
+            None,
+            cpu.metric_type(),
+        )?)?;
+        for (name, help, value) in [
+            (
+                "process_resident_memory_bytes",
+                "Resident memory size in bytes.",
+                stats.resident_memory_bytes as f64,
+            ),
+            (
+                "process_virtual_memory_bytes",
+                "Virtual memory size in bytes.",
+                stats.virtual_memory_bytes as f64,
+            ),
+            (
+                "process_start_time_seconds",
+                "Start time of the process since unix epoch in seconds.",
+                stats.start_time_seconds,
+            ),
+            (
+                "process_open_fds",
+                "Number of open file descriptors.",
+                stats.open_fds as f64,
+            ),
+            (
+                "process_max_fds",
+                "Maximum number of open file descriptors.",
+                stats.max_fds as f64,
+            ),
+        ] {
+            let gauge = ConstGauge::new(value);
+            gauge.encode(encoder.encode_descriptor(name, help, None, gauge.metric_type())?)?;
+        }
+        Ok(())
+    }
+}
+
+impl ProcessStats {
+    #[cfg(target_os = "linux")]
+    fn read() -> Option<Self> {
+        linux::read()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read() -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+mod linux {
+    use super::ProcessStats;
+
+    pub fn read() -> Option<ProcessStats> {
+        let (ticks, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        if ticks <= 0 || page_size <= 0 {
+            return None;
+        }
+        let ticks = ticks as f64;
+
+        let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+        let fields = parse_stat(&stat)?;
+        let btime = std::fs::read_to_string("/proc/stat")
+            .ok()?
+            .lines()
+            .find_map(|l| l.strip_prefix("btime "))?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        let open_fds = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+            return None;
+        }
+
+        Some(ProcessStats {
+            cpu_seconds: (fields.utime + fields.stime) as f64 / ticks,
+            resident_memory_bytes: fields.rss * page_size as u64,
+            virtual_memory_bytes: fields.vsize,
+            start_time_seconds: btime as f64 + fields.starttime as f64 / ticks,
+            open_fds,
+            max_fds: limit.rlim_cur,
+        })
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(super) struct StatFields {
+        pub utime: u64,
+        pub stime: u64,
+        pub starttime: u64,
+        pub vsize: u64,
+        pub rss: u64,
+    }
+
+    /// parse_stat extracts the fields we need from /proc/<pid>/stat. See proc(5) for the layout.
+    pub(super) fn parse_stat(stat: &str) -> Option<StatFields> {
+        // The command name may contain spaces or parens, so skip past the last ')'.
+        // The remaining fields start at field 3 (state).
+        let rest = &stat[stat.rfind(')')? + 1..];
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+        Some(StatFields {
+            utime: field(14)?,
+            stime: field(15)?,
+            starttime: field(22)?,
+            vsize: field(23)?,
+            rss: field(24)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_stat() {
+        let stat = "1234 (zt (un) nel) S 1 1234 1234 0 -1 4194560 2000 0 0 0 150 50 0 0 20 0 8 0 \
+                    4242 123456789 2048 18446744073709551615 1 1 0 0 0 0 0 4096 17663 0 0 0 17 3 0 0 0 0 0";
+        assert_eq!(
+            linux::parse_stat(stat),
+            Some(linux::StatFields {
+                utime: 150,
+                stime: 50,
+                starttime: 4242,
+                vsize: 123456789,
+                rss: 2048,
+            })
+        );
+        assert_eq!(linux::parse_stat("1234 (ztunnel) S 1"), None);
+    }
+
+    #[test]
+    fn encode() {
+        let mut registry = prometheus_client::registry::Registry::default();
+        registry.register_collector(Box::new(ProcessCollector));
+        let mut out = String::new();
+        prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+        if cfg!(target_os = "linux") {
+            for name in [
+                "process_cpu_seconds_total",
+                "process_resident_memory_bytes",
+                "process_open_fds",
+                "process_start_time_seconds",
+            ] {
+                assert!(
+                    out.lines().any(|l| l.starts_with(&format!("{name} "))),
+                    "missing {name}: {out}"
+                );
+            }
+        }
+    }
+}
@@ -16,6 +16,7 @@ use std::fmt::Debug;
 use std::fs::File;
 use std::io::Read;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, io};
@@ -25,7 +26,7 @@ use hickory_proto::error::ProtoError;
 use crate::strng::Strng;
 use rand::Rng;
 use socket2::TcpKeepalive;
-use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::time::timeout;
 use tracing::{Instrument, debug, trace, warn};
 
@@ -39,6 +40,8 @@ use crate::drain::DrainWatcher;
 use crate::proxy::connection_manager::{ConnectionManager, PolicyWatcher};
 use crate::proxy::inbound_passthrough::InboundPassthrough;
 use crate::proxy::outbound::Outbound;
+use crate::proxy::outbound_udp::OutboundUdp;
+use crate::proxy::outbound_uds::OutboundUds;
 use crate::proxy::socks5::Socks5;
 use crate::rbac::Connection;
 use crate::state::service::{Service, ServiceDescription};
@@ -47,15 +50,21 @@ use crate::state::workload::{GatewayAddress, Workload};
 use crate::state::{DemandProxyState, WorkloadInfo};
 use crate::{config, identity, socket, tls};
 
+pub mod circuit_breaker;
+pub mod connection_limit;
 pub mod connection_manager;
+mod h1;
 mod h2;
 mod inbound;
 mod inbound_passthrough;
 #[allow(non_camel_case_types)]
 pub mod metrics;
 mod outbound;
+mod outbound_udp;
+mod outbound_uds;
 pub mod pool;
 mod socks5;
+pub mod udp;
 pub mod util;
 
 pub trait SocketFactory {
@@ -75,23 +84,25 @@ pub struct DefaultSocketFactory(pub config::SocketConfig);
 
 impl SocketFactory for DefaultSocketFactory {
     fn new_tcp_v4(&self) -> std::io::Result<TcpSocket> {
-        TcpSocket::new_v4().and_then(|s| {
-            self.setup_socket(&s)?;
-            Ok(s)
-        })
+        self.new_tcp(socket2::Domain::IPV4, TcpSocket::new_v4)
+            .and_then(|s| {
+                self.setup_socket(&s)?;
+                Ok(s)
+            })
     }
 
     fn new_tcp_v6(&self) -> std::io::Result<TcpSocket> {
-        TcpSocket::new_v6().and_then(|s| {
-            self.setup_socket(&s)?;
-            Ok(s)
-        })
+        self.new_tcp(socket2::Domain::IPV6, TcpSocket::new_v6)
+            .and_then(|s| {
+                self.setup_socket(&s)?;
+                Ok(s)
+            })
     }
 
     fn tcp_bind(&self, addr: SocketAddr) -> std::io::Result<socket::Listener> {
-        let std_sock = std::net::TcpListener::bind(addr)?;
-        std_sock.set_nonblocking(true)?;
-        TcpListener::from_std(std_sock).map(socket::Listener::new)
+        let sock = self.new_listener_socket(addr)?;
+        sock.listen(self.0.listen_backlog)
+            .map(socket::Listener::new)
     }
 
     fn udp_bind(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
@@ -106,6 +117,33 @@ impl SocketFactory for DefaultSocketFactory {
 }
 
 impl DefaultSocketFactory {
+    /// new_listener_socket returns a socket bound to `addr`, ready to listen.
+    pub(crate) fn new_listener_socket(&self, addr: SocketAddr) -> io::Result<TcpSocket> {
+        let sock = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        // Match std::net::TcpListener::bind
+        sock.set_reuseaddr(true)?;
+        sock.bind(addr)?;
+        Ok(sock)
+    }
+
+    // new_tcp creates an MPTCP socket if enabled and supported, and a regular TCP socket otherwise.
+    fn new_tcp(
+        &self,
+        domain: socket2::Domain,
+        tcp: fn() -> io::Result<TcpSocket>,
+    ) -> io::Result<TcpSocket> {
+        if self.0.mptcp_enabled && socket::mptcp_supported() {
+            match socket::new_mptcp(domain) {
+                Ok(s) => return Ok(s),
+                Err(e) => tracing::debug!("failed to create MPTCP socket, using TCP: {e}"),
+            }
+        }
+        tcp()
+    }
+
     fn setup_socket(&self, s: &TcpSocket) -> io::Result<()> {
         s.set_nodelay(true)?;
         let cfg = self.0;
@@ -119,10 +157,13 @@ impl DefaultSocketFactory {
                 socket2::SockRef::from(&s).set_tcp_keepalive(&ka)
             );
         }
-        if cfg.user_timeout_enabled {
+        let user_timeout = cfg.user_timeout.or_else(|| {
             // https://blog.cloudflare.com/when-tcp-sockets-refuse-to-die/
             // TCP_USER_TIMEOUT = TCP_KEEPIDLE + TCP_KEEPINTVL * TCP_KEEPCNT.
-            let ut = cfg.keepalive_time + cfg.keepalive_retries * cfg.keepalive_interval;
+            cfg.user_timeout_enabled
+                .then(|| cfg.keepalive_time + cfg.keepalive_retries * cfg.keepalive_interval)
+        });
+        if let Some(ut) = user_timeout {
             tracing::trace!(
                 "set user timeout: {:?}",
                 socket2::SockRef::from(&s).set_tcp_user_timeout(Some(ut))
@@ -165,11 +206,54 @@ impl SocketFactory for MarkSocketFactory {
     }
 }
 
+/// DscpSocketFactory marks TCP sockets created by `inner` with a DSCP value, if one is set.
+/// Failing to set the mark, for instance if it is not permitted, is logged rather than fatal.
+pub struct DscpSocketFactory<'a> {
+    pub inner: &'a (dyn SocketFactory + Send + Sync),
+    pub dscp: Option<u8>,
+}
+
+impl DscpSocketFactory<'_> {
+    fn mark(&self, s: TcpSocket) -> TcpSocket {
+        if let Some(dscp) = self.dscp {
+            if let Err(e) = socket::set_dscp(&s, dscp) {
+                warn!(dscp, "failed to set DSCP: {e}");
+            }
+        }
+        s
+    }
+}
+
+impl SocketFactory for DscpSocketFactory<'_> {
+    fn new_tcp_v4(&self) -> io::Result<TcpSocket> {
+        self.inner.new_tcp_v4().map(|s| self.mark(s))
+    }
+
+    fn new_tcp_v6(&self) -> io::Result<TcpSocket> {
+        self.inner.new_tcp_v6().map(|s| self.mark(s))
+    }
+
+    fn tcp_bind(&self, addr: SocketAddr) -> io::Result<socket::Listener> {
+        self.inner.tcp_bind(addr)
+    }
+
+    fn udp_bind(&self, addr: SocketAddr) -> io::Result<tokio::net::UdpSocket> {
+        self.inner.udp_bind(addr)
+    }
+
+    fn ipv6_enabled_localhost(&self) -> io::Result<bool> {
+        self.inner.ipv6_enabled_localhost()
+    }
+}
+
 pub struct Proxy {
     inbound: Inbound,
+    inbound_plaintext_hbone: Option<Inbound>,
     inbound_passthrough: InboundPassthrough,
     outbound: Outbound,
     socks5: Option<Socks5>,
+    outbound_uds: Option<OutboundUds>,
+    outbound_udp: Option<OutboundUdp>,
     policy_watcher: PolicyWatcher,
 }
 
@@ -306,6 +390,11 @@ impl Proxy {
         }
 
         let inbound_passthrough = InboundPassthrough::new(pi.clone(), drain.clone()).await?;
+        let inbound_plaintext_hbone = if let Some(addr) = pi.cfg.inbound_plaintext_hbone_addr {
+            Some(Inbound::new_plaintext(pi.clone(), addr, drain.clone()).await?)
+        } else {
+            None
+        };
         let outbound = Outbound::new(pi.clone(), drain.clone()).await?;
         let socks5 = if pi.cfg.socks5_addr.is_some() {
             let socks5 = Socks5::new(pi.clone(), drain.clone()).await?;
@@ -313,14 +402,27 @@ impl Proxy {
         } else {
             None
         };
+        let outbound_uds = if pi.cfg.outbound_uds.is_some() {
+            Some(OutboundUds::new(pi.clone(), drain.clone())?)
+        } else {
+            None
+        };
+        let outbound_udp = if pi.cfg.outbound_udp_addr.is_some() {
+            Some(OutboundUdp::new(pi.clone(), drain.clone()).await?)
+        } else {
+            None
+        };
         let policy_watcher =
             PolicyWatcher::new(pi.state.clone(), drain, pi.connection_manager.clone());
 
         Ok(Proxy {
             inbound,
+            inbound_plaintext_hbone,
             inbound_passthrough,
             outbound,
             socks5,
+            outbound_uds,
+            outbound_udp,
             policy_watcher,
         })
     }
@@ -336,6 +438,15 @@ impl Proxy {
         if let Some(socks5) = self.socks5 {
             tasks.push(tokio::spawn(socks5.run().in_current_span()));
         };
+        if let Some(outbound_uds) = self.outbound_uds {
+            tasks.push(tokio::spawn(outbound_uds.run().in_current_span()));
+        };
+        if let Some(outbound_udp) = self.outbound_udp {
+            tasks.push(tokio::spawn(outbound_udp.run().in_current_span()));
+        };
+        if let Some(inbound) = self.inbound_plaintext_hbone {
+            tasks.push(tokio::spawn(inbound.run_plaintext().in_current_span()));
+        };
 
         futures::future::join_all(tasks).await;
     }
@@ -345,6 +456,7 @@ impl Proxy {
             outbound: self.outbound.address(),
             inbound: self.inbound.address(),
             socks5: self.socks5.as_ref().map(|s| s.address()),
+            outbound_udp: self.outbound_udp.as_ref().map(|s| s.address()),
         }
     }
 }
@@ -354,6 +466,7 @@ pub struct Addresses {
     pub outbound: SocketAddr,
     pub inbound: SocketAddr,
     pub socks5: Option<SocketAddr>,
+    pub outbound_udp: Option<SocketAddr>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -363,6 +476,19 @@ pub enum AuthorizationRejectionError {
     ExplicitlyDenied(Strng, Strng),
     NotAllowed,
 }
+impl AuthorizationRejectionError {
+    /// code is a stable, generic identifier for the rejection. Unlike the Display form it does not
+    /// name the policy involved, so it is safe to return to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoWorkload => "no_workload",
+            Self::WorkloadMismatch => "workload_mismatch",
+            Self::ExplicitlyDenied(_, _) => "explicitly_denied",
+            Self::NotAllowed => "not_allowed",
+        }
+    }
+}
+
 impl fmt::Display for AuthorizationRejectionError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -379,6 +505,9 @@ pub enum Error {
     #[error("failed to bind to address {0}: {1}")]
     Bind(SocketAddr, io::Error),
 
+    #[error("failed to bind to unix socket {0}: {1}")]
+    BindUnix(PathBuf, io::Error),
+
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 
@@ -401,9 +530,33 @@ pub enum Error {
     #[error("connection failed: {0}")]
     ConnectionFailed(io::Error),
 
+    #[error("connection to {0} timed out")]
+    ConnectTimeout(SocketAddr),
+
+    #[error("tls handshake with {0} failed: {1}")]
+    TlsHandshake(SocketAddr, io::Error),
+
+    #[error("upstream reset the stream: {0}")]
+    UpstreamReset(::h2::Reason),
+
+    #[error("connection closed after being idle for {0:?}")]
+    IdleTimeout(Duration),
+
+    #[error("connection closed after being half-closed for {0:?}")]
+    HalfCloseTimeout(Duration),
+
+    #[error("invalid PROXY protocol header: {0}")]
+    ProxyProtocol(String),
+
     #[error("connection tracking failed")]
     ConnectionTrackingFailed,
 
+    #[error("connection limit exceeded for {0}")]
+    ConnectionLimitExceeded(Identity),
+
+    #[error("circuit breaker open for {0} endpoint {1}")]
+    CircuitBreakerOpen(Strng, SocketAddr),
+
     #[error("connection closed due to policy change")]
     AuthorizationPolicyLateRejection,
 
@@ -455,6 +608,15 @@ pub enum Error {
     #[error("no valid authority pseudo header: {0}")]
     NoValidAuthority(String),
 
+    #[error("authority pseudo header is too long: {0} bytes")]
+    AuthorityTooLong(usize),
+
+    #[error("request headers are too large: {0} bytes")]
+    HeadersTooLarge(usize),
+
+    #[error("invalid connection tag: {0}")]
+    InvalidConnectionTag(String),
+
     #[error("no valid service port in authority header: {0}")]
     NoValidServicePort(String, u16),
 
@@ -467,10 +629,16 @@ pub enum Error {
     #[error("no healthy upstream: {0}")]
     NoHealthyUpstream(SocketAddr),
 
+    #[error("passthrough to unknown destination {0} is not allowed")]
+    PassthroughDenied(SocketAddr),
+
+    #[error("UDP is only supported directly to HBONE workloads, not to {0}")]
+    UdpUnsupported(SocketAddr),
+
     #[error("no ip addresses were resolved for workload: {0}")]
     NoResolvedAddresses(String),
 
-    #[error("requested service {0}:{1} found, but cannot resolve port")]
+    #[error("requested service {0} found, but it does not expose port {1}")]
     NoPortForServices(String, u16),
 
     #[error("requested service {0} found, but has no IP addresses")]
@@ -481,8 +649,11 @@ pub enum Error {
     )]
     EmptyResolvedAddresses(String),
 
-    #[error("attempted recursive call to ourselves")]
-    SelfCall,
+    #[error("original destination unavailable; the connection was not redirected to ztunnel")]
+    NoOriginalDst,
+
+    #[error("connection to {0} would loop back to ztunnel")]
+    TunnelLoop(SocketAddr),
 
     #[error("no gateway address: {0}")]
     NoGatewayAddress(Box<Workload>),
@@ -506,18 +677,25 @@ pub enum Error {
     DnsEmpty,
 }
 
-// Custom TLV for proxy protocol for the identity of the source
-const PROXY_PROTOCOL_AUTHORITY_TLV: u8 = 0xD0;
+// TLV types ztunnel writes into PROXY protocol v2 headers, so a receiving native-tunnel backend (or
+// another ztunnel) can recover what was verified on the HBONE connection. Both values are UTF-8.
+/// PROXY_PROTOCOL_IDENTITY_TLV (0xD0) carries the SPIFFE identity of the verified source,
+/// e.g. `spiffe://cluster.local/ns/default/sa/client`. It is omitted if the source is unauthenticated.
+pub const PROXY_PROTOCOL_IDENTITY_TLV: u8 = 0xD0;
+/// PROXY_PROTOCOL_HBONE_AUTHORITY_TLV (0xD1) carries the HBONE CONNECT authority the client requested,
+/// e.g. `10.0.0.1:8080` or `echo.default.svc.cluster.local:8080`. Unlike the address block, this may
+/// be a service hostname. The custom range 0xE0-0xEF is left to load balancers, whose TLVs we ignore.
+pub const PROXY_PROTOCOL_HBONE_AUTHORITY_TLV: u8 = 0xD1;
 
 pub async fn write_proxy_protocol<T>(
     stream: &mut TcpStream,
     addresses: T,
     src_id: Option<Identity>,
+    authority: Option<&HboneAddress>,
 ) -> io::Result<()>
 where
     T: Into<ppp::v2::Addresses> + std::fmt::Debug,
 {
-    use ppp::v2::{Builder, Command, Protocol, Version};
     use tokio::io::AsyncWriteExt;
 
     // When the hbone_addr populated from the authority header contains a svc hostname, the address included
@@ -525,15 +703,200 @@ where
     // This is done since addresses doesn't support hostnames.
     // See ref https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
     debug!("writing proxy protocol addresses: {:?}", addresses);
+    let header = build_proxy_protocol(addresses, src_id.as_ref(), authority)?;
+    stream.write_all(&header).await
+}
+
+fn build_proxy_protocol<T>(
+    addresses: T,
+    src_id: Option<&Identity>,
+    authority: Option<&HboneAddress>,
+) -> io::Result<Vec<u8>>
+where
+    T: Into<ppp::v2::Addresses>,
+{
+    use ppp::v2::{Builder, Command, Protocol, Version};
+
     let mut builder =
         Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, addresses);
-
     if let Some(id) = src_id {
-        builder = builder.write_tlv(PROXY_PROTOCOL_AUTHORITY_TLV, id.to_string().as_bytes())?;
+        builder = builder.write_tlv(PROXY_PROTOCOL_IDENTITY_TLV, id.to_string().as_bytes())?;
     }
+    if let Some(authority) = authority {
+        builder = builder.write_tlv(
+            PROXY_PROTOCOL_HBONE_AUTHORITY_TLV,
+            authority.to_string().as_bytes(),
+        )?;
+    }
+    builder.build()
+}
 
-    let header = builder.build()?;
-    stream.write_all(&header).await
+// Signature that starts every PROXY protocol v2 header
+const PROXY_PROTOCOL_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// A v1 header, including the trailing CRLF, is at most 107 bytes
+const PROXY_PROTOCOL_V1_MAX_LEN: usize = 107;
+
+/// ProxyProtocolHeader is the information recovered from a PROXY protocol header.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProxyProtocolHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+    /// The source identity, from the PROXY_PROTOCOL_IDENTITY_TLV. Only present in v2 headers.
+    pub identity: Option<Identity>,
+    /// The HBONE authority, from the PROXY_PROTOCOL_HBONE_AUTHORITY_TLV. Only present in v2 headers.
+    pub authority: Option<HboneAddress>,
+}
+
+/// PROXY_PROTOCOL_TIMEOUT bounds how long we wait for a PROXY protocol header, so a peer that never
+/// sends one cannot hold the connection open.
+pub(crate) const PROXY_PROTOCOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// inbound_proxy_protocol_source returns the source of a connection accepted from `peer` on a
+/// plaintext inbound listener. Peers in the configured trusted ranges must start the stream with a
+/// PROXY protocol header, whose source is used; other peers are never parsed for one, so they
+/// cannot spoof their address.
+pub async fn inbound_proxy_protocol_source<S>(
+    cfg: &config::Config,
+    stream: &mut S,
+    peer: SocketAddr,
+) -> Result<SocketAddr, Error>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    if !cfg
+        .inbound_proxy_protocol_trusted_ranges
+        .iter()
+        .any(|r| r.contains(&peer.ip()))
+    {
+        return Ok(peer);
+    }
+    Ok(read_proxy_protocol(stream)
+        .await?
+        .map(socket::to_canonical)
+        .unwrap_or(peer))
+}
+
+/// read_proxy_protocol consumes a PROXY protocol (v1 or v2) header from the start of the stream
+/// and returns the original client address it describes.
+/// Only the header is read, so any payload following it is left on the stream untouched.
+/// Headers that carry no address (v1 UNKNOWN, or the v2 LOCAL command used for LB health checks)
+/// return None, and the caller should fall back to the peer address.
+/// Fails if the header does not arrive within PROXY_PROTOCOL_TIMEOUT.
+pub async fn read_proxy_protocol<S>(stream: &mut S) -> Result<Option<SocketAddr>, Error>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    let header = timeout(PROXY_PROTOCOL_TIMEOUT, read_proxy_protocol_header(stream))
+        .await
+        .map_err(|_| Error::ProxyProtocol("timed out waiting for header".to_string()))??;
+    Ok(header.source)
+}
+
+/// read_proxy_protocol_header is read_proxy_protocol, but returns everything the header carries,
+/// including the identity and HBONE authority TLVs written by write_proxy_protocol.
+pub async fn read_proxy_protocol_header<S>(stream: &mut S) -> Result<ProxyProtocolHeader, Error>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    // Both "PROXY" and the v2 signature are at least 5 bytes; read those to decide the version.
+    let mut buf = vec![0u8; 5];
+    stream.read_exact(&mut buf).await?;
+    if buf == b"PROXY" {
+        // v1 is line based; read byte-by-byte so we never consume past the CRLF.
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() >= PROXY_PROTOCOL_V1_MAX_LEN {
+                return Err(Error::ProxyProtocol("v1 header too long".to_string()));
+            }
+            buf.push(stream.read_u8().await?);
+        }
+    } else if PROXY_PROTOCOL_V2_SIGNATURE.starts_with(&buf) {
+        // v2 has a fixed 16 byte prefix, the last 2 bytes of which are the remaining length.
+        buf.resize(16, 0);
+        stream.read_exact(&mut buf[5..]).await?;
+        let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        buf.resize(16 + len, 0);
+        stream.read_exact(&mut buf[16..]).await?;
+    } else {
+        return Err(Error::ProxyProtocol("header not present".to_string()));
+    }
+    parse_proxy_protocol(&buf)
+}
+
+fn parse_proxy_protocol(buf: &[u8]) -> Result<ProxyProtocolHeader, Error> {
+    use ppp::HeaderResult;
+
+    match HeaderResult::parse(buf) {
+        HeaderResult::V1(Ok(header)) => {
+            let (source, destination) = match header.addresses {
+                ppp::v1::Addresses::Tcp4(a) => (
+                    SocketAddr::from((a.source_address, a.source_port)),
+                    SocketAddr::from((a.destination_address, a.destination_port)),
+                ),
+                ppp::v1::Addresses::Tcp6(a) => (
+                    SocketAddr::from((a.source_address, a.source_port)),
+                    SocketAddr::from((a.destination_address, a.destination_port)),
+                ),
+                ppp::v1::Addresses::Unknown => return Ok(ProxyProtocolHeader::default()),
+            };
+            Ok(ProxyProtocolHeader {
+                source: Some(source),
+                destination: Some(destination),
+                ..Default::default()
+            })
+        }
+        HeaderResult::V2(Ok(header)) => {
+            let mut res = match header.addresses {
+                ppp::v2::Addresses::IPv4(a) => ProxyProtocolHeader {
+                    source: Some(SocketAddr::from((a.source_address, a.source_port))),
+                    destination: Some(SocketAddr::from((
+                        a.destination_address,
+                        a.destination_port,
+                    ))),
+                    ..Default::default()
+                },
+                ppp::v2::Addresses::IPv6(a) => ProxyProtocolHeader {
+                    source: Some(SocketAddr::from((a.source_address, a.source_port))),
+                    destination: Some(SocketAddr::from((
+                        a.destination_address,
+                        a.destination_port,
+                    ))),
+                    ..Default::default()
+                },
+                ppp::v2::Addresses::Unspecified | ppp::v2::Addresses::Unix(_) => {
+                    ProxyProtocolHeader::default()
+                }
+            };
+            for tlv in header.tlvs() {
+                let tlv = tlv.map_err(|e| Error::ProxyProtocol(e.to_string()))?;
+                let value = || {
+                    std::str::from_utf8(&tlv.value).map_err(|_| {
+                        Error::ProxyProtocol(format!("TLV {:#x} is not valid UTF-8", tlv.kind))
+                    })
+                };
+                match tlv.kind {
+                    PROXY_PROTOCOL_IDENTITY_TLV => {
+                        let id = value()?.parse().map_err(|e| {
+                            Error::ProxyProtocol(format!("invalid identity TLV: {e}"))
+                        })?;
+                        res.identity = Some(id);
+                    }
+                    PROXY_PROTOCOL_HBONE_AUTHORITY_TLV => {
+                        let uri: http::Uri = value()?.parse().map_err(|e| {
+                            Error::ProxyProtocol(format!("invalid authority TLV: {e}"))
+                        })?;
+                        res.authority = Some(HboneAddress::try_from(&uri)?);
+                    }
+                    // Other TLVs (for example from a load balancer) are not ours to interpret.
+                    _ => {}
+                }
+            }
+            Ok(res)
+        }
+        HeaderResult::V1(Err(e)) => Err(Error::ProxyProtocol(e.to_string())),
+        HeaderResult::V2(Err(e)) => Err(Error::ProxyProtocol(e.to_string())),
+    }
 }
 
 /// Represents a traceparent, as defined by https://www.w3.org/TR/trace-context/
@@ -547,11 +910,34 @@ pub struct TraceParent {
 
 pub const BAGGAGE_HEADER: &str = "baggage";
 pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// Set on inbound HBONE responses that were denied by authorization policy, with a generic code
+/// describing why. Policy names are never included.
+pub const DENY_REASON_HEADER: &str = "x-ztunnel-deny-reason";
 
 impl TraceParent {
     pub fn header(&self) -> hyper::header::HeaderValue {
         hyper::header::HeaderValue::from_bytes(format!("{self:?}").as_bytes()).unwrap()
     }
+
+    /// set_as_parent makes this traceparent the parent of `span`, if spans are exported.
+    #[cfg(feature = "otel")]
+    pub fn set_as_parent(&self, span: &tracing::Span) {
+        use opentelemetry::trace::{
+            SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+        };
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let parent = SpanContext::new(
+            TraceId::from_bytes(self.trace_id.to_be_bytes()),
+            SpanId::from_bytes(self.parent_id.to_be_bytes()),
+            TraceFlags::new(self.flags),
+            true,
+            TraceState::default(),
+        );
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(parent));
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn set_as_parent(&self, _span: &tracing::Span) {}
 }
 impl TraceParent {
     fn new() -> Self {
@@ -588,14 +974,24 @@ impl TryFrom<&str> for TraceParent {
         if value.len() != 55 {
             anyhow::bail!("traceparent malformed length was {}", value.len())
         }
+        // from_str_radix accepts a leading '+', so check the digits ourselves.
+        if !value.bytes().all(|b| b == b'-' || b.is_ascii_hexdigit()) {
+            anyhow::bail!("traceparent contains non-hex characters")
+        }
 
         let segs: Vec<&str> = value.split('-').collect();
+        let [version, trace_id, parent_id, flags] = segs[..] else {
+            anyhow::bail!("traceparent malformed with {} segments", segs.len())
+        };
+        if (version.len(), trace_id.len(), parent_id.len(), flags.len()) != (2, 32, 16, 2) {
+            anyhow::bail!("traceparent malformed segment lengths")
+        }
 
         Ok(Self {
-            version: u8::from_str_radix(segs[0], 16)?,
-            trace_id: u128::from_str_radix(segs[1], 16)?,
-            parent_id: u64::from_str_radix(segs[2], 16)?,
-            flags: u8::from_str_radix(segs[3], 16)?,
+            version: u8::from_str_radix(version, 16)?,
+            trace_id: u128::from_str_radix(trace_id, 16)?,
+            parent_id: u64::from_str_radix(parent_id, 16)?,
+            flags: u8::from_str_radix(flags, 16)?,
         })
     }
 }
@@ -621,21 +1017,56 @@ pub(super) fn maybe_set_transparent(
     })
 }
 
+/// maybe_bind_device restricts `listener` to the network interface `iface`, if one is configured.
+/// Platforms without SO_BINDTODEVICE only log a warning and leave the listener unrestricted.
+pub(super) fn maybe_bind_device(
+    listener: &socket::Listener,
+    iface: &Option<String>,
+) -> Result<(), Error> {
+    let Some(iface) = iface else {
+        return Ok(());
+    };
+    match listener.bind_device(iface) {
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            warn!(address=%listener.local_addr(), %iface, "cannot bind listener to interface: {e}");
+            Ok(())
+        }
+        res => Ok(res?),
+    }
+}
+
 pub fn get_original_src_from_stream(stream: &TcpStream) -> Option<IpAddr> {
     stream
         .peer_addr()
         .map_or(None, |sa| Some(socket::to_canonical(sa).ip()))
 }
 
-const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
-
 pub async fn freebind_connect(
     local: Option<IpAddr>,
     addr: SocketAddr,
     socket_factory: &(dyn SocketFactory + Send + Sync),
+    connect_timeout: Duration,
+) -> io::Result<TcpStream> {
+    freebind_connect_with_port(
+        local.map(|ip| SocketAddr::new(ip, 0)),
+        addr,
+        socket_factory,
+        connect_timeout,
+    )
+    .await
+}
+
+/// freebind_connect_with_port is like freebind_connect, but also attempts to bind the source port
+/// of `local`, if non-zero. If that port is unavailable, either because it is bound locally or the
+/// resulting connection would collide with an existing one, an ephemeral port is used instead.
+pub async fn freebind_connect_with_port(
+    local: Option<SocketAddr>,
+    addr: SocketAddr,
+    socket_factory: &(dyn SocketFactory + Send + Sync),
+    connect_timeout: Duration,
 ) -> io::Result<TcpStream> {
     async fn connect(
-        local: Option<IpAddr>,
+        local: Option<SocketAddr>,
         addr: SocketAddr,
         socket_factory: &(dyn SocketFactory + Send + Sync),
     ) -> io::Result<TcpStream> {
@@ -655,33 +1086,55 @@ pub async fn freebind_connect(
             }
             // TODO: Need figure out how to handle case of loadbalancing to itself.
             //       We use ztunnel addr instead, otherwise app side will be confused.
-            Some(src) if src == socket::to_canonical(addr).ip() => {
+            Some(src) if src.ip() == socket::to_canonical(addr).ip() => {
                 let socket = create_socket(addr.is_ipv4())?;
                 trace!(%src, dest=%addr, "dest and source are the same, connect directly");
                 Ok(socket.connect(addr).await?)
             }
             Some(src) => {
                 let socket = create_socket(src.is_ipv4())?;
-                let local_addr = SocketAddr::new(src, 0);
                 match socket::set_freebind_and_transparent(&socket) {
                     Err(err) => warn!("failed to set freebind: {:?}", err),
-                    _ => {
-                        if let Err(err) = socket.bind(local_addr) {
-                            warn!("failed to bind local addr: {:?}", err)
+                    _ => match socket.bind(src) {
+                        Err(err) if src.port() != 0 && is_port_conflict(&err) => {
+                            debug!(%src, "source port unavailable, using an ephemeral port");
+                            if let Err(err) = socket.bind(SocketAddr::new(src.ip(), 0)) {
+                                warn!("failed to bind local addr: {:?}", err)
+                            }
                         }
-                    }
+                        Err(err) => warn!("failed to bind local addr: {:?}", err),
+                        Ok(()) => {}
+                    },
                 };
                 trace!(%src, dest=%addr, "connect with source IP");
                 Ok(socket.connect(addr).await?)
             }
         }
     }
+    let connect = async {
+        match connect(local, addr, socket_factory).await {
+            // The 4-tuple may already be in use, which is only detected at connect time.
+            Err(err) if local.is_some_and(|l| l.port() != 0) && is_port_conflict(&err) => {
+                debug!(dest=%addr, "source port conflict on connect, retrying with an ephemeral port");
+                let local = local.map(|l| SocketAddr::new(l.ip(), 0));
+                connect(local, addr, socket_factory).await
+            }
+            res => res,
+        }
+    };
     // Wrap the entire connect function in a timeout
-    timeout(CONNECTION_TIMEOUT, connect(local, addr, socket_factory))
+    timeout(connect_timeout, connect)
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
 }
 
+fn is_port_conflict(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+    )
+}
+
 // guess_inbound_service selects an upstream service for inbound metrics.
 // There may be many services for a single workload. We find the the first one with an applicable port
 // as a best guess.
@@ -722,6 +1175,14 @@ pub fn guess_inbound_service(
         .map(|s| ServiceDescription::from(s.as_ref()))
 }
 
+// connection_rate_limit returns the byte rate limit for connections to `service`, falling back to
+// the default.
+pub fn connection_rate_limit(cfg: &config::Config, service: Option<&Strng>) -> Option<u64> {
+    service
+        .and_then(|svc| cfg.service_connection_rate_limit.get(svc).copied())
+        .or(cfg.connection_rate_limit)
+}
+
 // Checks that the source identiy and address match the upstream's waypoint
 async fn check_from_waypoint(
     state: &DemandProxyState,
@@ -729,8 +1190,10 @@ async fn check_from_waypoint(
     src_identity: Option<&Identity>,
     src_ip: &IpAddr,
 ) -> bool {
+    let trust_domains = state.equivalent_trust_domains(&upstream.trust_domain);
     let is_waypoint = |wl: &Workload| {
-        Some(wl.identity()).as_ref() == src_identity && wl.workload_ips.contains(src_ip)
+        src_identity.is_some_and(|id| id.equivalent(&wl.identity(), &trust_domains))
+            && wl.workload_ips.contains(src_ip)
     };
     check_gateway_address(state, upstream.waypoint.as_ref(), is_waypoint).await
 }
@@ -797,7 +1260,7 @@ pub fn parse_forwarded_host(input: &str) -> Option<String> {
         .filter(|host| !host.is_empty())
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HboneAddress {
     SocketAddr(SocketAddr),
     SvcHostname(Strng, u16),
@@ -854,10 +1317,22 @@ impl From<(Strng, u16)> for HboneAddress {
     }
 }
 
+/// MAX_HBONE_AUTHORITY_LEN bounds the CONNECT authority we are willing to parse. This fits any valid
+/// hostname (253 bytes) with a port, and is checked before the URI is copied.
+pub const MAX_HBONE_AUTHORITY_LEN: usize = 260;
+
 impl TryFrom<&http::Uri> for HboneAddress {
     type Error = Error;
 
     fn try_from(value: &http::Uri) -> Result<Self, Self::Error> {
+        let len = value.authority().map(|a| a.as_str().len()).unwrap_or(0)
+            + value
+                .path_and_query()
+                .map(|p| p.as_str().len())
+                .unwrap_or(0);
+        if len > MAX_HBONE_AUTHORITY_LEN {
+            return Err(Error::AuthorityTooLong(len));
+        }
         match value.to_string().parse::<SocketAddr>() {
             Ok(addr) => Ok(HboneAddress::SocketAddr(addr)),
             Err(_) => {
@@ -877,6 +1352,118 @@ impl TryFrom<&http::Uri> for HboneAddress {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn freebind_connect_port_in_use() {
+        // Binding the source address needs IP_TRANSPARENT, without which we never get to the port.
+        if !crate::test_helpers::can_run_privilged_test() {
+            eprintln!("This test requires root; skipping");
+            return;
+        }
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let taken = tokio::net::TcpListener::bind("127.0.0.2:0").await.unwrap();
+        let local = taken.local_addr().unwrap();
+        let stream = freebind_connect_with_port(
+            Some(local),
+            upstream.local_addr().unwrap(),
+            &DefaultSocketFactory::default(),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+        // The source IP is still preserved; only the port falls back to an ephemeral one.
+        let got = stream.local_addr().unwrap();
+        assert_eq!(got.ip(), local.ip());
+        assert_ne!(got.port(), local.port());
+    }
+
+    #[test]
+    fn connection_rate_limit_service_override() {
+        let cfg = config::Config {
+            connection_rate_limit: Some(1000),
+            service_connection_rate_limit: std::collections::HashMap::from([(
+                crate::strng::new("a.example.com"),
+                10,
+            )]),
+            ..config::parse_config().unwrap()
+        };
+        let svc = |s| Some(crate::strng::new(s));
+        assert_eq!(
+            connection_rate_limit(&cfg, svc("a.example.com").as_ref()),
+            Some(10)
+        );
+        assert_eq!(
+            connection_rate_limit(&cfg, svc("b.example.com").as_ref()),
+            Some(1000)
+        );
+        assert_eq!(connection_rate_limit(&cfg, None), Some(1000));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dscp_socket_factory() {
+        let inner = DefaultSocketFactory::default();
+        let factory = DscpSocketFactory {
+            inner: &inner,
+            dscp: Some(46),
+        };
+        let v4 = factory.new_tcp_v4().unwrap();
+        assert_eq!(socket2::SockRef::from(&v4).tos().unwrap(), 46 << 2);
+        let v6 = factory.new_tcp_v6().unwrap();
+        assert_eq!(socket2::SockRef::from(&v6).tclass_v6().unwrap(), 46 << 2);
+
+        let unmarked = DscpSocketFactory {
+            inner: &inner,
+            dscp: None,
+        };
+        let v4 = unmarked.new_tcp_v4().unwrap();
+        assert_eq!(socket2::SockRef::from(&v4).tos().unwrap(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn user_timeout_socket_factory() {
+        let user_timeout = |cfg: config::SocketConfig| {
+            let factory = DefaultSocketFactory(cfg);
+            let v4 = factory.new_tcp_v4().unwrap();
+            let v6 = factory.new_tcp_v6().unwrap();
+            let v4 = socket2::SockRef::from(&v4).tcp_user_timeout().unwrap();
+            assert_eq!(v4, socket2::SockRef::from(&v6).tcp_user_timeout().unwrap());
+            v4
+        };
+        assert_eq!(user_timeout(Default::default()), None);
+        assert_eq!(
+            user_timeout(config::SocketConfig {
+                user_timeout: Some(Duration::from_secs(30)),
+                ..Default::default()
+            }),
+            Some(Duration::from_secs(30))
+        );
+        // An explicit timeout takes precedence over the one derived from keepalives.
+        assert_eq!(
+            user_timeout(config::SocketConfig {
+                user_timeout_enabled: true,
+                user_timeout: Some(Duration::from_secs(30)),
+                ..Default::default()
+            }),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[tokio::test]
+    async fn mptcp_socket_factory() {
+        let factory = DefaultSocketFactory(config::SocketConfig {
+            mptcp_enabled: true,
+            ..Default::default()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Whether or not the kernel supports MPTCP, we should get a usable socket.
+        let socket = factory.new_tcp_v4().unwrap();
+        let stream = socket.connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert_eq!(server.peer_addr().unwrap(), stream.local_addr().unwrap());
+    }
+
     #[test]
     fn test_parse_forwarded_host() {
         let header = "by=identifier;for=identifier;host=example.com;proto=https";
@@ -896,4 +1483,200 @@ mod tests {
         let header = r#"for=for;by=by;host=host;proto="pröto""#;
         assert_eq!(parse_forwarded_host(header), None);
     }
+
+    #[tokio::test]
+    async fn test_read_proxy_protocol() {
+        use ppp::v2::{Builder, Command, Protocol, Version};
+
+        // v1, leaving the payload on the stream
+        let mut stream: &[u8] = b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\nhello";
+        let src = read_proxy_protocol(&mut stream).await.unwrap();
+        assert_eq!(src, Some("10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(stream, b"hello");
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_proxy_protocol(&mut stream).await.unwrap(), None);
+
+        // v2
+        let src: SocketAddr = "[2001:db8::1]:5678".parse().unwrap();
+        let dst: SocketAddr = "[2001:db8::2]:80".parse().unwrap();
+        let mut header =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst))
+                .write_tlv(
+                    PROXY_PROTOCOL_IDENTITY_TLV,
+                    b"spiffe://cluster.local/ns/a/sa/b",
+                )
+                .unwrap()
+                .build()
+                .unwrap();
+        header.extend_from_slice(b"hello");
+        let mut stream: &[u8] = &header;
+        assert_eq!(read_proxy_protocol(&mut stream).await.unwrap(), Some(src));
+        assert_eq!(stream, b"hello");
+
+        // No header at all must be rejected
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(matches!(
+            read_proxy_protocol(&mut stream).await,
+            Err(Error::ProxyProtocol(_))
+        ));
+        let mut stream: &[u8] = b"";
+        assert!(read_proxy_protocol(&mut stream).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inbound_proxy_protocol_source() {
+        let header: &[u8] = b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\nhello";
+        let trusted: SocketAddr = "192.168.0.1:4000".parse().unwrap();
+        let untrusted: SocketAddr = "172.16.0.1:4000".parse().unwrap();
+        let cfg = config::Config {
+            inbound_proxy_protocol_trusted_ranges: vec!["192.168.0.0/24".parse().unwrap()],
+            ..config::parse_config().unwrap()
+        };
+
+        // Trusted peers name the source in the header
+        let mut stream = header;
+        let src = inbound_proxy_protocol_source(&cfg, &mut stream, trusted).await;
+        assert_eq!(src.unwrap(), "10.0.0.1:1234".parse().unwrap());
+        assert_eq!(stream, b"hello");
+
+        // Other peers cannot, and their stream is left untouched
+        let mut stream = header;
+        let src = inbound_proxy_protocol_source(&cfg, &mut stream, untrusted).await;
+        assert_eq!(src.unwrap(), untrusted);
+        assert_eq!(stream, header);
+
+        // No peer is trusted by default
+        let mut stream = header;
+        let default = config::parse_config().unwrap();
+        let src = inbound_proxy_protocol_source(&default, &mut stream, trusted).await;
+        assert_eq!(src.unwrap(), trusted);
+        assert_eq!(stream, header);
+
+        // A trusted peer that never sends the header is cut off
+        let (_client, mut server) = tokio::io::duplex(64);
+        assert!(matches!(
+            inbound_proxy_protocol_source(&cfg, &mut server, trusted).await,
+            Err(Error::ProxyProtocol(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_round_trip() {
+        let src: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dst: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        let id = Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "ns".into(),
+            service_account: "sa".into(),
+        };
+
+        for (identity, authority) in [
+            (None, None),
+            (Some(id.clone()), None),
+            (None, Some(HboneAddress::SocketAddr(dst))),
+            (
+                Some(id.clone()),
+                Some(HboneAddress::SvcHostname(
+                    "echo.ns.svc.cluster.local".into(),
+                    8080,
+                )),
+            ),
+        ] {
+            let mut header =
+                build_proxy_protocol((src, dst), identity.as_ref(), authority.as_ref()).unwrap();
+            header.extend_from_slice(b"hello");
+            let mut stream: &[u8] = &header;
+            let got = read_proxy_protocol_header(&mut stream).await.unwrap();
+            assert_eq!(
+                got,
+                ProxyProtocolHeader {
+                    source: Some(src),
+                    destination: Some(dst),
+                    identity,
+                    authority,
+                }
+            );
+            assert_eq!(stream, b"hello");
+        }
+
+        // IPv6 addresses are preserved as well
+        let src: SocketAddr = "[2001:db8::1]:5678".parse().unwrap();
+        let dst: SocketAddr = "[2001:db8::2]:80".parse().unwrap();
+        let header = build_proxy_protocol((src, dst), Some(&id), None).unwrap();
+        let got = parse_proxy_protocol(&header).unwrap();
+        assert_eq!(got.source, Some(src));
+        assert_eq!(got.identity, Some(id));
+
+        // TLVs in the custom range belong to load balancers and are ignored, but a malformed
+        // identity is rejected
+        use ppp::v2::{Builder, Command, Protocol, Version};
+        let header =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst))
+                .write_tlv(0xE0, b"lb-metadata")
+                .unwrap()
+                .build()
+                .unwrap();
+        let got = parse_proxy_protocol(&header).unwrap();
+        assert_eq!(got.identity, None);
+        assert_eq!(got.authority, None);
+        let header =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst))
+                .write_tlv(PROXY_PROTOCOL_IDENTITY_TLV, b"not-an-identity")
+                .unwrap()
+                .build()
+                .unwrap();
+        assert!(matches!(
+            parse_proxy_protocol(&header),
+            Err(Error::ProxyProtocol(_))
+        ));
+    }
+
+    #[test]
+    fn traceparent_round_trip() {
+        let tp = TraceParent::new();
+        let parsed = TraceParent::try_from(tp.to_string().as_str()).unwrap();
+        assert_eq!(parsed.to_string(), tp.to_string());
+    }
+
+    #[test]
+    fn traceparent_malformed() {
+        for input in [
+            "",
+            // Right length, but no separators
+            &"a".repeat(55),
+            // Separators in the wrong places
+            "00-0af7651916cd43dd8448eb211c80319-cb7ad6b7169203331-01",
+            // Sign prefixes are accepted by from_str_radix
+            "+0-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-+1",
+            // Non-hex
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b716920333z-01",
+            // Multi-byte characters
+            "00-0af7651916cd43dd8448eb211c8031é-b7ad6b7169203331-01",
+        ] {
+            assert!(TraceParent::try_from(input).is_err(), "{input}");
+        }
+        assert!(
+            TraceParent::try_from("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn traceparent_random_input() {
+        let mut rng = rand::rng();
+        for _ in 0..10_000 {
+            // Bias towards the valid alphabet and length so we get past the early checks.
+            let s: String = (0..55)
+                .map(|_| match rng.random_range(0..20) {
+                    0 => '-',
+                    1 => '+',
+                    2 => 'é',
+                    _ => char::from_digit(rng.random_range(0..16), 16).unwrap(),
+                })
+                .collect();
+            let _ = TraceParent::try_from(s.as_str());
+        }
+    }
 }
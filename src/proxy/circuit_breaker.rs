@@ -0,0 +1,271 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::strng::Strng;
+
+/// CircuitBreaker tracks consecutive upstream connection failures per endpoint of a destination
+/// service, so one failing pod does not take down its healthy peers.
+/// Once an endpoint reaches the failure threshold its circuit opens and new connections to it are
+/// rejected until the cooldown elapses; after that a single probe connection is let through per
+/// cooldown window, and the first success closes the circuit again.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    endpoints: Arc<Mutex<HashMap<BreakerKey, Breaker>>>,
+    // If unset, the breaker is disabled and every connection is admitted.
+    threshold: Option<u32>,
+    cooldown: Duration,
+}
+
+type BreakerKey = (Strng, SocketAddr);
+
+struct Breaker {
+    consecutive_failures: u32,
+    // Set while the circuit is open. Moved forward each time a probe is admitted.
+    opened_at: Option<Instant>,
+    // The last failure or admitted probe, used to drop endpoints that are no longer dialed.
+    updated: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreakerDump {
+    pub service: Strng,
+    pub endpoint: SocketAddr,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(None, Duration::ZERO)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: Option<u32>, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            endpoints: Default::default(),
+            threshold,
+            cooldown,
+        }
+    }
+
+    /// admit returns whether a new connection to `endpoint` of `service` may be attempted.
+    pub fn admit(&self, service: &Strng, endpoint: SocketAddr) -> bool {
+        if self.threshold.is_none() {
+            return true;
+        }
+        let mut endpoints = self.endpoints.lock().expect("mutex");
+        let Some(breaker) = endpoints.get_mut(&(service.clone(), endpoint)) else {
+            return true;
+        };
+        let Some(opened_at) = breaker.opened_at.as_mut() else {
+            return true;
+        };
+        let now = Instant::now();
+        if now < *opened_at + self.cooldown {
+            return false;
+        }
+        // Half-open: let this connection probe the endpoint, and hold back others for another window.
+        debug!(%service, %endpoint, "circuit half-open, admitting probe");
+        *opened_at = now;
+        breaker.updated = now;
+        true
+    }
+
+    /// record reports the outcome of an upstream connection attempt to `endpoint` of `service`.
+    pub fn record(&self, service: &Strng, endpoint: SocketAddr, success: bool) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut endpoints = self.endpoints.lock().expect("mutex");
+        let key = (service.clone(), endpoint);
+        if success {
+            if endpoints
+                .remove(&key)
+                .is_some_and(|b| b.opened_at.is_some())
+            {
+                info!(%service, %endpoint, "circuit closed");
+            }
+            return;
+        }
+        let now = Instant::now();
+        if !endpoints.contains_key(&key) {
+            // Only new endpoints grow the map, so this is a good time to drop the stale ones.
+            endpoints.retain(|_, b| !self.is_stale(b, now));
+        }
+        let breaker = endpoints.entry(key).or_insert(Breaker {
+            consecutive_failures: 0,
+            opened_at: None,
+            updated: now,
+        });
+        breaker.consecutive_failures += 1;
+        breaker.updated = now;
+        if breaker.opened_at.is_some() {
+            // A failed probe keeps the circuit open for another cooldown.
+            breaker.opened_at = Some(now);
+        } else if breaker.consecutive_failures >= threshold {
+            info!(
+                %service,
+                %endpoint,
+                failures=breaker.consecutive_failures,
+                cooldown=?self.cooldown,
+                "circuit opened"
+            );
+            breaker.opened_at = Some(now);
+        }
+    }
+
+    // is_stale returns whether nothing has failed or probed the endpoint for two cooldowns, covering
+    // a full open window followed by a half-open one. Endpoints that went away are never dialed
+    // again, so without this they would be kept forever.
+    fn is_stale(&self, breaker: &Breaker, now: Instant) -> bool {
+        now >= breaker.updated + 2 * self.cooldown
+    }
+
+    pub fn snapshot(&self) -> Vec<CircuitBreakerDump> {
+        let now = Instant::now();
+        let mut endpoints = self.endpoints.lock().expect("mutex");
+        endpoints.retain(|_, b| !self.is_stale(b, now));
+        endpoints
+            .iter()
+            .map(|((service, endpoint), b)| CircuitBreakerDump {
+                service: service.clone(),
+                endpoint: *endpoint,
+                state: match b.opened_at {
+                    None => CircuitState::Closed,
+                    Some(t) if now < t + self.cooldown => CircuitState::Open,
+                    Some(_) => CircuitState::HalfOpen,
+                },
+                consecutive_failures: b.consecutive_failures,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strng;
+
+    fn state(cb: &CircuitBreaker, service: &Strng, endpoint: SocketAddr) -> CircuitState {
+        cb.snapshot()
+            .into_iter()
+            .find(|d| &d.service == service && d.endpoint == endpoint)
+            .map(|d| d.state)
+            .unwrap_or(CircuitState::Closed)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_and_half_open() {
+        let cb = CircuitBreaker::new(Some(3), Duration::from_secs(5));
+        let svc = strng::new("svc.ns.svc.cluster.local");
+        let other = strng::new("other.ns.svc.cluster.local");
+        let ep: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let other_ep: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+
+        for _ in 0..2 {
+            assert!(cb.admit(&svc, ep));
+            cb.record(&svc, ep, false);
+        }
+        assert_eq!(state(&cb, &svc, ep), CircuitState::Closed);
+        cb.record(&svc, ep, false);
+        assert_eq!(state(&cb, &svc, ep), CircuitState::Open);
+        assert!(!cb.admit(&svc, ep));
+        // Other endpoints of the service, and other services, are unaffected.
+        assert!(cb.admit(&svc, other_ep));
+        assert!(cb.admit(&other, ep));
+
+        // After the cooldown a single probe is admitted.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(state(&cb, &svc, ep), CircuitState::HalfOpen);
+        assert!(cb.admit(&svc, ep));
+        assert!(!cb.admit(&svc, ep));
+
+        // A failed probe re-opens the circuit for another cooldown.
+        cb.record(&svc, ep, false);
+        assert_eq!(state(&cb, &svc, ep), CircuitState::Open);
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert!(!cb.admit(&svc, ep));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(cb.admit(&svc, ep));
+
+        // A successful probe closes it.
+        cb.record(&svc, ep, true);
+        assert!(cb.admit(&svc, ep));
+        assert!(cb.admit(&svc, ep));
+        assert!(cb.snapshot().is_empty());
+    }
+
+    #[tokio::test]
+    async fn success_resets_failures() {
+        let cb = CircuitBreaker::new(Some(2), Duration::from_secs(5));
+        let svc = strng::new("svc");
+        let ep: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        cb.record(&svc, ep, false);
+        cb.record(&svc, ep, true);
+        cb.record(&svc, ep, false);
+        assert_eq!(state(&cb, &svc, ep), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_endpoints_removed() {
+        let cb = CircuitBreaker::new(Some(1), Duration::from_secs(5));
+        let svc = strng::new("svc");
+        let ep: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        cb.record(&svc, ep, false);
+        assert_eq!(state(&cb, &svc, ep), CircuitState::Open);
+
+        // The endpoint is never dialed again, for example because the pod was deleted.
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert_eq!(state(&cb, &svc, ep), CircuitState::HalfOpen);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(cb.snapshot().is_empty());
+
+        // Recording a failure for a new endpoint drops stale entries too.
+        let other_ep: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        cb.record(&svc, ep, false);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        cb.record(&svc, other_ep, false);
+        assert_eq!(cb.endpoints.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        let cb = CircuitBreaker::default();
+        let svc = strng::new("svc");
+        let ep: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        for _ in 0..10 {
+            cb.record(&svc, ep, false);
+        }
+        assert!(cb.admit(&svc, ep));
+        assert!(cb.snapshot().is_empty());
+    }
+}
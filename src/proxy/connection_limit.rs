@@ -0,0 +1,139 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::proxy::Metrics;
+
+/// ConnectionLimit bounds how many inbound connections are served at once, across every proxy
+/// sharing it. Listeners acquire a permit for each connection they accept; once the limit is
+/// reached, they stop accepting until a connection finishes, leaving further connections queued in
+/// the kernel rather than spawning unbounded tasks.
+#[derive(Clone, Default)]
+pub struct ConnectionLimit {
+    // If unset, connections are not limited.
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+/// ConnectionPermit marks a connection as in flight until it is dropped. It should be held by the
+/// task serving the connection, so it is released however that task exits, including by panic.
+pub struct ConnectionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.metrics.inbound_connections_in_flight.dec();
+    }
+}
+
+impl ConnectionLimit {
+    pub fn new(max: Option<usize>) -> Self {
+        ConnectionLimit {
+            semaphore: max.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    /// acquire waits until another connection may be served.
+    pub async fn acquire(&self, metrics: &Arc<Metrics>) -> ConnectionPermit {
+        let permit = match &self.semaphore {
+            Some(semaphore) => {
+                let start = Instant::now();
+                let permit = semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed");
+                metrics
+                    .inbound_connection_limit_wait
+                    .observe(start.elapsed().as_secs_f64());
+                Some(permit)
+            }
+            None => None,
+        };
+        metrics.inbound_connections_in_flight.inc();
+        ConnectionPermit {
+            _permit: permit,
+            metrics: metrics.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use prometheus_client::registry::Registry;
+
+    #[tokio::test]
+    async fn saturated() {
+        let metrics = Arc::new(Metrics::new(&mut Registry::default()));
+        let limit = ConnectionLimit::new(Some(2));
+
+        let first = limit.acquire(&metrics).await;
+        let _second = limit.acquire(&metrics).await;
+        assert_eq!(metrics.inbound_connections_in_flight.get(), 2);
+
+        // The limit is reached, so the next connection has to wait.
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            let metrics = metrics.clone();
+            async move { limit.acquire(&metrics).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        let _third = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .expect("permit released")
+            .unwrap();
+        assert_eq!(metrics.inbound_connections_in_flight.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn released_on_panic() {
+        let metrics = Arc::new(Metrics::new(&mut Registry::default()));
+        let limit = ConnectionLimit::new(Some(1));
+
+        let permit = limit.acquire(&metrics).await;
+        let res = tokio::spawn(async move {
+            let _permit = permit;
+            panic!("connection task failed");
+        })
+        .await;
+        assert!(res.unwrap_err().is_panic());
+        assert_eq!(metrics.inbound_connections_in_flight.get(), 0);
+
+        tokio::time::timeout(Duration::from_secs(5), limit.acquire(&metrics))
+            .await
+            .expect("permit released");
+    }
+
+    #[tokio::test]
+    async fn unlimited() {
+        let metrics = Arc::new(Metrics::new(&mut Registry::default()));
+        let limit = ConnectionLimit::default();
+        let permits: Vec<_> =
+            futures::future::join_all((0..100).map(|_| limit.acquire(&metrics))).await;
+        assert_eq!(metrics.inbound_connections_in_flight.get(), 100);
+        drop(permits);
+        assert_eq!(metrics.inbound_connections_in_flight.get(), 0);
+    }
+}
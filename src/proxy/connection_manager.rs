@@ -13,21 +13,26 @@
 // limitations under the License.
 
 use crate::proxy::Error;
+use crate::proxy::circuit_breaker::{CircuitBreaker, CircuitBreakerDump};
+use crate::proxy::connection_limit::ConnectionLimit;
 
 use crate::state::DemandProxyState;
 use crate::state::ProxyRbacContext;
 use serde::{Serialize, Serializer};
+use std::collections::HashMap;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::net::SocketAddr;
+use std::time::Instant;
 
 use crate::drain;
 use crate::drain::{DrainTrigger, DrainWatcher};
+use crate::identity::Identity;
+use crate::proxy::metrics::ConnectionBytes;
 use crate::state::workload::Protocol;
-use std::sync::Arc;
 use std::sync::RwLock;
-use tracing::{debug, error, info, warn};
+use std::sync::{Arc, Weak};
+use tracing::{debug, error, info};
 
 struct ConnectionDrain {
     // TODO: this should almost certainly be changed to a type which has counted references exposed.
@@ -36,12 +41,21 @@ struct ConnectionDrain {
     tx: DrainTrigger,
     rx: DrainWatcher,
     count: usize,
+    opened: Instant,
+    // Byte counts of the connections sharing this entry, dropped once they are recorded.
+    bytes: Vec<Weak<ConnectionBytes>>,
 }
 
 impl ConnectionDrain {
     fn new() -> Self {
         let (tx, rx) = drain::new();
-        ConnectionDrain { tx, rx, count: 1 }
+        ConnectionDrain {
+            tx,
+            rx,
+            count: 1,
+            opened: Instant::now(),
+            bytes: Vec::new(),
+        }
     }
 
     /// drain drops the internal reference to rx and then signals drain on the tx
@@ -58,7 +72,14 @@ impl ConnectionDrain {
 #[derive(Clone)]
 pub struct ConnectionManager {
     drains: Arc<RwLock<HashMap<InboundConnection, ConnectionDrain>>>,
-    outbound_connections: Arc<RwLock<HashSet<OutboundConnection>>>,
+    // Tracked outbound connections, with when they were opened and their byte counts.
+    outbound_connections:
+        Arc<RwLock<HashMap<OutboundConnection, (Instant, Weak<ConnectionBytes>)>>>,
+    // Number of tracked inbound connections per source identity. Guarded by the drains lock.
+    identity_connections: Arc<RwLock<HashMap<Identity, usize>>>,
+    max_connections_per_identity: Option<usize>,
+    circuit_breaker: CircuitBreaker,
+    connection_limit: ConnectionLimit,
 }
 
 impl std::fmt::Debug for ConnectionManager {
@@ -69,10 +90,7 @@ impl std::fmt::Debug for ConnectionManager {
 
 impl Default for ConnectionManager {
     fn default() -> Self {
-        ConnectionManager {
-            drains: Arc::new(RwLock::new(HashMap::new())),
-            outbound_connections: Arc::new(RwLock::new(HashSet::new())),
-        }
+        ConnectionManager::new(None)
     }
 }
 
@@ -106,6 +124,13 @@ impl ConnectionGuard {
     pub fn release(self) {
         self.cm.release(&self.conn);
     }
+    /// track_bytes reports the connection's byte counts in the connection manager's snapshot.
+    pub fn track_bytes(&self, bytes: &Arc<ConnectionBytes>) {
+        if let Some(cd) = self.cm.drains.write().expect("mutex").get_mut(&self.conn) {
+            cd.bytes.retain(|b| b.strong_count() > 0);
+            cd.bytes.push(Arc::downgrade(bytes));
+        }
+    }
 }
 
 impl Drop for ConnectionGuard {
@@ -122,6 +147,21 @@ pub struct OutboundConnectionGuard {
     conn: OutboundConnection,
 }
 
+impl OutboundConnectionGuard {
+    /// track_bytes reports the connection's byte counts in the connection manager's snapshot.
+    pub fn track_bytes(&self, bytes: &Arc<ConnectionBytes>) {
+        if let Some((_, b)) = self
+            .cm
+            .outbound_connections
+            .write()
+            .expect("mutex")
+            .get_mut(&self.conn)
+        {
+            *b = Arc::downgrade(bytes);
+        }
+    }
+}
+
 impl Drop for OutboundConnectionGuard {
     fn drop(&mut self) {
         self.cm.release_outbound(&self.conn)
@@ -144,6 +184,21 @@ pub struct InboundConnectionDump {
     pub original_dst: Option<String>,
     pub actual_dst: SocketAddr,
     pub protocol: Protocol,
+    pub src_identity: Option<Identity>,
+    pub dst_identity: Identity,
+    pub age_secs: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundConnectionDump {
+    #[serde(flatten)]
+    pub conn: OutboundConnection,
+    pub age_secs: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, serde::Serialize)]
@@ -155,6 +210,40 @@ pub struct InboundConnection {
 }
 
 impl ConnectionManager {
+    /// Creates a new ConnectionManager. If `max_connections_per_identity` is set, inbound connections
+    /// from a source identity that already has that many tracked connections are rejected.
+    pub fn new(max_connections_per_identity: Option<usize>) -> Self {
+        ConnectionManager {
+            drains: Arc::new(RwLock::new(HashMap::new())),
+            outbound_connections: Arc::new(RwLock::new(HashMap::new())),
+            identity_connections: Arc::new(RwLock::new(HashMap::new())),
+            max_connections_per_identity,
+            circuit_breaker: CircuitBreaker::default(),
+            connection_limit: ConnectionLimit::default(),
+        }
+    }
+
+    /// with_circuit_breaker sets the breaker used to fast-fail connections to failing services.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    /// with_connection_limit sets the limit on inbound connections served at once. This is
+    /// typically shared with other proxies, to bound the total.
+    pub fn with_connection_limit(mut self, connection_limit: ConnectionLimit) -> Self {
+        self.connection_limit = connection_limit;
+        self
+    }
+
+    pub fn connection_limit(&self) -> &ConnectionLimit {
+        &self.connection_limit
+    }
+
     pub fn track_outbound(
         &self,
         src: SocketAddr,
@@ -172,7 +261,7 @@ impl ConnectionManager {
         self.outbound_connections
             .write()
             .expect("mutex")
-            .insert(c.clone());
+            .insert(c.clone(), (Instant::now(), Weak::new()));
 
         OutboundConnectionGuard {
             cm: self.clone(),
@@ -192,11 +281,7 @@ impl ConnectionManager {
             ctx: ctx.clone(),
             dest_service,
         };
-        let Some(watch) = self.register(&conn) else {
-            warn!("failed to track {conn:?}");
-            debug_assert!(false, "failed to track {conn:?}");
-            return Err(Error::ConnectionTrackingFailed);
-        };
+        let watch = self.register(&conn)?;
         if let Err(err) = state.assert_rbac(ctx).await {
             self.release(&conn);
             return Err(Error::AuthorizationPolicyRejection(err));
@@ -211,18 +296,48 @@ impl ConnectionManager {
     // this must be done before a connection can be tracked
     // allows policy to be asserted against the connection
     // even no tasks have a receiver channel yet
-    fn register(&self, c: &InboundConnection) -> Option<DrainWatcher> {
-        match self.drains.write().expect("mutex").entry(c.clone()) {
+    fn register(&self, c: &InboundConnection) -> Result<DrainWatcher, Error> {
+        let mut drains = self.drains.write().expect("mutex");
+        if let Some(id) = &c.ctx.conn.src_identity {
+            let mut counts = self.identity_connections.write().expect("mutex");
+            let count = counts.get(id).copied().unwrap_or_default();
+            if self
+                .max_connections_per_identity
+                .is_some_and(|max| count >= max)
+            {
+                debug!(identity=%id, count, "rejecting connection; identity at connection limit");
+                return Err(Error::ConnectionLimitExceeded(id.clone()));
+            }
+            counts.insert(id.clone(), count + 1);
+        }
+        match drains.entry(c.clone()) {
             Entry::Occupied(mut cd) => {
                 cd.get_mut().count += 1;
                 let rx = cd.get().rx.clone();
-                Some(rx)
+                Ok(rx)
             }
             Entry::Vacant(entry) => {
                 let drain = ConnectionDrain::new();
                 let rx = drain.rx.clone();
                 entry.insert(drain);
-                Some(rx)
+                Ok(rx)
+            }
+        }
+    }
+
+    // decrements the per-identity connection count by `n`.
+    // must be called while holding the drains lock so counts stay consistent with the tracked connections.
+    fn release_identity(&self, c: &InboundConnection, n: usize) {
+        let Some(id) = &c.ctx.conn.src_identity else {
+            return;
+        };
+        let mut counts = self.identity_connections.write().expect("mutex");
+        if let Entry::Occupied(mut e) = counts.entry(id.clone()) {
+            let remaining = e.get().saturating_sub(n);
+            if remaining == 0 {
+                e.remove();
+            } else {
+                *e.get_mut() = remaining;
             }
         }
     }
@@ -232,6 +347,7 @@ impl ConnectionManager {
     pub fn release(&self, c: &InboundConnection) {
         let mut drains = self.drains.write().expect("mutex");
         if let Some((k, mut v)) = drains.remove_entry(c) {
+            self.release_identity(&k, 1);
             if v.count > 1 {
                 // something else is tracking this connection, decrement count but retain
                 v.count -= 1;
@@ -246,7 +362,14 @@ impl ConnectionManager {
 
     // signal all connections listening to this channel to take action (typically terminate traffic)
     async fn close(&self, c: &InboundConnection) {
-        let drain = { self.drains.write().expect("mutex").remove(c) };
+        let drain = {
+            let mut drains = self.drains.write().expect("mutex");
+            let drain = drains.remove(c);
+            if let Some(cd) = &drain {
+                self.release_identity(c, cd.count);
+            }
+            drain
+        };
         if let Some(cd) = drain {
             cd.drain().await;
         } else {
@@ -255,6 +378,34 @@ impl ConnectionManager {
         }
     }
 
+    /// drain_identity closes every tracked inbound connection that `id` is the source or destination
+    /// of, and returns how many were closed.
+    pub async fn drain_identity(&self, id: &Identity) -> usize {
+        let drains: Vec<_> = {
+            let mut drains = self.drains.write().expect("mutex");
+            let matching: Vec<_> = drains
+                .keys()
+                .filter(|c| {
+                    c.ctx.conn.src_identity.as_ref() == Some(id)
+                        || &c.ctx.dest_workload.identity() == id
+                })
+                .cloned()
+                .collect();
+            matching
+                .into_iter()
+                .filter_map(|c| {
+                    let cd = drains.remove(&c)?;
+                    self.release_identity(&c, cd.count);
+                    Some(cd)
+                })
+                .collect()
+        };
+        let closed = drains.iter().map(|cd| cd.count).sum();
+        info!(identity=%id, closed, "draining connections for identity");
+        futures::future::join_all(drains.into_iter().map(ConnectionDrain::drain)).await;
+        closed
+    }
+
     //  get a list of all connections being tracked
     pub fn connections(&self) -> Vec<InboundConnection> {
         // potentially large copy under read lock, could require optimization
@@ -262,32 +413,44 @@ impl ConnectionManager {
     }
 }
 
-#[derive(serde::Serialize)]
-struct ConnectionManagerDump {
-    inbound: Vec<InboundConnectionDump>,
-    outbound: Vec<OutboundConnection>,
+#[derive(Debug, serde::Serialize)]
+pub struct ConnectionManagerDump {
+    pub inbound: Vec<InboundConnectionDump>,
+    pub outbound: Vec<OutboundConnectionDump>,
+    pub circuit_breakers: Vec<CircuitBreakerDump>,
 }
 
-impl Serialize for ConnectionManager {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+impl ConnectionManager {
+    /// snapshot copies the currently tracked connections. The internal locks are only held while
+    /// copying, so the result can be serialized or inspected at leisure.
+    pub fn snapshot(&self) -> ConnectionManagerDump {
+        let now = Instant::now();
         let inbound: Vec<_> = self
             .drains
             .read()
             .expect("mutex")
-            .keys()
-            .cloned()
-            .map(|c| InboundConnectionDump {
-                src: c.ctx.conn.src,
-                original_dst: c.dest_service,
-                actual_dst: c.ctx.conn.dst,
-                protocol: if c.ctx.conn.src_identity.is_some() {
-                    Protocol::HBONE
-                } else {
-                    Protocol::TCP
-                },
+            .iter()
+            .map(|(c, cd)| {
+                let (bytes_sent, bytes_received) = cd
+                    .bytes
+                    .iter()
+                    .filter_map(Weak::upgrade)
+                    .fold((0, 0), |(s, r), b| (s + b.sent(), r + b.recv()));
+                InboundConnectionDump {
+                    src: c.ctx.conn.src,
+                    original_dst: c.dest_service.clone(),
+                    actual_dst: c.ctx.conn.dst,
+                    protocol: if c.ctx.conn.src_identity.is_some() {
+                        Protocol::HBONE
+                    } else {
+                        Protocol::TCP
+                    },
+                    src_identity: c.ctx.conn.src_identity.clone(),
+                    dst_identity: c.ctx.dest_workload.identity(),
+                    age_secs: now.duration_since(cd.opened).as_secs(),
+                    bytes_sent,
+                    bytes_received,
+                }
             })
             .collect();
         let outbound: Vec<_> = self
@@ -295,10 +458,40 @@ impl Serialize for ConnectionManager {
             .read()
             .expect("mutex")
             .iter()
-            .cloned()
+            .map(|(c, (opened, bytes))| {
+                let bytes = bytes.upgrade();
+                OutboundConnectionDump {
+                    conn: c.clone(),
+                    age_secs: now.duration_since(*opened).as_secs(),
+                    bytes_sent: bytes.as_ref().map_or(0, |b| b.sent()),
+                    bytes_received: bytes.as_ref().map_or(0, |b| b.recv()),
+                }
+            })
             .collect();
-        let dump = ConnectionManagerDump { inbound, outbound };
-        dump.serialize(serializer)
+        ConnectionManagerDump {
+            inbound,
+            outbound,
+            circuit_breakers: self.circuit_breaker.snapshot(),
+        }
+    }
+}
+
+impl Serialize for ConnectionManager {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+impl crate::admin::AdminHandler for ConnectionManager {
+    fn key(&self) -> &'static str {
+        "connections"
+    }
+
+    fn handle(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::to_value(self.snapshot())?)
     }
 }
 
@@ -352,7 +545,10 @@ mod tests {
     use std::sync::{Arc, RwLock};
     use std::time::Duration;
 
+    use crate::identity::Identity;
+    use crate::proxy::Error;
     use crate::rbac::Connection;
+    use crate::state::workload::Protocol;
     use crate::state::{DemandProxyState, ProxyState};
     use crate::test_helpers::test_default_workload;
     use crate::xds::ProxyStateUpdateMutator;
@@ -571,6 +767,183 @@ mod tests {
         assert_eq!(cm.connections().len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_connection_manager_identity_limit() {
+        let cm = ConnectionManager::new(Some(2));
+        let identity = Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: "client".into(),
+        };
+        let conn = |port: u16| InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src_identity: Some(identity.clone()),
+                    src: std::net::SocketAddr::new(Ipv4Addr::new(192, 168, 0, 1).into(), port),
+                    dst_network: "".into(),
+                    dst: std::net::SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::new(192, 168, 0, 2),
+                        8080,
+                    )),
+                },
+                dest_workload: Arc::new(test_default_workload()),
+            },
+            dest_service: None,
+        };
+        let register = |cm: &ConnectionManager, c: &InboundConnection| {
+            cm.register(c).map(|watch| ConnectionGuard {
+                cm: cm.clone(),
+                conn: c.clone(),
+                watch: Some(watch),
+            })
+        };
+
+        let close1 = register(&cm, &conn(1)).unwrap();
+        let close2 = register(&cm, &conn(2)).unwrap();
+        // the identity is at its limit, so the next connection is rejected
+        assert!(matches!(
+            register(&cm, &conn(3)),
+            Err(Error::ConnectionLimitExceeded(id)) if id == identity
+        ));
+        assert_eq!(cm.connections().len(), 2);
+
+        // releasing a connection frees up a slot
+        close1.release();
+        let mut close3 = register(&cm, &conn(3)).unwrap();
+
+        // closing a connection due to policy also frees up its slot
+        tokio::spawn(assert_close(close3.watch.take().unwrap()));
+        cm.close(&conn(3)).await;
+        let close4 = register(&cm, &conn(4)).unwrap();
+        assert!(register(&cm, &conn(5)).is_err());
+
+        drop(close2);
+        drop(close4);
+        assert_eq!(cm.connections().len(), 0);
+        assert!(cm.identity_connections.read().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_manager_drain_identity() {
+        let cm = ConnectionManager::default();
+        let identity = |sa: &str| Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: sa.into(),
+        };
+        let conn = |sa: &str, port: u16| InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src_identity: Some(identity(sa)),
+                    src: std::net::SocketAddr::new(Ipv4Addr::new(192, 168, 0, 1).into(), port),
+                    dst_network: "".into(),
+                    dst: std::net::SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::new(192, 168, 0, 2),
+                        8080,
+                    )),
+                },
+                dest_workload: Arc::new(test_default_workload()),
+            },
+            dest_service: None,
+        };
+
+        let a1 = cm.register(&conn("a", 1)).unwrap();
+        let a2 = cm.register(&conn("a", 2)).unwrap();
+        let b1 = cm.register(&conn("b", 3)).unwrap();
+
+        // Only connections from the drained identity are closed.
+        tokio::spawn(assert_close(a1));
+        tokio::spawn(assert_close(a2));
+        assert_eq!(cm.drain_identity(&identity("a")).await, 2);
+        assert_eq!(cm.connections(), vec![conn("b", 3)]);
+        assert_eq!(cm.drain_identity(&identity("a")).await, 0);
+
+        // Connections are also matched by their destination identity.
+        tokio::spawn(assert_close(b1));
+        assert_eq!(
+            cm.drain_identity(&test_default_workload().identity()).await,
+            1
+        );
+        assert!(cm.connections().is_empty());
+        assert!(cm.identity_connections.read().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_manager_snapshot() {
+        let cm = ConnectionManager::default();
+        let identity = Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: "client".into(),
+        };
+        let src = std::net::SocketAddr::new(Ipv4Addr::new(192, 168, 0, 1).into(), 1);
+        let dst = std::net::SocketAddr::new(Ipv4Addr::new(192, 168, 0, 2).into(), 8080);
+        let conn = InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src_identity: Some(identity.clone()),
+                    src,
+                    dst_network: "".into(),
+                    dst,
+                },
+                dest_workload: Arc::new(test_default_workload()),
+            },
+            dest_service: None,
+        };
+        let guard = ConnectionGuard {
+            cm: cm.clone(),
+            conn: conn.clone(),
+            watch: Some(cm.register(&conn).unwrap()),
+        };
+        let outbound = cm.track_outbound(src, dst, dst, Protocol::TCP);
+        let result = crate::proxy::metrics::ConnectionResult::new(
+            src,
+            dst,
+            None,
+            Instant::now(),
+            crate::proxy::metrics::ConnectionOpen {
+                reporter: crate::proxy::Reporter::destination,
+                source: None,
+                derived_source: None,
+                destination: None,
+                connection_security_policy: crate::proxy::metrics::SecurityPolicy::unknown,
+                destination_service: None,
+            },
+            crate::test_helpers::helpers::test_proxy_metrics(),
+        );
+        guard.track_bytes(&result.bytes());
+        outbound.track_bytes(&result.bytes());
+        result.increment_send(10);
+        result.increment_recv(20);
+
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.inbound.len(), 1);
+        assert_eq!(snapshot.inbound[0].src, src);
+        assert_eq!(snapshot.inbound[0].src_identity, Some(identity));
+        assert_eq!(
+            (
+                snapshot.inbound[0].bytes_sent,
+                snapshot.inbound[0].bytes_received
+            ),
+            (10, 20)
+        );
+        assert_eq!(snapshot.outbound.len(), 1);
+        assert_eq!(snapshot.outbound[0].conn.actual_dst, dst);
+        assert_eq!(
+            (
+                snapshot.outbound[0].bytes_sent,
+                snapshot.outbound[0].bytes_received
+            ),
+            (10, 20)
+        );
+
+        guard.release();
+        drop(outbound);
+        let snapshot = cm.snapshot();
+        assert!(snapshot.inbound.is_empty());
+        assert!(snapshot.outbound.is_empty());
+    }
+
     #[tokio::test]
     async fn test_policy_watcher_lifecycle() {
         // preamble: setup an environment
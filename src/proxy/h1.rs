@@ -0,0 +1,176 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::drain::DrainWatcher;
+use crate::proxy::Error;
+use bytes::Bytes;
+use http::Response;
+use http::request::Parts;
+use http_body_util::Empty;
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{oneshot, watch};
+use tracing::{Instrument, debug};
+
+/// H1Request is a CONNECT request received over HTTP/1.1, for peers that cannot speak HTTP/2.
+/// Once the response is sent the whole connection becomes the tunnel, so there is no multiplexing.
+pub struct H1Request {
+    request: Parts,
+    upgrade: OnUpgrade,
+    respond: oneshot::Sender<Response<()>>,
+}
+
+impl Debug for H1Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("H1Request")
+            .field("request", &self.request)
+            .finish()
+    }
+}
+
+impl H1Request {
+    pub fn send_error(self, resp: Response<()>) -> Result<(), Error> {
+        self.respond
+            .send(resp)
+            .map_err(|_| Error::ClientDisconnected)
+    }
+
+    pub async fn send_response(self, resp: Response<()>) -> Result<TokioIo<Upgraded>, Error> {
+        let H1Request {
+            upgrade, respond, ..
+        } = self;
+        respond.send(resp).map_err(|_| Error::ClientDisconnected)?;
+        let upgraded = upgrade.await.map_err(|e| Error::Generic(Box::new(e)))?;
+        Ok(TokioIo::new(upgraded))
+    }
+
+    pub fn get_request(&self) -> &Parts {
+        &self.request
+    }
+
+    pub fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        self.request.headers()
+    }
+}
+
+pub async fn serve_connection<S, F, Fut>(
+    s: S,
+    drain: DrainWatcher,
+    mut force_shutdown: watch::Receiver<()>,
+    handler: F,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(H1Request) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handler_drain = drain.clone();
+    let handler_shutdown = force_shutdown.clone();
+    let service =
+        hyper::service::service_fn(move |mut req: hyper::Request<hyper::body::Incoming>| {
+            let upgrade = hyper::upgrade::on(&mut req);
+            let (request, _body) = req.into_parts();
+            let (respond, response) = oneshot::channel();
+            let handle = handler(H1Request {
+                request,
+                upgrade,
+                respond,
+            });
+            // The tunnel outlives the HTTP connection once upgraded, so it holds on to drain itself.
+            let drain = handler_drain.clone();
+            let mut force_shutdown = handler_shutdown.clone();
+            tokio::task::spawn(
+                async move {
+                    tokio::select! {
+                        _ = force_shutdown.changed() => {}
+                        _ = handle => {}
+                    }
+                    drop(drain);
+                }
+                .in_current_span(),
+            );
+            async move {
+                // The handler always responds; if it did not, the tunnel could not be established.
+                let resp = response.await.unwrap_or_else(|_| {
+                    Response::builder()
+                        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+                        .body(())
+                        .expect("builder with known status code should not fail")
+                });
+                Ok::<_, Infallible>(resp.map(|()| Empty::<Bytes>::new()))
+            }
+        });
+    let conn = crate::hyper_util::http1_server()
+        .serve_connection(TokioIo::new(s), service)
+        .with_upgrades();
+    let mut conn = std::pin::pin!(conn);
+    tokio::select! {
+        res = &mut conn => return res.map_err(|e| Error::Generic(Box::new(e))),
+        _shutdown = drain.wait_for_drain() => {
+            debug!("starting graceful drain...");
+            conn.as_mut().graceful_shutdown();
+        }
+    }
+    tokio::select! {
+        _ = force_shutdown.changed() => Err(Error::DrainTimeOut),
+        res = conn => res.map_err(|e| Error::Generic(Box::new(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drain;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn connect_upgrade() {
+        let (client, server) = tokio::io::duplex(4096);
+        let (_trigger, watcher) = drain::new();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(());
+        let handler = |req: H1Request| async move {
+            assert_eq!(req.get_request().method, http::Method::CONNECT);
+            assert_eq!(req.get_request().uri, "127.0.0.1:8080");
+            let resp = Response::builder()
+                .status(http::StatusCode::OK)
+                .body(())
+                .unwrap();
+            let stream = req.send_response(resp).await.unwrap();
+            let (mut r, mut w) = tokio::io::split(stream);
+            tokio::io::copy(&mut r, &mut w).await.unwrap();
+        };
+        tokio::spawn(serve_connection(server, watcher, shutdown_rx, handler));
+
+        let mut client = client;
+        client
+            .write_all(b"CONNECT 127.0.0.1:8080 HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n\r\n")
+            .await
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
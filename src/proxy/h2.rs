@@ -30,20 +30,26 @@ pub mod server;
 
 async fn do_ping_pong(
     mut ping_pong: h2::PingPong,
-    tx: oneshot::Sender<()>,
+    mut tx: oneshot::Sender<()>,
     dropped: Arc<AtomicBool>,
+    interval: Option<Duration>,
+    ping_timeout: Duration,
 ) {
-    const PING_INTERVAL: Duration = Duration::from_secs(10);
-    const PING_TIMEOUT: Duration = Duration::from_secs(20);
+    let Some(interval) = interval else {
+        // Keepalive is disabled. Hold on to tx until the connection is gone, so it is never torn down
+        // on our account.
+        tx.closed().await;
+        return;
+    };
     // delay before sending the first ping, no need to race with the first request
-    tokio::time::sleep(PING_INTERVAL).await;
+    tokio::time::sleep(interval).await;
     loop {
         if dropped.load(Ordering::Relaxed) {
             return;
         }
         let ping_fut = ping_pong.ping(h2::Ping::opaque());
         log::trace!("ping sent");
-        match tokio::time::timeout(PING_TIMEOUT, ping_fut).await {
+        match tokio::time::timeout(ping_timeout, ping_fut).await {
             Err(_) => {
                 // We will log this again up in drive_connection, so don't worry about a high log level
                 log::trace!("ping timeout");
@@ -53,7 +59,7 @@ async fn do_ping_pong(
             Ok(r) => match r {
                 Ok(_) => {
                     log::trace!("pong received");
-                    tokio::time::sleep(PING_INTERVAL).await;
+                    tokio::time::sleep(interval).await;
                 }
                 Err(e) => {
                     if dropped.load(Ordering::Relaxed) {
@@ -26,11 +26,10 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
 use tokio::sync::oneshot;
 use tokio::sync::watch::Receiver;
-use tokio_rustls::client::TlsStream;
 use tracing::{Instrument, debug, error, trace, warn};
 
 #[derive(Debug, Clone)]
@@ -40,6 +39,9 @@ pub struct H2ConnectClient {
     pub max_allowed_streams: u16,
     stream_count: Arc<AtomicU16>,
     wl_key: WorkloadKey,
+    // When the certificate the connection was established with gets close to expiring; after this
+    // the connection should not take new streams.
+    rotate_at: Option<SystemTime>,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -52,6 +54,11 @@ pub struct WorkloadKey {
     // Because we spoof the source IP, we need to key on this as well. Note: for in-pod its already per-pod
     // pools anyways.
     pub src: IpAddr,
+    // Connections are marked with a single DSCP value, so they can only be shared by requests that
+    // want the same one.
+    pub dscp: Option<u8>,
+    // HTTP/2 windows to use for the connection, if overridden for the destination.
+    pub window_sizes: Option<config::WindowSizes>,
 }
 
 impl Display for WorkloadKey {
@@ -85,6 +92,17 @@ impl H2ConnectClient {
         future_count >= self.max_allowed_streams
     }
 
+    // rotate_at stops the connection from being reused at the given time. Streams already open on it
+    // are unaffected.
+    pub fn rotate_at(&mut self, at: SystemTime) {
+        self.rotate_at = Some(at);
+    }
+
+    // needs_rotation checks if the connection's certificate is too close to expiring to reuse it
+    pub fn needs_rotation(&self) -> bool {
+        self.rotate_at.is_some_and(|at| SystemTime::now() >= at)
+    }
+
     pub fn ready_to_use(&mut self) -> bool {
         let cx = &mut Context::from_waker(futures::task::noop_waker_ref());
         match self.sender.poll_ready(cx) {
@@ -137,7 +155,7 @@ impl H2ConnectClient {
         // We should always be ready though, because we make sure we don't go over the max stream limit out of band.
         futures::future::poll_fn(|cx| self.sender.poll_ready(cx)).await?;
         let (response, stream) = self.sender.send_request(req, false)?;
-        let response = response.await?;
+        let response = response.await.map_err(upstream_error)?;
         if response.status() != 200 {
             return Err(Error::HttpStatus(response.status()));
         }
@@ -145,21 +163,36 @@ impl H2ConnectClient {
     }
 }
 
-pub async fn spawn_connection(
+// upstream_error distinguishes the peer resetting our stream from other HTTP/2 errors.
+fn upstream_error(e: h2::Error) -> Error {
+    match e.reason() {
+        Some(reason) if e.is_reset() && e.is_remote() => Error::UpstreamReset(reason),
+        _ => Error::H2(e),
+    }
+}
+
+pub async fn spawn_connection<S>(
     cfg: Arc<config::Config>,
-    s: TlsStream<TcpStream>,
+    s: S,
     driver_drain: Receiver<bool>,
     wl_key: WorkloadKey,
-) -> Result<H2ConnectClient, Error> {
+) -> Result<H2ConnectClient, Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let window = wl_key.window_sizes.unwrap_or(config::WindowSizes {
+        window_size: cfg.window_size,
+        connection_window_size: cfg.connection_window_size,
+    });
     let mut builder = h2::client::Builder::new();
     builder
-        .initial_window_size(cfg.window_size)
-        .initial_connection_window_size(cfg.connection_window_size)
+        .initial_window_size(window.window_size)
+        .initial_connection_window_size(window.connection_window_size)
         .max_frame_size(cfg.frame_size)
         .initial_max_send_streams(cfg.pool_max_streams_per_conn as usize)
         .max_header_list_size(1024 * 16)
         // 4mb. Aligned with window_size such that we can fill up the buffer, then flush it all in one go, without buffering up too much.
-        .max_send_buffer_size(cfg.window_size as usize)
+        .max_send_buffer_size(window.window_size as usize)
         .enable_push(false);
 
     let (send_req, connection) = builder
@@ -175,12 +208,20 @@ pub async fn spawn_connection(
             .try_into()
             .unwrap_or(u16::MAX),
     );
+    let keepalive_interval = cfg.http2_keepalive_interval;
+    let keepalive_timeout = cfg.http2_keepalive_timeout;
     // spawn a task to poll the connection and drive the HTTP state
     // if we got a drain for that connection, respect it in a race
     // it is important to have a drain here, or this connection will never terminate
     tokio::spawn(
         async move {
-            drive_connection(connection, driver_drain).await;
+            drive_connection(
+                connection,
+                driver_drain,
+                keepalive_interval,
+                keepalive_timeout,
+            )
+            .await;
         }
         .in_current_span(),
     );
@@ -190,12 +231,17 @@ pub async fn spawn_connection(
         stream_count: Arc::new(AtomicU16::new(0)),
         max_allowed_streams,
         wl_key,
+        rotate_at: None,
     };
     Ok(c)
 }
 
-async fn drive_connection<S, B>(mut conn: Connection<S, B>, mut driver_drain: Receiver<bool>)
-where
+async fn drive_connection<S, B>(
+    mut conn: Connection<S, B>,
+    mut driver_drain: Receiver<bool>,
+    keepalive_interval: Option<Duration>,
+    keepalive_timeout: Duration,
+) where
     S: AsyncRead + AsyncWrite + Send + Unpin,
     B: Buf,
 {
@@ -207,7 +253,14 @@ where
     // for this fn to inform ping to give up when it is already dropped
     let dropped = Arc::new(AtomicBool::new(false));
     tokio::task::spawn(
-        super::do_ping_pong(ping_pong, ping_drop_tx, dropped.clone()).in_current_span(),
+        super::do_ping_pong(
+            ping_pong,
+            ping_drop_tx,
+            dropped.clone(),
+            keepalive_interval,
+            keepalive_timeout,
+        )
+        .in_current_span(),
     );
 
     tokio::select! {
@@ -231,3 +284,65 @@ where
     // Signal to the ping_pong it should also stop.
     dropped.store(true, Ordering::Relaxed);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn window_size_override() {
+        let window_sizes = config::WindowSizes {
+            window_size: 100_000,
+            connection_window_size: 200_000,
+        };
+        let cfg = Arc::new(config::parse_config().unwrap());
+        assert_ne!(cfg.window_size, window_sizes.window_size);
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (_drain_tx, drain_rx) = tokio::sync::watch::channel(false);
+        let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/default").unwrap();
+        let wl_key = WorkloadKey {
+            src_id: id.clone(),
+            dst_id: vec![id],
+            dst: "127.0.0.1:15008".parse().unwrap(),
+            src: "127.0.0.1".parse().unwrap(),
+            dscp: None,
+            window_sizes: Some(window_sizes),
+        };
+
+        let server = tokio::spawn(async move {
+            let mut conn = h2::server::handshake(server_io).await.unwrap();
+            let (_req, mut respond) = conn.accept().await.unwrap().unwrap();
+            tokio::spawn(async move { while conn.accept().await.is_some() {} });
+            let mut send = respond
+                .send_response(http::Response::new(()), false)
+                .unwrap();
+            // The stream may send as much as the client's windows allow, no more.
+            send.reserve_capacity(1 << 20);
+            while send.capacity() < window_sizes.window_size as usize {
+                if futures::future::poll_fn(|cx| send.poll_capacity(cx))
+                    .await
+                    .is_none()
+                {
+                    break;
+                }
+            }
+            send.capacity()
+        });
+
+        let mut client = spawn_connection(cfg, client_io, drain_rx, wl_key)
+            .await
+            .unwrap();
+        let req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri("127.0.0.1:80")
+            .body(())
+            .unwrap();
+        let _stream = client.send_request(req).await.unwrap();
+        let capacity = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(capacity, window_sizes.window_size as usize);
+    }
+}
@@ -15,6 +15,7 @@
 use crate::config;
 use crate::drain::DrainWatcher;
 use crate::proxy::Error;
+use crate::proxy::metrics::Metrics;
 use bytes::Bytes;
 use futures_util::FutureExt;
 use http::Response;
@@ -23,7 +24,7 @@ use std::fmt::Debug;
 use std::future::Future;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::{oneshot, watch};
 use tracing::{Instrument, debug};
 
@@ -94,29 +95,38 @@ impl RequestParts for Parts {
     }
 }
 
-pub async fn serve_connection<F, Fut>(
+pub async fn serve_connection<S, F, Fut>(
     cfg: Arc<config::Config>,
-    s: tokio_rustls::server::TlsStream<TcpStream>,
+    metrics: Arc<Metrics>,
+    window_sizes: Option<config::WindowSizes>,
+    s: S,
     drain: DrainWatcher,
     mut force_shutdown: watch::Receiver<()>,
     handler: F,
 ) -> Result<(), Error>
 where
+    S: AsyncRead + AsyncWrite + Unpin,
     F: Fn(H2Request) -> Fut,
     Fut: Future<Output = ()> + Send + 'static,
 {
+    let window = window_sizes.unwrap_or(config::WindowSizes {
+        window_size: cfg.window_size,
+        connection_window_size: cfg.connection_window_size,
+    });
     let mut builder = h2::server::Builder::new();
     let mut conn = builder
-        .initial_window_size(cfg.window_size)
-        .initial_connection_window_size(cfg.connection_window_size)
+        .initial_window_size(window.window_size)
+        .initial_connection_window_size(window.connection_window_size)
         .max_frame_size(cfg.frame_size)
-        // 64KB max; default is 16MB driven from Golang's defaults
+        // 64KB by default; h2's default is 16MB driven from Golang's defaults
         // Since we know we are going to receive a bounded set of headers, more is overkill.
-        .max_header_list_size(65536)
+        // h2 answers header blocks over this limit with a 431 itself, without telling us, so it
+        // only bounds buffering; requests over the configured limit are rejected below, where they
+        // can be counted.
+        .max_header_list_size(cfg.max_header_list_size.saturating_mul(2))
         // 400kb, default from hyper
         .max_send_buffer_size(1024 * 400)
-        // default from hyper
-        .max_concurrent_streams(200)
+        .max_concurrent_streams(cfg.max_concurrent_streams)
         .handshake(s)
         .await?;
 
@@ -131,12 +141,21 @@ where
         ping_pong,
         ping_drop_tx,
         dropped.clone(),
+        cfg.http2_keepalive_interval,
+        cfg.http2_keepalive_timeout,
     ));
 
     let handler = |req| handler(req).map(|_| ());
     loop {
         let drain = drain.clone();
         tokio::select! {
+            // Check for drain first, so no new streams are handled once it has started.
+            biased;
+            _shutdown = drain.wait_for_drain() => {
+                debug!("starting graceful drain...");
+                conn.graceful_shutdown();
+                break;
+            }
             request = conn.accept() => {
                 let Some(request) = request else {
                     // done!
@@ -144,8 +163,13 @@ where
                     dropped.store(true, Ordering::Relaxed);
                     return Ok(());
                 };
-                let (request, send) = request?;
+                let (request, mut send) = request?;
                 let (request, recv) = request.into_parts();
+                if header_list_size(&request.headers) > cfg.max_header_list_size as usize {
+                    metrics.oversized_headers_rejected.inc();
+                    let _ = send.send_response(oversized_headers_response(), true);
+                    continue;
+                }
                 let req = H2Request {
                     request,
                     recv,
@@ -164,16 +188,29 @@ where
                 conn.abrupt_shutdown(h2::Reason::NO_ERROR);
                 break
             }
-            _shutdown = drain.wait_for_drain() => {
-                debug!("starting graceful drain...");
-                conn.graceful_shutdown();
-                break;
-            }
         }
     }
     // Signal to the ping_pong it should also stop.
     dropped.store(true, Ordering::Relaxed);
-    let poll_closed = futures_util::future::poll_fn(move |cx| conn.poll_closed(cx));
+    // Keep driving the connection until it closes; established tunnels continue to be served.
+    // Streams the client opened before it saw our GOAWAY are rejected, so it can retry elsewhere.
+    let poll_closed = async move {
+        loop {
+            match conn.accept().await {
+                Some(Ok((_, mut send))) => {
+                    let _ = send.send_response(draining_response(), true);
+                }
+                Some(Err(e)) => {
+                    // Dropping the connection here would cut off every tunnel on it, so keep
+                    // driving it until they are done.
+                    debug!("failed to accept stream while draining: {e}");
+                    let _ = futures_util::future::poll_fn(|cx| conn.poll_closed(cx)).await;
+                    return;
+                }
+                None => return,
+            }
+        }
+    };
     tokio::select! {
         _ = force_shutdown.changed() => {
             return Err(Error::DrainTimeOut)
@@ -184,3 +221,239 @@ where
     drop(drain);
     Ok(())
 }
+
+/// header_list_size returns the size of the headers as HTTP/2 accounts for them: the length of each
+/// name and value, plus 32 bytes of overhead per field.
+pub fn header_list_size(headers: &http::HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(k, v)| k.as_str().len() + v.len() + 32)
+        .sum()
+}
+
+fn oversized_headers_response() -> Response<()> {
+    Response::builder()
+        .status(http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        .body(())
+        .expect("builder with known status code should not fail")
+}
+
+fn draining_response() -> Response<()> {
+    Response::builder()
+        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+        .header(http::header::RETRY_AFTER, "1")
+        .body(())
+        .expect("builder with known status code should not fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn max_concurrent_streams() {
+        let cfg = Arc::new(config::Config {
+            max_concurrent_streams: 2,
+            ..config::parse_config().unwrap()
+        });
+        let (_drain_tx, drain_rx) = crate::drain::new();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(());
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let handler_accepted = accepted.clone();
+        let handler = move |req: H2Request| {
+            let accepted = handler_accepted.clone();
+            async move {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                // Hold the stream open for the remainder of the test
+                std::future::pending::<()>().await;
+                drop(req);
+            }
+        };
+        tokio::spawn(serve_connection(
+            cfg,
+            crate::test_helpers::helpers::test_proxy_metrics(),
+            None,
+            server_io,
+            drain_rx,
+            shutdown_rx,
+            handler,
+        ));
+
+        let (client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(conn);
+        for _ in 0..3 {
+            let client = client.clone();
+            tokio::spawn(async move {
+                let mut client = client.ready().await.unwrap();
+                let req = http::Request::builder()
+                    .method(http::Method::CONNECT)
+                    .uri("127.0.0.1:80")
+                    .body(())
+                    .unwrap();
+                let (resp, send) = client.send_request(req, false).unwrap();
+                let _ = resp.await;
+                drop(send);
+            });
+        }
+
+        // Only the first two streams may proceed; the third waits for one to complete.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn window_size_override() {
+        let window_sizes = config::WindowSizes {
+            window_size: 100_000,
+            connection_window_size: 200_000,
+        };
+        let cfg = Arc::new(config::parse_config().unwrap());
+        assert_ne!(cfg.window_size, window_sizes.window_size);
+        let (_drain_tx, drain_rx) = crate::drain::new();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(());
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        let handler = |req: H2Request| async move {
+            let _stream = req.send_response(Response::new(())).await;
+            // Hold the stream open for the remainder of the test
+            std::future::pending::<()>().await;
+        };
+        tokio::spawn(serve_connection(
+            cfg,
+            crate::test_helpers::helpers::test_proxy_metrics(),
+            Some(window_sizes),
+            server_io,
+            drain_rx,
+            shutdown_rx,
+            handler,
+        ));
+
+        let (client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri("127.0.0.1:80")
+            .body(())
+            .unwrap();
+        let (resp, mut send) = client.send_request(req, false).unwrap();
+        assert_eq!(resp.await.unwrap().status(), http::StatusCode::OK);
+
+        // The stream may send as much as the server's windows allow, no more.
+        send.reserve_capacity(1 << 20);
+        let fill = async {
+            while send.capacity() < window_sizes.window_size as usize {
+                if futures_util::future::poll_fn(|cx| send.poll_capacity(cx))
+                    .await
+                    .is_none()
+                {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), fill)
+            .await
+            .unwrap();
+        assert_eq!(send.capacity(), window_sizes.window_size as usize);
+    }
+
+    #[tokio::test]
+    async fn reject_oversized_headers() {
+        let cfg = Arc::new(config::Config {
+            max_header_list_size: 1024,
+            ..config::parse_config().unwrap()
+        });
+        let (_drain_tx, drain_rx) = crate::drain::new();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(());
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        // Send the request before the server's settings arrive, so the client cannot hold it back.
+        let (client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri("127.0.0.1:80")
+            .header("baggage", "a".repeat(1500))
+            .body(())
+            .unwrap();
+        let (resp, _send) = client.send_request(req, false).unwrap();
+
+        let metrics = crate::test_helpers::helpers::test_proxy_metrics();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let handler_accepted = accepted.clone();
+        let handler = move |req: H2Request| {
+            let accepted = handler_accepted.clone();
+            async move {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let _ = req.send_error(Response::new(()));
+            }
+        };
+        tokio::spawn(serve_connection(
+            cfg,
+            metrics.clone(),
+            None,
+            server_io,
+            drain_rx,
+            shutdown_rx,
+            handler,
+        ));
+
+        let resp = resp.await.unwrap();
+        assert_eq!(
+            resp.status(),
+            http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        assert_eq!(accepted.load(Ordering::SeqCst), 0);
+        assert_eq!(metrics.oversized_headers_rejected.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn reject_streams_during_drain() {
+        let cfg = Arc::new(config::parse_config().unwrap());
+        let (drain_tx, drain_rx) = crate::drain::new();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(());
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        // Open a stream before the server is running, so it arrives once draining has started.
+        let (client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri("127.0.0.1:80")
+            .body(())
+            .unwrap();
+        let (resp, _send) = client.send_request(req, false).unwrap();
+
+        tokio::spawn(drain_tx.start_drain_and_wait(crate::drain::DrainMode::Graceful));
+        tokio::task::yield_now().await;
+
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let handler_accepted = accepted.clone();
+        let handler = move |_req: H2Request| {
+            let accepted = handler_accepted.clone();
+            async move {
+                accepted.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        tokio::spawn(serve_connection(
+            cfg,
+            crate::test_helpers::helpers::test_proxy_metrics(),
+            None,
+            server_io,
+            drain_rx,
+            shutdown_rx,
+            handler,
+        ));
+
+        let resp = resp.await.unwrap();
+        assert_eq!(resp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[http::header::RETRY_AFTER], "1");
+        assert_eq!(accepted.load(Ordering::SeqCst), 0);
+    }
+}
@@ -12,32 +12,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use futures_util::TryFutureExt;
+use http::request::Parts;
 use http::{Method, Response, StatusCode};
+use std::fmt::Debug;
+use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Instant;
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::sync::watch;
 
 use tracing::{Instrument, debug, info, info_span, trace_span};
 
 use super::{ConnectionResult, Error, HboneAddress, LocalWorkloadInformation, ResponseFlags};
-use crate::baggage::parse_baggage_header;
+use crate::baggage::parse_baggage_header_with_labels;
 use crate::identity::Identity;
 
-use crate::config::Config;
+use crate::config::{Config, WindowSizes};
 use crate::drain::DrainWatcher;
 use crate::proxy::h2::server::{H2Request, RequestParts};
-use crate::proxy::metrics::{ConnectionOpen, Reporter};
-use crate::proxy::{BAGGAGE_HEADER, ProxyInputs, TRACEPARENT_HEADER, TraceParent, metrics};
+use crate::proxy::metrics::{
+    ConnectFailureCause, ConnectionOpen, DenyReason, Reporter, TlsHandshakeFailureCause, TlsInfo,
+};
+use crate::proxy::{
+    BAGGAGE_HEADER, DENY_REASON_HEADER, ProxyInputs, SocketFactory, TRACEPARENT_HEADER,
+    TraceParent, metrics, udp,
+};
 use crate::rbac::Connection;
 use crate::socket::to_canonical;
 use crate::state::service::Service;
 use crate::{assertions, copy, handle_connection, proxy, socket, strng, tls};
 
 use crate::drain::run_with_drain;
-use crate::proxy::h2;
+use crate::proxy::{h1, h2};
 use crate::state::workload::address::Address;
 use crate::state::workload::application_tunnel::Protocol;
 use crate::state::workload::{self, NetworkAddress, Workload};
@@ -58,6 +67,7 @@ impl Inbound {
             .socket_factory
             .tcp_bind(pi.cfg.inbound_addr)
             .map_err(|e| Error::Bind(pi.cfg.inbound_addr, e))?;
+        super::maybe_bind_device(&listener, &pi.cfg.inbound_iface)?;
         let enable_orig_src = super::maybe_set_transparent(&pi, &listener)?;
 
         info!(
@@ -74,83 +84,294 @@ impl Inbound {
         })
     }
 
+    /// new_plaintext sets up an HBONE listener without mTLS on `addr`.
+    pub(super) async fn new_plaintext(
+        pi: Arc<ProxyInputs>,
+        addr: SocketAddr,
+        drain: DrainWatcher,
+    ) -> Result<Inbound, Error> {
+        let listener = pi
+            .socket_factory
+            .tcp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
+        super::maybe_bind_device(&listener, &pi.cfg.inbound_iface)?;
+        let enable_orig_src = super::maybe_set_transparent(&pi, &listener)?;
+
+        info!(
+            address=%listener.local_addr(),
+            component="inbound_plaintext_hbone",
+            transparent=enable_orig_src,
+            "listener established",
+        );
+        Ok(Inbound {
+            listener,
+            drain,
+            pi,
+            enable_orig_src,
+        })
+    }
+
     pub(super) fn address(&self) -> SocketAddr {
         self.listener.local_addr()
     }
 
-    pub(super) async fn run(self) {
+    /// run_plaintext serves HBONE directly over TCP. There is no peer certificate, so every
+    /// connection is treated as unauthenticated for RBAC purposes.
+    pub(super) async fn run_plaintext(self) {
+        let metrics = self.pi.metrics.clone();
+        let stream = crate::hyper_util::plaintext_server(
+            "inbound_plaintext_hbone",
+            self.listener.inner(),
+            move |e| {
+                if proxy::util::is_fd_exhausted(e) {
+                    metrics.accept_fd_exhausted.inc();
+                }
+            },
+        );
+
         let pi = self.pi.clone();
+        let enable_orig_src = self.enable_orig_src;
+        let serve_client = move |mut stream: TcpStream,
+                                 drain: DrainWatcher,
+                                 force_shutdown: watch::Receiver<()>| {
+            let pi = pi.clone();
+            let dst = to_canonical(stream.local_addr().expect("local_addr available"));
+            let src = to_canonical(stream.peer_addr().expect("peer_addr available"));
+            let network = pi.cfg.network.clone();
+            async move {
+                let src =
+                    match super::inbound_proxy_protocol_source(&pi.cfg, &mut stream, src).await {
+                        Ok(src) => src,
+                        Err(e) => {
+                            metrics::log_early_deny(src, dst, Reporter::destination, e);
+                            return Ok(());
+                        }
+                    };
+                let conn = Connection {
+                    src_identity: None,
+                    src,
+                    dst_network: strng::new(&network), // inbound request must be on our network
+                    dst,
+                };
+                debug!(%conn, "accepted plaintext connection");
+                let cfg = pi.cfg.clone();
+                let metrics = pi.metrics.clone();
+                let window_sizes = Self::window_sizes(&pi, &conn);
+                let request_handler = move |req: H2Request| {
+                    let id = Self::extract_traceparent(&req);
+                    let peer = conn.src;
+                    Self::serve_connect(pi.clone(), conn.clone(), None, enable_orig_src, req)
+                        .instrument(Self::request_span(&id, peer))
+                };
+
+                let serve_conn = h2::server::serve_connection(
+                    cfg,
+                    metrics,
+                    window_sizes,
+                    stream,
+                    drain,
+                    force_shutdown,
+                    request_handler,
+                );
+                Box::pin(serve_conn).await
+            }
+        };
+
+        Self::serve(
+            self.pi,
+            "inbound_plaintext_hbone",
+            self.drain,
+            stream,
+            serve_client,
+        )
+        .await
+    }
+
+    /// serve runs the accept loop shared by the inbound listeners. Each connection from `stream`
+    /// is spawned with `serve_client` once there is room under the connection limit.
+    async fn serve<S, F, Fut>(
+        pi: Arc<ProxyInputs>,
+        component: &str,
+        drain: DrainWatcher,
+        stream: impl Stream<Item = S>,
+        serve_client: F,
+    ) where
+        F: Fn(S, DrainWatcher, watch::Receiver<()>) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(conn) = stream.next().await {
+                // Wait for room under the connection limit before serving the connection. Until
+                // then we stop pulling connections off the listener, so a flood of connections
+                // backs up in the listen queue instead of spawning tasks. The permit is only taken
+                // once a connection has been accepted, so idle listeners never hold one.
+                let permit = pi
+                    .connection_manager
+                    .connection_limit()
+                    .acquire(&pi.metrics)
+                    .await;
+                let in_flight = drain.track();
+                let serve = serve_client(conn, drain.clone(), force_shutdown.clone());
+                let serve = async move {
+                    let _permit = permit;
+                    let _in_flight = in_flight;
+                    serve.await
+                };
+                tokio::task::spawn(serve.in_current_span());
+            }
+        };
+
+        run_with_drain(
+            component.to_string(),
+            drain,
+            pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
+            accept,
+        )
+        .await
+    }
+
+    /// record_accept_error counts a failed accept or TLS handshake on the inbound listener.
+    fn record_accept_error(metrics: &metrics::Metrics, e: &crate::hyper_util::TlsServerError) {
+        match e {
+            tls_listener::Error::TlsAcceptError { error, .. } => {
+                metrics.record_tls_handshake_failure(error);
+            }
+            tls_listener::Error::HandshakeTimeout { .. } => {
+                metrics.record_tls_handshake_failure_cause(TlsHandshakeFailureCause::timeout);
+            }
+            tls_listener::Error::ListenerError(e) if proxy::util::is_fd_exhausted(e) => {
+                metrics.accept_fd_exhausted.inc();
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) async fn run(self) {
         let acceptor = InboundCertProvider {
             local_workload: self.pi.local_workload_information.clone(),
+            http1_connect: self.pi.cfg.http1_connect,
+            require_alpn: self.pi.cfg.require_hbone_alpn,
+            trust_domain_aliases: self.pi.cfg.trust_domain_aliases.clone(),
+            session_cache_size: self.pi.cfg.tls_session_cache_size,
         };
 
         // Safety: we set nodelay directly in tls_server, so it is safe to convert to a normal listener.
         // Although, that is *after* the TLS handshake; in theory we may get some benefits to setting it earlier.
-        let mut stream = crate::hyper_util::tls_server(acceptor, self.listener.inner());
+        let metrics = self.pi.metrics.clone();
+        let stream = crate::hyper_util::tls_server_with_error_hook(
+            acceptor,
+            self.listener.inner(),
+            move |e| Self::record_accept_error(&metrics, e),
+        );
 
-        let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
-            while let Some(tls) = stream.next().await {
-                let pi = self.pi.clone();
-                let (raw_socket, ssl) = tls.get_ref();
-                let src_identity: Option<Identity> = tls::identity_from_connection(ssl);
-                let dst = to_canonical(raw_socket.local_addr().expect("local_addr available"));
-                let src = to_canonical(raw_socket.peer_addr().expect("peer_addr available"));
-                let drain = drain.clone();
-                let force_shutdown = force_shutdown.clone();
-                let network = pi.cfg.network.clone();
-                let serve_client = async move {
-                    let conn = Connection {
-                        src_identity,
-                        src,
-                        dst_network: strng::new(&network), // inbound request must be on our network
-                        dst,
-                    };
-                    debug!(%conn, "accepted connection");
-                    let cfg = pi.cfg.clone();
-                    let request_handler = move |req| {
+        let pi = self.pi.clone();
+        let enable_orig_src = self.enable_orig_src;
+        let serve_client = move |tls: tokio_rustls::server::TlsStream<TcpStream>,
+                                 drain: DrainWatcher,
+                                 force_shutdown: watch::Receiver<()>| {
+            let pi = pi.clone();
+            let (raw_socket, ssl) = tls.get_ref();
+            let src_identity: Option<Identity> = tls::identity_from_connection(ssl);
+            // Only offered when enabled; otherwise the acceptor has ensured the peer negotiated HTTP/2.
+            let http1 = ssl.alpn_protocol() == Some(b"http/1.1".as_slice());
+            let tls_info = pi.metrics.record_tls_connection(ssl);
+            let dst = to_canonical(raw_socket.local_addr().expect("local_addr available"));
+            let src = to_canonical(raw_socket.peer_addr().expect("peer_addr available"));
+            let network = pi.cfg.network.clone();
+            let serve_client = async move {
+                let conn = Connection {
+                    src_identity,
+                    src,
+                    dst_network: strng::new(&network), // inbound request must be on our network
+                    dst,
+                };
+                debug!(%conn, http1, "accepted connection");
+                if http1 {
+                    let request_handler = move |req: h1::H1Request| {
                         let id = Self::extract_traceparent(&req);
                         let peer = conn.src;
-                        let req_handler = Self::serve_connect(
+                        Self::serve_connect(
                             pi.clone(),
                             conn.clone(),
-                            self.enable_orig_src,
+                            Some(tls_info),
+                            enable_orig_src,
                             req,
                         )
-                        .instrument(info_span!("inbound", %id, %peer));
-                        // This is for each user connection, so most important to keep small
-                        assertions::size_between_ref(1500, 2500, &req_handler);
-                        req_handler
+                        .instrument(Self::request_span(&id, peer))
                     };
-
-                    let serve_conn = h2::server::serve_connection(
-                        cfg,
-                        tls,
-                        drain,
-                        force_shutdown,
-                        request_handler,
-                    );
-                    // This is per HBONE connection, so while would be nice to be small, at least it
-                    // is pooled so typically fewer of these.
-                    let serve = Box::pin(assertions::size_between(6000, 8000, serve_conn));
-                    serve.await
+                    let serve_conn =
+                        h1::serve_connection(tls, drain, force_shutdown, request_handler);
+                    return Box::pin(serve_conn).await;
+                }
+                let cfg = pi.cfg.clone();
+                let metrics = pi.metrics.clone();
+                let window_sizes = Self::window_sizes(&pi, &conn);
+                let request_handler = move |req: H2Request| {
+                    let id = Self::extract_traceparent(&req);
+                    let peer = conn.src;
+                    let req_handler = Self::serve_connect(
+                        pi.clone(),
+                        conn.clone(),
+                        Some(tls_info),
+                        enable_orig_src,
+                        req,
+                    )
+                    .instrument(Self::request_span(&id, peer));
+                    // This is for each user connection, so most important to keep small
+                    assertions::size_between_ref(1500, 2500, &req_handler);
+                    req_handler
                 };
-                // This is small since it only handles the TLS layer -- the HTTP2 layer is boxed
-                // and measured above.
-                assertions::size_between_ref(1000, 1500, &serve_client);
-                tokio::task::spawn(serve_client.in_current_span());
-            }
+
+                let serve_conn = h2::server::serve_connection(
+                    cfg,
+                    metrics,
+                    window_sizes,
+                    tls,
+                    drain,
+                    force_shutdown,
+                    request_handler,
+                );
+                // This is per HBONE connection, so while would be nice to be small, at least it
+                // is pooled so typically fewer of these.
+                let serve = Box::pin(assertions::size_between(6000, 8000, serve_conn));
+                serve.await
+            };
+            // This is small since it only handles the TLS layer -- the HTTP2 layer is boxed
+            // and measured above.
+            assertions::size_between_ref(1000, 1500, &serve_client);
+            serve_client
         };
 
-        run_with_drain(
-            "inbound".to_string(),
-            self.drain,
-            pi.cfg.self_termination_deadline,
-            accept,
-        )
-        .await
+        Self::serve(self.pi, "inbound", self.drain, stream, serve_client).await
+    }
+
+    // request_span is the span covering a single CONNECT request, parented to the client's trace.
+    fn request_span(id: &TraceParent, peer: SocketAddr) -> tracing::Span {
+        let span = info_span!("inbound", %id, %peer);
+        id.set_as_parent(&span);
+        span
+    }
+
+    // window_sizes returns the HTTP/2 windows for HBONE connections to the workload `conn` is
+    // destined for, if overridden.
+    fn window_sizes(pi: &ProxyInputs, conn: &Connection) -> Option<WindowSizes> {
+        if pi.cfg.workload_window_sizes.is_empty() {
+            return None;
+        }
+        let wl = pi.state.read().workloads.find_address(&NetworkAddress {
+            network: conn.dst_network.clone(),
+            address: conn.dst.ip(),
+        })?;
+        pi.cfg
+            .workload_window_sizes
+            .get(&strng::format!("{}/{}", wl.namespace, wl.name))
+            .copied()
     }
 
-    fn extract_traceparent(req: &H2Request) -> TraceParent {
+    fn extract_traceparent<R: HboneRequest>(req: &R) -> TraceParent {
         req.headers()
             .get(TRACEPARENT_HEADER)
             .and_then(|b| b.to_str().ok())
@@ -160,11 +381,12 @@ impl Inbound {
 
     /// serve_connect handles a single connection from a client.
     #[allow(clippy::too_many_arguments)]
-    async fn serve_connect(
+    async fn serve_connect<R: HboneRequest>(
         pi: Arc<ProxyInputs>,
         conn: Connection,
+        tls: Option<TlsInfo>,
         enable_original_source: bool,
-        req: H2Request,
+        req: R,
     ) {
         let src = conn.src;
         let dst = conn.dst;
@@ -175,13 +397,18 @@ impl Inbound {
         // phases.
 
         // Initial phase, build up context about the request.
-        let ri = match Self::build_inbound_request(&pi, conn, req.get_request()).await {
+        let ri = match Self::build_inbound_request(&pi, conn, tls, req.get_request()).await {
             Ok(i) => i,
             Err(InboundError(e, code)) => {
                 // At this point in processing, we never built up full context to log a complete access log.
                 // Instead, just log a minimal error line.
+                let resp = build_error_response(code, &e);
+                if let Some(reason) = DenyReason::from_error(&e) {
+                    pi.metrics
+                        .record_deny(Reporter::destination, reason, None, None);
+                }
                 metrics::log_early_deny(src, dst, Reporter::destination, e);
-                if let Err(err) = req.send_error(build_response(code)) {
+                if let Err(err) = req.send_error(resp) {
                     tracing::warn!("failed to send HTTP response: {err}");
                 }
                 return;
@@ -196,10 +423,19 @@ impl Inbound {
                 .connection_manager
                 .assert_rbac(&pi.state, &ri.rbac_ctx, ri.for_host)
                 .await
-                .map_err(InboundFlagError::build(
-                    StatusCode::UNAUTHORIZED,
-                    ResponseFlags::AuthorizationPolicyDenied,
-                ))?;
+                .map_err(|e| match e {
+                    Error::ConnectionLimitExceeded(_) => InboundFlagError(
+                        e,
+                        ResponseFlags::ConnectionLimitExceeded,
+                        StatusCode::TOO_MANY_REQUESTS,
+                    ),
+                    _ => InboundFlagError(
+                        e,
+                        ResponseFlags::AuthorizationPolicyDenied,
+                        StatusCode::UNAUTHORIZED,
+                    ),
+                })?;
+            conn_guard.track_bytes(&ri.result_tracker.bytes());
 
             // app tunnels should only bind to localhost to prevent
             // being accessed without going through ztunnel
@@ -230,24 +466,67 @@ impl Inbound {
                 )
             };
 
+            // Don't keep dialing a service endpoint that is known to be failing.
+            let breaker = pi.connection_manager.circuit_breaker();
+            if let Some(service) = &ri.dest_service {
+                if !breaker.admit(service, ri.upstream_addr) {
+                    return Err(InboundFlagError(
+                        Error::CircuitBreakerOpen(service.clone(), ri.upstream_addr),
+                        ResponseFlags::CircuitBreakerOpen,
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    ));
+                }
+            }
+
+            if ri.udp {
+                // The PROXY protocol headers we send to application tunnels are stream-oriented.
+                if ri.tunnel_request.is_some() {
+                    return Err(InboundFlagError(
+                        Error::UdpUnsupported(ri.upstream_addr),
+                        ResponseFlags::ConnectionFailure,
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                let socket = udp_connect(pi.socket_factory.as_ref(), dst).await;
+                if let Some(service) = &ri.dest_service {
+                    breaker.record(service, ri.upstream_addr, socket.is_ok());
+                }
+                let socket = socket.map_err(|e| connect_error(&pi, e))?;
+                debug!("associated with: {}", ri.upstream_addr);
+                return Ok((conn_guard, Upstream::Udp(socket)));
+            }
+
             // Establish upstream connection between original source and destination
             // We are allowing a bind to the original source address locally even if the ip address isn't on this node.
-            let stream = super::freebind_connect(src, dst, pi.socket_factory.as_ref())
-                .await
-                .map_err(Error::ConnectionFailed)
-                .map_err(InboundFlagError::build(
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    ResponseFlags::ConnectionFailure,
-                ))?;
+            let stream = super::freebind_connect(
+                src,
+                dst,
+                pi.socket_factory.as_ref(),
+                pi.cfg.connect_timeout,
+            )
+            .await;
+            if let Some(service) = &ri.dest_service {
+                breaker.record(service, ri.upstream_addr, stream.is_ok());
+            }
+            let stream = stream.map_err(|e| connect_error(&pi, e))?;
             debug!("connected to: {}", ri.upstream_addr);
-            Ok((conn_guard, stream))
+            Ok((conn_guard, Upstream::Tcp(stream)))
         };
         // Wait on establishing the upstream connection and connection guard before sending the 200 response to the client
-        let (mut conn_guard, mut stream) = match rx.await {
+        let (mut conn_guard, upstream) = match rx.await {
             Ok(res) => res,
             Err(InboundFlagError(err, flag, code)) => {
+                let resp = build_error_response(code, &err);
+                if let Some(reason) = DenyReason::from_inbound_error(&err, ri.bypassed_waypoint) {
+                    pi.metrics.record_deny(
+                        Reporter::destination,
+                        reason,
+                        ri.rbac_ctx.conn.src_identity.clone(),
+                        Some(ri.rbac_ctx.dest_workload.identity()),
+                    );
+                }
                 ri.result_tracker.record_with_flag(Err(err), flag);
-                if let Err(err) = req.send_error(build_response(code)) {
+                if let Err(err) = req.send_error(resp) {
                     tracing::warn!("failed to send HTTP response: {err}");
                 }
                 return;
@@ -265,7 +544,20 @@ impl Inbound {
         // proxy protocol.
         let send = req
             .send_response(build_response(StatusCode::OK))
-            .and_then(|h2_stream| async {
+            .and_then(|tunnel| async {
+                let mut stream = match upstream {
+                    Upstream::Tcp(stream) => stream,
+                    Upstream::Udp(socket) => {
+                        return udp::relay_to_socket(
+                            tunnel,
+                            socket,
+                            &ri.result_tracker,
+                            pi.cfg.udp_idle_timeout,
+                        )
+                        .instrument(trace_span!("hbone udp server"))
+                        .await;
+                    }
+                };
                 if let Some(TunnelRequest {
                     protocol: Protocol::PROXY,
                     tunnel_target,
@@ -274,14 +566,23 @@ impl Inbound {
                     let Connection {
                         src, src_identity, ..
                     } = ri.rbac_ctx.conn;
-                    super::write_proxy_protocol(&mut stream, (src, tunnel_target), src_identity)
-                        .instrument(trace_span!("proxy protocol"))
-                        .await?;
+                    super::write_proxy_protocol(
+                        &mut stream,
+                        (src, tunnel_target),
+                        src_identity,
+                        Some(&ri.hbone_addr),
+                    )
+                    .instrument(trace_span!("proxy protocol"))
+                    .await?;
                 }
-                copy::copy_bidirectional(
-                    h2_stream,
+                copy::copy_bidirectional_with_limits(
+                    tunnel,
                     copy::TcpStreamSplitter(stream),
                     &ri.result_tracker,
+                    pi.cfg.idle_timeout,
+                    pi.cfg.half_close_timeout,
+                    proxy::connection_rate_limit(&pi.cfg, ri.dest_service.as_ref()),
+                    pi.cfg.copy_buffer_max_size,
                 )
                 .instrument(trace_span!("hbone server"))
                 .await
@@ -294,6 +595,7 @@ impl Inbound {
     async fn build_inbound_request<T: RequestParts>(
         pi: &Arc<ProxyInputs>,
         conn: Connection,
+        tls: Option<TlsInfo>,
         req: &T,
     ) -> Result<InboundRequest, InboundError> {
         if req.method() != Method::CONNECT {
@@ -301,13 +603,33 @@ impl Inbound {
             return Err(InboundError(e, StatusCode::BAD_REQUEST));
         }
 
+        // HTTP/2 requests are already checked by the server, while HTTP/1.1 CONNECT is only bounded
+        // by hyper's read buffer.
+        let header_size = h2::server::header_list_size(req.headers());
+        if header_size > pi.cfg.max_header_list_size as usize {
+            pi.metrics.oversized_headers_rejected.inc();
+            return Err(InboundError(
+                Error::HeadersTooLarge(header_size),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ));
+        }
+
         let start = Instant::now();
 
         // Extract the host or IP from the authority pseudo-header of the URI
-        let hbone_addr: HboneAddress = req
-            .uri()
-            .try_into()
-            .map_err(InboundError::build(StatusCode::BAD_REQUEST))?;
+        let hbone_addr: HboneAddress = req.uri().try_into().map_err(|e| match e {
+            Error::AuthorityTooLong(_) => {
+                pi.metrics.oversized_authority_rejected.inc();
+                InboundError(e, StatusCode::URI_TOO_LONG)
+            }
+            e => InboundError(e, StatusCode::BAD_REQUEST),
+        })?;
+
+        let tag = match &pi.cfg.connection_tag_header {
+            Some(header) => parse_connection_tag(req.headers().get_all(header.as_str()))
+                .map_err(InboundError::build(StatusCode::BAD_REQUEST))?,
+            None => None,
+        };
 
         // Get the destination workload information of the destination pods (wds) workload (not destination ztunnel)
         let destination_workload = pi
@@ -317,10 +639,18 @@ impl Inbound {
             // At this point we already fetched the local workload for TLS, so it should be infallible.
             .map_err(InboundError::build(StatusCode::SERVICE_UNAVAILABLE))?;
 
-        // Check the request is allowed by verifying the destination
-        Self::validate_destination(&pi.state, &conn, &destination_workload, &hbone_addr)
-            .await
-            .map_err(InboundError::build(StatusCode::BAD_REQUEST))?;
+        // Check the request is allowed by verifying the destination.
+        // A well-formed authority for some other destination was sent on the wrong connection, so
+        // report it as misdirected rather than as a bad request.
+        let authority = Self::validate_destination(
+            &pi.cfg,
+            &pi.state,
+            &conn,
+            &destination_workload,
+            &hbone_addr,
+        )
+        .await
+        .map_err(InboundError::build(StatusCode::MISDIRECTED_REQUEST))?;
 
         // Determine the next hop.
         let (upstream_addr, tunnel_request, upstream_service) = Self::find_inbound_upstream(
@@ -329,8 +659,15 @@ impl Inbound {
             &conn,
             &destination_workload,
             &hbone_addr,
+            authority,
         )
-        .map_err(InboundError::build(StatusCode::SERVICE_UNAVAILABLE))?;
+        .map_err(|e| {
+            if matches!(e, Error::TunnelLoop(_)) {
+                pi.metrics.hbone_loops_detected.inc();
+            }
+            let code = upstream_error_status(&e);
+            InboundError(e, code)
+        })?;
 
         let original_dst = conn.dst;
         // Connection has 15008, swap with the real port
@@ -344,9 +681,16 @@ impl Inbound {
             dest_workload: destination_workload.clone(),
         };
 
+        let udp = req
+            .headers()
+            .get(udp::PROTOCOL_HEADER)
+            .is_some_and(|v| v == udp::PROTOCOL_UDP);
         let for_host = parse_forwarded_host(req);
-        let baggage =
-            parse_baggage_header(req.headers().get_all(BAGGAGE_HEADER)).unwrap_or_default();
+        let baggage = parse_baggage_header_with_labels(
+            req.headers().get_all(BAGGAGE_HEADER),
+            &pi.cfg.baggage_labels,
+        )
+        .unwrap_or_default();
 
         // We assume it is from gateway if it's a hostname request.
         // We may need a more explicit indicator in the future.
@@ -369,6 +713,31 @@ impl Inbound {
             }
         };
 
+        // The authority check already told us whether we are a sandwiched waypoint. Trusted
+        // authorities address the destination itself, so they count as direct.
+        let traversal = if from_gateway {
+            metrics::Traversal::via_gateway
+        } else if authority == AuthorityMatch::Sandwich {
+            metrics::Traversal::sandwich
+        } else if authority == AuthorityMatch::TrustedAuthority {
+            metrics::Traversal::direct
+        } else if proxy::check_from_waypoint(
+            &pi.state,
+            &destination_workload,
+            rbac_ctx.conn.src_identity.as_ref(),
+            &rbac_ctx.conn.src.ip(),
+        )
+        .await
+        {
+            metrics::Traversal::via_waypoint
+        } else {
+            metrics::Traversal::direct
+        };
+
+        // Traffic sent straight to a destination that has a waypoint skipped it.
+        let bypassed_waypoint =
+            traversal == metrics::Traversal::direct && destination_workload.waypoint.is_some();
+
         let derived_source = metrics::DerivedWorkload {
             identity: rbac_ctx.conn.src_identity.clone(),
             cluster_id: baggage.cluster_id,
@@ -378,6 +747,7 @@ impl Inbound {
             app: baggage.service_name,
             workload_name: baggage.workload_name,
             revision: baggage.revision,
+            labels: baggage.labels,
         };
         let ds = proxy::guess_inbound_service(
             &rbac_ctx.conn,
@@ -385,29 +755,39 @@ impl Inbound {
             upstream_service,
             &destination_workload,
         );
-        let result_tracker = Box::new(metrics::ConnectionResult::new(
-            rbac_ctx.conn.src,
-            // For consistency with outbound logs, report the original destination (with 15008 port)
-            // as dst.addr, and the target address as dst.hbone_addr
-            original_dst,
-            Some(hbone_addr.clone()),
-            start,
-            ConnectionOpen {
-                reporter: Reporter::destination,
-                source,
-                derived_source: Some(derived_source),
-                destination: Some(destination_workload),
-                connection_security_policy: metrics::SecurityPolicy::mutual_tls,
-                destination_service: ds,
-            },
-            pi.metrics.clone(),
-        ));
+        let dest_service = ds.as_ref().map(|s| s.hostname.clone());
+        let result_tracker = Box::new(
+            metrics::ConnectionResult::new(
+                rbac_ctx.conn.src,
+                // For consistency with outbound logs, report the original destination (with 15008 port)
+                // as dst.addr, and the target address as dst.hbone_addr
+                original_dst,
+                Some(hbone_addr.clone()),
+                start,
+                ConnectionOpen {
+                    reporter: Reporter::destination,
+                    source,
+                    derived_source: Some(derived_source),
+                    destination: Some(destination_workload),
+                    connection_security_policy: metrics::SecurityPolicy::mutual_tls,
+                    destination_service: ds,
+                },
+                pi.metrics.clone(),
+            )
+            .with_tag(tag)
+            .with_tls(tls),
+        );
+        pi.metrics.record_traversal(traversal);
         Ok(InboundRequest {
             for_host,
             rbac_ctx,
             result_tracker,
             upstream_addr,
             tunnel_request,
+            hbone_addr,
+            dest_service,
+            udp,
+            bypassed_waypoint,
         })
     }
 
@@ -434,26 +814,33 @@ impl Inbound {
             .ok_or_else(|| Error::NoHostname(hbone_host.to_string()))
     }
 
-    /// validate_destination ensures the destination is an allowed request.
+    /// validate_destination ensures the destination is an allowed request, and reports how the
+    /// HBONE authority relates to the connection's destination.
     async fn validate_destination(
+        cfg: &Config,
         state: &DemandProxyState,
         conn: &Connection,
         local_workload: &Workload,
         hbone_addr: &HboneAddress,
-    ) -> Result<(), Error> {
+    ) -> Result<AuthorityMatch, Error> {
         let HboneAddress::SocketAddr(hbone_addr) = hbone_addr else {
             // This is a hostname - it is valid. We may not find the hostname, at which point we will fail later
-            return Ok(());
+            return Ok(AuthorityMatch::Destination);
         };
         if conn.dst.ip() == hbone_addr.ip() {
             // Normal case: both are aligned. This is allowed (we really only need the HBONE address for the port.)
-            return Ok(());
+            return Ok(AuthorityMatch::Destination);
         }
         if local_workload.application_tunnel.is_some() {
             // In the case they have their own tunnel, they will get the HBONE target address in the PROXY
             // header, and their application can decide what to do with it; we don't validate this.
             // This is the case, for instance, with a waypoint using PROXY.
-            return Ok(());
+            return Ok(AuthorityMatch::ApplicationTunnel);
+        }
+        if Self::is_trusted_authority(cfg, state, conn, local_workload, hbone_addr) {
+            // Trusted clients may address a pod by another of its IPs. We still send to the captured
+            // destination and enforce its policy, so this only relaxes the check.
+            return Ok(AuthorityMatch::TrustedAuthority);
         }
         // There still may be the case where we are doing a "waypoint sandwich" but not using any tunnel.
         // Presumably, the waypoint is only matching on L7 attributes.
@@ -511,7 +898,36 @@ impl Inbound {
         if res.is_none() || res == Some(false) {
             return Err(Error::IPMismatch(conn.dst.ip(), hbone_addr.ip()));
         }
-        Ok(())
+        Ok(AuthorityMatch::Sandwich)
+    }
+
+    /// is_trusted_authority checks if the client is configured as trusted to send an HBONE authority
+    /// that does not match the connection destination, and that authority is another IP of the
+    /// captured workload. Other workloads, even on our node, are never trusted targets.
+    fn is_trusted_authority(
+        cfg: &Config,
+        state: &DemandProxyState,
+        conn: &Connection,
+        local_workload: &Workload,
+        hbone_addr: &SocketAddr,
+    ) -> bool {
+        let Some(src_identity) = &conn.src_identity else {
+            return false;
+        };
+        if !cfg.trusted_authority_identities.contains(src_identity) {
+            return false;
+        }
+        if local_workload.workload_ips.contains(&hbone_addr.ip()) {
+            return true;
+        }
+        let hbone_dst = NetworkAddress {
+            network: conn.dst_network.clone(),
+            address: hbone_addr.ip(),
+        };
+        match state.read().find_address(&hbone_dst) {
+            Some(Address::Workload(wl)) => wl.uid == local_workload.uid,
+            _ => false,
+        }
     }
 
     /// find_inbound_upstream determines the next hop for an inbound request.
@@ -522,6 +938,7 @@ impl Inbound {
         conn: &Connection,
         local_workload: &Workload,
         hbone_addr: &HboneAddress,
+        authority: AuthorityMatch,
     ) -> Result<(SocketAddr, Option<TunnelRequest>, Vec<Arc<Service>>), Error> {
         // We always target the local workload IP as the destination. But we need to determine the port to send to.
         let target_ip = conn.dst.ip();
@@ -556,17 +973,32 @@ impl Inbound {
                 };
                 (SocketAddr::new(target_ip, port), vec![svc])
             }
-            HboneAddress::SocketAddr(hbone_addr) => (
-                SocketAddr::new(target_ip, hbone_addr.port()),
-                state.get_services_by_workload(local_workload),
-            ),
+            HboneAddress::SocketAddr(hbone_addr) => {
+                let port = if authority == AuthorityMatch::Sandwich {
+                    // A waypoint sandwich: the authority is the final destination, so its port may
+                    // not be the one the waypoint listens on.
+                    cfg.sandwich_port_mappings
+                        .get(&hbone_addr.port())
+                        .copied()
+                        .unwrap_or(hbone_addr.port())
+                } else {
+                    hbone_addr.port()
+                };
+                (
+                    SocketAddr::new(target_ip, port),
+                    state.get_services_by_workload(local_workload),
+                )
+            }
         };
 
         // Check for illegal calls now that we have resolved to the final destination.
         // We need to do this here, rather than `validate_destination`, since the former doesn't
         // have access to the resolved service port.
+        // Such a destination would be served by ztunnel again, which would tunnel it back here.
+        // Tagging requests with our identity cannot catch this, as same-node traffic legitimately
+        // passes through the same ztunnel twice.
         if cfg.illegal_ports.contains(&dest.port()) {
-            return Err(Error::SelfCall);
+            return Err(Error::TunnelLoop(dest));
         }
 
         // Application tunnel may override the port.
@@ -619,6 +1051,86 @@ impl Inbound {
     }
 }
 
+/// HboneRequest abstracts over the HTTP version a CONNECT request arrived on, so HTTP/2 streams
+/// and HTTP/1.1 upgrades are served by the same inbound logic.
+trait HboneRequest: Debug + Send + 'static {
+    type Stream: copy::BufferedSplitter + Send;
+
+    fn get_request(&self) -> &Parts;
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue>;
+    fn send_error(self, resp: Response<()>) -> Result<(), Error>;
+    fn send_response(
+        self,
+        resp: Response<()>,
+    ) -> impl Future<Output = Result<Self::Stream, Error>> + Send;
+}
+
+impl HboneRequest for H2Request {
+    type Stream = h2::H2Stream;
+
+    fn get_request(&self) -> &Parts {
+        H2Request::get_request(self)
+    }
+
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        H2Request::headers(self)
+    }
+
+    fn send_error(self, resp: Response<()>) -> Result<(), Error> {
+        H2Request::send_error(self, resp)
+    }
+
+    fn send_response(
+        self,
+        resp: Response<()>,
+    ) -> impl Future<Output = Result<Self::Stream, Error>> + Send {
+        H2Request::send_response(self, resp)
+    }
+}
+
+impl HboneRequest for h1::H1Request {
+    type Stream = hyper_util::rt::TokioIo<hyper::upgrade::Upgraded>;
+
+    fn get_request(&self) -> &Parts {
+        h1::H1Request::get_request(self)
+    }
+
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        h1::H1Request::headers(self)
+    }
+
+    fn send_error(self, resp: Response<()>) -> Result<(), Error> {
+        h1::H1Request::send_error(self, resp)
+    }
+
+    fn send_response(
+        self,
+        resp: Response<()>,
+    ) -> impl Future<Output = Result<Self::Stream, Error>> + Send {
+        h1::H1Request::send_response(self, resp)
+    }
+}
+
+// Upstream is the connection to the destination application.
+enum Upstream {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+// udp_connect creates a UDP socket that sends to, and only receives from, `dst`.
+async fn udp_connect(
+    socket_factory: &(dyn SocketFactory + Send + Sync),
+    dst: SocketAddr,
+) -> std::io::Result<UdpSocket> {
+    let unspecified = match dst {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = socket_factory.udp_bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(dst).await?;
+    Ok(socket)
+}
+
 #[derive(Debug)]
 struct TunnelRequest {
     tunnel_target: SocketAddr,
@@ -632,6 +1144,28 @@ struct InboundRequest {
     result_tracker: Box<ConnectionResult>,
     upstream_addr: SocketAddr,
     tunnel_request: Option<TunnelRequest>,
+    // The authority of the CONNECT request, forwarded to the application tunnel if there is one.
+    hbone_addr: HboneAddress,
+    // Hostname of the destination service, if known. Circuit breaking is tracked per service.
+    dest_service: Option<Strng>,
+    // Whether the request carries UDP datagrams rather than a TCP stream.
+    udp: bool,
+    // Whether the request skipped the destination's waypoint. Policy denials of such requests are
+    // counted as waypoint bypasses.
+    bypassed_waypoint: bool,
+}
+
+/// AuthorityMatch describes how a valid HBONE authority relates to the connection's destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthorityMatch {
+    /// The authority is the connection's destination, or a service hostname.
+    Destination,
+    /// The authority is forwarded to the destination's application tunnel without validation.
+    ApplicationTunnel,
+    /// A trusted client addressed another IP of the destination workload.
+    TrustedAuthority,
+    /// We are the waypoint of the authority, which is the final destination ("waypoint sandwich").
+    Sandwich,
 }
 
 /// InboundError represents an error with an associated status code.
@@ -644,15 +1178,14 @@ impl InboundError {
 }
 
 struct InboundFlagError(Error, ResponseFlags, StatusCode);
-impl InboundFlagError {
-    pub fn build(code: StatusCode, flag: ResponseFlags) -> impl Fn(Error) -> Self {
-        move |err| InboundFlagError(err, flag, code)
-    }
-}
 
 #[derive(Clone)]
 struct InboundCertProvider {
     local_workload: Arc<LocalWorkloadInformation>,
+    http1_connect: bool,
+    require_alpn: bool,
+    trust_domain_aliases: Vec<Strng>,
+    session_cache_size: usize,
 }
 
 #[async_trait::async_trait]
@@ -663,7 +1196,16 @@ impl crate::tls::ServerCertProvider for InboundCertProvider {
             "fetching cert"
         );
         let cert = self.local_workload.fetch_certificate().await?;
-        Ok(Arc::new(cert.server_config()?))
+        let mut sc = cert.server_config(&self.trust_domain_aliases, self.session_cache_size)?;
+        if self.http1_connect {
+            // HTTP/2 stays preferred; HTTP/1.1 is only selected for peers that do not offer h2.
+            sc.alpn_protocols.push(b"http/1.1".into());
+        }
+        Ok(Arc::new(sc))
+    }
+
+    fn require_alpn(&self) -> bool {
+        self.require_alpn
     }
 }
 
@@ -674,6 +1216,50 @@ pub fn parse_forwarded_host<T: RequestParts>(req: &T) -> Option<String> {
         .and_then(proxy::parse_forwarded_host)
 }
 
+/// MAX_CONNECTION_TAGS and MAX_CONNECTION_TAG_LEN bound the tags a client may attach to a request,
+/// as they are copied into every access log for the connection.
+const MAX_CONNECTION_TAGS: usize = 4;
+const MAX_CONNECTION_TAG_LEN: usize = 64;
+
+/// parse_connection_tag validates the comma-separated tags in the connection tag header, returning
+/// them joined by commas. Tags are restricted to characters that are safe to log unescaped.
+fn parse_connection_tag(
+    values: http::header::GetAll<'_, http::HeaderValue>,
+) -> Result<Option<Strng>, Error> {
+    let mut tags = Vec::new();
+    for value in values {
+        let value = value
+            .to_str()
+            .map_err(|_| Error::InvalidConnectionTag("not ASCII".to_string()))?;
+        for tag in value.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if tags.len() == MAX_CONNECTION_TAGS {
+                return Err(Error::InvalidConnectionTag(format!(
+                    "more than {MAX_CONNECTION_TAGS} tags"
+                )));
+            }
+            if tag.len() > MAX_CONNECTION_TAG_LEN {
+                return Err(Error::InvalidConnectionTag(format!(
+                    "tag is {} bytes, more than {MAX_CONNECTION_TAG_LEN}",
+                    tag.len()
+                )));
+            }
+            if !tag
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.:/=".contains(&b))
+            {
+                return Err(Error::InvalidConnectionTag(format!(
+                    "tag {tag:?} contains invalid characters"
+                )));
+            }
+            tags.push(tag);
+        }
+    }
+    if tags.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(strng::new(tags.join(","))))
+}
+
 fn build_response(status: StatusCode) -> Response<()> {
     Response::builder()
         .status(status)
@@ -681,9 +1267,57 @@ fn build_response(status: StatusCode) -> Response<()> {
         .expect("builder with known status code should not fail")
 }
 
+/// build_error_response builds the response for a failed request. Authorization denials carry
+/// a generic reason code in DENY_REASON_HEADER so they can be diagnosed by the client; the
+/// policy that denied the request is only logged, not disclosed.
+fn build_error_response(status: StatusCode, err: &Error) -> Response<()> {
+    let mut resp = build_response(status);
+    if let Error::AuthorizationPolicyRejection(reason) = err {
+        resp.headers_mut().insert(
+            DENY_REASON_HEADER,
+            http::HeaderValue::from_static(reason.code()),
+        );
+    }
+    resp
+}
+
+// connect_error records a failed connect to the upstream, and reports it to the client with a
+// status reflecting why it failed.
+fn connect_error(pi: &ProxyInputs, err: std::io::Error) -> InboundFlagError {
+    let cause = pi
+        .metrics
+        .record_connect_failure(Reporter::destination, &err);
+    InboundFlagError(
+        Error::ConnectionFailed(err),
+        ResponseFlags::ConnectionFailure,
+        connect_error_status(cause),
+    )
+}
+
+fn connect_error_status(cause: ConnectFailureCause) -> StatusCode {
+    match cause {
+        ConnectFailureCause::refused
+        | ConnectFailureCause::host_unreachable
+        | ConnectFailureCause::network_unreachable => StatusCode::BAD_GATEWAY,
+        ConnectFailureCause::timeout => StatusCode::GATEWAY_TIMEOUT,
+        ConnectFailureCause::other => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// upstream_error_status picks the status to return when we cannot determine where to send a request,
+/// distinguishing a destination we know nothing about from a known service that doesn't expose the port.
+fn upstream_error_status(err: &Error) -> StatusCode {
+    match err {
+        Error::NoHostname(_) => StatusCode::NOT_FOUND,
+        Error::NoPortForServices(_, _) => StatusCode::BAD_REQUEST,
+        Error::TunnelLoop(_) => StatusCode::LOOP_DETECTED,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Inbound, ProxyInputs};
+    use super::{AuthorityMatch, Inbound, ProxyInputs};
     use crate::{config, proxy::ConnectionManager, proxy::inbound::HboneAddress, strng};
 
     use crate::{
@@ -699,7 +1333,7 @@ mod tests {
         test_helpers,
     };
     use std::{
-        net::SocketAddr,
+        net::{IpAddr, SocketAddr},
         sync::{Arc, RwLock},
         time::Duration,
     };
@@ -708,16 +1342,18 @@ mod tests {
     use crate::proxy::DefaultSocketFactory;
     use crate::proxy::LocalWorkloadInformation;
     use crate::proxy::h2::server::RequestParts;
+    use crate::proxy::{AuthorizationRejectionError, DENY_REASON_HEADER, Error};
     use crate::state::WorkloadInfo;
     use crate::state::workload::HealthStatus;
     use hickory_resolver::config::{ResolverConfig, ResolverOpts};
-    use http::{Method, Uri};
+    use http::{Method, StatusCode, Uri};
     use prometheus_client::registry::Registry;
     use test_case::test_case;
 
     const CLIENT_POD_IP: &str = "10.0.0.1";
 
     const SERVER_POD_IP: &str = "10.0.0.2";
+    const SERVER_POD_SECONDARY_IP: &str = "10.0.0.4";
     const SERVER_SVC_IP: &str = "10.10.0.1";
 
     const SERVER_POD_HOSTNAME: &str = "server.default.svc.cluster.local";
@@ -803,8 +1439,12 @@ mod tests {
             };
 
         let validate_destination =
-            Inbound::validate_destination(&state, &conn, &local_wl, &hbone_addr).await;
-        let res = Inbound::find_inbound_upstream(&cfg, &state, &conn, &local_wl, &hbone_addr);
+            Inbound::validate_destination(&cfg, &state, &conn, &local_wl, &hbone_addr).await;
+        let authority = *validate_destination
+            .as_ref()
+            .unwrap_or(&AuthorityMatch::Destination);
+        let res =
+            Inbound::find_inbound_upstream(&cfg, &state, &conn, &local_wl, &hbone_addr, authority);
 
         match want {
             Some((ip, port)) => {
@@ -817,6 +1457,55 @@ mod tests {
         }
     }
 
+    #[test_case(Waypoint::Workload(WAYPOINT_POD_IP, None), WAYPOINT_POD_IP, SERVER_POD_IP, Some(TARGET_PORT + 1), TARGET_PORT + 1; "sandwich remapped")]
+    #[test_case(Waypoint::Workload(WAYPOINT_POD_IP, None), WAYPOINT_POD_IP, SERVER_POD_IP, None, TARGET_PORT; "sandwich unmapped")]
+    #[test_case(Waypoint::None, SERVER_POD_IP, SERVER_POD_IP, Some(TARGET_PORT + 1), TARGET_PORT; "not sandwiched")]
+    #[test_case(Waypoint::None, SERVER_POD_IP, SERVER_POD_SECONDARY_IP, Some(TARGET_PORT + 1), TARGET_PORT; "trusted authority not remapped")]
+    #[tokio::test]
+    async fn test_find_inbound_upstream_sandwich_port_mapping(
+        target_waypoint: Waypoint<'_>,
+        connection_dst: &str,
+        hbone_dst: &str,
+        mapped_port: Option<u16>,
+        want_port: u16,
+    ) {
+        let state = test_state(target_waypoint).expect("state setup");
+        let trusted: crate::identity::Identity = "spiffe://cluster.local/ns/default/sa/trusted"
+            .parse()
+            .unwrap();
+        let cfg = config::Config {
+            sandwich_port_mappings: mapped_port.map(|p| (TARGET_PORT, p)).into_iter().collect(),
+            trusted_authority_identities: vec![trusted.clone()],
+            ..config::parse_config().unwrap()
+        };
+        let conn = Connection {
+            src_identity: Some(trusted),
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{connection_dst}:15008").parse().unwrap(),
+        };
+        let local_wl = state
+            .fetch_workload_by_address(&NetworkAddress {
+                network: "".into(),
+                address: conn.dst.ip(),
+            })
+            .await
+            .unwrap();
+        let hbone_addr =
+            HboneAddress::SocketAddr(format!("{hbone_dst}:{TARGET_PORT}").parse().unwrap());
+
+        let authority = Inbound::validate_destination(&cfg, &state, &conn, &local_wl, &hbone_addr)
+            .await
+            .expect("valid destination");
+        let (got, _, _) =
+            Inbound::find_inbound_upstream(&cfg, &state, &conn, &local_wl, &hbone_addr, authority)
+                .expect("upstream found");
+        assert_eq!(
+            got,
+            SocketAddr::new(connection_dst.parse().unwrap(), want_port)
+        );
+    }
+
     // Regular zTunnel workload traffic inbound
     #[test_case(Waypoint::None, SERVER_POD_IP, SERVER_POD_IP, TARGET_PORT, Some((SERVER_POD_IP, TARGET_PORT, None)); "to workload no waypoint")]
     // Svc hostname
@@ -857,16 +1546,599 @@ mod tests {
             uri: format!("{hbone_dst}:{hbobe_dst_port}").parse().unwrap(),
             headers: http::HeaderMap::new(),
         };
-        let cm = ConnectionManager::default();
-        let metrics = Arc::new(crate::proxy::Metrics::new(&mut Registry::default()));
-        let sf = Arc::new(DefaultSocketFactory::default());
-        let wl = state
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let pi = test_proxy_inputs(cfg, state, conn.dst.ip(), metrics).await;
+        let inbound_request = Inbound::build_inbound_request(&pi, conn, None, &request_parts).await;
+        match want {
+            Some((ip, port, protocol_addr)) => {
+                let ir = inbound_request.unwrap();
+                assert_eq!(ir.upstream_addr, SocketAddr::new(ip.parse().unwrap(), port));
+                match ir.tunnel_request {
+                    Some(addr) => assert_eq!(
+                        addr.tunnel_target,
+                        SocketAddr::new(protocol_addr.unwrap().parse().unwrap(), hbobe_dst_port)
+                    ),
+                    None => assert_eq!(protocol_addr, None),
+                };
+            }
+            None => {
+                inbound_request.expect_err("could not build inbound request");
+            }
+        }
+    }
+
+    #[test_case("unknown.default.svc.cluster.local", SERVER_PORT, StatusCode::NOT_FOUND; "unknown svc hostname")]
+    #[test_case(SERVER_POD_HOSTNAME, SERVER_PORT + 1, StatusCode::BAD_REQUEST; "svc hostname unknown port")]
+    #[tokio::test]
+    async fn test_build_inbound_request_no_upstream(hbone_dst: &str, port: u16, want: StatusCode) {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        let request_parts = MockParts {
+            method: Method::CONNECT,
+            uri: format!("{hbone_dst}:{port}").parse().unwrap(),
+            headers: http::HeaderMap::new(),
+        };
+        let pi = test_proxy_inputs(
+            config::parse_config().unwrap(),
+            state,
+            conn.dst.ip(),
+            test_helpers::helpers::test_proxy_metrics(),
+        )
+        .await;
+        let err = Inbound::build_inbound_request(&pi, conn, None, &request_parts)
+            .await
+            .expect_err("no upstream should be found");
+        assert_eq!(err.1, want, "{:?}", err.0);
+    }
+
+    #[test_case(Waypoint::None, CLIENT_POD_IP, false, SERVER_POD_IP, SERVER_POD_IP, TARGET_PORT, "direct"; "direct")]
+    #[test_case(Waypoint::Workload(WAYPOINT_POD_IP, None), WAYPOINT_POD_IP, true, SERVER_POD_IP, SERVER_POD_IP, TARGET_PORT, "via_waypoint"; "from waypoint")]
+    #[test_case(Waypoint::Workload(WAYPOINT_POD_IP, None), WAYPOINT_POD_IP, false, SERVER_POD_IP, SERVER_POD_IP, TARGET_PORT, "direct"; "from waypoint address without its identity")]
+    #[test_case(Waypoint::Workload(WAYPOINT_POD_IP, None), CLIENT_POD_IP, false, WAYPOINT_POD_IP, SERVER_POD_IP, TARGET_PORT, "sandwich"; "to sandwiched waypoint")]
+    #[test_case(Waypoint::None, CLIENT_POD_IP, false, SERVER_POD_IP, SERVER_POD_HOSTNAME, SERVER_PORT, "via_gateway"; "from gateway")]
+    #[test_case(Waypoint::None, CLIENT_POD_IP, true, SERVER_POD_IP, SERVER_POD_SECONDARY_IP, TARGET_PORT, "direct"; "trusted authority")]
+    #[tokio::test]
+    async fn test_build_inbound_request_traversal(
+        target_waypoint: Waypoint<'_>,
+        src_ip: &str,
+        with_src_identity: bool,
+        connection_dst: &str,
+        hbone_dst: &str,
+        hbone_dst_port: u16,
+        want: &str,
+    ) {
+        let state = test_state(target_waypoint).expect("state setup");
+        let fetch = async |ip: &str| {
+            state
+                .fetch_workload_by_address(&NetworkAddress {
+                    network: "".into(),
+                    address: ip.parse().unwrap(),
+                })
+                .await
+                .unwrap()
+        };
+        let src_identity = match with_src_identity {
+            true => Some(fetch(src_ip).await.identity()),
+            false => None,
+        };
+        let conn = Connection {
+            src_identity,
+            src: format!("{src_ip}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{connection_dst}:15008").parse().unwrap(),
+        };
+        let request_parts = MockParts {
+            method: Method::CONNECT,
+            uri: format!("{hbone_dst}:{hbone_dst_port}").parse().unwrap(),
+            headers: http::HeaderMap::new(),
+        };
+        let mut registry = Registry::default();
+        let metrics = Arc::new(crate::proxy::Metrics::new(&mut registry));
+        // The client may address the server by its other IP.
+        let cfg = config::Config {
+            trusted_authority_identities: vec![fetch(CLIENT_POD_IP).await.identity()],
+            ..config::parse_config().unwrap()
+        };
+        let pi = test_proxy_inputs(cfg, state.clone(), conn.dst.ip(), metrics).await;
+        Inbound::build_inbound_request(&pi, conn, None, &request_parts)
+            .await
+            .expect("inbound request");
+
+        let mut out = String::new();
+        prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+        let traversals: Vec<_> = out
+            .lines()
+            .filter(|l| l.starts_with("inbound_connection_traversals_total{"))
+            .collect();
+        assert_eq!(
+            traversals,
+            vec![format!(
+                "inbound_connection_traversals_total{{traversal=\"{want}\"}} 1"
+            )],
+        );
+        // The stable traffic metrics are not split by traversal.
+        let opened = out
+            .lines()
+            .find(|l| l.starts_with("tcp_connections_opened_total{"))
+            .expect("connection open recorded");
+        assert!(!opened.contains("traversal="), "{opened}");
+    }
+
+    #[tokio::test]
+    async fn test_build_inbound_request_oversized_authority() {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        let request_parts = MockParts {
+            method: Method::CONNECT,
+            uri: format!("{}.default.svc.cluster.local:80", "a".repeat(300))
+                .parse()
+                .unwrap(),
+            headers: http::HeaderMap::new(),
+        };
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let pi = test_proxy_inputs(
+            config::parse_config().unwrap(),
+            state,
+            conn.dst.ip(),
+            metrics.clone(),
+        )
+        .await;
+        let err = Inbound::build_inbound_request(&pi, conn, None, &request_parts)
+            .await
+            .expect_err("oversized authority should be rejected");
+        assert!(matches!(err.0, Error::AuthorityTooLong(_)), "{:?}", err.0);
+        assert_eq!(err.1, StatusCode::URI_TOO_LONG);
+        assert_eq!(metrics.oversized_authority_rejected.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_build_inbound_request_oversized_headers() {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        let mut headers = http::HeaderMap::new();
+        headers.insert("baggage", "a".repeat(2048).parse().unwrap());
+        let request_parts = MockParts {
+            method: Method::CONNECT,
+            uri: format!("{SERVER_POD_IP}:80").parse().unwrap(),
+            headers,
+        };
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let pi = test_proxy_inputs(
+            config::Config {
+                max_header_list_size: 1024,
+                ..config::parse_config().unwrap()
+            },
+            state,
+            conn.dst.ip(),
+            metrics.clone(),
+        )
+        .await;
+        let err = Inbound::build_inbound_request(&pi, conn, None, &request_parts)
+            .await
+            .expect_err("oversized headers should be rejected");
+        assert!(matches!(err.0, Error::HeadersTooLarge(_)), "{:?}", err.0);
+        assert_eq!(err.1, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        assert_eq!(metrics.oversized_headers_rejected.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_window_sizes() {
+        let window_sizes = config::WindowSizes {
+            window_size: 8 * 1024 * 1024,
+            connection_window_size: 16 * 1024 * 1024,
+        };
+        let state = test_state(Waypoint::None).expect("state setup");
+        let pi = test_proxy_inputs(
+            config::Config {
+                workload_window_sizes: std::collections::HashMap::from([(
+                    strng::new("default/workload-server"),
+                    window_sizes,
+                )]),
+                ..config::parse_config().unwrap()
+            },
+            state,
+            SERVER_POD_IP.parse().unwrap(),
+            test_helpers::helpers::test_proxy_metrics(),
+        )
+        .await;
+        let conn = |dst: &str| Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{dst}:15008").parse().unwrap(),
+        };
+        assert_eq!(
+            Inbound::window_sizes(&pi, &conn(SERVER_POD_IP)),
+            Some(window_sizes)
+        );
+        // Other workloads use the global windows.
+        assert_eq!(Inbound::window_sizes(&pi, &conn(WAYPOINT_POD_IP)), None);
+    }
+
+    #[tokio::test]
+    async fn test_build_inbound_request_loop() {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        // The authority points back at our own HBONE port.
+        let request_parts = MockParts {
+            method: Method::CONNECT,
+            uri: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+            headers: http::HeaderMap::new(),
+        };
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let pi = test_proxy_inputs(
+            config::parse_config().unwrap(),
+            state,
+            conn.dst.ip(),
+            metrics.clone(),
+        )
+        .await;
+        let err = Inbound::build_inbound_request(&pi, conn, None, &request_parts)
+            .await
+            .expect_err("self-referential authority should be rejected");
+        assert!(matches!(err.0, Error::TunnelLoop(_)), "{:?}", err.0);
+        assert_eq!(err.1, StatusCode::LOOP_DETECTED);
+        assert_eq!(metrics.hbone_loops_detected.get(), 1);
+    }
+
+    #[test_case(Some("spiffe://cluster.local/ns/default/sa/trusted"), true; "trusted identity")]
+    #[test_case(Some("spiffe://cluster.local/ns/default/sa/other"), false; "untrusted identity")]
+    #[test_case(None, false; "no identity")]
+    #[tokio::test]
+    async fn test_validate_destination_trusted_authority(
+        src_identity: Option<&str>,
+        allowed: bool,
+    ) {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let mut cfg = config::parse_config().unwrap();
+        cfg.trusted_authority_identities = vec![
+            "spiffe://cluster.local/ns/default/sa/trusted"
+                .parse()
+                .unwrap(),
+        ];
+        let conn = Connection {
+            src_identity: src_identity.map(|id| id.parse().unwrap()),
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        let local_wl = state
             .fetch_workload_by_address(&NetworkAddress {
                 network: "".into(),
                 address: conn.dst.ip(),
             })
             .await
             .unwrap();
+
+        // A secondary IP of the captured pod, not the captured destination.
+        let hbone_addr = HboneAddress::SocketAddr(
+            format!("{SERVER_POD_SECONDARY_IP}:{TARGET_PORT}")
+                .parse()
+                .unwrap(),
+        );
+        let res = Inbound::validate_destination(&cfg, &state, &conn, &local_wl, &hbone_addr).await;
+        if allowed {
+            res.expect("trusted identity should be allowed");
+        } else {
+            assert!(matches!(res, Err(Error::IPMismatch(_, _))), "{res:?}");
+        }
+
+        // Another pod on this node is always rejected.
+        let other =
+            HboneAddress::SocketAddr(format!("{WAYPOINT_POD_IP}:{TARGET_PORT}").parse().unwrap());
+        let res = Inbound::validate_destination(&cfg, &state, &conn, &local_wl, &other).await;
+        assert!(matches!(res, Err(Error::IPMismatch(_, _))), "{res:?}");
+
+        // An IP that is not a known workload is always rejected.
+        let unknown = HboneAddress::SocketAddr(format!("10.9.9.9:{TARGET_PORT}").parse().unwrap());
+        let res = Inbound::validate_destination(&cfg, &state, &conn, &local_wl, &unknown).await;
+        assert!(matches!(res, Err(Error::IPMismatch(_, _))), "{res:?}");
+    }
+
+    #[test]
+    fn test_error_response_deny_reason() {
+        let denied = super::build_error_response(
+            StatusCode::UNAUTHORIZED,
+            &Error::AuthorizationPolicyRejection(AuthorizationRejectionError::ExplicitlyDenied(
+                "ns".into(),
+                "deny-all".into(),
+            )),
+        );
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            denied.headers().get(DENY_REASON_HEADER).unwrap(),
+            "explicitly_denied"
+        );
+
+        // Other failures do not carry a deny reason
+        let failed = super::build_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &Error::ConnectionFailed(std::io::ErrorKind::ConnectionRefused.into()),
+        );
+        assert_eq!(failed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(failed.headers().get(DENY_REASON_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connect_error_status() {
+        use crate::proxy::metrics::{ConnectFailureCause, ConnectFailureLabels, Reporter};
+        use std::io;
+
+        // Nothing listens on a port once its listener is dropped, so connecting is refused.
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let refused = tokio::net::TcpStream::connect(addr).await.unwrap_err();
+        let timeout = io::Error::from(io::ErrorKind::TimedOut);
+
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let status = |err: &io::Error| {
+            super::connect_error_status(metrics.record_connect_failure(Reporter::destination, err))
+        };
+        assert_eq!(status(&refused), StatusCode::BAD_GATEWAY);
+        assert_eq!(status(&timeout), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            status(&io::ErrorKind::HostUnreachable.into()),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            status(&io::ErrorKind::PermissionDenied.into()),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let count = |cause| {
+            metrics
+                .upstream_connect_failures
+                .get_or_create(&ConnectFailureLabels {
+                    reporter: Reporter::destination,
+                    cause,
+                })
+                .get()
+        };
+        assert_eq!(count(ConnectFailureCause::refused), 1);
+        assert_eq!(count(ConnectFailureCause::timeout), 1);
+        assert_eq!(count(ConnectFailureCause::network_unreachable), 0);
+    }
+
+    #[test]
+    fn test_upstream_error_status() {
+        assert_eq!(
+            super::upstream_error_status(&Error::NoHostname("unknown".into())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            super::upstream_error_status(&Error::NoPortForServices(
+                SERVER_POD_HOSTNAME.into(),
+                SERVER_PORT + 1
+            )),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            super::upstream_error_status(&Error::NoOriginalDst),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            super::upstream_error_status(&Error::TunnelLoop(
+                format!("{SERVER_POD_IP}:15008").parse().unwrap()
+            )),
+            StatusCode::LOOP_DETECTED
+        );
+    }
+
+    #[test]
+    fn test_parse_connection_tag() {
+        let parse = |values: &[&str]| {
+            let mut headers = http::HeaderMap::new();
+            for v in values {
+                headers.append("x-tag", http::HeaderValue::from_str(v).unwrap());
+            }
+            super::parse_connection_tag(headers.get_all("x-tag"))
+        };
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(parse(&[" , "]).unwrap(), None);
+        assert_eq!(
+            parse(&["job=batch-1"]).unwrap(),
+            Some(strng::new("job=batch-1"))
+        );
+        assert_eq!(
+            parse(&["team:payments, trace/abc", "v1.2_3"]).unwrap(),
+            Some(strng::new("team:payments,trace/abc,v1.2_3"))
+        );
+        assert!(matches!(
+            parse(&["a,b,c,d,e"]),
+            Err(Error::InvalidConnectionTag(_))
+        ));
+        assert!(matches!(
+            parse(&[&"a".repeat(65)]),
+            Err(Error::InvalidConnectionTag(_))
+        ));
+        assert!(matches!(
+            parse(&["bad tag"]),
+            Err(Error::InvalidConnectionTag(_))
+        ));
+        assert!(matches!(
+            parse(&["quote\"d"]),
+            Err(Error::InvalidConnectionTag(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_failure_cert_lookup() {
+        use crate::identity::Identity;
+        use crate::proxy::metrics::{TlsHandshakeFailureCause, TlsHandshakeFailureLabels};
+        use futures::stream::StreamExt;
+
+        // The local workload is not known, so there is no certificate to serve
+        let state = test_helpers::new_proxy_state(&[], &[], &[]);
+        let local_workload = Arc::new(LocalWorkloadInformation::new(
+            Arc::new(WorkloadInfo {
+                name: "unknown".to_string(),
+                namespace: "default".to_string(),
+                service_account: "default".to_string(),
+            }),
+            state,
+            new_secret_manager(Duration::from_secs(10)),
+        ));
+        let provider = super::InboundCertProvider {
+            local_workload,
+            http1_connect: false,
+            require_alpn: false,
+            trust_domain_aliases: vec![],
+            session_cache_size: 0,
+        };
+
+        let metrics = test_helpers::helpers::test_proxy_metrics();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hook_metrics = metrics.clone();
+        let mut stream =
+            crate::hyper_util::tls_server_with_error_hook(provider, listener, move |e| {
+                Inbound::record_accept_error(&hook_metrics, e)
+            });
+        // Failed handshakes never come out of the stream, but it has to be polled to accept them.
+        let server = tokio::spawn(async move { stream.next().await });
+
+        let certs = crate::tls::mock::generate_test_certs(
+            &Identity::default().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let connector = certs
+            .outbound_connector(vec![Identity::default()], 0)
+            .unwrap();
+        let conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(connector.connect(conn).await.is_err());
+
+        let count = |cause| {
+            metrics
+                .tls_handshake_failures
+                .get_or_create(&TlsHandshakeFailureLabels { cause })
+                .get()
+        };
+        test_helpers::assert_eventually(
+            Duration::from_secs(5),
+            || async { count(TlsHandshakeFailureCause::certificate_lookup) },
+            1,
+        )
+        .await;
+        assert_eq!(count(TlsHandshakeFailureCause::protocol), 0);
+        assert_eq!(metrics.plaintext_rejected.get(), 0);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_http1_connect() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let (mut r, mut w) = stream.split();
+            tokio::io::copy(&mut r, &mut w).await.unwrap();
+        });
+
+        let cfg = config::Config {
+            http1_connect: true,
+            ..config::parse_config().unwrap()
+        };
+        let state = test_state(Waypoint::None).expect("state setup");
+        let pi = test_proxy_inputs(
+            cfg,
+            state,
+            SERVER_POD_IP.parse().unwrap(),
+            test_helpers::helpers::test_proxy_metrics(),
+        )
+        .await;
+        let server_identity = pi
+            .local_workload_information
+            .get_workload()
+            .await
+            .unwrap()
+            .identity();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_drain_tx, drain_rx) = crate::drain::new();
+        let inbound = Inbound {
+            listener: crate::socket::Listener::new(listener),
+            drain: drain_rx,
+            pi,
+            enable_orig_src: false,
+        };
+        tokio::spawn(inbound.run());
+
+        // Offer only HTTP/1.1, as a gateway without HTTP/2 support would.
+        let certs = crate::tls::mock::generate_test_certs(
+            &crate::identity::Identity::default().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let connector = certs
+            .outbound_connector(vec![server_identity], 0)
+            .unwrap()
+            .with_alpn(vec![b"http/1.1".to_vec()]);
+        let conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut tls = connector.connect(conn).await.unwrap();
+        assert_eq!(
+            tls.get_ref().1.alpn_protocol(),
+            Some(b"http/1.1".as_slice())
+        );
+
+        tls.write_all(
+            format!("CONNECT {upstream_addr} HTTP/1.1\r\nHost: {upstream_addr}\r\n\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(tls.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+
+        // Once upgraded, the connection is relayed to the upstream.
+        tls.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tls.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    // Builds the proxy inputs for an inbound proxy running on behalf of the workload at `dst`.
+    async fn test_proxy_inputs(
+        cfg: config::Config,
+        state: state::DemandProxyState,
+        dst: IpAddr,
+        metrics: Arc<crate::proxy::Metrics>,
+    ) -> Arc<ProxyInputs> {
+        let wl = state
+            .fetch_workload_by_address(&NetworkAddress {
+                network: "".into(),
+                address: dst,
+            })
+            .await
+            .unwrap();
         let local_workload = Arc::new(LocalWorkloadInformation::new(
             Arc::new(WorkloadInfo {
                 name: wl.name.to_string(),
@@ -876,32 +2148,15 @@ mod tests {
             state.clone(),
             new_secret_manager(Duration::from_secs(10)),
         ));
-        let pi = Arc::new(ProxyInputs::new(
+        Arc::new(ProxyInputs::new(
             Arc::new(cfg),
-            cm,
-            state.clone(),
-            metrics.clone(),
-            sf,
+            ConnectionManager::default(),
+            state,
+            metrics,
+            Arc::new(DefaultSocketFactory::default()),
             None,
             local_workload,
-        ));
-        let inbound_request = Inbound::build_inbound_request(&pi, conn, &request_parts).await;
-        match want {
-            Some((ip, port, protocol_addr)) => {
-                let ir = inbound_request.unwrap();
-                assert_eq!(ir.upstream_addr, SocketAddr::new(ip.parse().unwrap(), port));
-                match ir.tunnel_request {
-                    Some(addr) => assert_eq!(
-                        addr.tunnel_target,
-                        SocketAddr::new(protocol_addr.unwrap().parse().unwrap(), hbobe_dst_port)
-                    ),
-                    None => assert_eq!(protocol_addr, None),
-                };
-            }
-            None => {
-                inbound_request.expect_err("could not build inbound request");
-            }
-        }
+        ))
     }
 
     // Creates a test state for the `DemandProxyState` with predefined services and workloads.
@@ -932,6 +2187,7 @@ mod tests {
             waypoint: waypoint.service_attached(),
             load_balancer: None,
             ip_families: None,
+            subset_weights: Default::default(),
         });
 
         let workloads = vec![
@@ -947,7 +2203,13 @@ mod tests {
         ]
         .into_iter()
         .map(|(name, ip, waypoint, app_tunnel)| Workload {
-            workload_ips: vec![ip.parse().unwrap()],
+            workload_ips: match name {
+                "server" => vec![
+                    ip.parse().unwrap(),
+                    SERVER_POD_SECONDARY_IP.parse().unwrap(),
+                ],
+                _ => vec![ip.parse().unwrap()],
+            },
             waypoint: waypoint.workload_attached(),
             protocol: Protocol::HBONE,
             uid: strng::format!("cluster1//v1/Pod/default/{name}"),
@@ -19,7 +19,7 @@ use std::time::Instant;
 use tokio::net::TcpStream;
 use tokio::sync::watch;
 
-use tracing::{Instrument, debug, error, info, trace};
+use tracing::{Instrument, debug, info, trace};
 
 use crate::drain::DrainWatcher;
 use crate::drain::run_with_drain;
@@ -47,6 +47,7 @@ impl InboundPassthrough {
             .tcp_bind(pi.cfg.inbound_plaintext_addr)
             .map_err(|e| Error::Bind(pi.cfg.inbound_plaintext_addr, e))?;
 
+        super::maybe_bind_device(&listener, &pi.cfg.inbound_iface)?;
         let enable_orig_src = super::maybe_set_transparent(&pi, &listener)?;
 
         info!(
@@ -66,6 +67,7 @@ impl InboundPassthrough {
     pub(super) async fn run(self) {
         let pi = self.pi.clone();
         let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut backoff = util::AcceptBackoff::default();
             loop {
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
@@ -75,6 +77,8 @@ impl InboundPassthrough {
                 let pi = self.pi.clone();
                 match socket {
                     Ok((stream, remote)) => {
+                        backoff.reset();
+                        let in_flight = drain.track();
                         let serve_client = async move {
                             debug!(component="inbound passthrough", "connection started");
                                 // Since this task is spawned, make sure we are guaranteed to terminate
@@ -86,6 +90,7 @@ impl InboundPassthrough {
                             }
                             // Mark we are done with the connection, so drain can complete
                             drop(drain);
+                            drop(in_flight);
                             debug!(component="inbound passthrough", dur=?start.elapsed(), "connection completed");
                         }.in_current_span();
 
@@ -96,7 +101,10 @@ impl InboundPassthrough {
                         if util::is_runtime_shutdown(&e) {
                             return;
                         }
-                        error!("Failed TCP handshake {}", e);
+                        if util::is_fd_exhausted(&e) {
+                            self.pi.metrics.accept_fd_exhausted.inc();
+                        }
+                        tokio::time::sleep(backoff.failed("inbound passthrough", &e)).await;
                     }
                 }
             }
@@ -106,6 +114,7 @@ impl InboundPassthrough {
             "inbound passthrough".to_string(),
             self.drain,
             pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
             accept,
         )
         .await
@@ -114,20 +123,31 @@ impl InboundPassthrough {
     async fn proxy_inbound_plaintext(
         pi: Arc<ProxyInputs>,
         source_addr: SocketAddr,
-        inbound_stream: TcpStream,
+        mut inbound_stream: TcpStream,
         enable_orig_src: bool,
     ) {
         let start = Instant::now();
         let dest_addr = socket::orig_dst_addr_or_default(&inbound_stream);
+        let source_addr =
+            match proxy::inbound_proxy_protocol_source(&pi.cfg, &mut inbound_stream, source_addr)
+                .await
+            {
+                Ok(src) => src,
+                Err(e) => {
+                    metrics::log_early_deny(source_addr, dest_addr, Reporter::destination, e);
+                    return;
+                }
+            };
         // Check if it is an illegal call to ourself, which could trampoline to illegal addresses or
         // lead to infinite loops
         let illegal_call = pi.cfg.illegal_ports.contains(&dest_addr.port());
         if illegal_call {
+            pi.metrics.hbone_loops_detected.inc();
             metrics::log_early_deny(
                 source_addr,
                 dest_addr,
                 Reporter::destination,
-                Error::SelfCall,
+                Error::TunnelLoop(dest_addr),
             );
             return;
         }
@@ -176,6 +196,7 @@ impl InboundPassthrough {
             upstream_services,
             &upstream_workload,
         );
+        let rate_limit = proxy::connection_rate_limit(&pi.cfg, ds.as_ref().map(|s| &s.hostname));
         let result_tracker = Box::new(metrics::ConnectionResult::new(
             source_addr,
             dest_addr,
@@ -191,17 +212,34 @@ impl InboundPassthrough {
             },
             pi.metrics.clone(),
         ));
+        pi.metrics.record_traversal(metrics::Traversal::direct);
 
-        let mut conn_guard = match pi
-            .connection_manager
-            .assert_rbac(&pi.state, &rbac_ctx, None)
-            .await
-        {
-            Ok(cg) => cg,
-            Err(e) => {
-                result_tracker
-                    .record_with_flag(Err(e), metrics::ResponseFlags::AuthorizationPolicyDenied);
-                return;
+        // Health probes are not mesh traffic, so they skip policy and connection tracking entirely.
+        let is_probe = pi
+            .cfg
+            .probe_source_ranges
+            .iter()
+            .any(|r| r.contains(&source_addr.ip()));
+        let conn_guard = if is_probe {
+            debug!(%source_addr, %dest_addr, component="inbound plaintext", "passing through health probe");
+            None
+        } else {
+            match pi
+                .connection_manager
+                .assert_rbac(&pi.state, &rbac_ctx, None)
+                .await
+            {
+                Ok(cg) => {
+                    cg.track_bytes(&result_tracker.bytes());
+                    Some(cg)
+                }
+                Err(e) => {
+                    result_tracker.record_with_flag(
+                        Err(e),
+                        metrics::ResponseFlags::AuthorizationPolicyDenied,
+                    );
+                    return;
+                }
             }
         };
 
@@ -214,20 +252,32 @@ impl InboundPassthrough {
         let send = async {
             trace!(%source_addr, %dest_addr, component="inbound plaintext", "connecting...");
 
-            let outbound = super::freebind_connect(orig_src, dest_addr, pi.socket_factory.as_ref())
-                .await
-                .map_err(Error::ConnectionFailed)?;
+            let outbound = super::freebind_connect(
+                orig_src,
+                dest_addr,
+                pi.socket_factory.as_ref(),
+                pi.cfg.connect_timeout,
+            )
+            .await
+            .map_err(Error::ConnectionFailed)?;
 
             trace!(%source_addr, destination=%dest_addr, component="inbound plaintext", "connected");
-            copy::copy_bidirectional(
+            copy::copy_bidirectional_with_limits(
                 copy::TcpStreamSplitter(inbound_stream),
                 copy::TcpStreamSplitter(outbound),
                 &result_tracker,
+                pi.cfg.idle_timeout,
+                pi.cfg.half_close_timeout,
+                rate_limit,
+                pi.cfg.copy_buffer_max_size,
             )
             .await
         };
 
-        let res = handle_connection!(conn_guard, send);
+        let res = match conn_guard {
+            Some(mut conn_guard) => handle_connection!(conn_guard, send),
+            None => send.await,
+        };
         result_tracker.record(res);
     }
 }
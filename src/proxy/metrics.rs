@@ -12,18 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, atomic};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use prometheus_client::encoding::{
-    EncodeLabelSet, EncodeLabelValue, LabelSetEncoder, LabelValueEncoder,
+    EncodeLabel, EncodeLabelSet, EncodeLabelValue, LabelSetEncoder, LabelValueEncoder,
 };
 use prometheus_client::metrics::counter::{Atomic, Counter};
 use prometheus_client::metrics::family::Family;
-use prometheus_client::registry::Registry;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+use prometheus_client::registry::{Registry, Unit};
 
 use tracing::event;
 use tracing_core::field::Value;
@@ -34,7 +39,8 @@ use crate::proxy::{self, HboneAddress};
 
 use crate::state::service::ServiceDescription;
 use crate::state::workload::Workload;
-use crate::strng::{RichStrng, Strng};
+use crate::strng::{self, RichStrng, Strng};
+use crate::tls::TlsError;
 
 #[derive(Debug)]
 pub struct Metrics {
@@ -42,9 +48,63 @@ pub struct Metrics {
     pub connection_close: Family<CommonTrafficLabels, Counter>,
     pub received_bytes: Family<CommonTrafficLabels, Counter>,
     pub sent_bytes: Family<CommonTrafficLabels, Counter>,
+    // how long connections stay open, and the longest gap without traffic on each, recorded on close
+    pub connection_duration: Family<ConnectionLifetimeLabels, Histogram>,
+    pub connection_max_idle: Family<ConnectionLifetimeLabels, Histogram>,
 
     // on-demand DNS is not a part of DNS proxy, but part of ztunnel proxy itself
     pub on_demand_dns: Family<OnDemandDnsLabels, Counter>,
+
+    pub connections_denied: Family<DenyLabels, Counter>,
+    // inbound connections rejected because the client did not present a certificate
+    pub plaintext_rejected: Counter,
+    // inbound connections rejected because the client certificate was from an untrusted trust domain
+    pub unknown_trust_domain_rejected: Counter,
+    // accept failures because the process or system ran out of file descriptors
+    pub accept_fd_exhausted: Counter,
+    // inbound CONNECT requests rejected because the authority was too long
+    pub oversized_authority_rejected: Counter,
+    // inbound CONNECT requests rejected because their headers were too large
+    pub oversized_headers_rejected: Counter,
+    // connections rejected because their destination would loop back to ztunnel
+    pub hbone_loops_detected: Counter,
+    // outbound connections sent over plaintext because establishing mTLS to the destination failed
+    pub plaintext_fallbacks: Counter,
+    // connections still open when the drain deadline expired, which were then closed forcefully
+    pub connections_force_closed: Counter,
+    // inbound connections currently being served, and how long accepting waited for the limit on them
+    pub inbound_connections_in_flight: Gauge,
+    pub inbound_connection_limit_wait: Histogram,
+    // inbound TLS handshakes that failed, by cause
+    pub tls_handshake_failures: Family<TlsHandshakeFailureLabels, Counter>,
+    // inbound mTLS connections accepted, by negotiated TLS version and cipher suite
+    pub tls_connections: Family<TlsInfo, Counter>,
+    // times relaying stopped because the peer was not accepting writes, by direction
+    pub write_stalls: Family<WriteStallLabels, Counter>,
+    // the write_stalls series for each direction, fetched once since stalls are counted on the
+    // copy hot path
+    send_stalls: Counter,
+    receive_stalls: Counter,
+    // failed connects to the upstream of a connection, by cause
+    pub upstream_connect_failures: Family<ConnectFailureLabels, Counter>,
+
+    // certificates fetched ahead of time for local workloads, by outcome
+    pub cert_prefetch: Family<CertPrefetchLabels, Counter>,
+    // connections closed, by how they terminated
+    pub connection_close_reasons: Family<CloseReasonLabels, Counter>,
+    // connections opened, by whether they stayed within the source's zone
+    pub connection_locality: Family<LocalityMatchLabels, Counter>,
+    // inbound connections opened, by how they reached the destination
+    pub inbound_traversals: Family<TraversalLabels, Counter>,
+
+    // If set, traffic metrics only report whether traffic stayed in its zone, rather than the raw
+    // source and destination region and zone.
+    coarse_locality: bool,
+    // If set, only 1 in this many connections without errors are access logged.
+    access_log_sampling: Option<u32>,
+    // The extra baggage keys reported as source labels. Every key is reported on every connection,
+    // so traffic metrics keep a fixed label set.
+    baggage_labels: Vec<Strng>,
 }
 
 #[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
@@ -71,6 +131,10 @@ pub enum ResponseFlags {
     AuthorizationPolicyDenied,
     // connection denied because we could not establish an upstream connection
     ConnectionFailure,
+    // connection denied because the source identity has too many open connections
+    ConnectionLimitExceeded,
+    // connection denied because the destination service's circuit breaker is open
+    CircuitBreakerOpen,
 }
 
 impl EncodeLabelValue for ResponseFlags {
@@ -79,15 +143,282 @@ impl EncodeLabelValue for ResponseFlags {
             ResponseFlags::None => writer.write_str("-"),
             ResponseFlags::AuthorizationPolicyDenied => writer.write_str("DENY"),
             ResponseFlags::ConnectionFailure => writer.write_str("CONNECT"),
+            ResponseFlags::ConnectionLimitExceeded => writer.write_str("OVERFLOW"),
+            ResponseFlags::CircuitBreakerOpen => writer.write_str("CIRCUIT_OPEN"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum DenyReason {
+    // denied by an authorization policy
+    rbac_policy,
+    // denied by an authorization policy, for traffic that skipped the destination's waypoint
+    waypoint_bypass,
+    // the HBONE target did not match the connection, and we are not its waypoint
+    ip_mismatch,
+    // the HBONE target could not be parsed
+    malformed_authority,
+    // the HBONE target could not be resolved to a known destination
+    unknown_destination,
+    // the source identity has too many open connections
+    connection_limit,
+}
+
+impl DenyReason {
+    /// Classifies a request failure as a denial, if it is one.
+    pub fn from_error(err: &proxy::Error) -> Option<Self> {
+        match err {
+            proxy::Error::AuthorizationPolicyRejection(_) => Some(DenyReason::rbac_policy),
+            proxy::Error::IPMismatch(_, _) => Some(DenyReason::ip_mismatch),
+            proxy::Error::NoValidAuthority(_) | proxy::Error::ConnectAddress(_) => {
+                Some(DenyReason::malformed_authority)
+            }
+            proxy::Error::NoHostname(_)
+            | proxy::Error::NoPortForServices(_, _)
+            | proxy::Error::NoIPForService(_) => Some(DenyReason::unknown_destination),
+            proxy::Error::ConnectionLimitExceeded(_) => Some(DenyReason::connection_limit),
+            _ => None,
+        }
+    }
+
+    /// Classifies the failure of an inbound request as a denial, if it is one. Policy denials of
+    /// traffic that did not come through the destination's waypoint are reported as waypoint bypasses.
+    pub fn from_inbound_error(err: &proxy::Error, bypassed_waypoint: bool) -> Option<Self> {
+        match Self::from_error(err)? {
+            DenyReason::rbac_policy if bypassed_waypoint => Some(DenyReason::waypoint_bypass),
+            reason => Some(reason),
+        }
+    }
+}
+
+/// CloseReason classifies how a connection terminated, so abnormal closes can be told apart from
+/// connections that simply finished.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum CloseReason {
+    // both sides finished the connection normally
+    clean_eof,
+    // the connection failed or was reset
+    error,
+    // the connection was closed because policy denied it
+    policy_close,
+    // the connection was closed because ztunnel was draining
+    drain_close,
+    // the connection was dropped before its result was known, for example because its task was
+    // cancelled or panicked
+    cancelled,
+}
+
+impl CloseReason {
+    fn from_result<E: std::error::Error + 'static>(res: &Result<(), E>) -> Self {
+        let Err(e) = res else {
+            return CloseReason::clean_eof;
+        };
+        let e: &(dyn std::error::Error + 'static) = e;
+        match e.downcast_ref::<proxy::Error>() {
+            Some(
+                proxy::Error::AuthorizationPolicyRejection(_)
+                | proxy::Error::AuthorizationPolicyLateRejection,
+            ) => CloseReason::policy_close,
+            Some(proxy::Error::ClosedFromDrain | proxy::Error::DrainTimeOut) => {
+                CloseReason::drain_close
+            }
+            _ => CloseReason::error,
         }
     }
 }
 
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct CloseReasonLabels {
+    reporter: Reporter,
+    close_reason: CloseReason,
+}
+
+// Connection lifetime histograms are only split by direction; the full traffic labels would multiply
+// every bucket by their cardinality.
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionLifetimeLabels {
+    reporter: Reporter,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum PrefetchResult {
+    success,
+    failure,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct CertPrefetchLabels {
+    pub result: PrefetchResult,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum CopyDirection {
+    // writing to the downstream, as counted in sent bytes
+    send,
+    // writing to the upstream, as counted in received bytes
+    receive,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct WriteStallLabels {
+    pub direction: CopyDirection,
+}
+
+// Clients without a certificate or from an untrusted trust domain are counted by plaintext_rejected
+// and unknown_trust_domain_rejected instead, so they have no cause here.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TlsHandshakeFailureCause {
+    // We could not load a certificate to serve, typically because the local workload is unknown
+    certificate_lookup,
+    expired_certificate,
+    untrusted_certificate,
+    unexpected_alpn,
+    timeout,
+    protocol,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct TlsHandshakeFailureLabels {
+    pub cause: TlsHandshakeFailureCause,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TlsVersion {
+    tls1_2,
+    tls1_3,
+    other,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TlsCipher {
+    aes_128_gcm_sha256,
+    aes_256_gcm_sha384,
+    chacha20_poly1305_sha256,
+    other,
+}
+
+/// TlsInfo describes what a TLS connection negotiated. Unexpected values are reported as `other`,
+/// to keep the labels bounded.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TlsInfo {
+    pub tls_version: TlsVersion,
+    pub tls_cipher: TlsCipher,
+}
+
+impl TlsInfo {
+    pub fn from_connection(conn: &rustls::CommonState) -> Self {
+        use rustls::{CipherSuite, ProtocolVersion};
+        let tls_version = match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => TlsVersion::tls1_2,
+            Some(ProtocolVersion::TLSv1_3) => TlsVersion::tls1_3,
+            _ => TlsVersion::other,
+        };
+        let tls_cipher = match conn.negotiated_cipher_suite().map(|s| s.suite()) {
+            Some(
+                CipherSuite::TLS13_AES_128_GCM_SHA256
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+                | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+            ) => TlsCipher::aes_128_gcm_sha256,
+            Some(
+                CipherSuite::TLS13_AES_256_GCM_SHA384
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+                | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            ) => TlsCipher::aes_256_gcm_sha384,
+            Some(
+                CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+                | CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            ) => TlsCipher::chacha20_poly1305_sha256,
+            _ => TlsCipher::other,
+        };
+        TlsInfo {
+            tls_version,
+            tls_cipher,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ConnectFailureCause {
+    refused,
+    timeout,
+    host_unreachable,
+    network_unreachable,
+    other,
+}
+
+impl From<&io::Error> for ConnectFailureCause {
+    fn from(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Self::refused,
+            io::ErrorKind::TimedOut => Self::timeout,
+            io::ErrorKind::HostUnreachable => Self::host_unreachable,
+            io::ErrorKind::NetworkUnreachable => Self::network_unreachable,
+            _ => Self::other,
+        }
+    }
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectFailureLabels {
+    pub reporter: Reporter,
+    pub cause: ConnectFailureCause,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct DenyLabels {
+    reporter: Reporter,
+    reason: DenyReason,
+    source_principal: DefaultedUnknown<Identity>,
+    destination_principal: DefaultedUnknown<Identity>,
+}
+
 #[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum SecurityPolicy {
     #[default]
     unknown,
     mutual_tls,
+    // plaintext traffic to a destination outside of the mesh
+    passthrough,
+}
+
+/// LocalityMatch describes whether traffic stayed within the source's zone, without the cardinality
+/// of the raw zone names.
+#[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum LocalityMatch {
+    #[default]
+    unknown,
+    same_zone,
+    cross_zone,
+    cross_region,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct LocalityMatchLabels {
+    reporter: Reporter,
+    locality_match: LocalityMatch,
+}
+
+/// Traversal describes how an inbound connection reached the destination, so traffic through
+/// waypoints and gateways can be measured. It is only known to the destination.
+#[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Traversal {
+    #[default]
+    unknown,
+    // sent by the source directly to the destination
+    direct,
+    // sent by the destination's waypoint
+    via_waypoint,
+    // forwarded through a network gateway
+    via_gateway,
+    // the destination is a waypoint receiving a tunnel addressed to the final destination
+    sandwich,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct TraversalLabels {
+    pub traversal: Traversal,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -100,6 +431,8 @@ pub struct DerivedWorkload {
     pub cluster_id: Option<Strng>,
     pub region: Option<Strng>,
     pub zone: Option<Strng>,
+    // Additional allowlisted labels propagated through baggage.
+    pub labels: BTreeMap<Strng, Strng>,
 }
 
 #[derive(Clone)]
@@ -153,6 +486,7 @@ impl CommonTrafficLabels {
         local.source_region = w.region.clone().into();
         local.source_zone = w.zone.clone().into();
         self.locality = OptionallyEncode(Some(local));
+        self.source_labels = BaggageLabels(w.labels.clone());
 
         self
     }
@@ -234,6 +568,9 @@ pub struct CommonTrafficLabels {
 
     #[prometheus(flatten)]
     locality: OptionallyEncode<LocalityLabels>,
+
+    #[prometheus(flatten)]
+    source_labels: BaggageLabels,
 }
 
 /// OptionallyEncode is a wrapper that will optionally encode the entire label set.
@@ -249,6 +586,37 @@ impl<T: EncodeLabelSet> EncodeLabelSet for OptionallyEncode<T> {
         }
     }
 }
+/// BaggageLabels are the extra source labels received through baggage. Each key is reported as
+/// source_label_<key>, with any characters not valid in a label name replaced by '_'.
+#[derive(Clone, Hash, Default, Debug, PartialEq, Eq)]
+struct BaggageLabels(BTreeMap<Strng, Strng>);
+
+impl BaggageLabels {
+    // for_keys reports exactly `keys`, as unknown when they were not received.
+    fn for_keys(&self, keys: &[Strng]) -> Self {
+        BaggageLabels(
+            keys.iter()
+                .map(|k| {
+                    let v = self.0.get(k).cloned();
+                    (k.clone(), v.unwrap_or(strng::literal!("unknown")))
+                })
+                .collect(),
+        )
+    }
+}
+impl EncodeLabelSet for BaggageLabels {
+    fn encode(&self, mut encoder: LabelSetEncoder) -> Result<(), std::fmt::Error> {
+        for (k, v) in &self.0 {
+            let key: String = format!("source_label_{k}")
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            (key, v.as_str()).encode(encoder.encode_label())?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
 struct LocalityLabels {
     source_region: DefaultedUnknown<RichStrng>,
@@ -257,6 +625,25 @@ struct LocalityLabels {
     destination_zone: DefaultedUnknown<RichStrng>,
 }
 
+impl LocalityLabels {
+    fn locality_match(&self) -> LocalityMatch {
+        let (Some(src_region), Some(dst_region)) = (
+            self.source_region.as_ref(),
+            self.destination_region.as_ref(),
+        ) else {
+            return LocalityMatch::unknown;
+        };
+        if src_region != dst_region {
+            return LocalityMatch::cross_region;
+        }
+        match (self.source_zone.as_ref(), self.destination_zone.as_ref()) {
+            (Some(src), Some(dst)) if src == dst => LocalityMatch::same_zone,
+            (Some(_), Some(_)) => LocalityMatch::cross_zone,
+            _ => LocalityMatch::unknown,
+        }
+    }
+}
+
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
 pub struct OnDemandDnsLabels {
     // on-demand DNS client information is just nice-to-have
@@ -323,6 +710,28 @@ impl Metrics {
             "The size of total bytes sent during response in case of a TCP connection",
             sent_bytes.clone(),
         );
+        // 100ms up to ~7 hours
+        let connection_duration =
+            Family::<ConnectionLifetimeLabels, Histogram>::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.1, 4.0, 10))
+            });
+        registry.register_with_unit(
+            "tcp_connection_duration",
+            "The duration of TCP connections, recorded when they close (unstable)",
+            Unit::Seconds,
+            connection_duration.clone(),
+        );
+        // 10ms up to ~45 minutes
+        let connection_max_idle =
+            Family::<ConnectionLifetimeLabels, Histogram>::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.01, 4.0, 10))
+            });
+        registry.register_with_unit(
+            "tcp_connection_max_idle",
+            "The longest time a TCP connection went without sending or receiving data, recorded when it closes (unstable)",
+            Unit::Seconds,
+            connection_max_idle.clone(),
+        );
         let on_demand_dns = Family::default();
         registry.register(
             "on_demand_dns",
@@ -330,14 +739,312 @@ impl Metrics {
             on_demand_dns.clone(),
         );
 
+        let connections_denied = Family::default();
+        registry.register(
+            "connections_denied",
+            "The total number of connections denied, by reason (unstable)",
+            connections_denied.clone(),
+        );
+
+        let plaintext_rejected = Counter::default();
+        registry.register(
+            "plaintext_rejected",
+            "The total number of inbound connections rejected for not presenting a client certificate (unstable)",
+            plaintext_rejected.clone(),
+        );
+
+        let unknown_trust_domain_rejected = Counter::default();
+        registry.register(
+            "unknown_trust_domain_rejected",
+            "The total number of inbound connections rejected because the client certificate was not from an accepted trust domain (unstable)",
+            unknown_trust_domain_rejected.clone(),
+        );
+
+        let accept_fd_exhausted = Counter::default();
+        registry.register(
+            "accept_fd_exhausted",
+            "The total number of times a listener failed to accept a connection because file descriptors were exhausted (unstable)",
+            accept_fd_exhausted.clone(),
+        );
+
+        let oversized_authority_rejected = Counter::default();
+        registry.register(
+            "oversized_authority_rejected",
+            "The total number of inbound CONNECT requests rejected because the authority was too long (unstable)",
+            oversized_authority_rejected.clone(),
+        );
+
+        let oversized_headers_rejected = Counter::default();
+        registry.register(
+            "oversized_headers_rejected",
+            "The total number of inbound CONNECT requests rejected because their headers were too large (unstable)",
+            oversized_headers_rejected.clone(),
+        );
+
+        let hbone_loops_detected = Counter::default();
+        registry.register(
+            "hbone_loops_detected",
+            "The total number of connections rejected because they would loop back to ztunnel (unstable)",
+            hbone_loops_detected.clone(),
+        );
+
+        let plaintext_fallbacks = Counter::default();
+        registry.register(
+            "plaintext_fallbacks",
+            "The total number of outbound connections sent over plaintext because establishing mTLS to the destination failed (unstable)",
+            plaintext_fallbacks.clone(),
+        );
+
+        let connections_force_closed = Counter::default();
+        registry.register(
+            "connections_force_closed",
+            "The total number of connections forcefully closed because they did not complete before the drain deadline (unstable)",
+            connections_force_closed.clone(),
+        );
+
+        let inbound_connections_in_flight = Gauge::default();
+        registry.register(
+            "inbound_connections_in_flight",
+            "The number of inbound connections currently being served (unstable)",
+            inbound_connections_in_flight.clone(),
+        );
+        let inbound_connection_limit_wait = Histogram::new(exponential_buckets(0.001, 4.0, 10));
+        registry.register_with_unit(
+            "inbound_connection_limit_wait",
+            "Time spent waiting for the inbound connection limit before accepting a connection (unstable)",
+            Unit::Seconds,
+            inbound_connection_limit_wait.clone(),
+        );
+
+        let tls_handshake_failures = Family::default();
+        registry.register(
+            "tls_handshake_failures",
+            "The total number of inbound TLS handshakes that failed, by cause (unstable)",
+            tls_handshake_failures.clone(),
+        );
+
+        let tls_connections = Family::default();
+        registry.register(
+            "tls_connections",
+            "The total number of inbound mTLS connections accepted, by negotiated TLS version and cipher suite (unstable)",
+            tls_connections.clone(),
+        );
+
+        let write_stalls = Family::default();
+        registry.register(
+            "write_stalls",
+            "The total number of times relaying data stalled because the peer was not accepting writes (unstable)",
+            write_stalls.clone(),
+        );
+        let stalls = |direction| {
+            write_stalls
+                .get_or_create(&WriteStallLabels { direction })
+                .clone()
+        };
+        let (send_stalls, receive_stalls) =
+            (stalls(CopyDirection::send), stalls(CopyDirection::receive));
+
+        let upstream_connect_failures = Family::default();
+        registry.register(
+            "upstream_connect_failures",
+            "The total number of failed connects to a connection's upstream, by cause (unstable)",
+            upstream_connect_failures.clone(),
+        );
+
+        let cert_prefetch = Family::default();
+        registry.register(
+            "cert_prefetch",
+            "The total number of workload certificates fetched ahead of the first connection, by result (unstable)",
+            cert_prefetch.clone(),
+        );
+
+        let connection_close_reasons = Family::default();
+        registry.register(
+            "tcp_connection_close_reasons",
+            "The total number of TCP connections closed, by how they terminated (unstable)",
+            connection_close_reasons.clone(),
+        );
+
+        let connection_locality = Family::default();
+        registry.register(
+            "tcp_connections_locality",
+            "The total number of TCP connections opened, by whether they stayed within the source's zone or region (unstable)",
+            connection_locality.clone(),
+        );
+
+        let inbound_traversals = Family::default();
+        registry.register(
+            "inbound_connection_traversals",
+            "The total number of inbound connections opened, by how they reached the destination (unstable)",
+            inbound_traversals.clone(),
+        );
+
         Self {
             connection_opens,
             connection_close,
             received_bytes,
             sent_bytes,
+            connection_duration,
+            connection_max_idle,
             on_demand_dns,
+            connections_denied,
+            plaintext_rejected,
+            unknown_trust_domain_rejected,
+            accept_fd_exhausted,
+            oversized_authority_rejected,
+            oversized_headers_rejected,
+            hbone_loops_detected,
+            plaintext_fallbacks,
+            connections_force_closed,
+            inbound_connections_in_flight,
+            inbound_connection_limit_wait,
+            tls_handshake_failures,
+            tls_connections,
+            write_stalls,
+            send_stalls,
+            receive_stalls,
+            upstream_connect_failures,
+            cert_prefetch,
+            connection_close_reasons,
+            connection_locality,
+            inbound_traversals,
+            coarse_locality: false,
+            access_log_sampling: None,
+            baggage_labels: Vec::new(),
         }
     }
+
+    /// with_baggage_labels makes traffic metrics report a source_label_<key> label for each of
+    /// `keys`, whether or not the peer sent it.
+    pub fn with_baggage_labels(mut self, keys: Vec<Strng>) -> Self {
+        self.baggage_labels = keys;
+        self
+    }
+
+    /// with_access_log_sampling makes only 1 in `sampling` successful connections be access logged.
+    /// Failed connections are always logged.
+    pub fn with_access_log_sampling(mut self, sampling: Option<u32>) -> Self {
+        self.access_log_sampling = sampling;
+        self
+    }
+
+    // access_log_sampled returns whether the connection from src to dst is sampled for access logs.
+    // This is a hash of the addresses, so every log for a given flow is consistently kept or dropped.
+    fn access_log_sampled(&self, src: SocketAddr, dst: SocketAddr) -> bool {
+        let Some(n) = self.access_log_sampling.filter(|n| *n > 1) else {
+            return true;
+        };
+        let mut hasher = std::hash::DefaultHasher::new();
+        (src, dst).hash(&mut hasher);
+        hasher.finish() % u64::from(n) == 0
+    }
+
+    /// with_coarse_locality makes traffic metrics leave out the raw source and destination region
+    /// and zone, leaving only the tcp_connections_locality breakdown.
+    pub fn with_coarse_locality(mut self, coarse_locality: bool) -> Self {
+        self.coarse_locality = coarse_locality;
+        self
+    }
+
+    /// record_tls_handshake_failure counts an inbound TLS handshake that failed with `err`.
+    pub fn record_tls_handshake_failure(&self, err: &TlsError) {
+        let cause = match err {
+            TlsError::MissingClientCertificate => {
+                self.plaintext_rejected.inc();
+                return;
+            }
+            TlsError::SanTrustDomainError(..) => {
+                self.unknown_trust_domain_rejected.inc();
+                return;
+            }
+            TlsError::SigningError(_) | TlsError::SslError(_) => {
+                TlsHandshakeFailureCause::certificate_lookup
+            }
+            TlsError::SanError(..) => TlsHandshakeFailureCause::untrusted_certificate,
+            TlsError::UnexpectedAlpn(_) => TlsHandshakeFailureCause::unexpected_alpn,
+            TlsError::Handshake(e) => match e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            {
+                Some(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::Expired | rustls::CertificateError::NotValidYet,
+                )) => TlsHandshakeFailureCause::expired_certificate,
+                Some(rustls::Error::InvalidCertificate(_)) => {
+                    TlsHandshakeFailureCause::untrusted_certificate
+                }
+                _ => TlsHandshakeFailureCause::protocol,
+            },
+        };
+        self.record_tls_handshake_failure_cause(cause);
+    }
+
+    pub fn record_tls_handshake_failure_cause(&self, cause: TlsHandshakeFailureCause) {
+        self.tls_handshake_failures
+            .get_or_create(&TlsHandshakeFailureLabels { cause })
+            .inc();
+    }
+
+    /// record_tls_connection counts an accepted inbound mTLS connection, returning what it negotiated.
+    pub fn record_tls_connection(&self, conn: &rustls::CommonState) -> TlsInfo {
+        let tls = TlsInfo::from_connection(conn);
+        self.tls_connections.get_or_create(&tls).inc();
+        tls
+    }
+
+    /// record_connect_failure counts a failed connect to an upstream, returning its cause.
+    pub fn record_connect_failure(
+        &self,
+        reporter: Reporter,
+        err: &io::Error,
+    ) -> ConnectFailureCause {
+        let cause = err.into();
+        self.upstream_connect_failures
+            .get_or_create(&ConnectFailureLabels { reporter, cause })
+            .inc();
+        cause
+    }
+
+    /// record_traversal counts an inbound connection that reached the destination by `traversal`.
+    pub fn record_traversal(&self, traversal: Traversal) {
+        self.inbound_traversals
+            .get_or_create(&TraversalLabels { traversal })
+            .inc();
+    }
+
+    /// record_deny counts a connection denied for `reason`.
+    pub fn record_deny(
+        &self,
+        reporter: Reporter,
+        reason: DenyReason,
+        source: Option<Identity>,
+        destination: Option<Identity>,
+    ) {
+        self.connections_denied
+            .get_or_create(&DenyLabels {
+                reporter,
+                reason,
+                source_principal: source.into(),
+                destination_principal: destination.into(),
+            })
+            .inc();
+    }
+}
+
+/// ConnectionBytes counts the bytes relayed on a single connection so far.
+#[derive(Debug, Default)]
+pub struct ConnectionBytes {
+    sent: AtomicU64,
+    recv: AtomicU64,
+}
+
+impl ConnectionBytes {
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn recv(&self) -> u64 {
+        self.recv.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug)]
@@ -357,14 +1064,20 @@ pub struct ConnectionResult {
     tl: CommonTrafficLabels,
     metrics: Arc<Metrics>,
 
-    // sent records the number of bytes sent on this connection
-    sent: AtomicU64,
+    // bytes records the number of bytes sent and received on this connection
+    bytes: Arc<ConnectionBytes>,
     // sent_metric records the number of bytes sent on this connection to the aggregated metric counter
     sent_metric: Counter,
-    // recv records the number of bytes received on this connection
-    recv: AtomicU64,
     // recv_metric records the number of bytes received on this connection to the aggregated metric counter
     recv_metric: Counter,
+    // last_activity is when bytes were last sent or received, in nanoseconds since start
+    last_activity: AtomicU64,
+    // max_idle is the longest gap between activity seen so far, in nanoseconds
+    max_idle: AtomicU64,
+    // Opaque tags supplied by the client, reported in access logs
+    tag: Option<Strng>,
+    // What the mTLS connection carrying this one negotiated, if any, reported in access logs
+    tls: Option<TlsInfo>,
     // Have we recorded yet?
     recorded: bool,
 }
@@ -440,38 +1153,56 @@ impl ConnectionResult {
             dst,
             conn.destination.as_ref().map(|wl| wl.name.clone().into()),
         );
-        let tl = CommonTrafficLabels::from(conn);
+        let mut tl = CommonTrafficLabels::from(conn);
+        tl.source_labels = tl.source_labels.for_keys(&metrics.baggage_labels);
+        metrics
+            .connection_locality
+            .get_or_create(&LocalityMatchLabels {
+                reporter: tl.reporter,
+                locality_match: tl
+                    .locality
+                    .0
+                    .as_ref()
+                    .map(LocalityLabels::locality_match)
+                    .unwrap_or_default(),
+            })
+            .inc();
+        if metrics.coarse_locality {
+            tl.locality = OptionallyEncode(None);
+        }
         metrics.connection_opens.get_or_create(&tl).inc();
 
         let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
 
         src.1 = src.1.or(tl.source_canonical_service.clone().inner());
         dst.1 = dst.1.or(tl.destination_canonical_service.clone().inner());
-        event!(
-            target: "access",
-            parent: None,
-            tracing::Level::DEBUG,
-
-            src.addr = %src.0,
-            src.workload = src.1.as_deref().map(to_value),
-            src.namespace = tl.source_workload_namespace.to_value(),
-            src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(to_value_owned),
-
-            dst.addr = %dst.0,
-            dst.hbone_addr = hbone_target.as_ref().map(display),
-            dst.service = tl.destination_service.to_value(),
-            dst.workload = dst.1.as_deref().map(to_value),
-            dst.namespace = tl.destination_workload_namespace.to_value(),
-            dst.identity = tl.destination_principal.as_ref().filter(|_| mtls).map(to_value_owned),
-
-            direction = if tl.reporter == Reporter::source {
-                "outbound"
-            } else {
-                "inbound"
-            },
-
-            "connection opened"
-        );
+        if metrics.access_log_sampled(src.0, dst.0) {
+            event!(
+                target: "access",
+                parent: None,
+                tracing::Level::DEBUG,
+
+                src.addr = %src.0,
+                src.workload = src.1.as_deref().map(to_value),
+                src.namespace = tl.source_workload_namespace.to_value(),
+                src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(to_value_owned),
+
+                dst.addr = %dst.0,
+                dst.hbone_addr = hbone_target.as_ref().map(display),
+                dst.service = tl.destination_service.to_value(),
+                dst.workload = dst.1.as_deref().map(to_value),
+                dst.namespace = tl.destination_workload_namespace.to_value(),
+                dst.identity = tl.destination_principal.as_ref().filter(|_| mtls).map(to_value_owned),
+
+                direction = if tl.reporter == Reporter::source {
+                    "outbound"
+                } else {
+                    "inbound"
+                },
+
+                "connection opened"
+            );
+        }
         // Grab the metrics with our labels now, so we don't need to fetch them each time.
         // The inner metric is an Arc so clone is fine/cheap.
         // With the raw Counter, we increment is a simple atomic add operation (~1ns).
@@ -479,8 +1210,6 @@ impl ConnectionResult {
         // add up.
         let sent_metric = metrics.sent_bytes.get_or_create(&tl).clone();
         let recv_metric = metrics.received_bytes.get_or_create(&tl).clone();
-        let sent = atomic::AtomicU64::new(0);
-        let recv = atomic::AtomicU64::new(0);
         Self {
             src,
             dst,
@@ -489,26 +1218,70 @@ impl ConnectionResult {
             tl,
             metrics,
 
-            sent,
+            bytes: Default::default(),
             sent_metric,
-            recv,
             recv_metric,
+            last_activity: AtomicU64::new(0),
+            max_idle: AtomicU64::new(0),
+            tag: None,
+            tls: None,
             recorded: false,
         }
     }
 
+    pub fn with_tag(mut self, tag: Option<Strng>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: Option<TlsInfo>) -> Self {
+        self.tls = tls;
+        self
+    }
+
     pub fn increment_send(&self, res: u64) {
-        self.sent.inc_by(res);
+        self.bytes.sent.inc_by(res);
         self.sent_metric.inc_by(res);
+        self.mark_active();
     }
 
     pub fn increment_recv(&self, res: u64) {
-        self.recv.inc_by(res);
+        self.bytes.recv.inc_by(res);
         self.recv_metric.inc_by(res);
+        self.mark_active();
+    }
+
+    // increment_write_stall records that writing to the peer in the given direction could not
+    // make progress.
+    pub fn increment_write_stall(&self, direction: CopyDirection) {
+        match direction {
+            CopyDirection::send => &self.metrics.send_stalls,
+            CopyDirection::receive => &self.metrics.receive_stalls,
+        }
+        .inc();
+    }
+
+    // mark_active records activity now, tracking the idle gap since the previous activity.
+    fn mark_active(&self) -> u64 {
+        let now = self.start.elapsed().as_nanos() as u64;
+        let prev = self.last_activity.swap(now, Ordering::Relaxed);
+        self.max_idle
+            .fetch_max(now.saturating_sub(prev), Ordering::Relaxed);
+        now
+    }
+
+    // bytes_transferred returns the total number of bytes transferred on this connection, in either direction.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.bytes.sent() + self.bytes.recv()
+    }
+
+    // bytes returns the byte counts of this connection, which stay current as data is relayed.
+    pub fn bytes(&self) -> Arc<ConnectionBytes> {
+        self.bytes.clone()
     }
 
     // Record our final result, with more details as a response flag.
-    pub fn record_with_flag<E: std::error::Error>(
+    pub fn record_with_flag<E: std::error::Error + 'static>(
         mut self,
         res: Result<(), E>,
         flag: ResponseFlags,
@@ -518,12 +1291,17 @@ impl ConnectionResult {
     }
 
     // Record our final result.
-    pub fn record<E: std::error::Error>(mut self, res: Result<(), E>) {
-        self.record_internal(res)
+    pub fn record<E: std::error::Error + 'static>(mut self, res: Result<(), E>) {
+        let close_reason = CloseReason::from_result(&res);
+        self.record_internal(res, close_reason)
     }
 
     // Internal-only function that takes `&mut` to facilitate Drop. Public consumers must use consuming functions.
-    fn record_internal<E: std::error::Error>(&mut self, res: Result<(), E>) {
+    fn record_internal<E: std::error::Error + 'static>(
+        &mut self,
+        res: Result<(), E>,
+        close_reason: CloseReason,
+    ) {
         debug_assert!(!self.recorded, "record called multiple times");
         if self.recorded {
             return;
@@ -533,13 +1311,42 @@ impl ConnectionResult {
 
         // Unconditionally record the connection was closed
         self.metrics.connection_close.get_or_create(tl).inc();
+        self.metrics
+            .connection_close_reasons
+            .get_or_create(&CloseReasonLabels {
+                reporter: tl.reporter,
+                close_reason,
+            })
+            .inc();
+        // The time since the last activity counts as idle too; this is what idle timeouts cut off.
+        let elapsed = Duration::from_nanos(self.mark_active());
+        let max_idle = Duration::from_nanos(self.max_idle.load(Ordering::Relaxed));
+        let lifetime_labels = ConnectionLifetimeLabels {
+            reporter: tl.reporter,
+        };
+        self.metrics
+            .connection_duration
+            .get_or_create(&lifetime_labels)
+            .observe(elapsed.as_secs_f64());
+        self.metrics
+            .connection_max_idle
+            .get_or_create(&lifetime_labels)
+            .observe(max_idle.as_secs_f64());
 
-        // Unconditionally write out an access log
         let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
         let bytes = (
-            self.recv.load(Ordering::SeqCst),
-            self.sent.load(Ordering::SeqCst),
+            self.bytes.recv.load(Ordering::SeqCst),
+            self.bytes.sent.load(Ordering::SeqCst),
         );
+        #[cfg(feature = "otel")]
+        if crate::telemetry::otel::enabled() {
+            self.annotate_span(&res, mtls, bytes);
+        }
+
+        // Write out an access log. Failures are always logged, regardless of sampling.
+        if res.is_ok() && !self.metrics.access_log_sampled(self.src.0, self.dst.0) {
+            return;
+        }
         let dur = format!("{}ms", self.start.elapsed().as_millis());
 
         // We use our own macro to allow setting the level dynamically
@@ -569,14 +1376,66 @@ impl ConnectionResult {
             bytes_sent = if tl.reporter == Reporter::source {bytes.0} else {bytes.1},
             bytes_recv = if tl.reporter == Reporter::source {bytes.1} else {bytes.0},
             duration = dur,
+            tag = self.tag.as_ref().map(to_value),
+            tls.version = self.tls.map(|t| debug(t.tls_version)),
+            tls.cipher = self.tls.map(|t| debug(t.tls_cipher)),
         );
     }
 }
 
+impl ConnectionResult {
+    // annotate_span records the outcome of the connection on the exported span for it.
+    #[cfg(feature = "otel")]
+    fn annotate_span<E: std::error::Error>(
+        &self,
+        res: &Result<(), E>,
+        mtls: bool,
+        bytes: (u64, u64),
+    ) {
+        use opentelemetry::trace::Status;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let tl = &self.tl;
+        let span = tracing::Span::current();
+        span.set_attribute("src.addr", self.src.0.to_string());
+        span.set_attribute("dst.addr", self.dst.0.to_string());
+        if let Some(hbone) = &self.hbone_target {
+            span.set_attribute("dst.hbone_addr", hbone.to_string());
+        }
+        if mtls {
+            if let Some(id) = tl.source_principal.as_ref() {
+                span.set_attribute("src.identity", id.to_string());
+            }
+            if let Some(id) = tl.destination_principal.as_ref() {
+                span.set_attribute("dst.identity", id.to_string());
+            }
+        }
+        let (direction, (sent, recv)) = if tl.reporter == Reporter::source {
+            ("outbound", bytes)
+        } else {
+            ("inbound", (bytes.1, bytes.0))
+        };
+        if let Some(tag) = &self.tag {
+            span.set_attribute("tag", tag.to_string());
+        }
+        if let Some(tls) = self.tls {
+            span.set_attribute("tls.version", format!("{:?}", tls.tls_version));
+            span.set_attribute("tls.cipher", format!("{:?}", tls.tls_cipher));
+        }
+        span.set_attribute("direction", direction);
+        span.set_attribute("bytes_sent", sent as i64);
+        span.set_attribute("bytes_recv", recv as i64);
+        match res {
+            Ok(()) => span.set_status(Status::Ok),
+            Err(e) => span.set_status(Status::error(e.to_string())),
+        }
+    }
+}
+
 impl Drop for ConnectionResult {
     fn drop(&mut self) {
         if !self.recorded {
-            self.record_internal(Err(proxy::Error::ClosedFromDrain))
+            // We do not know why the connection was dropped; it was not necessarily drained.
+            self.record_internal(Err(proxy::Error::ClosedFromDrain), CloseReason::cancelled)
         }
     }
 }
@@ -589,3 +1448,247 @@ fn to_value<T: AsRef<str>>(t: &T) -> impl Value + '_ {
     let v: &str = t.as_ref();
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strng;
+    use prometheus_client::registry::Registry;
+
+    fn locality(src: (&str, &str), dst: (&str, &str)) -> LocalityLabels {
+        LocalityLabels {
+            source_region: strng::new(src.0).into(),
+            source_zone: strng::new(src.1).into(),
+            destination_region: strng::new(dst.0).into(),
+            destination_zone: strng::new(dst.1).into(),
+        }
+    }
+
+    #[test]
+    fn locality_match() {
+        use LocalityMatch::*;
+        let cases = [
+            (
+                ("us-east1", "us-east1-b"),
+                ("us-east1", "us-east1-b"),
+                same_zone,
+            ),
+            (
+                ("us-east1", "us-east1-b"),
+                ("us-east1", "us-east1-c"),
+                cross_zone,
+            ),
+            (
+                ("us-east1", "us-east1-b"),
+                ("us-west1", "us-west1-b"),
+                cross_region,
+            ),
+            (("us-east1", ""), ("us-east1", "us-east1-b"), unknown),
+            (("", ""), ("us-east1", "us-east1-b"), unknown),
+            // Different regions are known to be crossed, even without zones
+            (("us-east1", ""), ("us-west1", ""), cross_region),
+        ];
+        for (src, dst, want) in cases {
+            assert_eq!(
+                locality(src, dst).locality_match(),
+                want,
+                "{src:?} -> {dst:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn close_reason() {
+        let metrics = Arc::new(Metrics::new(&mut Registry::default()));
+        let open = ConnectionOpen {
+            reporter: Reporter::destination,
+            source: None,
+            derived_source: None,
+            destination: None,
+            destination_service: None,
+            connection_security_policy: SecurityPolicy::mutual_tls,
+        };
+        let conn = || {
+            ConnectionResult::new(
+                "10.0.0.1:1234".parse().unwrap(),
+                "10.0.0.2:8080".parse().unwrap(),
+                None,
+                Instant::now(),
+                open.clone(),
+                metrics.clone(),
+            )
+        };
+        let closed = |close_reason| {
+            metrics
+                .connection_close_reasons
+                .get_or_create(&CloseReasonLabels {
+                    reporter: Reporter::destination,
+                    close_reason,
+                })
+                .get()
+        };
+
+        conn().record(Ok::<(), proxy::Error>(()));
+        conn().record(Err(proxy::Error::BackendDisconnected));
+        conn().record(Err(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        )));
+        conn().record(Err(proxy::Error::AuthorizationPolicyLateRejection));
+        conn().record(Err(proxy::Error::ClosedFromDrain));
+        // A connection dropped before completing may have been cancelled for any reason.
+        drop(conn());
+
+        assert_eq!(closed(CloseReason::clean_eof), 1);
+        assert_eq!(closed(CloseReason::error), 2);
+        assert_eq!(closed(CloseReason::policy_close), 1);
+        assert_eq!(closed(CloseReason::drain_close), 1);
+        assert_eq!(closed(CloseReason::cancelled), 1);
+        // The stable counter is not split by reason.
+        assert_eq!(
+            metrics
+                .connection_close
+                .get_or_create(&CommonTrafficLabels::from(open.clone()))
+                .get(),
+            6
+        );
+    }
+
+    #[test]
+    fn baggage_labels() {
+        let mut registry = Registry::default();
+        let metrics = Arc::new(
+            Metrics::new(&mut registry)
+                .with_baggage_labels(vec![strng::new("team"), strng::new("tier")]),
+        );
+        let open = |labels: &[(&str, &str)]| ConnectionOpen {
+            reporter: Reporter::destination,
+            source: None,
+            derived_source: Some(DerivedWorkload {
+                labels: labels
+                    .iter()
+                    .map(|(k, v)| (strng::new(k), strng::new(v)))
+                    .collect(),
+                ..Default::default()
+            }),
+            destination: None,
+            destination_service: None,
+            connection_security_policy: SecurityPolicy::mutual_tls,
+        };
+        for labels in [&[("team", "payments")][..], &[]] {
+            ConnectionResult::new(
+                "10.0.0.1:1234".parse().unwrap(),
+                "10.0.0.2:8080".parse().unwrap(),
+                None,
+                Instant::now(),
+                open(labels),
+                metrics.clone(),
+            )
+            .record(Ok::<(), proxy::Error>(()));
+        }
+
+        let mut out = String::new();
+        prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+        let opened: Vec<_> = out
+            .lines()
+            .filter(|l| l.starts_with("tcp_connections_opened_total{"))
+            .collect();
+        assert_eq!(opened.len(), 2, "{out}");
+        // Every configured key is reported, whether or not it was received.
+        assert!(
+            opened.iter().any(|l| l
+                .contains("source_label_team=\"payments\",source_label_tier=\"unknown\"")),
+            "{out}"
+        );
+        assert!(
+            opened
+                .iter()
+                .any(|l| l.contains("source_label_team=\"unknown\",source_label_tier=\"unknown\"")),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn deny_reason_waypoint_bypass() {
+        let denied = proxy::Error::AuthorizationPolicyRejection(
+            proxy::AuthorizationRejectionError::NotAllowed,
+        );
+        assert_eq!(
+            DenyReason::from_inbound_error(&denied, false),
+            Some(DenyReason::rbac_policy)
+        );
+        assert_eq!(
+            DenyReason::from_inbound_error(&denied, true),
+            Some(DenyReason::waypoint_bypass)
+        );
+        // Only policy denials are attributed to skipping the waypoint.
+        assert_eq!(
+            DenyReason::from_inbound_error(
+                &proxy::Error::IPMismatch("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()),
+                true
+            ),
+            Some(DenyReason::ip_mismatch)
+        );
+        assert_eq!(
+            DenyReason::from_inbound_error(&proxy::Error::BackendDisconnected, true),
+            None
+        );
+    }
+
+    #[test]
+    fn access_log_sampling() {
+        let dst: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        let src = |port| SocketAddr::from(([10, 0, 0, 1], port));
+
+        let all = Metrics::new(&mut Registry::default());
+        assert!((1000..2000).all(|port| all.access_log_sampled(src(port), dst)));
+
+        let sampled = Metrics::new(&mut Registry::default()).with_access_log_sampling(Some(4));
+        let kept = (1000..5000)
+            .filter(|port| sampled.access_log_sampled(src(*port), dst))
+            .count();
+        assert!((800..1200).contains(&kept), "kept {kept} of 4000");
+        // A given flow is consistently kept or dropped
+        let again = Metrics::new(&mut Registry::default()).with_access_log_sampling(Some(4));
+        for port in 1000..1100 {
+            assert_eq!(
+                sampled.access_log_sampled(src(port), dst),
+                again.access_log_sampled(src(port), dst)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tls_connections() {
+        use std::str::FromStr;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/server").unwrap();
+        let certs = Arc::new(crate::tls::mock::generate_test_certs(
+            &id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connector = certs.outbound_connector(vec![id], 0).unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut tls = connector.connect(stream).await.unwrap();
+            let _ = tls.read_u8().await;
+        });
+        let acceptor =
+            tokio_rustls::TlsAcceptor::from(Arc::new(certs.server_config(&[], 0).unwrap()));
+        let (conn, _) = listener.accept().await.unwrap();
+        let mut tls = acceptor.accept(conn).await.unwrap();
+        tls.write_u8(1).await.unwrap();
+        client.await.unwrap();
+
+        let metrics = Metrics::new(&mut Registry::default());
+        let info = metrics.record_tls_connection(tls.get_ref().1);
+        // Only TLS 1.3 is offered.
+        assert_eq!(info.tls_version, TlsVersion::tls1_3);
+        assert_ne!(info.tls_cipher, TlsCipher::other);
+        assert_eq!(metrics.tls_connections.get_or_create(&info).get(), 1);
+    }
+}
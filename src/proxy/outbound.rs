@@ -12,38 +12,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use futures_util::TryFutureExt;
 use hyper::header::FORWARDED;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use tokio::net::TcpStream;
 use tokio::sync::watch;
 
-use tracing::{Instrument, debug, error, info, info_span, trace_span};
+use tracing::{Instrument, debug, info, info_span, trace_span, warn};
 
+use crate::baggage::Baggage;
+use crate::config::WindowSizes;
 use crate::identity::Identity;
 
+use crate::proxy::connection_manager::OutboundConnectionGuard;
 use crate::proxy::metrics::Reporter;
 use crate::proxy::{
-    BAGGAGE_HEADER, Error, HboneAddress, ProxyInputs, TRACEPARENT_HEADER, TraceParent, util,
+    BAGGAGE_HEADER, DscpSocketFactory, Error, HboneAddress, ProxyInputs, TRACEPARENT_HEADER,
+    TraceParent, util,
 };
 use crate::proxy::{ConnectionOpen, ConnectionResult, DerivedWorkload, metrics};
 
 use crate::drain::DrainWatcher;
 use crate::drain::run_with_drain;
 use crate::proxy::h2::{H2Stream, client::WorkloadKey};
-use crate::state::ServiceResolutionMode;
+use crate::proxy::outbound_udp::Association;
+use crate::proxy::udp;
 use crate::state::service::ServiceDescription;
-use crate::state::workload::{NetworkAddress, Protocol, Workload, address::Address};
-use crate::{assertions, copy, proxy, socket};
+use crate::state::workload::{Locality, NetworkAddress, Protocol, Workload, address::Address};
+use crate::state::{ProxyRbacContext, ServiceResolutionMode};
+use crate::strng::Strng;
+use crate::{assertions, copy, proxy, rbac, socket, strng};
 
 pub struct Outbound {
     pi: Arc<ProxyInputs>,
     drain: DrainWatcher,
     listener: socket::Listener,
+    enable_orig_src: bool,
 }
 
 impl Outbound {
@@ -52,7 +61,11 @@ impl Outbound {
             .socket_factory
             .tcp_bind(pi.cfg.outbound_addr)
             .map_err(|e| Error::Bind(pi.cfg.outbound_addr, e))?;
+        super::maybe_bind_device(&listener, &pi.cfg.outbound_iface)?;
         let transparent = super::maybe_set_transparent(&pi, &listener)?;
+        // Unlike inbound, spoofing the client IP on outbound is only done when explicitly requested.
+        // The reply traffic must be routed back through this node, which is not the case by default.
+        let enable_orig_src = transparent && pi.cfg.require_original_source == Some(true);
 
         info!(
             address=%listener.local_addr(),
@@ -64,6 +77,7 @@ impl Outbound {
             pi,
             listener,
             drain,
+            enable_orig_src,
         })
     }
 
@@ -79,6 +93,7 @@ impl Outbound {
         );
         let pi = self.pi.clone();
         let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut backoff = util::AcceptBackoff::default();
             loop {
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
@@ -87,13 +102,17 @@ impl Outbound {
                 let mut force_shutdown = force_shutdown.clone();
                 match socket {
                     Ok((stream, _remote)) => {
+                        backoff.reset();
+                        let in_flight = drain.track();
                         let mut oc = OutboundConnection {
                             pi: self.pi.clone(),
                             id: TraceParent::new(),
                             pool: pool.clone(),
                             hbone_port: self.pi.cfg.inbound_addr.port(),
+                            enable_orig_src: self.enable_orig_src,
                         };
                         let span = info_span!("outbound", id=%oc.id);
+                        oc.id.set_as_parent(&span);
                         let serve_outbound_connection = async move {
                             debug!(component="outbound", "connection started");
                             // Since this task is spawned, make sure we are guaranteed to terminate
@@ -105,6 +124,7 @@ impl Outbound {
                             }
                             // Mark we are done with the connection, so drain can complete
                             drop(drain);
+                            drop(in_flight);
                             debug!(component="outbound", dur=?start.elapsed(), "connection completed");
                         }.instrument(span);
 
@@ -115,7 +135,10 @@ impl Outbound {
                         if util::is_runtime_shutdown(&e) {
                             return;
                         }
-                        error!("Failed TCP handshake {}", e);
+                        if util::is_fd_exhausted(&e) {
+                            self.pi.metrics.accept_fd_exhausted.inc();
+                        }
+                        tokio::time::sleep(backoff.failed("outbound", &e)).await;
                     }
                 }
             }
@@ -125,6 +148,7 @@ impl Outbound {
             "outbound".to_string(),
             self.drain,
             pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
             accept,
         )
         .await
@@ -136,19 +160,35 @@ pub(super) struct OutboundConnection {
     pub(super) id: TraceParent,
     pub(super) pool: proxy::pool::WorkloadHBONEPool,
     pub(super) hbone_port: u16,
+    /// If set, plaintext TCP connections to the destination are made from the original client IP.
+    pub(super) enable_orig_src: bool,
 }
 
 impl OutboundConnection {
-    async fn proxy(&mut self, source_stream: TcpStream) {
+    async fn proxy(&mut self, mut source_stream: TcpStream) {
         let source_addr =
             socket::to_canonical(source_stream.peer_addr().expect("must receive peer addr"));
-        let dst_addr = socket::orig_dst_addr_or_default(&source_stream);
-        self.proxy_to(source_stream, source_addr, dst_addr).await;
+        let dst_addr = match original_dst_addr(&self.pi.cfg, &mut source_stream).await {
+            Ok(addr) => addr,
+            Err(err) => {
+                let local_addr = socket::to_canonical(
+                    source_stream.local_addr().expect("must receive local addr"),
+                );
+                metrics::log_early_deny(source_addr, local_addr, Reporter::source, err);
+                return;
+            }
+        };
+        self.proxy_to(
+            copy::TcpStreamSplitter(source_stream),
+            source_addr,
+            dst_addr,
+        )
+        .await;
     }
 
     pub async fn proxy_to(
         &mut self,
-        source_stream: TcpStream,
+        source_stream: impl copy::BufferedSplitter,
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
     ) {
@@ -157,7 +197,13 @@ impl OutboundConnection {
         let illegal_call =
             dest_addr.ip().is_loopback() && self.pi.cfg.illegal_ports.contains(&dest_addr.port());
         if illegal_call {
-            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, Error::SelfCall);
+            self.pi.metrics.hbone_loops_detected.inc();
+            metrics::log_early_deny(
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::TunnelLoop(dest_addr),
+            );
             return;
         }
         // First find the source workload of this traffic. If we don't know where the request is from
@@ -175,7 +221,7 @@ impl OutboundConnection {
             }
         };
         // TODO: should we use the original address or the actual address? Both seems nice!
-        let _conn_guard = self.pi.connection_manager.track_outbound(
+        let conn_guard = self.pi.connection_manager.track_outbound(
             source_addr,
             dest_addr,
             req.actual_destination,
@@ -192,37 +238,78 @@ impl OutboundConnection {
             Self::conn_metrics_from_request(&req),
             metrics,
         ));
+        conn_guard.track_bytes(&result_tracker.bytes());
 
         let res = match req.protocol {
-            Protocol::HBONE => {
-                self.proxy_to_hbone(source_stream, source_addr, &req, &result_tracker)
-                    .await
-            }
+            Protocol::HBONE => match Box::pin(self.connect_hbone(source_addr, &req)).await {
+                Ok(upgraded) => {
+                    self.proxy_to_hbone(source_stream, upgraded, &req, &result_tracker)
+                        .await
+                }
+                Err(err) => match self.plaintext_fallback(&req, &err) {
+                    Some(fallback) => {
+                        // The mTLS attempt is reported as failed, and the plaintext connection
+                        // separately, so metrics never claim it was secured.
+                        result_tracker.record(Err(err));
+                        let fallback = self.proxy_plaintext_fallback(
+                            source_stream,
+                            source_addr,
+                            fallback,
+                            &conn_guard,
+                        );
+                        return Box::pin(fallback).await;
+                    }
+                    None => Err(err),
+                },
+            },
             Protocol::TCP => {
-                self.proxy_to_tcp(source_stream, &req, &result_tracker)
+                self.proxy_to_tcp(source_stream, source_addr, &req, &result_tracker)
                     .await
             }
         };
         result_tracker.record(res)
     }
 
-    async fn proxy_to_hbone(
+    async fn connect_hbone(
         &mut self,
-        stream: TcpStream,
         remote_addr: SocketAddr,
         req: &Request,
+    ) -> Result<H2Stream, Error> {
+        // No client bytes have been forwarded yet, so it is safe to retry establishing the tunnel.
+        let retries = self.pi.cfg.hbone_connect_retries;
+        let backoff = self.pi.cfg.hbone_connect_retry_backoff;
+        Box::pin(retry_hbone_connect(retries, backoff, async || {
+            Box::pin(self.send_hbone_request(remote_addr, req, false)).await
+        }))
+        .await
+    }
+
+    async fn proxy_to_hbone(
+        &mut self,
+        stream: impl copy::BufferedSplitter,
+        upgraded: H2Stream,
+        req: &Request,
         connection_stats: &ConnectionResult,
     ) -> Result<(), Error> {
-        let upgraded = Box::pin(self.send_hbone_request(remote_addr, req)).await?;
-        copy::copy_bidirectional(copy::TcpStreamSplitter(stream), upgraded, connection_stats).await
+        copy::copy_bidirectional_with_limits(
+            stream,
+            upgraded,
+            connection_stats,
+            self.pi.cfg.idle_timeout,
+            self.pi.cfg.half_close_timeout,
+            self.rate_limit(req),
+            self.pi.cfg.copy_buffer_max_size,
+        )
+        .await
     }
 
     async fn send_hbone_request(
         &mut self,
         remote_addr: SocketAddr,
         req: &Request,
+        udp: bool,
     ) -> Result<H2Stream, Error> {
-        let request = http::Request::builder()
+        let mut request = http::Request::builder()
             .uri(
                 req.hbone_target_destination
                     .expect("HBONE must have target")
@@ -235,7 +322,11 @@ impl OutboundConnection {
                 FORWARDED,
                 build_forwarded(remote_addr, &req.intended_destination_service),
             )
-            .header(TRACEPARENT_HEADER, self.id.header())
+            .header(TRACEPARENT_HEADER, self.id.header());
+        if udp {
+            request = request.header(udp::PROTOCOL_HEADER, udp::PROTOCOL_UDP);
+        }
+        let request = request
             .body(())
             .expect("builder with known status code should not fail");
 
@@ -246,6 +337,8 @@ impl OutboundConnection {
             dst_id: req.upstream_sans.clone(),
             src: remote_addr.ip(),
             dst: req.actual_destination,
+            dscp: req.dscp,
+            window_sizes: req.window_sizes,
         });
         let upgraded = Box::pin(self.pool.send_request_pooled(&pool_key, request))
             .instrument(trace_span!("outbound connect"))
@@ -253,28 +346,179 @@ impl OutboundConnection {
         Ok(upgraded)
     }
 
+    /// proxy_udp carries a UDP association over HBONE. Waypoints, network gateways and plaintext
+    /// destinations only handle TCP, so the destination must be reached directly over HBONE.
+    pub(super) async fn proxy_udp(&mut self, association: Association) {
+        let start = Instant::now();
+        let source_addr = association.client;
+        let dest_addr = association.target;
+        let build = self
+            .pi
+            .local_workload_information
+            .get_workload()
+            .and_then(|source| self.build_request(source, source_addr.ip(), dest_addr));
+        let req = match Box::pin(build).await {
+            Ok(req) => Box::new(req),
+            Err(err) => {
+                metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+                return;
+            }
+        };
+        let direct = req.protocol == Protocol::HBONE
+            && req.hbone_target_destination.map(|t| t.ip()) == Some(req.actual_destination.ip());
+        if !direct {
+            let err = Error::UdpUnsupported(dest_addr);
+            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+            return;
+        }
+        let conn_guard = self.pi.connection_manager.track_outbound(
+            source_addr,
+            dest_addr,
+            req.actual_destination,
+            req.protocol,
+        );
+
+        let result_tracker = Box::new(ConnectionResult::new(
+            source_addr,
+            req.actual_destination,
+            req.hbone_target_destination.map(HboneAddress::SocketAddr),
+            start,
+            Self::conn_metrics_from_request(&req),
+            self.pi.metrics.clone(),
+        ));
+        conn_guard.track_bytes(&result_tracker.bytes());
+        let res = self
+            .proxy_udp_to_hbone(association, source_addr, &req, &result_tracker)
+            .await;
+        result_tracker.record(res)
+    }
+
+    async fn proxy_udp_to_hbone(
+        &mut self,
+        association: Association,
+        remote_addr: SocketAddr,
+        req: &Request,
+        connection_stats: &ConnectionResult,
+    ) -> Result<(), Error> {
+        let retries = self.pi.cfg.hbone_connect_retries;
+        let backoff = self.pi.cfg.hbone_connect_retry_backoff;
+        let upgraded = Box::pin(retry_hbone_connect(retries, backoff, async || {
+            Box::pin(self.send_hbone_request(remote_addr, req, true)).await
+        }))
+        .await?;
+        association
+            .relay(upgraded, connection_stats, self.pi.cfg.udp_idle_timeout)
+            .await
+    }
+
     async fn proxy_to_tcp(
         &mut self,
-        stream: TcpStream,
+        stream: impl copy::BufferedSplitter,
+        remote_addr: SocketAddr,
         req: &Request,
         connection_stats: &ConnectionResult,
     ) -> Result<(), Error> {
-        let outbound = super::freebind_connect(
-            None, // No need to spoof source IP on outbound
+        // HBONE connections are pooled and shared across clients, so only plain TCP can do this.
+        let orig_src = self.enable_orig_src.then(|| {
+            let port = match self.pi.cfg.preserve_source_port {
+                true => remote_addr.port(),
+                false => 0,
+            };
+            SocketAddr::new(remote_addr.ip(), port)
+        });
+        let outbound = super::freebind_connect_with_port(
+            orig_src,
             req.actual_destination,
-            self.pi.socket_factory.as_ref(),
+            &DscpSocketFactory {
+                inner: self.pi.socket_factory.as_ref(),
+                dscp: req.dscp,
+            },
+            self.pi.cfg.connect_timeout,
         )
-        .await?;
+        .await
+        .map_err(|e| {
+            self.pi.metrics.record_connect_failure(Reporter::source, &e);
+            match e.kind() {
+                io::ErrorKind::TimedOut => Error::ConnectTimeout(req.actual_destination),
+                _ => Error::ConnectionFailed(e),
+            }
+        })?;
 
         // Proxying data between downstream and upstream
-        copy::copy_bidirectional(
-            copy::TcpStreamSplitter(stream),
+        copy::copy_bidirectional_with_limits(
+            stream,
             copy::TcpStreamSplitter(outbound),
             connection_stats,
+            self.pi.cfg.idle_timeout,
+            self.pi.cfg.half_close_timeout,
+            self.rate_limit(req),
+            self.pi.cfg.copy_buffer_max_size,
         )
         .await
     }
 
+    // plaintext_fallback returns the request to send over plaintext instead, if establishing an mTLS
+    // tunnel directly to the destination workload failed and falling back is enabled for it.
+    // Certificate verification failures never fall back, as they may indicate an impersonator.
+    fn plaintext_fallback(&self, req: &Request, err: &Error) -> Option<Request> {
+        let unavailable = match err {
+            Error::ConnectionFailed(_) => true,
+            Error::TlsHandshake(_, e) => e.kind() != io::ErrorKind::InvalidData,
+            _ => false,
+        };
+        if !unavailable {
+            return None;
+        }
+        let target = req.hbone_target_destination?;
+        // Waypoints and network gateways in the path must always be reached over mTLS.
+        if target.ip() != req.actual_destination.ip() {
+            return None;
+        }
+        let wl = req.actual_destination_workload.as_ref()?;
+        if !self.pi.cfg.plaintext_fallback.allows(wl) {
+            return None;
+        }
+        Some(Request {
+            protocol: Protocol::TCP,
+            source: req.source.clone(),
+            actual_destination_workload: req.actual_destination_workload.clone(),
+            intended_destination_service: req.intended_destination_service.clone(),
+            actual_destination: target,
+            hbone_target_destination: None,
+            upstream_sans: vec![],
+            dscp: req.dscp,
+            window_sizes: None,
+        })
+    }
+
+    async fn proxy_plaintext_fallback(
+        &mut self,
+        stream: impl copy::BufferedSplitter,
+        source_addr: SocketAddr,
+        req: Request,
+        conn_guard: &OutboundConnectionGuard,
+    ) {
+        warn!(
+            src.addr = %source_addr,
+            dst.addr = %req.actual_destination,
+            "mTLS to destination failed, falling back to plaintext"
+        );
+        self.pi.metrics.plaintext_fallbacks.inc();
+        let result_tracker = Box::new(ConnectionResult::new(
+            source_addr,
+            req.actual_destination,
+            None,
+            Instant::now(),
+            Self::conn_metrics_from_request(&req),
+            self.pi.metrics.clone(),
+        ));
+        conn_guard.track_bytes(&result_tracker.bytes());
+        let res = self
+            .proxy_to_tcp(stream, source_addr, &req, &result_tracker)
+            .await;
+        result_tracker.record(res)
+    }
+
     fn conn_metrics_from_request(req: &Request) -> ConnectionOpen {
         let derived_source = if req.protocol == Protocol::HBONE {
             Some(DerivedWorkload {
@@ -292,6 +536,8 @@ impl OutboundConnection {
             destination: req.actual_destination_workload.clone(),
             connection_security_policy: if req.protocol == Protocol::HBONE {
                 metrics::SecurityPolicy::mutual_tls
+            } else if req.actual_destination_workload.is_none() {
+                metrics::SecurityPolicy::passthrough
             } else {
                 metrics::SecurityPolicy::unknown
             },
@@ -325,6 +571,11 @@ impl OutboundConnection {
             {
                 let upstream_sans = waypoint.workload_and_services_san();
                 let actual_destination = waypoint.workload_socket_addr();
+                let window_sizes = self.window_sizes(
+                    Some(&target_service.hostname),
+                    &source_workload,
+                    &waypoint.workload,
+                );
                 debug!("built request to service waypoint proxy");
                 return Ok(Request {
                     protocol: Protocol::HBONE,
@@ -334,6 +585,8 @@ impl OutboundConnection {
                     intended_destination_service: Some(ServiceDescription::from(&*target_service)),
                     actual_destination,
                     upstream_sans,
+                    dscp: self.dscp(Some(&target_service.hostname)),
+                    window_sizes,
                 });
             }
             // this was service addressed but we did not find a waypoint
@@ -355,6 +608,9 @@ impl OutboundConnection {
             if svc_addressed {
                 return Err(Error::NoHealthyUpstream(target));
             }
+            if !self.pi.cfg.allow_outbound_passthrough {
+                return Err(Error::PassthroughDenied(target));
+            }
             debug!("built request as passthrough; no upstream found");
             return Ok(Request {
                 protocol: Protocol::TCP,
@@ -364,6 +620,8 @@ impl OutboundConnection {
                 intended_destination_service: None,
                 actual_destination: target,
                 upstream_sans: vec![],
+                dscp: self.dscp(None),
+                window_sizes: None,
             });
         };
 
@@ -386,6 +644,11 @@ impl OutboundConnection {
             if let Some(waypoint) = waypoint {
                 let actual_destination = waypoint.workload_socket_addr();
                 let upstream_sans = waypoint.workload_and_services_san();
+                let window_sizes = self.window_sizes(
+                    us.destination_service.as_ref().map(|s| &s.hostname),
+                    &source_workload,
+                    &waypoint.workload,
+                );
                 debug!("built request to workload waypoint proxy");
                 return Ok(Request {
                     // Always use HBONE here
@@ -397,34 +660,146 @@ impl OutboundConnection {
                     intended_destination_service: us.destination_service.clone(),
                     actual_destination,
                     upstream_sans,
+                    dscp: self.dscp(us.destination_service.as_ref().map(|s| &s.hostname)),
+                    window_sizes,
                 });
             }
             // Workload doesn't have a waypoint; send directly
         }
 
+        let protocol = self
+            .protocol(&us.workload, downstream, us.workload_socket_addr())
+            .await;
         // only change the port if we're sending HBONE
-        let actual_destination = match us.workload.protocol {
+        let actual_destination = match protocol {
             Protocol::HBONE => SocketAddr::from((us.selected_workload_ip, self.hbone_port)),
             Protocol::TCP => us.workload_socket_addr(),
         };
-        let hbone_target_destination = match us.workload.protocol {
+        let hbone_target_destination = match protocol {
             Protocol::HBONE => Some(us.workload_socket_addr()),
             Protocol::TCP => None,
         };
 
         // For case no waypoint for both side and direct to remote node proxy
         let upstream_sans = us.workload_and_services_san();
+        let window_sizes = self.window_sizes(
+            us.destination_service.as_ref().map(|s| &s.hostname),
+            &source_workload,
+            &us.workload,
+        );
         debug!("built request to workload");
         Ok(Request {
-            protocol: us.workload.protocol,
+            protocol,
             source: source_workload,
             hbone_target_destination,
             actual_destination_workload: Some(us.workload.clone()),
             intended_destination_service: us.destination_service.clone(),
             actual_destination,
             upstream_sans,
+            dscp: self.dscp(us.destination_service.as_ref().map(|s| &s.hostname)),
+            window_sizes,
         })
     }
+
+    // protocol returns the protocol to reach `wl` with. A configured override takes precedence over
+    // the workload's declared protocol, unless it would send plaintext to a workload whose policies
+    // deny unauthenticated clients.
+    async fn protocol(&self, wl: &Arc<Workload>, downstream: IpAddr, dst: SocketAddr) -> Protocol {
+        if self.pi.cfg.protocol_overrides.is_empty() {
+            return wl.protocol;
+        }
+        let key = strng::format!("{}/{}", wl.namespace, wl.name);
+        let Some(&protocol) = self.pi.cfg.protocol_overrides.get(&key) else {
+            return wl.protocol;
+        };
+        if protocol == Protocol::TCP && wl.protocol == Protocol::HBONE {
+            let ctx = ProxyRbacContext {
+                conn: rbac::Connection {
+                    src_identity: None,
+                    src: SocketAddr::new(downstream, 0),
+                    dst_network: wl.network.clone(),
+                    dst,
+                },
+                dest_workload: wl.clone(),
+            };
+            if let Err(e) = self.pi.state.assert_rbac(&ctx).await {
+                warn!(workload = %key, "ignoring protocol override to TCP, destination requires mTLS: {e}");
+                return wl.protocol;
+            }
+        }
+        protocol
+    }
+
+    // dscp returns the DSCP marking for connections to `service`, falling back to the default.
+    fn dscp(&self, service: Option<&Strng>) -> Option<u8> {
+        service
+            .and_then(|svc| self.pi.cfg.service_dscp.get(svc).copied())
+            .or(self.pi.cfg.dscp)
+    }
+
+    // rate_limit returns the byte rate limit for the connection `req` is proxied over.
+    fn rate_limit(&self, req: &Request) -> Option<u64> {
+        let service = req.intended_destination_service.as_ref();
+        proxy::connection_rate_limit(&self.pi.cfg, service.map(|s| &s.hostname))
+    }
+
+    // window_sizes returns the HTTP/2 windows for HBONE connections from `source` to `destination`
+    // for `service`, if overridden. Service overrides take precedence over locality ones.
+    fn window_sizes(
+        &self,
+        service: Option<&Strng>,
+        source: &Workload,
+        destination: &Workload,
+    ) -> Option<WindowSizes> {
+        let cfg = &self.pi.cfg;
+        service
+            .and_then(|svc| cfg.service_window_sizes.get(svc).copied())
+            .or_else(|| {
+                let relation = locality_relation(&source.locality, &destination.locality)?;
+                cfg.locality_window_sizes.get(relation).copied()
+            })
+    }
+}
+
+/// locality_relation returns where `destination` is relative to `source`, as one of
+/// [crate::config::LOCALITY_RELATIONS]. It is None if the localities are not known well enough.
+fn locality_relation(source: &Locality, destination: &Locality) -> Option<&'static str> {
+    if source.region.is_empty() || destination.region.is_empty() {
+        return None;
+    }
+    if source.region != destination.region {
+        return Some("cross-region");
+    }
+    if source.zone.is_empty() || destination.zone.is_empty() {
+        return None;
+    }
+    if source.zone != destination.zone {
+        return Some("cross-zone");
+    }
+    Some("same-zone")
+}
+
+/// original_dst_addr returns the original destination of a connection accepted on the outbound
+/// listener. If outbound PROXY protocol is enabled, the header's destination takes precedence, which
+/// allows clients that were not redirected to us to name their destination. The header's source is
+/// ignored: the client is always the peer, so it cannot choose the source used for orig-src.
+async fn original_dst_addr(
+    cfg: &crate::config::Config,
+    stream: &mut TcpStream,
+) -> Result<SocketAddr, Error> {
+    let mut dst_addr = socket::redirected_dst_addr(stream);
+    if cfg.outbound_proxy_protocol {
+        let header = tokio::time::timeout(
+            proxy::PROXY_PROTOCOL_TIMEOUT,
+            proxy::read_proxy_protocol_header(stream),
+        )
+        .await
+        .map_err(|_| Error::ProxyProtocol("timed out waiting for header".to_string()))??;
+        if let Some(dst) = header.destination {
+            dst_addr = Some(socket::to_canonical(dst));
+        }
+    }
+    dst_addr.ok_or(Error::NoOriginalDst)
 }
 
 fn build_forwarded(remote_addr: SocketAddr, server: &Option<ServiceDescription>) -> String {
@@ -439,16 +814,19 @@ fn build_forwarded(remote_addr: SocketAddr, server: &Option<ServiceDescription>)
 }
 
 fn baggage(r: &Request, cluster: String) -> String {
-    format!(
-        "k8s.cluster.name={cluster},k8s.namespace.name={namespace},k8s.{workload_type}.name={workload_name},service.name={name},service.version={version},cloud.region={region},cloud.availability_zone={zone}",
-        namespace = r.source.namespace,
-        workload_type = r.source.workload_type,
-        workload_name = r.source.workload_name,
-        name = r.source.canonical_name,
-        version = r.source.canonical_revision,
-        region = r.source.locality.region,
-        zone = r.source.locality.zone,
-    )
+    // Workloads carry no pod labels, so only the well-known fields are sent.
+    Baggage {
+        cluster_id: Some(cluster.into()),
+        namespace: Some(r.source.namespace.clone()),
+        workload_type: Some(r.source.workload_type.clone()),
+        workload_name: Some(r.source.workload_name.clone()),
+        service_name: Some(r.source.canonical_name.clone()),
+        revision: Some(r.source.canonical_revision.clone()),
+        region: Some(r.source.locality.region.clone()),
+        zone: Some(r.source.locality.zone.clone()),
+        labels: Default::default(),
+    }
+    .encode()
 }
 
 #[derive(Debug)]
@@ -474,12 +852,47 @@ struct Request {
     // The identity we will assert for the next hop; this may not be the same as actual_destination_workload
     // in the case of proxies along the path.
     upstream_sans: Vec<Identity>,
+    // DSCP marking for the connection to the next hop, if any.
+    dscp: Option<u8>,
+    // HTTP/2 windows for the HBONE connection to the next hop, if overridden.
+    window_sizes: Option<WindowSizes>,
+}
+
+/// retry_hbone_connect calls `connect` until it succeeds, fails with an error that is not worth
+/// retrying, or has been retried `retries` times. The nth retry waits `backoff * n`.
+async fn retry_hbone_connect<T>(
+    retries: u32,
+    backoff: Duration,
+    mut connect: impl AsyncFnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut attempt = 0;
+    loop {
+        match connect().await {
+            Err(e) if attempt < retries && is_retryable_hbone_error(&e) => {
+                attempt += 1;
+                debug!(err=%e, attempt, "HBONE connect failed, retrying");
+                tokio::time::sleep(backoff * attempt).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+// Only transient connection-level failures are retried; timeouts, certificate/identity failures,
+// and responses from the peer (such as a policy denial) would fail the same way again.
+fn is_retryable_hbone_error(e: &Error) -> bool {
+    match e {
+        Error::Io(_) | Error::ConnectionFailed(_) | Error::Http2Handshake(_) | Error::H2(_) => true,
+        // rustls reports certificate and protocol failures as InvalidData
+        Error::TlsHandshake(_, e) => e.kind() != io::ErrorKind::InvalidData,
+        Error::UpstreamReset(reason) => *reason == ::h2::Reason::REFUSED_STREAM,
+        _ => false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::net::Ipv6Addr;
-    use std::time::Duration;
 
     use bytes::Bytes;
 
@@ -490,6 +903,11 @@ mod tests {
     use crate::state::WorkloadInfo;
     use crate::test_helpers::helpers::{initialize_telemetry, test_proxy_metrics};
     use crate::test_helpers::new_proxy_state;
+    use crate::xds::istio::security::string_match::MatchType as XdsMatchType;
+    use crate::xds::istio::security::{
+        Action as XdsAction, Authorization as XdsAuthorization, Clause as XdsClause,
+        Match as XdsMatch, Rule as XdsRule, Scope as XdsScope, StringMatch as XdsStringMatch,
+    };
     use crate::xds::istio::workload::TunnelProtocol as XdsProtocol;
     use crate::xds::istio::workload::Workload as XdsWorkload;
     use crate::xds::istio::workload::address::Type as XdsAddressType;
@@ -513,9 +931,27 @@ mod tests {
         xds: Vec<XdsAddressType>,
         expect: Option<ExpectedRequest<'_>>,
     ) -> Option<Request> {
+        run_build_request_with(
+            crate::config::parse_config().unwrap(),
+            from,
+            to,
+            xds,
+            &[],
+            expect,
+        )
+        .await
+    }
+
+    // test_outbound_connection returns an OutboundConnection from a source workload at 127.0.0.1,
+    // with `xds` and `policies` added to its state.
+    fn test_outbound_connection(
+        cfg: Config,
+        xds: Vec<XdsAddressType>,
+        policies: &[XdsAuthorization],
+    ) -> OutboundConnection {
         let cfg = Arc::new(Config {
             local_node: Some("local-node".to_string()),
-            ..crate::config::parse_config().unwrap()
+            ..cfg
         });
         let source = XdsWorkload {
             uid: "cluster1//v1/Pod/ns/source-workload".to_string(),
@@ -557,7 +993,7 @@ mod tests {
                 XdsAddressType::Service(svc) => services.push(svc),
             };
         }
-        let state = new_proxy_state(&workloads, &services, &[]);
+        let state = new_proxy_state(&workloads, &services, policies);
 
         let sock_fact = std::sync::Arc::new(crate::proxy::DefaultSocketFactory::default());
 
@@ -571,7 +1007,7 @@ mod tests {
             state.clone(),
             identity::mock::new_secret_manager(Duration::from_secs(10)),
         ));
-        let outbound = OutboundConnection {
+        OutboundConnection {
             pi: Arc::new(ProxyInputs {
                 state: state.clone(),
                 cfg: cfg.clone(),
@@ -588,8 +1024,19 @@ mod tests {
                 local_workload_information.clone(),
             ),
             hbone_port: cfg.inbound_addr.port(),
-        };
+            enable_orig_src: false,
+        }
+    }
 
+    async fn run_build_request_with(
+        cfg: Config,
+        from: &str,
+        to: &str,
+        xds: Vec<XdsAddressType>,
+        policies: &[XdsAuthorization],
+        expect: Option<ExpectedRequest<'_>>,
+    ) -> Option<Request> {
+        let outbound = test_outbound_connection(cfg, xds, policies);
         let local = outbound
             .pi
             .local_workload_information
@@ -618,6 +1065,38 @@ mod tests {
         req
     }
 
+    #[tokio::test]
+    async fn tcp_preserves_original_source() {
+        // Binding the client IP needs IP_TRANSPARENT.
+        if !crate::test_helpers::can_run_privilged_test() {
+            eprintln!("This test requires root; skipping");
+            return;
+        }
+        initialize_telemetry();
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let downstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(downstream.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (source_stream, _) = downstream.accept().await.unwrap();
+
+        let mut outbound =
+            test_outbound_connection(crate::config::parse_config().unwrap(), vec![], &[]);
+        outbound.enable_orig_src = true;
+        // A client IP other than the one the kernel would pick for the destination.
+        let client_addr: SocketAddr = "127.0.0.5:12345".parse().unwrap();
+        let dest = upstream.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            outbound.proxy_to(source_stream, client_addr, dest).await;
+        });
+
+        let (conn, peer) = upstream.accept().await.unwrap();
+        assert_eq!(peer.ip(), client_addr.ip());
+        drop(client);
+        drop(conn);
+        proxy.await.unwrap();
+    }
+
     #[tokio::test]
     async fn build_request_unknown_dest() {
         run_build_request(
@@ -637,6 +1116,89 @@ mod tests {
         .await;
     }
 
+    #[test]
+    fn hbone_connect_error_classes() {
+        let addr: SocketAddr = "127.0.0.1:15008".parse().unwrap();
+        let retryable = [
+            Error::ConnectionFailed(io::ErrorKind::ConnectionRefused.into()),
+            Error::TlsHandshake(addr, io::ErrorKind::ConnectionReset.into()),
+            Error::Http2Handshake(::h2::Reason::PROTOCOL_ERROR.into()),
+            Error::UpstreamReset(::h2::Reason::REFUSED_STREAM),
+        ];
+        for e in retryable {
+            assert!(is_retryable_hbone_error(&e), "{e} should be retryable");
+        }
+        let fatal = [
+            Error::MaybeHBONENetworkPolicyError(io::ErrorKind::TimedOut.into()),
+            Error::TlsHandshake(addr, io::ErrorKind::InvalidData.into()),
+            Error::UpstreamReset(::h2::Reason::CANCEL),
+            Error::HttpStatus(http::StatusCode::UNAUTHORIZED),
+        ];
+        for e in fatal {
+            assert!(!is_retryable_hbone_error(&e), "{e} should not be retryable");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hbone_connect_retry() {
+        let refused = || Error::Io(io::Error::from(io::ErrorKind::ConnectionRefused));
+        let backoff = Duration::from_millis(100);
+
+        // The first dial fails, the retry succeeds
+        let mut attempts = 0;
+        let res = retry_hbone_connect(2, backoff, async || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(refused())
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(attempts, 2);
+
+        // Retries are bounded
+        let mut attempts = 0;
+        let res: Result<(), _> = retry_hbone_connect(2, backoff, async || {
+            attempts += 1;
+            Err(refused())
+        })
+        .await;
+        assert!(matches!(res, Err(Error::Io(_))));
+        assert_eq!(attempts, 3);
+
+        // Responses from the peer are not retried
+        let mut attempts = 0;
+        let res: Result<(), _> = retry_hbone_connect(2, backoff, async || {
+            attempts += 1;
+            Err(Error::HttpStatus(http::StatusCode::FORBIDDEN))
+        })
+        .await;
+        assert!(matches!(res, Err(Error::HttpStatus(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn passthrough_metrics() {
+        let req = run_build_request_multi(
+            "127.0.0.1",
+            "1.2.3.4:80",
+            vec![],
+            Some(ExpectedRequest {
+                protocol: Protocol::TCP,
+                hbone_destination: "",
+                destination: "1.2.3.4:80",
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            OutboundConnection::conn_metrics_from_request(&req).connection_security_policy,
+            metrics::SecurityPolicy::passthrough
+        );
+    }
+
     #[tokio::test]
     async fn build_request_known_dest_remote_node_tcp() {
         run_build_request(
@@ -683,6 +1245,103 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_custom_inbound_port() {
+        // HBONE is sent to the port our own inbound listener is configured with, for workloads on
+        // this node as well as others.
+        let cfg = Config {
+            inbound_addr: "[::]:16008".parse().unwrap(),
+            ..crate::config::parse_config().unwrap()
+        };
+        for node in ["local-node", "remote-node"] {
+            run_build_request_with(
+                cfg.clone(),
+                "127.0.0.1",
+                "127.0.0.2:80",
+                vec![XdsAddressType::Workload(XdsWorkload {
+                    uid: "cluster1//v1/Pod/ns/test-hbone".to_string(),
+                    name: "test-hbone".to_string(),
+                    namespace: "ns".to_string(),
+                    addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                    tunnel_protocol: XdsProtocol::Hbone as i32,
+                    node: node.to_string(),
+                    ..Default::default()
+                })],
+                &[],
+                Some(ExpectedRequest {
+                    protocol: Protocol::HBONE,
+                    hbone_destination: "127.0.0.2:80",
+                    destination: "127.0.0.2:16008",
+                }),
+            )
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn build_request_protocol_override() {
+        let cfg = Config {
+            protocol_overrides: std::collections::HashMap::from([(
+                strng::new("ns/test-hbone"),
+                Protocol::TCP,
+            )]),
+            ..crate::config::parse_config().unwrap()
+        };
+        let dest = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/test-hbone".to_string(),
+            name: "test-hbone".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+            tunnel_protocol: XdsProtocol::Hbone as i32,
+            node: "remote-node".to_string(),
+            ..Default::default()
+        };
+        run_build_request_with(
+            cfg.clone(),
+            "127.0.0.1",
+            "127.0.0.2:80",
+            vec![XdsAddressType::Workload(dest.clone())],
+            &[],
+            Some(ExpectedRequest {
+                protocol: Protocol::TCP,
+                hbone_destination: "",
+                destination: "127.0.0.2:80",
+            }),
+        )
+        .await;
+
+        // Plaintext would be denied by the destination's policy, so the override is ignored.
+        let require_identity = XdsAuthorization {
+            name: "require-identity".to_string(),
+            namespace: "ns".to_string(),
+            scope: XdsScope::Namespace as i32,
+            action: XdsAction::Allow as i32,
+            rules: vec![XdsRule {
+                clauses: vec![XdsClause {
+                    matches: vec![XdsMatch {
+                        principals: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Presence(())),
+                        }],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+        };
+        run_build_request_with(
+            cfg,
+            "127.0.0.1",
+            "127.0.0.2:80",
+            vec![XdsAddressType::Workload(dest)],
+            &[require_identity],
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                hbone_destination: "127.0.0.2:80",
+                destination: "127.0.0.2:15008",
+            }),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn build_request_known_dest_local_node_tcp() {
         run_build_request(
@@ -856,6 +1515,134 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_service_window_sizes() {
+        let window_sizes = WindowSizes {
+            window_size: 8 * 1024 * 1024,
+            connection_window_size: 16 * 1024 * 1024,
+        };
+        let cfg = Config {
+            service_window_sizes: std::collections::HashMap::from([(
+                strng::new("example.com"),
+                window_sizes,
+            )]),
+            ..crate::config::parse_config().unwrap()
+        };
+        let svc = |hostname: &str| {
+            XdsAddressType::Service(XdsService {
+                hostname: hostname.to_string(),
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                waypoint: Some(xds::istio::workload::GatewayAddress {
+                    destination: Some(xds::istio::workload::gateway_address::Destination::Address(
+                        XdsNetworkAddress {
+                            network: "".to_string(),
+                            address: [127, 0, 0, 10].to_vec(),
+                        },
+                    )),
+                    hbone_mtls_port: 15008,
+                }),
+                ..Default::default()
+            })
+        };
+        let expect = || {
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                hbone_destination: "127.0.0.3:80",
+                destination: "127.0.0.10:15008",
+            })
+        };
+
+        let req = run_build_request_with(
+            cfg.clone(),
+            "127.0.0.1",
+            "127.0.0.3:80",
+            vec![svc("example.com")],
+            &[],
+            expect(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(req.window_sizes, Some(window_sizes));
+
+        // Other services use the global windows.
+        let req = run_build_request_with(
+            cfg,
+            "127.0.0.1",
+            "127.0.0.3:80",
+            vec![svc("other.com")],
+            &[],
+            expect(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(req.window_sizes, None);
+    }
+
+    #[tokio::test]
+    async fn locality_window_sizes() {
+        let same_zone = WindowSizes {
+            window_size: 1024 * 1024,
+            connection_window_size: 2 * 1024 * 1024,
+        };
+        let cross_region = WindowSizes {
+            window_size: 16 * 1024 * 1024,
+            connection_window_size: 32 * 1024 * 1024,
+        };
+        let service = WindowSizes {
+            window_size: 8 * 1024 * 1024,
+            connection_window_size: 8 * 1024 * 1024,
+        };
+        let cfg = Config {
+            locality_window_sizes: std::collections::HashMap::from([
+                (strng::new("same-zone"), same_zone),
+                (strng::new("cross-region"), cross_region),
+            ]),
+            service_window_sizes: std::collections::HashMap::from([(
+                strng::new("example.com"),
+                service,
+            )]),
+            ..crate::config::parse_config().unwrap()
+        };
+        let outbound = test_outbound_connection(cfg, vec![], &[]);
+        let wl = |region: &str, zone: &str| Workload {
+            locality: Locality {
+                region: region.into(),
+                zone: zone.into(),
+                subzone: "".into(),
+            },
+            ..crate::test_helpers::test_default_workload()
+        };
+        let src = wl("us-east", "us-east-1");
+
+        let window_sizes = |svc: Option<&str>, dst: &Workload| {
+            outbound.window_sizes(svc.map(strng::new).as_ref(), &src, dst)
+        };
+        assert_eq!(
+            window_sizes(None, &wl("us-east", "us-east-1")),
+            Some(same_zone)
+        );
+        assert_eq!(
+            window_sizes(None, &wl("eu-west", "eu-west-1")),
+            Some(cross_region)
+        );
+        // No override is configured for other zones in the region.
+        assert_eq!(window_sizes(None, &wl("us-east", "us-east-2")), None);
+        // Workloads without a locality use the global windows.
+        assert_eq!(window_sizes(None, &wl("", "")), None);
+        // Service overrides take precedence.
+        assert_eq!(
+            window_sizes(Some("example.com"), &wl("eu-west", "eu-west-1")),
+            Some(service)
+        );
+    }
+
     #[tokio::test]
     async fn build_request_empty_service() {
         run_build_request(
@@ -941,6 +1728,59 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_destination_service() {
+        let xds = vec![
+            XdsAddressType::Service(XdsService {
+                hostname: "example.com".to_string(),
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                ..Default::default()
+            }),
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/default/pod".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                services: std::collections::HashMap::from([(
+                    "/example.com".to_string(),
+                    PortList {
+                        ports: vec![Port {
+                            service_port: 80,
+                            target_port: 8080,
+                        }],
+                    },
+                )]),
+                ..Default::default()
+            }),
+        ];
+        let expect = Some(ExpectedRequest {
+            protocol: Protocol::TCP,
+            hbone_destination: "",
+            destination: "127.0.0.2:8080",
+        });
+        // Dialing the VIP reports the service it mapped to
+        let req = run_build_request_multi("127.0.0.1", "127.0.0.3:80", xds.clone(), expect.clone())
+            .await
+            .unwrap();
+        let metrics = OutboundConnection::conn_metrics_from_request(&req);
+        assert_eq!(
+            metrics.destination_service.map(|s| s.hostname),
+            Some(crate::strng::new("example.com"))
+        );
+
+        // Dialing the pod directly has no service
+        let req = run_build_request_multi("127.0.0.1", "127.0.0.2:8080", xds, expect)
+            .await
+            .unwrap();
+        let metrics = OutboundConnection::conn_metrics_from_request(&req);
+        assert_eq!(metrics.destination_service, None);
+    }
+
     #[tokio::test]
     async fn build_request_host_network() {
         let xds = vec![
@@ -1060,6 +1900,31 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_ipv6_client() {
+        run_build_request(
+            "::1",
+            "[ff06::c3]:80",
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/ns/test-v6".to_string(),
+                name: "test-v6".to_string(),
+                namespace: "ns".to_string(),
+                addresses: vec![Bytes::copy_from_slice(
+                    "ff06::c3".parse::<Ipv6Addr>().unwrap().octets().as_slice(),
+                )],
+                tunnel_protocol: XdsProtocol::Hbone as i32,
+                node: "remote-node".to_string(),
+                ..Default::default()
+            }),
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                hbone_destination: "[ff06::c3]:80",
+                destination: "[ff06::c3]:15008",
+            }),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn service_ip_families() {
         initialize_telemetry();
@@ -1148,6 +2013,40 @@ mod tests {
         .await;
     }
 
+    #[test]
+    fn baggage_round_trip() {
+        let source = Workload {
+            namespace: "ns".into(),
+            workload_name: "source-workload".into(),
+            canonical_name: "source".into(),
+            canonical_revision: "v1".into(),
+            ..crate::test_helpers::test_default_workload()
+        };
+        let req = Request {
+            protocol: Protocol::HBONE,
+            source: Arc::new(source),
+            actual_destination_workload: None,
+            intended_destination_service: None,
+            actual_destination: "127.0.0.2:15008".parse().unwrap(),
+            hbone_target_destination: Some("127.0.0.2:80".parse().unwrap()),
+            upstream_sans: vec![],
+            dscp: None,
+            window_sizes: None,
+        };
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            BAGGAGE_HEADER,
+            baggage(&req, "Kubernetes".to_string()).parse().unwrap(),
+        );
+        // The receiving side must be able to derive the source from what we send
+        let parsed = crate::baggage::parse_baggage_header(headers.get_all(BAGGAGE_HEADER)).unwrap();
+        assert_eq!(parsed.cluster_id, Some("Kubernetes".into()));
+        assert_eq!(parsed.namespace, Some("ns".into()));
+        assert_eq!(parsed.workload_name, Some("source-workload".into()));
+        assert_eq!(parsed.service_name, Some("source".into()));
+        assert_eq!(parsed.revision, Some("v1".into()));
+    }
+
     #[test]
     fn build_forwarded() {
         assert_eq!(
@@ -1171,7 +2070,125 @@ mod tests {
         );
     }
 
-    #[derive(PartialEq, Debug)]
+    #[tokio::test]
+    async fn original_dst_addr_direct_connection() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // A client connecting directly, without being redirected, gets a clean error.
+        let cfg = crate::config::parse_config().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let res = super::original_dst_addr(&cfg, &mut stream).await;
+        assert!(matches!(res, Err(Error::NoOriginalDst)), "{res:?}");
+
+        // With PROXY protocol enabled, the client can name the destination itself, but not the source.
+        let cfg = Config {
+            outbound_proxy_protocol: true,
+            ..crate::config::parse_config().unwrap()
+        };
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n")
+            .await
+            .unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let res = super::original_dst_addr(&cfg, &mut stream).await;
+        assert_eq!(res.unwrap(), "10.0.0.2:80".parse().unwrap());
+
+        // A missing header is still rejected.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hello world").await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let res = super::original_dst_addr(&cfg, &mut stream).await;
+        assert!(matches!(res, Err(Error::ProxyProtocol(_))), "{res:?}");
+
+        // As is a client that never sends one.
+        tokio::time::pause();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let res = super::original_dst_addr(&cfg, &mut stream).await;
+        assert!(matches!(res, Err(Error::ProxyProtocol(_))), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn plaintext_fallback() {
+        use crate::config::PlaintextFallback;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The destination serves plaintext only; nothing is listening on its HBONE port.
+        let server = tokio::net::TcpListener::bind("127.0.0.2:0").await.unwrap();
+        let dst = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = server.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (mut r, mut w) = stream.split();
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+            }
+        });
+        let dest = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/onboarding".to_string(),
+            name: "onboarding".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+            tunnel_protocol: XdsProtocol::Hbone as i32,
+            node: "remote-node".to_string(),
+            ..Default::default()
+        };
+
+        let run = async |plaintext_fallback: PlaintextFallback| {
+            let cfg = Config {
+                plaintext_fallback,
+                hbone_connect_retries: 0,
+                ..crate::config::parse_config().unwrap()
+            };
+            let mut outbound =
+                test_outbound_connection(cfg, vec![XdsAddressType::Workload(dest.clone())], &[]);
+            let metrics = outbound.pi.metrics.clone();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (stream, source_addr) = listener.accept().await.unwrap();
+            let client = async {
+                client.write_all(b"hello").await.unwrap();
+                client.shutdown().await.unwrap();
+                let mut got = Vec::new();
+                // Without a fallback the connection is dropped, possibly with a reset.
+                let _ = client.read_to_end(&mut got).await;
+                got
+            };
+            let (_, got) = tokio::join!(
+                outbound.proxy_to(copy::TcpStreamSplitter(stream), source_addr, dst),
+                client
+            );
+            (got, metrics.plaintext_fallbacks.get())
+        };
+
+        // Disabled by default: the connection is rejected rather than sent in plaintext.
+        assert_eq!(run(PlaintextFallback::Disabled).await, (vec![], 0));
+        // Only the listed workloads fall back.
+        assert_eq!(
+            run(PlaintextFallback::Workloads(
+                [strng::new("ns/other")].into_iter().collect()
+            ))
+            .await,
+            (vec![], 0)
+        );
+        assert_eq!(
+            run(PlaintextFallback::Workloads(
+                [strng::new("ns/onboarding")].into_iter().collect()
+            ))
+            .await,
+            (b"hello".to_vec(), 1)
+        );
+        assert_eq!(run(PlaintextFallback::All).await, (b"hello".to_vec(), 1));
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
     struct ExpectedRequest<'a> {
         protocol: Protocol,
         hbone_destination: &'a str,
@@ -0,0 +1,311 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+use tracing::{Instrument, debug, info, info_span, trace, warn};
+
+use crate::copy::{self, BufferedSplitter};
+use crate::drain::DrainWatcher;
+use crate::drain::run_with_drain;
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::{ConnectionResult, Error, ProxyInputs, TraceParent, udp, util};
+use crate::socket;
+
+// How many datagrams may queue up for an association while its HBONE stream is being established
+// or is backed up. Beyond this they are dropped, as the network would.
+const ASSOCIATION_QUEUE: usize = 64;
+
+/// OutboundUdp accepts UDP from local applications and carries it over HBONE. Redirected UDP has no
+/// original destination to recover, so clients prefix each datagram with a SOCKS5 UDP request
+/// header (RFC 1928, section 7) naming its destination; replies carry the same header naming their
+/// source. Each client and destination pair forms an association, with its own HBONE stream.
+pub(super) struct OutboundUdp {
+    pi: Arc<ProxyInputs>,
+    socket: Arc<UdpSocket>,
+    drain: DrainWatcher,
+}
+
+impl OutboundUdp {
+    pub(super) async fn new(
+        pi: Arc<ProxyInputs>,
+        drain: DrainWatcher,
+    ) -> Result<OutboundUdp, Error> {
+        let addr = pi
+            .cfg
+            .outbound_udp_addr
+            .expect("outbound_udp_addr must be set");
+        let socket = pi
+            .socket_factory
+            .udp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
+
+        info!(
+            address=%socket.local_addr().expect("local_addr available"),
+            component="outbound_udp",
+            "listener established",
+        );
+
+        Ok(OutboundUdp {
+            pi,
+            socket: Arc::new(socket),
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.socket.local_addr().expect("local_addr available")
+    }
+
+    pub(super) async fn run(self) {
+        let pi = self.pi.clone();
+        let pool = crate::proxy::pool::WorkloadHBONEPool::new(
+            self.pi.cfg.clone(),
+            self.pi.socket_factory.clone(),
+            self.pi.local_workload_information.clone(),
+        );
+        let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut associations: HashMap<(SocketAddr, SocketAddr), mpsc::Sender<Bytes>> =
+                HashMap::new();
+            let mut buf = vec![0; udp::MAX_DATAGRAM_SIZE];
+            loop {
+                let (n, reply_to) = match self.socket.recv_from(&mut buf).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        warn!(
+                            component = "outbound_udp",
+                            "failed to receive datagram: {e}"
+                        );
+                        continue;
+                    }
+                };
+                let (target, payload) = match parse_header(&buf[..n]) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        debug!(component="outbound_udp", client=%reply_to, "dropping datagram: {e}");
+                        continue;
+                    }
+                };
+                let payload = Bytes::copy_from_slice(payload);
+                let key = (reply_to, target);
+                let payload = match associations.get(&key).map(|tx| tx.try_send(payload)) {
+                    Some(Ok(())) => continue,
+                    Some(Err(TrySendError::Full(_))) => {
+                        trace!(client=%reply_to, %target, "association backed up, dropping datagram");
+                        continue;
+                    }
+                    // The association has ended, so start a new one.
+                    Some(Err(TrySendError::Closed(payload))) => payload,
+                    None => payload,
+                };
+
+                associations.retain(|_, tx| !tx.is_closed());
+                let (tx, rx) = mpsc::channel(ASSOCIATION_QUEUE);
+                tx.try_send(payload).expect("new channel has capacity");
+                associations.insert(key, tx);
+
+                let client = socket::to_canonical(reply_to);
+                let mut oc = OutboundConnection {
+                    pi: self.pi.clone(),
+                    id: TraceParent::new(),
+                    pool: pool.clone(),
+                    hbone_port: self.pi.cfg.inbound_addr.port(),
+                    // Datagrams are carried over pooled HBONE connections.
+                    enable_orig_src: false,
+                };
+                let span = info_span!("outbound_udp", id=%oc.id, %client, %target);
+                oc.id.set_as_parent(&span);
+                let association = Association {
+                    client,
+                    target,
+                    reply_to,
+                    socket: self.socket.clone(),
+                    datagrams: rx,
+                };
+                let in_flight = drain.track();
+                let drain = drain.clone();
+                let mut force_shutdown = force_shutdown.clone();
+                let serve = (async move {
+                    debug!(component = "outbound_udp", "association started");
+                    // Since this task is spawned, make sure we are guaranteed to terminate
+                    tokio::select! {
+                        _ = force_shutdown.changed() => {
+                            debug!(component="outbound_udp", "association forcefully terminated");
+                        }
+                        _ = oc.proxy_udp(association) => {}
+                    }
+                    // Mark we are done with the association, so drain can complete
+                    drop(drain);
+                    drop(in_flight);
+                    debug!(component = "outbound_udp", "association completed");
+                })
+                .instrument(span);
+                tokio::spawn(serve);
+            }
+        };
+
+        run_with_drain(
+            "outbound_udp".to_string(),
+            self.drain,
+            pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
+            accept,
+        )
+        .await
+    }
+}
+
+/// Association is the datagrams sent from one client to one destination, and the replies to them.
+pub(super) struct Association {
+    pub(super) client: SocketAddr,
+    pub(super) target: SocketAddr,
+    // The client address as seen by the listener, which may not be canonical.
+    reply_to: SocketAddr,
+    socket: Arc<UdpSocket>,
+    datagrams: mpsc::Receiver<Bytes>,
+}
+
+impl Association {
+    /// relay forwards datagrams over an HBONE stream and sends replies back to the client, until
+    /// the stream ends or no datagrams are seen in either direction for `idle_timeout`.
+    pub(super) async fn relay(
+        self,
+        tunnel: impl BufferedSplitter,
+        stats: &ConnectionResult,
+        idle_timeout: Duration,
+    ) -> Result<(), Error> {
+        let Association {
+            target,
+            reply_to,
+            socket,
+            mut datagrams,
+            ..
+        } = self;
+        let (r, mut w) = tunnel.split_into_buffered_reader();
+        let mut r = udp::DatagramReader::new(r);
+        let to_tunnel = async {
+            while let Some(datagram) = datagrams.recv().await {
+                udp::write_datagram(&mut w, &datagram).await?;
+                stats.increment_send(datagram.len() as u64);
+            }
+            Ok::<_, io::Error>(())
+        };
+        let from_tunnel = async {
+            while let Some(datagram) = r.read().await? {
+                match socket
+                    .send_to(&encode_header(target, &datagram), reply_to)
+                    .await
+                {
+                    Err(e) if !udp::is_icmp_error(&e) => return Err(e),
+                    _ => stats.increment_recv(datagram.len() as u64),
+                }
+            }
+            Ok::<_, io::Error>(())
+        };
+        tokio::select! {
+            res = to_tunnel => res?,
+            res = from_tunnel => res?,
+            _ = copy::wait_for_idle(stats, idle_timeout) => {
+                trace!(?idle_timeout, "UDP association idle, closing");
+            }
+        }
+        Ok(())
+    }
+}
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// parse_header splits a datagram into the destination named by its SOCKS5 UDP request header,
+/// and its payload.
+fn parse_header(datagram: &[u8]) -> Result<(SocketAddr, &[u8]), &'static str> {
+    const TRUNCATED: &str = "truncated header";
+    let [_, _, frag, atyp, rest @ ..] = datagram else {
+        return Err(TRUNCATED);
+    };
+    if *frag != 0 {
+        return Err("fragmented datagrams are not supported");
+    }
+    let (ip, rest): (IpAddr, _) = match *atyp {
+        ATYP_IPV4 => {
+            let (ip, rest) = rest.split_first_chunk::<4>().ok_or(TRUNCATED)?;
+            (Ipv4Addr::from(*ip).into(), rest)
+        }
+        ATYP_IPV6 => {
+            let (ip, rest) = rest.split_first_chunk::<16>().ok_or(TRUNCATED)?;
+            (Ipv6Addr::from(*ip).into(), rest)
+        }
+        ATYP_DOMAIN => return Err("domain names are not supported"),
+        _ => return Err("unknown address type"),
+    };
+    let (port, payload) = rest.split_first_chunk::<2>().ok_or(TRUNCATED)?;
+    Ok((SocketAddr::new(ip, u16::from_be_bytes(*port)), payload))
+}
+
+/// encode_header prefixes a reply with a SOCKS5 UDP request header naming its source.
+fn encode_header(source: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(22 + payload.len());
+    // RSV and FRAG
+    buf.extend_from_slice(&[0, 0, 0]);
+    match source.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&source.port().to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        for addr in ["10.0.0.1:53", "[fd00::1]:443"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let datagram = encode_header(addr, b"payload");
+            assert_eq!(parse_header(&datagram), Ok((addr, b"payload".as_slice())));
+        }
+    }
+
+    #[test]
+    fn header_rejected() {
+        // Too short to hold an IPv4 address and port
+        assert!(parse_header(&[0, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0]).is_err());
+        // Fragmented
+        assert!(parse_header(&[0, 0, 1, ATYP_IPV4, 10, 0, 0, 1, 0, 53]).is_err());
+        // Domain names are not resolved
+        assert!(parse_header(&[0, 0, 0, ATYP_DOMAIN, 3, b'f', b'o', b'o', 0, 53]).is_err());
+        assert!(parse_header(&[0, 0, 0, 0x05, 10, 0, 0, 1, 0, 53]).is_err());
+    }
+}
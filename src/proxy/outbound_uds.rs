@@ -0,0 +1,259 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::drain::DrainWatcher;
+use crate::drain::run_with_drain;
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::socks5::{negotiate_socks_connection, send_error, send_success};
+use crate::proxy::{Error, ProxyInputs, TraceParent, util};
+
+/// OutboundUds accepts outbound traffic on a Unix domain socket, for applications that reach
+/// ztunnel over a shared socket file rather than redirected TCP. A Unix socket carries no original
+/// destination, so clients name it with a SOCKS5 CONNECT before sending data.
+pub(super) struct OutboundUds {
+    pi: Arc<ProxyInputs>,
+    listener: UnixListener,
+    path: PathBuf,
+    drain: DrainWatcher,
+}
+
+impl OutboundUds {
+    pub(super) fn new(pi: Arc<ProxyInputs>, drain: DrainWatcher) -> Result<OutboundUds, Error> {
+        let path = pi
+            .cfg
+            .outbound_uds
+            .clone()
+            .expect("outbound_uds must be set");
+        // A socket file left behind by a previous instance would make bind fail. Anything else at
+        // the path is not ours to remove.
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                std::fs::remove_file(&path).map_err(|e| Error::BindUnix(path.clone(), e))?;
+                debug!(path=%path.display(), "removed stale socket");
+            }
+            Ok(_) => {
+                return Err(Error::BindUnix(
+                    path,
+                    io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "path exists and is not a socket",
+                    ),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::BindUnix(path, e)),
+        }
+        let listener = UnixListener::bind(&path).map_err(|e| Error::BindUnix(path.clone(), e))?;
+
+        info!(
+            path=%path.display(),
+            component="outbound_uds",
+            "listener established",
+        );
+
+        Ok(OutboundUds {
+            pi,
+            listener,
+            path,
+            drain,
+        })
+    }
+
+    pub(super) async fn run(self) {
+        let pi = self.pi.clone();
+        let path = self.path.clone();
+        let pool = crate::proxy::pool::WorkloadHBONEPool::new(
+            self.pi.cfg.clone(),
+            self.pi.socket_factory.clone(),
+            self.pi.local_workload_information.clone(),
+        );
+        let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut backoff = util::AcceptBackoff::default();
+            loop {
+                let socket = self.listener.accept().await;
+                let start = Instant::now();
+                let drain = drain.clone();
+                let mut force_shutdown = force_shutdown.clone();
+                match socket {
+                    Ok((stream, _remote)) => {
+                        backoff.reset();
+                        let in_flight = drain.track();
+                        let oc = OutboundConnection {
+                            pi: self.pi.clone(),
+                            id: TraceParent::new(),
+                            pool: pool.clone(),
+                            hbone_port: self.pi.cfg.inbound_addr.port(),
+                            // There is no client IP to preserve on a Unix socket.
+                            enable_orig_src: false,
+                        };
+                        // The peer credentials are the only hint of which process is connecting.
+                        let cred = stream.peer_cred().ok();
+                        let span = info_span!(
+                            "outbound_uds",
+                            id=%oc.id,
+                            uid=cred.map(|c| c.uid()),
+                            pid=cred.and_then(|c| c.pid()),
+                        );
+                        oc.id.set_as_parent(&span);
+                        let serve = (async move {
+                            debug!(component="outbound_uds", "connection started");
+                            // Since this task is spawned, make sure we are guaranteed to terminate
+                            tokio::select! {
+                                _ = force_shutdown.changed() => {
+                                    debug!(component="outbound_uds", "connection forcefully terminated");
+                                }
+                                _ = handle_uds_connection(oc, stream) => {}
+                            }
+                            // Mark we are done with the connection, so drain can complete
+                            drop(drain);
+                            drop(in_flight);
+                            debug!(component="outbound_uds", dur=?start.elapsed(), "connection completed");
+                        }).instrument(span);
+
+                        tokio::spawn(serve);
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        if util::is_fd_exhausted(&e) {
+                            self.pi.metrics.accept_fd_exhausted.inc();
+                        }
+                        tokio::time::sleep(backoff.failed("outbound_uds", &e)).await;
+                    }
+                }
+            }
+        };
+
+        run_with_drain(
+            "outbound_uds".to_string(),
+            self.drain,
+            pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
+            accept,
+        )
+        .await;
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!(path=%path.display(), "failed to remove socket: {e}");
+        }
+    }
+}
+
+async fn handle_uds_connection(mut oc: OutboundConnection, mut stream: UnixStream) {
+    // Traffic on the socket is attributed to the local workload, so use its address as the source.
+    let source_ip = match oc.pi.local_workload_information.get_workload().await {
+        Ok(wl) => wl.workload_ips.first().copied(),
+        Err(e) => {
+            warn!("failed to find local workload: {e}");
+            return;
+        }
+    };
+    let Some(source_ip) = source_ip else {
+        warn!("local workload has no address");
+        return;
+    };
+    let source_addr = SocketAddr::new(source_ip, 0);
+    match negotiate_socks_connection(&oc.pi, source_addr, &mut stream).await {
+        Ok(target) => {
+            let dummy_addr = SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), 0);
+            if let Err(err) = send_success(&mut stream, dummy_addr).await {
+                warn!("failed to send socks success response: {err}");
+                return;
+            }
+            oc.proxy_to(stream, source_addr, target).await
+        }
+        Err(e) => {
+            warn!("failed to negotiate socks connection: {e}");
+            send_error(&e, &mut stream).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::drain;
+    use crate::drain::DrainMode;
+    use crate::identity;
+    use crate::proxy::connection_manager::ConnectionManager;
+    use crate::proxy::{DefaultSocketFactory, LocalWorkloadInformation};
+    use crate::state::WorkloadInfo;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use crate::test_helpers::{new_proxy_state, test_config};
+
+    #[tokio::test]
+    async fn socket_file_lifecycle() {
+        let path = std::env::temp_dir().join(format!(
+            "ztunnel-outbound-{}-{}.sock",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        // A regular file at the path is left alone.
+        std::fs::write(&path, b"").unwrap();
+        assert!(OutboundUds::new(pi(&path), drain::new().1).is_err());
+        assert!(path.exists(), "non-socket file should not be removed");
+        std::fs::remove_file(&path).unwrap();
+
+        // Simulate a socket left behind by a previous run.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let (trigger, watcher) = drain::new();
+        let uds = OutboundUds::new(pi(&path), watcher).unwrap();
+        let task = tokio::spawn(uds.run());
+        UnixStream::connect(&path).await.unwrap();
+
+        trigger.start_drain_and_wait(DrainMode::Immediate).await;
+        task.await.unwrap();
+        assert!(!path.exists(), "socket file should be removed on shutdown");
+    }
+
+    fn pi(path: &std::path::Path) -> Arc<ProxyInputs> {
+        let mut cfg = test_config();
+        cfg.outbound_uds = Some(path.to_path_buf());
+        let state = new_proxy_state(&[], &[], &[]);
+        let local_workload_information = Arc::new(LocalWorkloadInformation::new(
+            Arc::new(WorkloadInfo {
+                name: "source-workload".to_string(),
+                namespace: "ns".to_string(),
+                service_account: "default".to_string(),
+            }),
+            state.clone(),
+            identity::mock::new_secret_manager(Duration::from_secs(10)),
+        ));
+        Arc::new(ProxyInputs {
+            cfg: Arc::new(cfg),
+            state,
+            metrics: test_proxy_metrics(),
+            socket_factory: Arc::new(DefaultSocketFactory::default()),
+            local_workload_information,
+            connection_manager: ConnectionManager::default(),
+            resolver: None,
+        })
+    }
+}
@@ -81,23 +81,42 @@ impl ConnSpawner {
         debug!("spawning new pool conn for {}", key);
 
         let cert = self.local_workload.fetch_certificate().await?;
-        let connector = cert.outbound_connector(key.dst_id.clone())?;
-        let tcp_stream = super::freebind_connect(None, key.dst, self.socket_factory.as_ref())
+        let connector =
+            cert.outbound_connector(key.dst_id.clone(), self.cfg.tls_session_cache_size)?;
+        let tcp_stream = super::freebind_connect(
+            None,
+            key.dst,
+            &super::DscpSocketFactory {
+                inner: self.socket_factory.as_ref(),
+                dscp: key.dscp,
+            },
+            self.cfg.connect_timeout,
+        )
+        .await
+        .map_err(|e: io::Error| match e.kind() {
+            io::ErrorKind::TimedOut => Error::MaybeHBONENetworkPolicyError(e),
+            _ => Error::ConnectionFailed(e),
+        })?;
+
+        let tls_stream = connector
+            .connect(tcp_stream)
             .await
-            .map_err(|e: io::Error| match e.kind() {
-                io::ErrorKind::TimedOut => Error::MaybeHBONENetworkPolicyError(e),
-                _ => e.into(),
-            })?;
-
-        let tls_stream = connector.connect(tcp_stream).await?;
+            .map_err(|e| Error::TlsHandshake(key.dst, e))?;
         trace!("connector connected, handshaking");
-        let sender = h2::client::spawn_connection(
+        let mut sender = h2::client::spawn_connection(
             self.cfg.clone(),
             tls_stream,
             self.timeout_rx.clone(),
             key,
         )
         .await?;
+        if let Some(at) = self
+            .cfg
+            .cert_rotation_threshold
+            .and_then(|t| cert.rotate_at(t))
+        {
+            sender.rotate_at(at);
+        }
         Ok(sender)
     }
 }
@@ -301,6 +320,15 @@ impl PoolState {
                         );
                         continue;
                     }
+                    if existing.needs_rotation() {
+                        // Let the streams already on it finish, but move new ones to a connection
+                        // with the refreshed certificate.
+                        debug!(
+                            "checked out connection for {} has a certificate near expiry, dropping it",
+                            workload_key
+                        );
+                        continue;
+                    }
                     debug!("re-using connection for {}", workload_key);
                     break existing;
                 }
@@ -738,6 +766,28 @@ mod test {
         assert_opens_drops!(srv, 2, 1);
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn rotate_connections_near_cert_expiry() {
+        // Certificates live for 10s, so they are within the threshold about a second after issuance.
+        let (pool, mut srv) = setup_test_with_cfg(crate::config::Config {
+            pool_max_streams_per_conn: 3,
+            cert_rotation_threshold: Some(Duration::from_secs(9)),
+            ..crate::config::parse_config().unwrap()
+        })
+        .await;
+
+        let key = key(&srv, 1);
+
+        spawn_clients_concurrently(pool.clone(), key.clone(), srv.addr, 1).await;
+        assert_opens_drops!(srv, 1, 0);
+
+        // The pooled connection is not reused once its certificate is near expiry; it is closed
+        // and a new one is opened instead.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        spawn_clients_concurrently(pool.clone(), key.clone(), srv.addr, 1).await;
+        assert_opens_drops!(srv, 2, 1);
+    }
+
     async fn spawn_clients_concurrently(
         mut pool: WorkloadHBONEPool,
         key: WorkloadKey,
@@ -940,17 +990,21 @@ mod test {
         max_conns: u16,
         idle: Duration,
     ) -> (WorkloadHBONEPool, TestServer) {
+        setup_test_with_cfg(crate::config::Config {
+            pool_max_streams_per_conn: max_conns,
+            pool_unused_release_timeout: idle,
+            ..crate::config::parse_config().unwrap()
+        })
+        .await
+    }
+
+    async fn setup_test_with_cfg(cfg: crate::config::Config) -> (WorkloadHBONEPool, TestServer) {
         initialize_telemetry();
         let conn_counter: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
         let (drop_tx, drop_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
         let (goaway_tx, goaway_rx) = oneshot::channel::<()>();
         let addr = spawn_server(conn_counter.clone(), drop_tx, goaway_rx).await;
 
-        let cfg = crate::config::Config {
-            pool_max_streams_per_conn: max_conns,
-            pool_unused_release_timeout: idle,
-            ..crate::config::parse_config().unwrap()
-        };
         let sock_fact = Arc::new(crate::proxy::DefaultSocketFactory::default());
 
         let mut state = ProxyState::new(None);
@@ -1003,6 +1057,8 @@ mod test {
             dst_id: vec![Identity::default()],
             src: IpAddr::from([127, 0, 0, ip]),
             dst: srv.addr,
+            dscp: None,
+            window_sizes: None,
         }
     }
 }
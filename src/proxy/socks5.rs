@@ -26,15 +26,16 @@ use std::sync::Arc;
 use std::time::Instant;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::sync::watch;
-use tracing::{Instrument, debug, error, info, info_span, warn};
+use tracing::{Instrument, debug, info, info_span, warn};
 
 use crate::drain::DrainWatcher;
 use crate::drain::run_with_drain;
 use crate::proxy::outbound::OutboundConnection;
 use crate::proxy::{Error, ProxyInputs, TraceParent, util};
-use crate::{assertions, socket};
+use crate::{assertions, copy, socket};
 
 pub(super) struct Socks5 {
     pi: Arc<ProxyInputs>,
@@ -77,6 +78,7 @@ impl Socks5 {
             self.pi.local_workload_information.clone(),
         );
         let accept = async move |drain: DrainWatcher, force_shutdown: watch::Receiver<()>| {
+            let mut backoff = util::AcceptBackoff::default();
             loop {
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
@@ -85,13 +87,18 @@ impl Socks5 {
                 let mut force_shutdown = force_shutdown.clone();
                 match socket {
                     Ok((stream, _remote)) => {
+                        backoff.reset();
+                        let in_flight = drain.track();
                         let oc = OutboundConnection {
                             pi: self.pi.clone(),
                             id: TraceParent::new(),
                             pool: pool.clone(),
                             hbone_port: self.pi.cfg.inbound_addr.port(),
+                            // SOCKS5 clients connect to us explicitly, so there is nothing to preserve.
+                            enable_orig_src: false,
                         };
                         let span = info_span!("socks5", id=%oc.id);
+                        oc.id.set_as_parent(&span);
                         let serve = (async move {
                             debug!(component="socks5", "connection started");
                             // Since this task is spawned, make sure we are guaranteed to terminate
@@ -103,6 +110,7 @@ impl Socks5 {
                             }
                             // Mark we are done with the connection, so drain can complete
                             drop(drain);
+                            drop(in_flight);
                             debug!(component="socks5", dur=?start.elapsed(), "connection completed");
                         }).instrument(span);
 
@@ -113,7 +121,10 @@ impl Socks5 {
                         if util::is_runtime_shutdown(&e) {
                             return;
                         }
-                        error!("Failed TCP handshake {}", e);
+                        if util::is_fd_exhausted(&e) {
+                            self.pi.metrics.accept_fd_exhausted.inc();
+                        }
+                        tokio::time::sleep(backoff.failed("socks5", &e)).await;
                     }
                 }
             }
@@ -123,13 +134,15 @@ impl Socks5 {
             "socks5".to_string(),
             self.drain,
             pi.cfg.self_termination_deadline,
+            pi.metrics.connections_force_closed.clone(),
             accept,
         )
         .await
     }
 }
 async fn handle_socks_connection(mut oc: OutboundConnection, mut stream: TcpStream) {
-    match negotiate_socks_connection(&oc.pi, &mut stream).await {
+    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+    match negotiate_socks_connection(&oc.pi, remote_addr, &mut stream).await {
         Ok(target) => {
             // TODO: ideally, we send the success after we connect. This allows us to actually give a
             // success only when we really succeeded, rather than if we completed the SOCKS handshake.
@@ -139,9 +152,8 @@ async fn handle_socks_connection(mut oc: OutboundConnection, mut stream: TcpStre
                 warn!("failed to send socks success response: {err}");
                 return;
             }
-            let remote_addr =
-                socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
-            oc.proxy_to(stream, remote_addr, target).await
+            oc.proxy_to(copy::TcpStreamSplitter(stream), remote_addr, target)
+                .await
         }
         Err(e) => {
             warn!("failed to negotiate socks connection: {e}");
@@ -155,12 +167,11 @@ async fn handle_socks_connection(mut oc: OutboundConnection, mut stream: TcpStre
 // This supports a minimal subset of the protocol, sufficient to integrate with common clients:
 // - only unauthenticated requests
 // - only CONNECT, with IPv4/IPv6/Hostname
-async fn negotiate_socks_connection(
+pub(super) async fn negotiate_socks_connection<S: AsyncRead + AsyncWrite + Unpin>(
     pi: &ProxyInputs,
-    stream: &mut TcpStream,
+    remote_addr: SocketAddr,
+    stream: &mut S,
 ) -> Result<SocketAddr, SocksError> {
-    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
-
     // Version(5), Number of auth methods
     let mut version = [0u8; 2];
     stream.read_exact(&mut version).await?;
@@ -325,7 +336,7 @@ async fn dns_lookup(
 /// send_error sends an error back to the SOCKS client
 /// This may fail, but since there is nothing a caller can do about it, failures are simply logged and
 /// not returned.
-pub async fn send_error(err: &SocksError, source: &mut TcpStream) {
+pub async fn send_error<S: AsyncWrite + Unpin>(err: &SocksError, source: &mut S) {
     // SOCKS response requires us to send a 'server bound address'.
     // It's supposed to be the local address we have bound to.
     // In many cases, when we are fail we don't have this.
@@ -336,13 +347,16 @@ pub async fn send_error(err: &SocksError, source: &mut TcpStream) {
 }
 
 /// send_success sends a success back to the SOCKS client.
-pub async fn send_success(source: &mut TcpStream, local_addr: SocketAddr) -> Result<(), Error> {
+pub async fn send_success<S: AsyncWrite + Unpin>(
+    source: &mut S,
+    local_addr: SocketAddr,
+) -> Result<(), Error> {
     send_response(None, source, local_addr).await
 }
 
-async fn send_response(
+async fn send_response<S: AsyncWrite + Unpin>(
     err: Option<&SocksError>,
-    source: &mut TcpStream,
+    source: &mut S,
     local_addr: SocketAddr,
 ) -> Result<(), Error> {
     // https://www.rfc-editor.org/rfc/rfc1928#section-6
@@ -0,0 +1,182 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! UDP over HBONE. Each UDP association is carried on its own HBONE stream, marked with
+//! [PROTOCOL_HEADER], with every datagram framed as a 2-byte big-endian length followed by the
+//! payload.
+
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::net::UdpSocket;
+use tracing::trace;
+
+use crate::copy::{self, AsyncWriteBuf, BufferedSplitter, ResizeBufRead};
+use crate::proxy::{ConnectionResult, Error};
+
+/// PROTOCOL_HEADER marks an HBONE request as carrying UDP rather than a TCP stream.
+pub const PROTOCOL_HEADER: &str = "x-ztunnel-protocol";
+pub const PROTOCOL_UDP: &str = "udp";
+
+/// The largest UDP payload; this is also the most the length prefix can describe.
+pub const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+/// DatagramReader reads length-prefixed datagrams off of an HBONE stream.
+pub struct DatagramReader<R> {
+    inner: R,
+    buf: BytesMut,
+}
+
+impl<R: ResizeBufRead + Unpin> DatagramReader<R> {
+    pub fn new(inner: R) -> Self {
+        DatagramReader {
+            inner,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// read returns the next datagram, or None once the stream has ended.
+    pub async fn read(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            if self.buf.len() >= 2 {
+                let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+                if self.buf.len() >= 2 + len {
+                    self.buf.advance(2);
+                    return Ok(Some(self.buf.split_to(len).freeze()));
+                }
+            }
+            let chunk = poll_fn(|cx| Pin::new(&mut self.inner).poll_bytes(cx)).await?;
+            if chunk.is_empty() {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream ended mid-datagram",
+                ));
+            }
+            self.buf.extend_from_slice(&chunk);
+        }
+    }
+}
+
+/// write_datagram writes a single length-prefixed datagram to an HBONE stream.
+pub async fn write_datagram<W: AsyncWriteBuf + Unpin>(w: &mut W, payload: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "datagram too large"))?;
+    let mut frame = BytesMut::with_capacity(2 + payload.len());
+    frame.put_u16(len);
+    frame.put_slice(payload);
+    let mut frame = frame.freeze();
+    while !frame.is_empty() {
+        let n = poll_fn(|cx| Pin::new(&mut *w).poll_write_buf(cx, frame.clone())).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        frame.advance(n);
+    }
+    poll_fn(|cx| Pin::new(&mut *w).poll_flush(cx)).await
+}
+
+// A connected UDP socket reports ICMP errors from earlier sends on later calls. These say nothing
+// about the datagram at hand, and UDP is lossy anyway, so they are not worth ending the association.
+pub(super) fn is_icmp_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::ConnectionRefused
+}
+
+/// relay_to_socket forwards datagrams between an HBONE stream and a connected UDP socket, until the
+/// stream ends or no datagrams are seen in either direction for `idle_timeout`.
+pub async fn relay_to_socket(
+    tunnel: impl BufferedSplitter,
+    socket: UdpSocket,
+    stats: &ConnectionResult,
+    idle_timeout: Duration,
+) -> Result<(), Error> {
+    let (r, mut w) = tunnel.split_into_buffered_reader();
+    let mut r = DatagramReader::new(r);
+    let to_socket = async {
+        while let Some(datagram) = r.read().await? {
+            match socket.send(&datagram).await {
+                Err(e) if !is_icmp_error(&e) => return Err(e),
+                _ => stats.increment_send(datagram.len() as u64),
+            }
+        }
+        Ok::<_, io::Error>(())
+    };
+    let from_socket = async {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let n = match socket.recv(&mut buf).await {
+                Ok(n) => n,
+                Err(e) if is_icmp_error(&e) => continue,
+                Err(e) => break Err::<(), _>(e),
+            };
+            if let Err(e) = write_datagram(&mut w, &buf[..n]).await {
+                break Err(e);
+            }
+            stats.increment_recv(n as u64);
+        }
+    };
+    tokio::select! {
+        res = to_socket => res?,
+        res = from_socket => res?,
+        _ = copy::wait_for_idle(stats, idle_timeout) => {
+            trace!(?idle_timeout, "UDP association idle, closing");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn datagram_framing() {
+        let (client, server) = tokio::io::duplex(64);
+        let (_, mut w) = client.split_into_buffered_reader();
+        let (r, _) = server.split_into_buffered_reader();
+        let mut r = DatagramReader::new(r);
+
+        // Larger than the pipe, so frames are split across reads.
+        let large = vec![7u8; 200];
+        let write = async {
+            write_datagram(&mut w, b"hello").await.unwrap();
+            write_datagram(&mut w, b"").await.unwrap();
+            write_datagram(&mut w, &large).await.unwrap();
+        };
+        let read = async {
+            assert_eq!(
+                r.read().await.unwrap().unwrap(),
+                Bytes::from_static(b"hello")
+            );
+            assert_eq!(r.read().await.unwrap().unwrap(), Bytes::new());
+            assert_eq!(r.read().await.unwrap().unwrap(), Bytes::from(large.clone()));
+        };
+        tokio::join!(write, read);
+    }
+
+    #[tokio::test]
+    async fn datagram_too_large() {
+        let (client, _server) = tokio::io::duplex(64);
+        let (_, mut w) = client.split_into_buffered_reader();
+        let err = write_datagram(&mut w, &vec![0; MAX_DATAGRAM_SIZE + 1])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
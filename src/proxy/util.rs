@@ -13,6 +13,9 @@
 // limitations under the License.
 
 use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use tracing::error;
 
 pub fn is_runtime_shutdown(e: &Error) -> bool {
     if e.kind() == ErrorKind::Other
@@ -22,3 +25,83 @@ pub fn is_runtime_shutdown(e: &Error) -> bool {
     }
     false
 }
+
+/// is_fd_exhausted returns true if `e` indicates the process or system has run out of file descriptors.
+pub fn is_fd_exhausted(e: &Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
+/// is_resource_exhausted returns true if `e` indicates the process or system has run out of file
+/// descriptors or memory. Unlike most accept errors, these do not clear up on the next attempt.
+pub fn is_resource_exhausted(e: &Error) -> bool {
+    is_fd_exhausted(e) || matches!(e.raw_os_error(), Some(libc::ENOBUFS) | Some(libc::ENOMEM))
+}
+
+// Running out of file descriptors or memory will not clear until connections close, so wait a while.
+const ACCEPT_BACKOFF_BASE: Duration = Duration::from_millis(100);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// AcceptBackoff tracks consecutive accept failures caused by resource exhaustion on a listener,
+/// so running out of file descriptors or memory slows down the accept loop rather than spinning
+/// the CPU. Other errors (such as ECONNABORTED) only affect a single connection and are retried
+/// immediately.
+#[derive(Default)]
+pub struct AcceptBackoff {
+    failures: u32,
+}
+
+impl AcceptBackoff {
+    /// failed records and logs an accept error, returning how long to wait before accepting again.
+    pub fn failed(&mut self, component: &str, e: &Error) -> Duration {
+        if !is_resource_exhausted(e) {
+            error!(component, "Failed TCP handshake {e}");
+            return Duration::ZERO;
+        }
+        self.failures = self.failures.saturating_add(1);
+        error!(
+            component,
+            failures = self.failures,
+            "failed to accept connection: out of resources: {e}"
+        );
+        std::cmp::min(
+            ACCEPT_BACKOFF_BASE * 2u32.pow(std::cmp::min(self.failures - 1, 16)),
+            ACCEPT_BACKOFF_MAX,
+        )
+    }
+
+    /// reset should be called once a connection is accepted successfully.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_backoff() {
+        let mut b = AcceptBackoff::default();
+        let aborted = Error::from(ErrorKind::ConnectionAborted);
+        assert!(!is_resource_exhausted(&aborted));
+        assert_eq!(b.failed("test", &aborted), Duration::ZERO);
+        assert_eq!(b.failed("test", &aborted), Duration::ZERO);
+
+        let emfile = Error::from_raw_os_error(libc::EMFILE);
+        assert!(is_fd_exhausted(&emfile));
+        assert_eq!(b.failed("test", &emfile), Duration::from_millis(100));
+        assert_eq!(b.failed("test", &emfile), Duration::from_millis(200));
+        // Errors unrelated to resource exhaustion do not add to the backoff.
+        assert_eq!(b.failed("test", &aborted), Duration::ZERO);
+        assert_eq!(b.failed("test", &emfile), Duration::from_millis(400));
+        for _ in 0..20 {
+            b.failed("test", &emfile);
+        }
+        assert_eq!(b.failed("test", &emfile), ACCEPT_BACKOFF_MAX);
+
+        b.reset();
+        let enomem = Error::from_raw_os_error(libc::ENOMEM);
+        assert!(!is_fd_exhausted(&enomem));
+        assert_eq!(b.failed("test", &enomem), Duration::from_millis(100));
+    }
+}
@@ -21,6 +21,8 @@ use tracing::error;
 use crate::dns;
 use crate::drain::DrainWatcher;
 
+use crate::proxy::circuit_breaker::CircuitBreaker;
+use crate::proxy::connection_limit::ConnectionLimit;
 use crate::proxy::connection_manager::ConnectionManager;
 use crate::proxy::{Error, LocalWorkloadInformation, Metrics};
 
@@ -35,6 +37,8 @@ pub struct ProxyFactory {
     proxy_metrics: Arc<Metrics>,
     dns_metrics: Option<Arc<dns::Metrics>>,
     drain: DrainWatcher,
+    // Shared by every proxy, so the limit applies to the node as a whole.
+    connection_limit: ConnectionLimit,
 }
 
 impl ProxyFactory {
@@ -56,6 +60,7 @@ impl ProxyFactory {
             }
         };
 
+        let connection_limit = ConnectionLimit::new(config.max_inbound_connections);
         Ok(ProxyFactory {
             config,
             state,
@@ -63,6 +68,7 @@ impl ProxyFactory {
             proxy_metrics,
             dns_metrics,
             drain,
+            connection_limit,
         })
     }
 
@@ -121,7 +127,12 @@ impl ProxyFactory {
 
         // Optionally create the HBONE proxy.
         if self.config.proxy {
-            let cm = ConnectionManager::default();
+            let cm = ConnectionManager::new(self.config.max_connections_per_identity)
+                .with_circuit_breaker(CircuitBreaker::new(
+                    self.config.circuit_breaker_threshold,
+                    self.config.circuit_breaker_cooldown,
+                ))
+                .with_connection_limit(self.connection_limit.clone());
             let pi = crate::proxy::ProxyInputs::new(
                 self.config.clone(),
                 cm.clone(),
@@ -81,14 +81,28 @@ impl Authorization {
         res.into()
     }
 
-    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
     pub fn matches(&self, conn: &Connection) -> bool {
+        self.matches_with_trust_domains(conn, &[])
+    }
+
+    /// matches_with_trust_domains is like matches, but principals also match if the source identity
+    /// is the same in any of the equivalent `trust_domains`.
+    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
+    pub fn matches_with_trust_domains(&self, conn: &Connection, trust_domains: &[Strng]) -> bool {
         let full_identity = conn.src_identity.as_ref();
         let id = conn
             .src_identity
             .as_ref()
             .map(|i| i.to_strng())
             .unwrap_or_default();
+        let aliased_ids: Vec<Strng> = match full_identity {
+            Some(i) if trust_domains.contains(&i.trust_domain()) => trust_domains
+                .iter()
+                .filter(|td| **td != i.trust_domain())
+                .map(|td| i.with_trust_domain(td.clone()).to_strng())
+                .collect(),
+            _ => Vec::new(),
+        };
         let ns = conn
             .src_identity
             .as_ref()
@@ -145,7 +159,10 @@ impl Authorization {
                         "principals",
                         &mg.principals,
                         &mg.not_principals,
-                        |p| p.matches_principal(&id),
+                        |p| {
+                            p.matches_principal(&id)
+                                || aliased_ids.iter().any(|id| p.matches_principal(id))
+                        },
                     );
                     m &= Self::matches_internal(
                         "namespaces",
@@ -614,6 +631,34 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn rbac_trust_domain_aliases() {
+        let pol = allow_policy(
+            "federated",
+            vec![vec![vec![RbacMatch {
+                principals: vec![StringMatch::Exact("td/ns/namespace/sa/account".into())],
+                ..Default::default()
+            }]]],
+        );
+        let conn = |td: &str| Connection {
+            src_identity: Some(Identity::Spiffe {
+                trust_domain: td.into(),
+                namespace: "namespace".into(),
+                service_account: "account".into(),
+            }),
+            src: "127.0.0.1:1234".parse().unwrap(),
+            dst_network: "".into(),
+            dst: "127.0.0.2:80".parse().unwrap(),
+        };
+        let tds = ["td".into(), "remote.td".into()];
+        // A federated trust domain matches only when it is configured as equivalent.
+        assert!(!pol.matches(&conn("remote.td")));
+        assert!(pol.matches_with_trust_domains(&conn("remote.td"), &tds));
+        assert!(pol.matches_with_trust_domains(&conn("td"), &tds));
+        // Unknown trust domains never match.
+        assert!(!pol.matches_with_trust_domains(&conn("unknown.td"), &tds));
+    }
+
     #[test]
     fn rbac_multi_rule() {
         let pol = allow_policy(
@@ -57,6 +57,30 @@ pub fn orig_dst_addr_or_default(stream: &tokio::net::TcpStream) -> SocketAddr {
     })
 }
 
+/// redirected_dst_addr returns the original destination of a connection redirected to us, or None
+/// if the client connected to our listener directly.
+/// Transparent (TPROXY) connections are accepted on the original destination, so the local address
+/// is used for those.
+pub fn redirected_dst_addr(stream: &tokio::net::TcpStream) -> Option<SocketAddr> {
+    let local = to_canonical(stream.local_addr().ok()?);
+    match orig_dst_addr(stream) {
+        // With conntrack enabled, a direct connection reports itself as the original destination.
+        Ok(addr) if to_canonical(addr) != local => Some(to_canonical(addr)),
+        _ if is_transparent(stream) => Some(local),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_transparent(stream: &tokio::net::TcpStream) -> bool {
+    SockRef::from(stream).ip_transparent().unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_transparent(_: &tokio::net::TcpStream) -> bool {
+    false
+}
+
 #[cfg(target_os = "linux")]
 fn orig_dst_addr(stream: &tokio::net::TcpStream) -> io::Result<SocketAddr> {
     let sock = SockRef::from(stream);
@@ -110,6 +134,68 @@ pub fn set_mark(_socket: &TcpSocket, _mark: u32) -> io::Result<()> {
     ))
 }
 
+/// new_mptcp creates a Multipath TCP socket in the given family, in non-blocking mode.
+#[cfg(target_os = "linux")]
+pub fn new_mptcp(domain: Domain) -> io::Result<TcpSocket> {
+    let socket = socket2::Socket::new(
+        domain,
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::MPTCP),
+    )?;
+    socket.set_nonblocking(true)?;
+    Ok(TcpSocket::from_std_stream(socket.into()))
+}
+
+/// mptcp_supported returns whether the kernel lets us create Multipath TCP sockets. This is checked
+/// once; MPTCP being disabled or unavailable is not expected to change at runtime.
+#[cfg(target_os = "linux")]
+pub fn mptcp_supported() -> bool {
+    static SUPPORTED: once_cell::sync::Lazy<bool> =
+        once_cell::sync::Lazy::new(|| match new_mptcp(Domain::IPV4) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("MPTCP is not supported by the kernel, using TCP: {e}");
+                false
+            }
+        });
+    *SUPPORTED
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn new_mptcp(_domain: socket2::Domain) -> io::Result<TcpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "MPTCP not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mptcp_supported() -> bool {
+    false
+}
+
+/// set_dscp marks packets sent on `socket` with a DSCP value, through IP_TOS or IPV6_TCLASS
+/// depending on the socket's family.
+#[cfg(target_os = "linux")]
+pub fn set_dscp(socket: &TcpSocket, dscp: u8) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+    // DSCP is the upper 6 bits of the TOS/traffic class byte.
+    let tos = u32::from(dscp) << 2;
+    if socket.domain()? == Domain::IPV6 {
+        socket.set_tclass_v6(tos)
+    } else {
+        socket.set_tos(tos)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_dscp(_socket: &TcpSocket, _dscp: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "DSCP marking not supported on this operating system",
+    ))
+}
+
 #[cfg(target_os = "linux")]
 #[allow(unsafe_code)]
 mod linux {
@@ -169,6 +255,11 @@ impl Listener {
     pub fn set_transparent(&self) -> io::Result<()> {
         SockRef::from(&self.0).set_ip_transparent(true)
     }
+
+    /// bind_device restricts the listener to connections arriving on the named interface.
+    pub fn bind_device(&self, iface: &str) -> io::Result<()> {
+        SockRef::from(&self.0).bind_device(Some(iface.as_bytes()))
+    }
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -179,4 +270,11 @@ impl Listener {
             "IP_TRANSPARENT not supported on this operating system",
         ))
     }
+
+    pub fn bind_device(&self, _iface: &str) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_BINDTODEVICE not supported on this operating system",
+        ))
+    }
 }
@@ -15,9 +15,10 @@
 use crate::identity::{Identity, SecretManager};
 use crate::proxy::{Error, OnDemandDnsLabels};
 use crate::rbac::Authorization;
+use crate::state::health_check::HealthChecker;
 use crate::state::policy::PolicyStore;
 use crate::state::service::{
-    Endpoint, IpFamily, LoadBalancerMode, LoadBalancerScopes, ServiceStore,
+    ConsistentHashKey, Endpoint, IpFamily, LoadBalancerMode, LoadBalancerScopes, ServiceStore,
 };
 use crate::state::service::{Service, ServiceDescription};
 use crate::state::workload::{
@@ -44,14 +45,17 @@ use std::collections::HashMap;
 use std::convert::Into;
 use std::default::Default;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::Duration;
+use tokio::time::Instant;
 use tracing::{debug, trace, warn};
 
 use self::workload::ApplicationTunnel;
 
+pub mod health_check;
 pub mod policy;
 pub mod service;
 pub mod workload;
@@ -157,6 +161,9 @@ pub struct ProxyState {
     pub services: ServiceStore,
 
     pub policies: PolicyStore,
+
+    /// If set, newly discovered endpoints are ramped up over this window. See [Self::slow_start_factor].
+    slow_start_window: Option<Duration>,
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -166,6 +173,13 @@ struct ProxyStateSerialization<'a> {
     services: Vec<Arc<Service>>,
     policies: Vec<Authorization>,
     staged_services: &'a HashMap<NamespacedHostname, HashMap<Strng, Endpoint>>,
+    ejected_endpoints: Vec<EjectedEndpoint<'a>>,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct EjectedEndpoint<'a> {
+    service: &'a NamespacedHostname,
+    workload: &'a Strng,
 }
 
 impl serde::Serialize for ProxyState {
@@ -204,6 +218,13 @@ impl serde::Serialize for ProxyState {
             services,
             policies,
             staged_services: &self.services.staged_services,
+            ejected_endpoints: self
+                .services
+                .ejected
+                .iter()
+                .map(|(service, workload)| EjectedEndpoint { service, workload })
+                .sorted_by_key(|e| (e.service.to_string(), e.workload))
+                .collect(),
         };
         serializable.serialize(serializer)
     }
@@ -215,9 +236,15 @@ impl ProxyState {
             workloads: WorkloadStore::new(local_node),
             services: Default::default(),
             policies: Default::default(),
+            slow_start_window: None,
         }
     }
 
+    pub fn with_slow_start_window(mut self, window: Option<Duration>) -> Self {
+        self.slow_start_window = window;
+        self
+    }
+
     /// Find either a workload or service by the destination.
     pub fn find_destination(&self, dest: &Destination) -> Option<Address> {
         match dest {
@@ -346,11 +373,24 @@ impl ProxyState {
             return None;
         };
 
+        // Endpoints failing health checks are skipped, unless all of them are; then there is nothing
+        // better to pick, and we fall back to the control plane's view.
+        let svc_key = svc.namespaced_hostname();
+        let skip_ejected = !self.services.ejected.is_empty()
+            && !svc
+                .endpoints
+                .iter()
+                .all(|ep| self.services.is_ejected(&svc_key, &ep.workload_uid));
+
         let endpoints = svc.endpoints.iter().filter_map(|ep| {
             let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
                 debug!("failed to fetch workload for {}", ep.workload_uid);
                 return None;
             };
+            if skip_ejected && self.services.is_ejected(&svc_key, &ep.workload_uid) {
+                trace!("filter endpoint {}, it is ejected", ep.workload_uid);
+                return None;
+            }
             match resolution_mode {
                 ServiceResolutionMode::Standard => {
                     if target_port.unwrap_or_default() == 0 && !ep.port.contains_key(&svc_port) {
@@ -427,13 +467,119 @@ impl ProxyState {
             }
             _ => endpoints.collect(),
         };
+        // Split traffic between subsets first, then between the endpoints of the chosen subset.
+        let options = match choose_subset(&svc.subset_weights, &options) {
+            Some(revision) => options
+                .into_iter()
+                .filter(|(_, wl)| wl.canonical_revision == revision)
+                .collect(),
+            None => options,
+        };
+        if let Some(key) = svc.load_balancer.as_ref().and_then(|lb| lb.consistent_hash) {
+            return choose_consistent(consistent_hash_key(key, src), &options);
+        }
+        let now = Instant::now();
         options
-            .choose_weighted(&mut rand::rng(), |(_, wl)| wl.capacity as u64)
-            // This can fail if there are no weights, the sum is zero (not possible in our API), or if it overflows
-            // The API has u32 but we sum into an u64, so it would take ~4 billion entries of max weight to overflow
+            .choose_weighted(&mut rand::rng(), |(_, wl)| {
+                wl.capacity as f64 * self.slow_start_factor(&wl.uid, now)
+            })
+            // This can fail if there are no weights or the sum is zero (not possible in our API)
             .ok()
             .cloned()
     }
+
+    /// slow_start_factor scales an endpoint's selection weight by how long we have known it, ramping
+    /// linearly from SLOW_START_MIN_FACTOR to 1 over the slow start window.
+    fn slow_start_factor(&self, uid: &Strng, now: Instant) -> f64 {
+        let (Some(window), Some(first_seen)) =
+            (self.slow_start_window, self.workloads.first_seen(uid))
+        else {
+            return 1.0;
+        };
+        if window.is_zero() {
+            return 1.0;
+        }
+        let age = now.saturating_duration_since(first_seen);
+        (age.as_secs_f64() / window.as_secs_f64()).clamp(SLOW_START_MIN_FACTOR, 1.0)
+    }
+}
+
+/// The smallest share of its full weight a newly discovered endpoint is selected with, so it still
+/// receives some traffic to warm up with.
+const SLOW_START_MIN_FACTOR: f64 = 0.1;
+
+/// consistent_hash_key hashes the part of the source that identifies it for consistent hashing.
+fn consistent_hash_key(key: ConsistentHashKey, src: &Workload) -> u64 {
+    match key {
+        ConsistentHashKey::SourceIp => match src.workload_ips.first() {
+            Some(IpAddr::V4(ip)) => stable_hash(&[&ip.octets()]),
+            Some(IpAddr::V6(ip)) => stable_hash(&[&ip.octets()]),
+            None => stable_hash(&[]),
+        },
+        ConsistentHashKey::SourceWorkload => stable_hash(&[src.uid.as_bytes()]),
+    }
+}
+
+/// stable_hash hashes the concatenation of `parts` with 64-bit FNV-1a, followed by the MurmurHash3
+/// finalizer so every output bit depends on the input. Unlike std's DefaultHasher, the result is
+/// fixed across Rust releases, so consistent hashing keeps its assignments through an upgrade.
+fn stable_hash(parts: &[&[u8]]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let mut h = FNV_OFFSET_BASIS;
+    for b in parts.iter().flat_map(|p| p.iter()) {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(FNV_PRIME);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    h
+}
+
+/// choose_consistent deterministically picks the endpoint for `key` with weighted rendezvous
+/// hashing: every endpoint scores the key, scaled by its capacity, and the highest score wins.
+/// When endpoints are added or removed, only the keys won by, or held by, those endpoints move.
+fn choose_consistent<'a>(
+    key: u64,
+    options: &[(&'a Endpoint, Arc<Workload>)],
+) -> Option<(&'a Endpoint, Arc<Workload>)> {
+    let score = |(ep, wl): &(&Endpoint, Arc<Workload>)| {
+        let h = stable_hash(&[&key.to_le_bytes(), ep.workload_uid.as_bytes()]);
+        // Map the hash into (0, 1), so the logarithm is finite and non-zero.
+        let u = ((h >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        wl.capacity as f64 / -u.ln()
+    };
+    options
+        .iter()
+        .filter(|(_, wl)| wl.capacity > 0)
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+        .cloned()
+}
+
+/// choose_subset picks a revision among the candidate endpoints according to the service's subset
+/// weights. Revisions without a weight receive no traffic. If no weights are configured, or none of
+/// the candidates have one, there is no preference and all endpoints are considered.
+fn choose_subset(
+    weights: &HashMap<Strng, u32>,
+    options: &[(&Endpoint, Arc<Workload>)],
+) -> Option<Strng> {
+    if weights.is_empty() {
+        return None;
+    }
+    let subsets: Vec<(&Strng, u32)> = options
+        .iter()
+        .map(|(_, wl)| &wl.canonical_revision)
+        .unique()
+        .filter_map(|rev| weights.get(rev).map(|w| (rev, *w)))
+        .collect();
+    subsets
+        .choose_weighted(&mut rand::rng(), |(_, w)| *w as u64)
+        // Fails if all the weights are zero, in which case we have no preference.
+        .ok()
+        .map(|(rev, _)| (*rev).clone())
 }
 
 /// Wrapper around [ProxyState] that provides additional methods for requesting information
@@ -452,6 +598,10 @@ pub struct DemandProxyState {
 
     #[serde(skip_serializing)]
     dns_resolver: TokioAsyncResolver,
+
+    /// Trust domains treated as equivalent to a workload's own when comparing identities.
+    #[serde(skip_serializing)]
+    trust_domain_aliases: Arc<Vec<Strng>>,
 }
 
 impl DemandProxyState {
@@ -482,7 +632,24 @@ impl DemandProxyState {
             demand,
             dns_resolver,
             metrics,
+            trust_domain_aliases: Default::default(),
+        }
+    }
+
+    pub fn with_trust_domain_aliases(mut self, aliases: Vec<Strng>) -> Self {
+        self.trust_domain_aliases = Arc::new(aliases);
+        self
+    }
+
+    /// equivalent_trust_domains returns the trust domains whose identities are interchangeable with
+    /// those in `local`. This is empty when no aliases are configured, meaning only exact matches.
+    pub fn equivalent_trust_domains(&self, local: &Strng) -> Vec<Strng> {
+        if self.trust_domain_aliases.is_empty() {
+            return Vec::new();
         }
+        std::iter::once(local.clone())
+            .chain(self.trust_domain_aliases.iter().cloned())
+            .collect()
     }
 
     pub fn read(&self) -> RwLockReadGuard<'_, ProxyState> {
@@ -493,8 +660,18 @@ impl DemandProxyState {
         &self,
         ctx: &ProxyRbacContext,
     ) -> Result<(), proxy::AuthorizationRejectionError> {
+        self.evaluate_rbac(ctx).await.map(|_| ())
+    }
+
+    /// evaluate_rbac is assert_rbac, additionally returning the key of the ALLOW policy that
+    /// admitted the connection, if any. It only reads state, so it is safe to use as a dry-run.
+    pub async fn evaluate_rbac(
+        &self,
+        ctx: &ProxyRbacContext,
+    ) -> Result<Option<Strng>, proxy::AuthorizationRejectionError> {
         let wl = &ctx.dest_workload;
         let conn = &ctx.conn;
+        let trust_domains = self.equivalent_trust_domains(&wl.trust_domain);
         let state = self.state.read().unwrap();
 
         // We can get policies from namespace, global, and workload...
@@ -528,7 +705,7 @@ impl DemandProxyState {
 
         // "If there are any DENY policies that match the request, deny the request."
         for pol in deny.iter() {
-            if pol.matches(conn) {
+            if pol.matches_with_trust_domains(conn, &trust_domains) {
                 debug!(policy = pol.to_key().as_str(), "deny policy match");
                 return Err(proxy::AuthorizationRejectionError::ExplicitlyDenied(
                     pol.namespace.to_owned(),
@@ -541,13 +718,13 @@ impl DemandProxyState {
         // "If there are no ALLOW policies for the workload, allow the request."
         if allow.is_empty() {
             debug!("no allow policies, allow");
-            return Ok(());
+            return Ok(None);
         }
         // "If any of the ALLOW policies match the request, allow the request."
         for pol in allow.iter() {
-            if pol.matches(conn) {
+            if pol.matches_with_trust_domains(conn, &trust_domains) {
                 debug!(policy = pol.to_key().as_str(), "allow policy match");
-                return Ok(());
+                return Ok(Some(pol.to_key()));
             } else {
                 trace!(
                     policy = pol.to_key().as_str(),
@@ -941,6 +1118,9 @@ pub struct ProxyStateManager {
 
     #[serde(skip_serializing)]
     xds_client: Option<AdsClient>,
+
+    #[serde(skip_serializing)]
+    health_checker: Option<HealthChecker>,
 }
 
 impl ProxyStateManager {
@@ -951,10 +1131,11 @@ impl ProxyStateManager {
         awaiting_ready: tokio::sync::watch::Sender<()>,
         cert_manager: Arc<SecretManager>,
     ) -> anyhow::Result<ProxyStateManager> {
-        let cert_fetcher = cert_fetcher::new(&config, cert_manager);
-        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState::new(
-            config.local_node.as_ref().map(strng::new),
-        )));
+        let cert_fetcher = cert_fetcher::new(&config, cert_manager, proxy_metrics.clone());
+        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(
+            ProxyState::new(config.local_node.as_ref().map(strng::new))
+                .with_slow_start_window(config.endpoint_slow_start_window),
+        ));
         let xds_client = if config.xds_address.is_some() {
             let updater = ProxyStateUpdater::new(state.clone(), cert_fetcher.clone());
             let tls_client_fetcher = Box::new(tls::ControlPlaneAuthentication::RootCert(
@@ -979,15 +1160,28 @@ impl ProxyStateManager {
             local_client.run().await?;
         }
         let demand = xds_client.as_ref().and_then(AdsClient::demander);
+        let health_checker = config.endpoint_health_check_interval.map(|interval| {
+            // Probes are sent like any other connection ztunnel originates, so they are marked to
+            // skip redirection.
+            let base = proxy::DefaultSocketFactory(config.socket_config);
+            let socket_factory: Arc<dyn proxy::SocketFactory + Send + Sync> =
+                match config.packet_mark {
+                    Some(mark) => Arc::new(proxy::MarkSocketFactory { inner: base, mark }),
+                    None => Arc::new(base),
+                };
+            HealthChecker::new(state.clone(), socket_factory, interval, &config)
+        });
         Ok(ProxyStateManager {
             xds_client,
+            health_checker,
             state: DemandProxyState::new(
                 state,
                 demand,
                 config.dns_resolver_cfg.clone(),
                 config.dns_resolver_opts.clone(),
                 proxy_metrics,
-            ),
+            )
+            .with_trust_domain_aliases(config.trust_domain_aliases.clone()),
         })
     }
 
@@ -996,6 +1190,9 @@ impl ProxyStateManager {
     }
 
     pub async fn run(self) -> anyhow::Result<()> {
+        if let Some(health_checker) = self.health_checker {
+            tokio::spawn(health_checker.run());
+        }
         match self.xds_client {
             Some(xds) => xds.run().await.map_err(|e| anyhow::anyhow!(e)),
             None => Ok(()),
@@ -1282,6 +1479,140 @@ mod tests {
         assert_eq!(port, tc.expected_port());
     }
 
+    #[tokio::test]
+    async fn find_upstream_port_mappings_per_endpoint() {
+        initialize_telemetry();
+        // Each endpoint may expose the service port on a different target port; endpoints without
+        // their own mapping use the service's.
+        let endpoints = [
+            ("a", 1, HashMap::from([(80, 9090)])),
+            ("b", 2, HashMap::from([(80, 9091)])),
+            ("c", 3, HashMap::new()),
+        ];
+        let mut state = ProxyState::new(None);
+        for (name, ip, _) in &endpoints {
+            state.workloads.insert(
+                Workload {
+                    uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                    name: (*name).into(),
+                    namespace: "default".into(),
+                    workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, *ip))],
+                    ..test_helpers::test_default_workload()
+                }
+                .into(),
+            );
+        }
+        state.services.insert(Service {
+            name: "test-svc".into(),
+            hostname: "example.com".into(),
+            namespace: "default".into(),
+            vips: vec![NetworkAddress {
+                address: "10.0.0.1".parse().unwrap(),
+                network: "".into(),
+            }],
+            endpoints: EndpointSet::from_list(endpoints.clone().map(|(name, _, port)| Endpoint {
+                workload_uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                port,
+                status: HealthStatus::Healthy,
+            })),
+            ports: HashMap::from([(80, 8080)]),
+            ..test_helpers::mock_default_service()
+        });
+
+        let src = test_helpers::test_default_workload();
+        let want = HashMap::from([("a", 9090), ("b", 9091), ("c", 8080)]);
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let (wl, port, _) = state
+                .find_upstream(
+                    "".into(),
+                    &src,
+                    "10.0.0.1:80".parse().unwrap(),
+                    ServiceResolutionMode::Standard,
+                )
+                .expect("upstream to be found");
+            assert_eq!(port, want[wl.name.as_str()], "{}", wl.name);
+            seen.insert(wl.name.clone());
+        }
+        assert_eq!(seen.len(), want.len(), "all endpoints selected: {seen:?}");
+    }
+
+    #[tokio::test]
+    async fn fetch_upstream_resolves_hostname_endpoints() {
+        use crate::test_helpers::dns::{ip, n, run_dns};
+        initialize_telemetry();
+        let dns = run_dns(HashMap::from([
+            (n("single.example.com."), vec![ip("10.1.0.1")]),
+            (
+                n("multi.example.com."),
+                vec![ip("10.2.0.1"), ip("10.2.0.2")],
+            ),
+        ]))
+        .await
+        .unwrap();
+
+        // Each service has a single endpoint, which has a hostname rather than an address.
+        let mut state = ProxyState::new(None);
+        for (name, vip) in [("single", "10.0.0.1"), ("multi", "10.0.0.2")] {
+            state.workloads.insert(Arc::new(Workload {
+                uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                name: name.into(),
+                hostname: format!("{name}.example.com").into(),
+                workload_ips: vec![],
+                ..test_helpers::test_default_workload()
+            }));
+            state.services.insert(Service {
+                name: name.into(),
+                hostname: format!("{name}.default.svc.cluster.local").into(),
+                vips: vec![NetworkAddress {
+                    address: vip.parse().unwrap(),
+                    network: "".into(),
+                }],
+                endpoints: EndpointSet::from_list([Endpoint {
+                    workload_uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                    port: HashMap::new(),
+                    status: HealthStatus::Healthy,
+                }]),
+                ports: HashMap::from([(80, 8080)]),
+                ..test_helpers::mock_default_service()
+            });
+        }
+        let mut registry = Registry::default();
+        let state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            dns.resolver_config(),
+            ResolverOpts::default(),
+            Arc::new(proxy::Metrics::new(&mut registry)),
+        );
+        let src = test_helpers::test_default_workload();
+        let resolve = async |vip: &str| {
+            let us = state
+                .fetch_upstream(
+                    "".into(),
+                    &src,
+                    format!("{vip}:80").parse().unwrap(),
+                    ServiceResolutionMode::Standard,
+                )
+                .await
+                .unwrap()
+                .expect("upstream");
+            us.workload_socket_addr()
+        };
+
+        assert_eq!(resolve("10.0.0.1").await, "10.1.0.1:8080".parse().unwrap());
+
+        // Connections are spread across all of the resolved addresses.
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..20 {
+            seen.insert(resolve("10.0.0.2").await);
+        }
+        let want: std::collections::HashSet<SocketAddr> = ["10.2.0.1:8080", "10.2.0.2:8080"]
+            .map(|a| a.parse().unwrap())
+            .into();
+        assert_eq!(seen, want);
+    }
+
     fn create_workload(dest_uid: u8) -> Workload {
         Workload {
             name: "test".into(),
@@ -1548,6 +1879,7 @@ mod tests {
                     LoadBalancerScopes::Zone,
                 ],
                 health_policy: LoadBalancerHealthPolicy::OnlyHealthy,
+                consistent_hash: None,
             }),
             ports: HashMap::from([(80u16, 80u16)]),
             ..test_helpers::mock_default_service()
@@ -1562,6 +1894,7 @@ mod tests {
                     LoadBalancerScopes::Zone,
                 ],
                 health_policy: LoadBalancerHealthPolicy::OnlyHealthy,
+                consistent_hash: None,
             }),
             ports: HashMap::from([(80u16, 80u16)]),
             ..test_helpers::mock_default_service()
@@ -1629,4 +1962,207 @@ mod tests {
             "failover full match selects closest match",
         );
     }
+
+    #[test]
+    fn test_load_balance_subset_weights() {
+        let mut state = ProxyState::new(None);
+        let mut endpoints = Vec::new();
+        for (i, revision) in ["v1", "v1", "v2", "v3"].into_iter().enumerate() {
+            let wl = Workload {
+                uid: strng::format!("cluster1//v1/Pod/default/wl{i}"),
+                name: strng::format!("wl{i}"),
+                workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, i as u8 + 1))],
+                canonical_revision: revision.into(),
+                ..test_helpers::test_default_workload()
+            };
+            endpoints.push(Endpoint {
+                workload_uid: wl.uid.clone(),
+                port: HashMap::from([(80u16, 80u16)]),
+                status: HealthStatus::Healthy,
+            });
+            state.workloads.insert(Arc::new(wl));
+        }
+        let src = test_helpers::test_default_workload();
+        let svc = |weights: &[(&str, u32)]| Service {
+            endpoints: EndpointSet::from_list(endpoints.clone()),
+            ports: HashMap::from([(80u16, 80u16)]),
+            subset_weights: weights.iter().map(|(r, w)| (strng::new(r), *w)).collect(),
+            ..test_helpers::mock_default_service()
+        };
+        let distribution = |svc: &Service| {
+            const SAMPLES: usize = 10_000;
+            let mut counts: HashMap<Strng, usize> = HashMap::new();
+            for _ in 0..SAMPLES {
+                let (_, wl) = state
+                    .load_balance(&src, svc, 80, ServiceResolutionMode::Standard)
+                    .expect("an endpoint should be selected");
+                *counts.entry(wl.canonical_revision.clone()).or_default() += 1;
+            }
+            counts
+                .into_iter()
+                .map(|(rev, n)| (rev, n as f64 / SAMPLES as f64))
+                .collect::<HashMap<_, _>>()
+        };
+        let assert_share = |got: &HashMap<Strng, f64>, revision: &str, want: f64| {
+            let share = got.get(revision).copied().unwrap_or_default();
+            assert!((share - want).abs() < 0.03, "{revision}: {share} != {want}");
+        };
+
+        // Weights apply per subset, regardless of how many endpoints each has; v3 is unweighted.
+        let got = distribution(&svc(&[("v1", 80), ("v2", 20)]));
+        assert_share(&got, "v1", 0.8);
+        assert_share(&got, "v2", 0.2);
+        assert_share(&got, "v3", 0.0);
+
+        // Without weights, endpoints are picked evenly.
+        let got = distribution(&svc(&[]));
+        assert_share(&got, "v1", 0.5);
+        assert_share(&got, "v2", 0.25);
+        assert_share(&got, "v3", 0.25);
+
+        // Weights for revisions with no endpoints are no preference at all.
+        let got = distribution(&svc(&[("v4", 100)]));
+        assert_share(&got, "v1", 0.5);
+    }
+
+    #[test]
+    fn test_load_balance_consistent_hash() {
+        let mut state = ProxyState::new(None);
+        for i in 0..11u8 {
+            state.workloads.insert(Arc::new(Workload {
+                uid: strng::format!("cluster1//v1/Pod/default/wl{i}"),
+                name: strng::format!("wl{i}"),
+                workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, i + 1))],
+                ..test_helpers::test_default_workload()
+            }));
+        }
+        let svc = |endpoints: std::ops::Range<u8>| Service {
+            endpoints: EndpointSet::from_list(endpoints.map(|i| Endpoint {
+                workload_uid: strng::format!("cluster1//v1/Pod/default/wl{i}"),
+                port: HashMap::from([(80u16, 80u16)]),
+                status: HealthStatus::Healthy,
+            })),
+            ports: HashMap::from([(80u16, 80u16)]),
+            load_balancer: Some(LoadBalancer {
+                mode: LoadBalancerMode::Standard,
+                routing_preferences: vec![],
+                health_policy: LoadBalancerHealthPolicy::OnlyHealthy,
+                consistent_hash: Some(ConsistentHashKey::SourceIp),
+            }),
+            ..test_helpers::mock_default_service()
+        };
+        let clients: Vec<Workload> = (0..500u16)
+            .map(|i| Workload {
+                workload_ips: vec![IpAddr::V4(Ipv4Addr::new(10, 1, (i >> 8) as u8, i as u8))],
+                ..test_helpers::test_default_workload()
+            })
+            .collect();
+        let assign = |svc: &Service| {
+            clients
+                .iter()
+                .map(|src| {
+                    let (_, wl) = state
+                        .load_balance(src, svc, 80, ServiceResolutionMode::Standard)
+                        .expect("an endpoint should be selected");
+                    wl.name.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let base = assign(&svc(0..10));
+        assert_eq!(base, assign(&svc(0..10)), "assignment is stable");
+        let used: std::collections::HashSet<_> = base.iter().collect();
+        assert_eq!(used.len(), 10, "clients are spread across all endpoints");
+
+        // Removing an endpoint only moves the clients that were on it.
+        let removed = assign(&svc(0..9));
+        for (before, after) in base.iter().zip(&removed) {
+            if before.as_str() == "wl9" {
+                assert_ne!(after.as_str(), "wl9");
+            } else {
+                assert_eq!(before, after);
+            }
+        }
+
+        // Adding an endpoint only moves clients onto the new one.
+        let added = assign(&svc(0..11));
+        let mut moved = 0;
+        for (before, after) in base.iter().zip(&added) {
+            if before != after {
+                assert_eq!(after.as_str(), "wl10");
+                moved += 1;
+            }
+        }
+        assert!(moved > 0 && moved < clients.len() / 5, "moved {moved}");
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        // Consistent hashing assignments depend on these exact values; they must never change.
+        assert_eq!(stable_hash(&[b"hello"]), 0xe9c562c0fdb23244);
+        assert_eq!(
+            stable_hash(&[&42u64.to_le_bytes(), b"wl0"]),
+            0x6cd0e5d10dc897a5
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_balance_slow_start() {
+        let window = Duration::from_secs(100);
+        let mut state = ProxyState::new(None).with_slow_start_window(Some(window));
+        let workload = |i: u8| Workload {
+            uid: strng::format!("cluster1//v1/Pod/default/wl{i}"),
+            name: strng::format!("wl{i}"),
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, i))],
+            ..test_helpers::test_default_workload()
+        };
+        let old = workload(1);
+        let new = workload(2);
+        state.workloads.insert(Arc::new(old.clone()));
+        tokio::time::advance(window).await;
+        state.workloads.insert(Arc::new(new.clone()));
+
+        let factor =
+            |state: &ProxyState, wl: &Workload| state.slow_start_factor(&wl.uid, Instant::now());
+        assert_eq!(factor(&state, &old), 1.0);
+        assert_eq!(factor(&state, &new), SLOW_START_MIN_FACTOR);
+
+        // Updates to a known workload do not restart its ramp.
+        tokio::time::advance(window / 4).await;
+        state.workloads.insert(Arc::new(new.clone()));
+        assert_eq!(factor(&state, &new), 0.25);
+
+        tokio::time::advance(window / 4).await;
+        assert_eq!(factor(&state, &new), 0.5);
+        let svc = Service {
+            endpoints: EndpointSet::from_list([&old, &new].map(|wl| Endpoint {
+                workload_uid: wl.uid.clone(),
+                port: HashMap::from([(80u16, 80u16)]),
+                status: HealthStatus::Healthy,
+            })),
+            ports: HashMap::from([(80u16, 80u16)]),
+            ..test_helpers::mock_default_service()
+        };
+        let src = test_helpers::test_default_workload();
+        const SAMPLES: usize = 10_000;
+        let picked_new = (0..SAMPLES)
+            .filter(|_| {
+                let (_, wl) = state
+                    .load_balance(&src, &svc, 80, ServiceResolutionMode::Standard)
+                    .unwrap();
+                wl.uid == new.uid
+            })
+            .count();
+        // Half weight against a full weight endpoint is 1/3 of the traffic.
+        let share = picked_new as f64 / SAMPLES as f64;
+        assert!((share - 1.0 / 3.0).abs() < 0.03, "{share}");
+
+        tokio::time::advance(window).await;
+        assert_eq!(factor(&state, &new), 1.0);
+
+        // A removed and re-added workload ramps up again.
+        state.workloads.remove(&new.uid);
+        state.workloads.insert(Arc::new(new.clone()));
+        assert_eq!(factor(&state, &new), SLOW_START_MIN_FACTOR);
+    }
 }
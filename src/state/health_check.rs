@@ -0,0 +1,291 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::proxy::{SocketFactory, freebind_connect};
+use crate::state::ProxyState;
+use crate::state::workload::{NamespacedHostname, Protocol};
+use crate::strng::Strng;
+
+// The number of probes in flight at once, so large clusters are probed gradually rather than with a
+// burst of connections from every node.
+const MAX_CONCURRENT_PROBES: usize = 32;
+
+/// HealthChecker actively probes service endpoints with a TCP connect on their target port, so
+/// endpoints that stop accepting connections are taken out of load balancing before the control
+/// plane notices. An endpoint is ejected after `unhealthy_threshold` consecutive failed probes, and
+/// readmitted after `healthy_threshold` consecutive successful ones.
+pub struct HealthChecker {
+    state: Arc<RwLock<ProxyState>>,
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+    interval: Duration,
+    timeout: Duration,
+    unhealthy_threshold: u32,
+    healthy_threshold: u32,
+    // Consecutive probe results per service endpoint.
+    probes: HashMap<EndpointKey, Probe>,
+}
+
+type EndpointKey = (NamespacedHostname, Strng);
+
+#[derive(Default)]
+struct Probe {
+    failures: u32,
+    successes: u32,
+}
+
+impl HealthChecker {
+    pub fn new(
+        state: Arc<RwLock<ProxyState>>,
+        socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+        interval: Duration,
+        cfg: &Config,
+    ) -> Self {
+        HealthChecker {
+            state,
+            socket_factory,
+            interval,
+            timeout: cfg.endpoint_health_check_timeout,
+            unhealthy_threshold: cfg.endpoint_health_check_unhealthy_threshold,
+            healthy_threshold: cfg.endpoint_health_check_healthy_threshold,
+            probes: Default::default(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.probe_all().await;
+        }
+    }
+
+    /// probe_all probes every endpoint once, and ejects or readmits endpoints based on the results.
+    async fn probe_all(&mut self) {
+        let targets = self.targets();
+        // Endpoints of several services often share an address, so probe each address only once.
+        let addrs: HashSet<SocketAddr> = targets.values().copied().collect();
+        let timeout = self.timeout;
+        let socket_factory = self.socket_factory.as_ref();
+        let healthy: HashMap<SocketAddr, bool> = futures::stream::iter(addrs)
+            .map(|addr| async move {
+                let res = freebind_connect(None, addr, socket_factory, timeout).await;
+                (addr, res.is_ok())
+            })
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .collect()
+            .await;
+
+        let mut probes = HashMap::with_capacity(targets.len());
+        let mut state = self.state.write().expect("mutex");
+        for ((svc, uid), addr) in targets {
+            let key = (svc, uid);
+            let mut probe = self.probes.remove(&key).unwrap_or_default();
+            let (svc, uid) = &key;
+            if healthy[&addr] {
+                probe.failures = 0;
+                probe.successes += 1;
+                if probe.successes >= self.healthy_threshold
+                    && state.services.readmit(svc.clone(), uid.clone())
+                {
+                    info!(service=%svc, endpoint=%uid, %addr, "endpoint passed health checks, readmitting");
+                }
+            } else {
+                probe.successes = 0;
+                probe.failures += 1;
+                debug!(service=%svc, endpoint=%uid, %addr, failures=probe.failures, "endpoint health check failed");
+                if probe.failures >= self.unhealthy_threshold
+                    && state.services.eject(svc.clone(), uid.clone())
+                {
+                    info!(service=%svc, endpoint=%uid, %addr, "endpoint failed health checks, ejecting");
+                }
+            }
+            probes.insert(key, probe);
+        }
+        // Anything not probed this round is no longer an endpoint of that service.
+        self.probes = probes;
+    }
+
+    // targets returns the address to probe for each service endpoint we could send to directly.
+    fn targets(&self) -> HashMap<EndpointKey, SocketAddr> {
+        let state = self.state.read().expect("mutex");
+        let mut targets = HashMap::new();
+        for svc in state.services.by_host.values().flatten() {
+            // Probe the lowest service port, so the same port is checked every round.
+            let mut svc_ports: Vec<_> = svc.ports.iter().collect();
+            svc_ports.sort();
+            for ep in svc.endpoints.iter() {
+                let Some(wl) = state.workloads.find_uid(&ep.workload_uid) else {
+                    continue;
+                };
+                // Endpoints behind a network gateway cannot be reached directly. Mesh endpoints
+                // only receive traffic through their node's ztunnel, which would answer the probe
+                // itself.
+                if wl.network_gateway.is_some() || wl.protocol == Protocol::HBONE {
+                    continue;
+                }
+                let Some(&ip) = wl.workload_ips.first() else {
+                    continue;
+                };
+                // Prefer the endpoint's own port mapping, as load balancing does.
+                let port = svc_ports
+                    .iter()
+                    .map(|&(svc_port, &target)| ep.port.get(svc_port).copied().unwrap_or(target))
+                    .find(|&p| p != 0);
+                if let Some(port) = port {
+                    targets.insert(
+                        (svc.namespaced_hostname(), ep.workload_uid.clone()),
+                        SocketAddr::new(ip, port),
+                    );
+                }
+            }
+        }
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::DefaultSocketFactory;
+    use crate::state::ServiceResolutionMode;
+    use crate::state::service::{Endpoint, EndpointSet, Service};
+    use crate::state::workload::{HealthStatus, NetworkAddress, Workload};
+    use crate::test_helpers;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn eject_and_readmit() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Both endpoints serve on the same port, but nothing listens on 127.0.0.2 yet.
+        let mut state = ProxyState::new(None);
+        for (name, ip) in [("a", [127, 0, 0, 1]), ("b", [127, 0, 0, 2])] {
+            state.workloads.insert(Arc::new(Workload {
+                uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                name: name.into(),
+                workload_ips: vec![ip.into()],
+                ..test_helpers::test_default_workload()
+            }));
+        }
+        state.services.insert(Service {
+            vips: vec![NetworkAddress {
+                address: "10.0.0.1".parse().unwrap(),
+                network: "".into(),
+            }],
+            endpoints: EndpointSet::from_list(["a", "b"].map(|name| Endpoint {
+                workload_uid: format!("cluster1//v1/Pod/default/{name}").into(),
+                port: HashMap::from([(80, port)]),
+                status: HealthStatus::Healthy,
+            })),
+            // The lowest service port is probed; nothing listens on the other one.
+            ports: HashMap::from([(80, 0), (81, 1)]),
+            ..test_helpers::mock_default_service()
+        });
+        // b also backs another service, on a port where it is healthy.
+        let other = tokio::net::TcpListener::bind("127.0.0.2:0").await.unwrap();
+        state.services.insert(Service {
+            hostname: "other".into(),
+            vips: vec![NetworkAddress {
+                address: "10.0.0.2".parse().unwrap(),
+                network: "".into(),
+            }],
+            endpoints: EndpointSet::from_list([Endpoint {
+                workload_uid: "cluster1//v1/Pod/default/b".into(),
+                port: HashMap::new(),
+                status: HealthStatus::Healthy,
+            }]),
+            ports: HashMap::from([(80, other.local_addr().unwrap().port())]),
+            ..test_helpers::mock_default_service()
+        });
+        let state = Arc::new(RwLock::new(state));
+        let cfg = Config {
+            endpoint_health_check_unhealthy_threshold: 2,
+            endpoint_health_check_healthy_threshold: 1,
+            ..crate::config::parse_config().unwrap()
+        };
+        let mut checker = HealthChecker::new(
+            state.clone(),
+            Arc::new(DefaultSocketFactory::default()),
+            Duration::from_secs(1),
+            &cfg,
+        );
+        let b: Strng = "cluster1//v1/Pod/default/b".into();
+        let svc = NamespacedHostname {
+            namespace: "default".into(),
+            hostname: "defaulthost".into(),
+        };
+        let other_svc = NamespacedHostname {
+            namespace: "default".into(),
+            hostname: "other".into(),
+        };
+        let is_ejected = |svc, uid| state.read().unwrap().services.is_ejected(svc, uid);
+        let selected = || {
+            let src = test_helpers::test_default_workload();
+            (0..20)
+                .map(|_| {
+                    let (wl, _, _) = state
+                        .read()
+                        .unwrap()
+                        .find_upstream(
+                            "".into(),
+                            &src,
+                            "10.0.0.1:80".parse().unwrap(),
+                            ServiceResolutionMode::Standard,
+                        )
+                        .expect("upstream");
+                    wl.name.clone()
+                })
+                .collect::<HashSet<Strng>>()
+        };
+
+        checker.probe_all().await;
+        assert!(!is_ejected(&svc, &b), "a single failure is tolerated");
+        checker.probe_all().await;
+        assert!(is_ejected(&svc, &b));
+        assert!(!is_ejected(&svc, &"cluster1//v1/Pod/default/a".into()));
+        assert_eq!(selected(), HashSet::from(["a".into()]));
+        // Failing one service's port does not eject the workload from other services.
+        assert!(!is_ejected(&other_svc, &b));
+
+        let _recovered = tokio::net::TcpListener::bind(("127.0.0.2", port))
+            .await
+            .unwrap();
+        checker.probe_all().await;
+        assert!(!is_ejected(&svc, &b));
+        assert_eq!(selected().len(), 2);
+
+        // Removing a service forgets its ejections.
+        assert!(
+            state
+                .write()
+                .unwrap()
+                .services
+                .eject(svc.clone(), b.clone())
+        );
+        assert!(state.write().unwrap().services.remove(&svc));
+        assert!(!is_ejected(&svc, &b));
+    }
+}
@@ -55,6 +55,12 @@ pub struct Service {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub ip_families: Option<IpFamily>,
+
+    /// Relative weights for splitting traffic between subsets of the endpoints, keyed by the
+    /// workloads' `canonical_revision`. These are not part of the xDS API, and are only set by
+    /// local configuration.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub subset_weights: HashMap<Strng, u32>,
 }
 
 /// EndpointSet is an abstraction over a set of endpoints.
@@ -86,11 +92,11 @@ impl<'de> serde::Deserialize<'de> for EndpointSet {
 }
 
 impl EndpointSet {
-    pub fn from_list<const N: usize>(eps: [Endpoint; N]) -> EndpointSet {
-        let mut endpoints = HashMap::with_capacity(eps.len());
-        for ep in eps.into_iter() {
-            endpoints.insert(ep.workload_uid.clone(), Arc::new(ep));
-        }
+    pub fn from_list(eps: impl IntoIterator<Item = Endpoint>) -> EndpointSet {
+        let endpoints = eps
+            .into_iter()
+            .map(|ep| (ep.workload_uid.clone(), Arc::new(ep)))
+            .collect();
         EndpointSet { inner: endpoints }
     }
 
@@ -188,6 +194,19 @@ pub struct LoadBalancer {
     pub routing_preferences: Vec<LoadBalancerScopes>,
     pub mode: LoadBalancerMode,
     pub health_policy: LoadBalancerHealthPolicy,
+    /// If set, each client is consistently sent to the same endpoint, rather than a random one.
+    /// This is only available through local configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consistent_hash: Option<ConsistentHashKey>,
+}
+
+/// ConsistentHashKey selects what identifies a client when consistently hashing it to an endpoint.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub enum ConsistentHashKey {
+    /// The client's IP address.
+    SourceIp,
+    /// The client workload, so all of a workload's addresses reach the same endpoint.
+    SourceWorkload,
 }
 
 impl From<xds::istio::workload::IpFamilies> for Option<IpFamily> {
@@ -303,6 +322,7 @@ impl TryFrom<&XdsService> for Service {
                     lb.health_policy,
                 )?
                 .into(),
+                consistent_hash: None,
             })
         } else {
             None
@@ -322,6 +342,7 @@ impl TryFrom<&XdsService> for Service {
             waypoint,
             load_balancer: lb,
             ip_families,
+            subset_weights: Default::default(),
         };
         Ok(svc)
     }
@@ -342,6 +363,10 @@ pub struct ServiceStore {
     /// service for a given hostname. However, `ServiceEntry` allows hostnames to be overridden
     /// on a per-namespace basis.
     pub(super) by_host: HashMap<Strng, Vec<Arc<Service>>>,
+
+    /// Endpoints, by service and workload UID, that active health checks have ejected from load
+    /// balancing. A workload failing probes for one service may still serve others.
+    pub(super) ejected: HashSet<(NamespacedHostname, Strng)>,
 }
 
 impl ServiceStore {
@@ -442,6 +467,8 @@ impl ServiceStore {
             services_to_update.insert(svc.clone());
         }
 
+        self.ejected.retain(|(_, uid)| uid != workload_uid);
+
         // Now remove the endpoint from all Services.
         for svc in &services_to_update {
             if let Some(svc) = self.get_by_namespaced_host(svc) {
@@ -454,6 +481,26 @@ impl ServiceStore {
         }
     }
 
+    /// eject removes the endpoint from load balancing for the service until it is readmitted.
+    /// Returns whether it was not already ejected.
+    pub fn eject(&mut self, service: NamespacedHostname, workload_uid: Strng) -> bool {
+        self.ejected.insert((service, workload_uid))
+    }
+
+    /// readmit returns an ejected endpoint to load balancing for the service. Returns whether it
+    /// was ejected.
+    pub fn readmit(&mut self, service: NamespacedHostname, workload_uid: Strng) -> bool {
+        self.ejected.remove(&(service, workload_uid))
+    }
+
+    pub fn is_ejected(&self, service: &NamespacedHostname, workload_uid: &Strng) -> bool {
+        // Avoid building the key for the common case of nothing being ejected.
+        !self.ejected.is_empty()
+            && self
+                .ejected
+                .contains(&(service.clone(), workload_uid.clone()))
+    }
+
     /// Adds the given service.
     pub fn insert(&mut self, service: Service) {
         self.insert_internal(service, false)
@@ -549,6 +596,9 @@ impl ServiceStore {
                 // TODO(nmittler): no endpoints for this service should be staged at this point.
                 self.staged_services.remove(namespaced_host);
 
+                // Forget ejections, so they do not apply if the service is added back.
+                self.ejected.retain(|(svc, _)| svc != namespaced_host);
+
                 // Remove successful.
                 true
             }
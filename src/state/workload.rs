@@ -36,6 +36,7 @@ use std::sync::Arc;
 use std::{fmt, net};
 use thiserror::Error;
 use tokio::sync::watch::{Receiver, Sender};
+use tokio::time::Instant;
 use tracing::{error, trace};
 use xds::istio::workload::ApplicationTunnel as XdsApplicationTunnel;
 use xds::istio::workload::GatewayAddress as XdsGatewayAddress;
@@ -666,6 +667,9 @@ pub struct WorkloadStore {
     pub(super) by_uid: HashMap<Strng, Arc<Workload>>,
     // Identity->Set of UIDs. Only stores local nodes
     node_local_by_identity: HashMap<WorkloadIdentity, HashSet<Strng>>,
+    /// first_seen tracks when each workload UID was first inserted. Updates to a workload do not
+    /// reset this; it is only cleared when the workload is removed.
+    first_seen: HashMap<Strng, Instant>,
 }
 
 #[derive(Debug)]
@@ -745,6 +749,7 @@ impl WorkloadStore {
             by_addr: Default::default(),
             node_local_by_identity: Default::default(),
             by_uid: Default::default(),
+            first_seen: Default::default(),
         }
     }
 
@@ -757,7 +762,10 @@ impl WorkloadStore {
 
     pub fn insert(&mut self, w: Arc<Workload>) {
         // First, remove the entry entirely to make sure things are cleaned up properly.
-        self.remove(&w.uid);
+        self.remove_indexes(&w.uid);
+        self.first_seen
+            .entry(w.uid.clone())
+            .or_insert_with(Instant::now);
 
         if w.network_mode != NetworkMode::HostNetwork {
             for ip in &w.workload_ips {
@@ -783,6 +791,16 @@ impl WorkloadStore {
     }
 
     pub fn remove(&mut self, uid: &Strng) -> Option<Workload> {
+        self.first_seen.remove(uid);
+        self.remove_indexes(uid)
+    }
+
+    /// Returns when the workload was first inserted, if it is known.
+    pub fn first_seen(&self, uid: &Strng) -> Option<Instant> {
+        self.first_seen.get(uid).copied()
+    }
+
+    fn remove_indexes(&mut self, uid: &Strng) -> Option<Workload> {
         match self.by_uid.remove(uid) {
             None => {
                 trace!("tried to remove workload but it was not found");
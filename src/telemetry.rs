@@ -39,6 +39,9 @@ use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFi
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{Layer, Registry, filter, prelude::*, reload};
 
+#[cfg(feature = "otel")]
+pub mod otel;
+
 pub static APPLICATION_START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
 static LOG_HANDLE: OnceCell<LogHandle> = OnceCell::new();
 
@@ -48,9 +51,11 @@ pub fn setup_logging() -> tracing_appender::non_blocking::WorkerGuard {
         .lossy(false)
         .buffered_lines_limit(1000) // Buffer up to 1000 lines to avoid blocking on logs
         .finish(std::io::stdout());
-    tracing_subscriber::registry()
-        .with(fmt_layer(non_blocking))
-        .init();
+    let registry = tracing_subscriber::registry().with(fmt_layer(non_blocking));
+    // Logging starts before the config is read, so check the endpoint directly.
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel::layer(env::var("TRACING_ENDPOINT").ok().as_deref()));
+    registry.init();
     _guard
 }
 
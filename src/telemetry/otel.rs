@@ -0,0 +1,154 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of per-connection spans to an OpenTelemetry collector over OTLP.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use once_cell::sync::OnceCell;
+use opentelemetry::trace::{TraceError, TraceResult, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::runtime::TokioCurrentThread;
+use opentelemetry_sdk::trace::{BatchSpanProcessor, Sampler, Span, SpanProcessor, TracerProvider};
+use tracing::subscriber::Interest;
+use tracing::{Metadata, Subscriber, warn};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, filter};
+
+// Only the per-connection spans are exported; everything else stays in the logs.
+const EXPORTED_SPANS: &[&str] = &[
+    "inbound",
+    "outbound",
+    "socks5",
+    "outbound_uds",
+    "outbound_udp",
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PROVIDER: OnceCell<TracerProvider> = OnceCell::new();
+static EXPORTER: OnceCell<BatchSpanProcessor<TokioCurrentThread>> = OnceCell::new();
+
+/// DeferredProcessor hands finished spans to the exporter once one is configured. The tracing layer
+/// is installed before the configuration is read, so the exporter cannot be built up front.
+#[derive(Debug)]
+struct DeferredProcessor;
+
+impl SpanProcessor for DeferredProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if let Some(p) = EXPORTER.get() {
+            p.on_start(span, cx)
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if let Some(p) = EXPORTER.get() {
+            p.on_end(span)
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        EXPORTER.get().map_or(Ok(()), |p| p.force_flush())
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        EXPORTER.get().map_or(Ok(()), |p| p.shutdown())
+    }
+}
+
+fn resource() -> Resource {
+    Resource::new([KeyValue::new("service.name", "ztunnel")])
+}
+
+fn is_exported(metadata: &Metadata<'_>) -> bool {
+    metadata.is_span() && EXPORTED_SPANS.contains(&metadata.name())
+}
+
+/// layer returns the layer turning connection spans into OpenTelemetry spans, if span export is
+/// configured with `endpoint`. It is inert until [setup] is called.
+pub(super) fn layer<S>(endpoint: Option<&str>) -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    endpoint.filter(|e| !e.is_empty())?;
+    let provider = PROVIDER.get_or_init(|| {
+        TracerProvider::builder()
+            .with_span_processor(DeferredProcessor)
+            // Most connections carry a traceparent we minted ourselves, unsampled, so sampling
+            // based on the parent would drop nearly everything.
+            .with_sampler(Sampler::AlwaysOn)
+            .with_resource(resource())
+            .build()
+    });
+    let filter = filter::dynamic_filter_fn(|metadata, _| {
+        ENABLED.load(Ordering::Relaxed) && is_exported(metadata)
+    })
+    .with_callsite_filter(|metadata| {
+        if is_exported(metadata) {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    });
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("ztunnel"))
+        .with_filter(filter);
+    Some(layer)
+}
+
+/// enabled returns whether spans are being exported.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// setup starts exporting connection spans over OTLP/gRPC to `endpoint`. This must be called from
+/// within a Tokio runtime.
+pub fn setup(endpoint: &str) -> TraceResult<()> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let mut processor = BatchSpanProcessor::builder(exporter, TokioCurrentThread).build();
+    processor.set_resource(&resource());
+    EXPORTER
+        .set(processor)
+        .map_err(|_| TraceError::from("span export is already configured"))?;
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// shutdown flushes spans that have not been exported yet.
+pub fn shutdown() {
+    if let Some(p) = EXPORTER.get() {
+        ENABLED.store(false, Ordering::Relaxed);
+        if let Err(e) = p.shutdown() {
+            warn!("failed to flush spans: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn layer_requires_endpoint() {
+        assert!(layer::<Registry>(None).is_none());
+        assert!(layer::<Registry>(Some("")).is_none());
+        assert!(layer::<Registry>(Some("http://otel-collector:4317")).is_some());
+    }
+}
@@ -196,6 +196,7 @@ pub fn mock_default_service() -> Service {
         waypoint: None,
         load_balancer: None,
         ip_families: None,
+        subset_weights: Default::default(),
     }
 }
 
@@ -290,6 +291,7 @@ fn test_custom_svc(
         waypoint: None,
         load_balancer: None,
         ip_families: None,
+        subset_weights: Default::default(),
     })
 }
 
@@ -187,6 +187,7 @@ impl WorkloadManager {
                     inbound: "0.0.0.0:0".parse()?,
                     outbound: "0.0.0.0:0".parse()?,
                     socks5: Some("0.0.0.0:0".parse()?),
+                    outbound_udp: None,
                 });
 
                 let ta = TestApp {
@@ -198,6 +199,9 @@ impl WorkloadManager {
                         outbound: helpers::with_ip(proxy_addresses.outbound, ip),
                         inbound: helpers::with_ip(proxy_addresses.inbound, ip),
                         socks5: proxy_addresses.socks5.map(|i| helpers::with_ip(i, ip)),
+                        outbound_udp: proxy_addresses
+                            .outbound_udp
+                            .map(|i| helpers::with_ip(i, ip)),
                     },
                     tcp_dns_proxy_address: Some(helpers::with_ip(
                         app.tcp_dns_proxy_address.unwrap_or("0.0.0.0:0".parse()?),
@@ -334,6 +338,7 @@ impl<'a> TestServiceBuilder<'a> {
                 waypoint: None,
                 load_balancer: None,
                 ip_families: None,
+                subset_weights: Default::default(),
             },
             manager,
         }
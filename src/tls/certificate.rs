@@ -13,20 +13,25 @@
 // limitations under the License.
 
 use crate::identity::Identity;
+use crate::strng::Strng;
 use crate::tls::{Error, IdentityVerifier, OutboundConnector};
 use base64::engine::general_purpose::STANDARD;
 use bytes::Bytes;
 use itertools::Itertools;
 
-use rustls::client::Resumption;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::client::{
+    ClientSessionStore, Resumption, Tls12ClientSessionValue, Tls13ClientSessionValue,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 
-use rustls::server::WebPkiClientVerifier;
-use rustls::{ClientConfig, RootCertStore, ServerConfig, server};
+use rustls::server::{NoServerSessionStorage, ServerSessionMemoryCache, WebPkiClientVerifier};
+use rustls::{ClientConfig, NamedGroup, RootCertStore, ServerConfig, server};
 use rustls_pemfile::Item;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::io::Cursor;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::warn;
 
@@ -55,6 +60,140 @@ pub struct WorkloadCertificate {
 
     /// precomputed roots
     pub roots: Arc<RootCertStore>,
+
+    /// sessions holds state for resuming TLS sessions established with this certificate. It is
+    /// created on first use, so rotating the certificate starts from an empty cache.
+    sessions: OnceLock<SessionCache>,
+}
+
+/// SessionCache holds TLS session state, allowing HBONE connections to skip the full handshake.
+#[derive(Debug)]
+struct SessionCache {
+    server: Arc<ServerSessionMemoryCache>,
+    client: Arc<ClientSessions>,
+}
+
+impl SessionCache {
+    fn new(size: usize) -> Self {
+        SessionCache {
+            server: ServerSessionMemoryCache::new(size),
+            client: Arc::new(ClientSessions {
+                size,
+                servers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    fn client_store(&self, identity: &[Identity]) -> Arc<dyn ClientSessionStore> {
+        let mut identity = identity.to_vec();
+        identity.sort();
+        Arc::new(ScopedClientSessions {
+            identity,
+            sessions: self.client.clone(),
+        })
+    }
+}
+
+// Matches the per-server bound of rustls' ClientSessionMemoryCache.
+const MAX_TICKETS_PER_SERVER: usize = 8;
+
+type ClientSessionKey = (Vec<Identity>, ServerName<'static>);
+
+/// ClientSessions holds the client side session tickets for a certificate. It holds at most `size`
+/// tickets in total, regardless of how many peers or identities they are spread across.
+struct ClientSessions {
+    size: usize,
+    servers: Mutex<HashMap<ClientSessionKey, ServerSessions>>,
+}
+
+#[derive(Default)]
+struct ServerSessions {
+    kx_hint: Option<NamedGroup>,
+    tickets: VecDeque<Tls13ClientSessionValue>,
+}
+
+impl fmt::Debug for ClientSessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Omit the sessions themselves, as they hold secrets.
+        f.debug_struct("ClientSessions")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl ClientSessions {
+    /// edit applies `f` to the sessions for `key`, first making room for `added` more tickets.
+    fn edit(&self, key: ClientSessionKey, added: usize, f: impl FnOnce(&mut ServerSessions)) {
+        let mut servers = self.servers.lock().unwrap();
+        let tickets: usize = servers.values().map(|s| s.tickets.len()).sum();
+        if (!servers.contains_key(&key) && servers.len() >= self.size)
+            || tickets + added > self.size
+        {
+            // Dropping sessions only costs a full handshake, so keep this simple rather than LRU.
+            servers.clear();
+        }
+        f(servers.entry(key).or_default());
+    }
+}
+
+/// ScopedClientSessions is the view of ClientSessions for a connection to a peer expected to have
+/// one of `identity`. A resumed session does not re-verify the peer certificate, so sessions are
+/// partitioned by the identities the peer was verified against and are never offered to a peer
+/// that is expected to have a different identity (for example, when a pod IP is reused).
+#[derive(Debug)]
+struct ScopedClientSessions {
+    identity: Vec<Identity>,
+    sessions: Arc<ClientSessions>,
+}
+
+impl ScopedClientSessions {
+    fn key(&self, server_name: &ServerName<'_>) -> ClientSessionKey {
+        (self.identity.clone(), server_name.to_owned())
+    }
+}
+
+impl ClientSessionStore for ScopedClientSessions {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.sessions
+            .edit(self.key(&server_name), 0, |s| s.kx_hint = Some(group));
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        let servers = self.sessions.servers.lock().unwrap();
+        servers.get(&self.key(server_name)).and_then(|s| s.kx_hint)
+    }
+
+    // Only TLS 1.3 is offered, so there are never TLS 1.2 sessions to store.
+    fn set_tls12_session(&self, _: ServerName<'static>, _: Tls12ClientSessionValue) {}
+
+    fn tls12_session(&self, _: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        None
+    }
+
+    fn remove_tls12_session(&self, _: &ServerName<'static>) {}
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.sessions.edit(self.key(&server_name), 1, |s| {
+            if s.tickets.len() >= MAX_TICKETS_PER_SERVER {
+                s.tickets.pop_front();
+            }
+            s.tickets.push_back(value);
+        });
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        let mut servers = self.sessions.servers.lock().unwrap();
+        servers
+            .get_mut(&self.key(server_name))
+            .and_then(|s| s.tickets.pop_back())
+    }
 }
 
 pub fn identity_from_connection(conn: &server::ServerConnection) -> Option<Identity> {
@@ -232,6 +371,7 @@ impl WorkloadCertificate {
             chain,
             private_key: key,
             roots: Arc::new(roots),
+            sessions: OnceLock::new(),
         })
     }
 
@@ -247,10 +387,30 @@ impl WorkloadCertificate {
             .collect()
     }
 
-    pub fn server_config(&self) -> Result<ServerConfig, Error> {
-        let td = self.cert.identity().map(|i| match i {
-            Identity::Spiffe { trust_domain, .. } => trust_domain,
-        });
+    /// session_cache returns the session cache for this certificate, or None if resumption is
+    /// disabled. The cache is sized by the first caller.
+    fn session_cache(&self, size: usize) -> Option<&SessionCache> {
+        if size == 0 {
+            return None;
+        }
+        Some(self.sessions.get_or_init(|| SessionCache::new(size)))
+    }
+
+    /// server_config builds the config for accepting mTLS connections. Clients must be in the same
+    /// trust domain as this certificate, or one of `trust_domain_aliases`.
+    /// If `session_cache_size` is non-zero, clients may resume sessions previously established
+    /// with this certificate; the client certificate is not re-verified on resumption.
+    pub fn server_config(
+        &self,
+        trust_domain_aliases: &[Strng],
+        session_cache_size: usize,
+    ) -> Result<ServerConfig, Error> {
+        let trust_domains = match self.cert.identity() {
+            Some(Identity::Spiffe { trust_domain, .. }) => std::iter::once(trust_domain)
+                .chain(trust_domain_aliases.iter().cloned())
+                .collect(),
+            None => Vec::new(),
+        };
         let raw_client_cert_verifier = WebPkiClientVerifier::builder_with_provider(
             self.roots.clone(),
             crate::tls::lib::provider(),
@@ -258,17 +418,35 @@ impl WorkloadCertificate {
         .build()?;
 
         let client_cert_verifier =
-            crate::tls::workload::TrustDomainVerifier::new(raw_client_cert_verifier, td);
+            crate::tls::workload::TrustDomainVerifier::new(raw_client_cert_verifier, trust_domains);
         let mut sc = ServerConfig::builder_with_provider(crate::tls::lib::provider())
             .with_protocol_versions(tls::TLS_VERSIONS)
             .expect("server config must be valid")
             .with_client_cert_verifier(client_cert_verifier)
             .with_single_cert(self.cert_and_intermediates(), self.private_key.clone_key())?;
-        sc.alpn_protocols = vec![b"h2".into()];
+        sc.alpn_protocols = vec![tls::HBONE_ALPN.into()];
+        match self.session_cache(session_cache_size) {
+            Some(cache) => sc.session_storage = cache.server.clone(),
+            None => {
+                sc.session_storage = Arc::new(NoServerSessionStorage {});
+                sc.send_tls13_tickets = 0;
+            }
+        }
         Ok(sc)
     }
 
-    pub fn outbound_connector(&self, identity: Vec<Identity>) -> Result<OutboundConnector, Error> {
+    /// outbound_connector builds a connector for mTLS connections to a peer with one of `identity`.
+    /// If `session_cache_size` is non-zero, sessions are resumed only with peers verified against
+    /// the same identities.
+    pub fn outbound_connector(
+        &self,
+        identity: Vec<Identity>,
+        session_cache_size: usize,
+    ) -> Result<OutboundConnector, Error> {
+        let resumption = match self.session_cache(session_cache_size) {
+            Some(cache) => Resumption::store(cache.client_store(&identity)),
+            None => Resumption::disabled(),
+        };
         let roots = self.roots.clone();
         let verifier = IdentityVerifier { roots, identity };
         let mut cc = ClientConfig::builder_with_provider(crate::tls::lib::provider())
@@ -277,8 +455,8 @@ impl WorkloadCertificate {
             .dangerous() // Customer verifier is requires "dangerous" opt-in
             .with_custom_certificate_verifier(Arc::new(verifier))
             .with_client_auth_cert(self.cert_and_intermediates(), self.private_key.clone_key())?;
-        cc.alpn_protocols = vec![b"h2".into()];
-        cc.resumption = Resumption::disabled();
+        cc.alpn_protocols = vec![tls::HBONE_ALPN.into()];
+        cc.resumption = resumption;
         cc.enable_sni = false;
         Ok(OutboundConnector {
             client_config: Arc::new(cc),
@@ -301,6 +479,16 @@ impl WorkloadCertificate {
         }
     }
 
+    // rotate_at returns when the certificate comes within `threshold` of expiring, or None if the
+    // threshold is longer than its lifetime, since rotating would not yield a better certificate.
+    pub fn rotate_at(&self, threshold: Duration) -> Option<SystemTime> {
+        let expiry = &self.cert.expiry;
+        expiry
+            .not_after
+            .checked_sub(threshold)
+            .filter(|at| *at > expiry.not_before)
+    }
+
     pub fn get_duration_until_refresh(&self) -> Duration {
         let expiry = &self.cert.expiry;
         let halflife = expiry
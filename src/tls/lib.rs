@@ -36,10 +36,19 @@ pub trait ControlPlaneClientCertProvider: Send + Sync {
 #[async_trait::async_trait]
 pub trait ServerCertProvider: Send + Sync + Clone {
     async fn fetch_cert(&mut self) -> Result<Arc<ServerConfig>, TlsError>;
+
+    /// require_alpn returns whether clients must negotiate one of the server config's ALPN
+    /// protocols. If false, clients that offer no ALPN at all are accepted as well.
+    fn require_alpn(&self) -> bool {
+        false
+    }
 }
 
 pub(super) static TLS_VERSIONS: &[&rustls::SupportedProtocolVersion] = &[&rustls::version::TLS13];
 
+/// The ALPN token used to establish HBONE connections, which run over HTTP/2.
+pub const HBONE_ALPN: &[u8] = b"h2";
+
 // Ztunnel use `rustls` with pluggable crypto modules.
 // All crypto MUST be done via the below providers.
 //
@@ -94,6 +103,10 @@ pub(super) fn provider() -> Arc<CryptoProvider> {
 pub enum TlsError {
     #[error("tls handshake error: {0:?}")]
     Handshake(std::io::Error),
+    #[error(
+        "client did not present a certificate; plaintext and non-mTLS connections are not allowed"
+    )]
+    MissingClientCertificate,
     #[error("signing error: {0}")]
     SigningError(#[from] identity::Error),
     #[error(
@@ -108,10 +121,40 @@ pub enum TlsError {
         display_list(.1)
     )]
     SanTrustDomainError(String, Vec<Identity>),
+    #[error(
+        "peer did not negotiate a supported application protocol, got {}",
+        .0.as_deref().map(String::from_utf8_lossy).unwrap_or("none".into())
+    )]
+    UnexpectedAlpn(Option<Vec<u8>>),
     #[error("ssl error: {0}")]
     SslError(#[from] Error),
 }
 
+impl TlsError {
+    /// from_handshake classifies a handshake failure, distinguishing clients that did not present
+    /// a certificate at all, presented one from an untrusted trust domain, or offered no supported
+    /// ALPN protocol, from other handshake errors.
+    pub fn from_handshake(err: std::io::Error) -> TlsError {
+        match err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        {
+            Some(rustls::Error::NoCertificatesPresented) => TlsError::MissingClientCertificate,
+            // The client offered ALPN protocols, but none that we support.
+            Some(rustls::Error::NoApplicationProtocol) => TlsError::UnexpectedAlpn(None),
+            Some(rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other))) => {
+                match other.0.downcast_ref::<TlsError>() {
+                    Some(TlsError::SanTrustDomainError(want, got)) => {
+                        TlsError::SanTrustDomainError(want.clone(), got.clone())
+                    }
+                    _ => TlsError::Handshake(err),
+                }
+            }
+            _ => TlsError::Handshake(err),
+        }
+    }
+}
+
 fn display_list<T: ToString>(i: &[T]) -> String {
     i.iter()
         .map(|id| id.to_string())
@@ -168,6 +211,37 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn missing_client_certificate() {
+        use crate::tls::TlsError;
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::NoCertificatesPresented,
+        );
+        assert!(matches!(
+            TlsError::from_handshake(err),
+            TlsError::MissingClientCertificate
+        ));
+        let err = std::io::Error::new(std::io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert!(matches!(
+            TlsError::from_handshake(err),
+            TlsError::Handshake(_)
+        ));
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::InvalidCertificate(rustls::CertificateError::Other(rustls::OtherError(
+                std::sync::Arc::new(TlsError::SanTrustDomainError(
+                    "cluster.local".to_string(),
+                    vec![],
+                )),
+            ))),
+        );
+        assert!(matches!(
+            TlsError::from_handshake(err),
+            TlsError::SanTrustDomainError(..)
+        ));
+    }
+
     #[test]
     fn cert_expiration() {
         let expiry_seconds = 1000;
@@ -185,7 +185,7 @@ impl ServerCertProvider for MockServerCertProvider {
                 self.0.private_key.clone_key(),
             )
             .unwrap();
-        sc.alpn_protocols = vec![b"h2".into()];
+        sc.alpn_protocols = vec![crate::tls::HBONE_ALPN.into()];
         Ok(Arc::new(sc))
     }
 }
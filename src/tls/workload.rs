@@ -52,17 +52,21 @@ impl<F: ServerCertProvider> InboundAcceptor<F> {
 #[derive(Debug)]
 pub(super) struct TrustDomainVerifier {
     base: Arc<dyn ClientCertVerifier>,
-    trust_domain: Option<Strng>,
+    // The trust domains a client may be in. If empty, any trust domain is accepted.
+    trust_domains: Vec<Strng>,
 }
 
 impl TrustDomainVerifier {
-    pub fn new(base: Arc<dyn ClientCertVerifier>, trust_domain: Option<Strng>) -> Arc<Self> {
-        Arc::new(Self { base, trust_domain })
+    pub fn new(base: Arc<dyn ClientCertVerifier>, trust_domains: Vec<Strng>) -> Arc<Self> {
+        Arc::new(Self {
+            base,
+            trust_domains,
+        })
     }
 
     fn verify_trust_domain(&self, client_cert: &CertificateDer<'_>) -> Result<(), rustls::Error> {
         use x509_parser::prelude::*;
-        let Some(want_trust_domain) = &self.trust_domain else {
+        if self.trust_domains.is_empty() {
             // No need to verify
             return Ok(());
         };
@@ -75,17 +79,17 @@ impl TrustDomainVerifier {
             )
         })?;
         trace!(
-            "verifying client identities {ids:?} against trust domain {:?}",
-            want_trust_domain
+            "verifying client identities {ids:?} against trust domains {:?}",
+            self.trust_domains
         );
         ids.iter()
             .find(|id| match id {
-                Identity::Spiffe { trust_domain, .. } => trust_domain == want_trust_domain,
+                Identity::Spiffe { trust_domain, .. } => self.trust_domains.contains(trust_domain),
             })
             .ok_or_else(|| {
                 rustls::Error::InvalidCertificate(rustls::CertificateError::Other(
                     rustls::OtherError(Arc::new(TlsError::SanTrustDomainError(
-                        want_trust_domain.to_string(),
+                        self.trust_domains.join(", "),
                         ids.clone(),
                     ))),
                 ))
@@ -149,10 +153,19 @@ where
         let mut acceptor = self.provider.clone();
         Box::pin(async move {
             let tls = acceptor.fetch_cert().await?;
-            tokio_rustls::TlsAcceptor::from(tls)
+            let stream = tokio_rustls::TlsAcceptor::from(tls.clone())
                 .accept(conn)
-                .map_err(TlsError::Handshake)
-                .await
+                .map_err(TlsError::from_handshake)
+                .await?;
+            if acceptor.require_alpn() {
+                // rustls already rejects clients offering only unsupported protocols, but accepts
+                // clients that offer none at all.
+                let negotiated = stream.get_ref().1.alpn_protocol();
+                if !negotiated.is_some_and(|p| tls.alpn_protocols.iter().any(|a| a == p)) {
+                    return Err(TlsError::UnexpectedAlpn(negotiated.map(<[u8]>::to_vec)));
+                }
+            }
+            Ok(stream)
         })
     }
 }
@@ -177,6 +190,15 @@ impl OutboundConnector {
         let c = tokio_rustls::TlsConnector::from(self.client_config);
         c.connect(dest, stream).await
     }
+
+    /// with_alpn replaces the protocols offered over ALPN, to act as a peer that does not speak HBONE.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_alpn(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        let mut cc = (*self.client_config).clone();
+        cc.alpn_protocols = protocols;
+        self.client_config = Arc::new(cc);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -305,3 +327,202 @@ impl ServerCertVerifier for IdentityVerifier {
             .supported_schemes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tls::WorkloadCertificate;
+    use crate::tls::mock::generate_test_certs;
+
+    #[test]
+    fn verify_upstream_identity() {
+        let server_id = Identity::from_str("spiffe://cluster.local/ns/default/sa/server").unwrap();
+        let other_id = Identity::from_str("spiffe://cluster.local/ns/default/sa/other").unwrap();
+        let server = generate_test_certs(
+            &server_id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let leaf = &server.cert_and_intermediates()[0];
+
+        let verifier = IdentityVerifier {
+            roots: server.roots.clone(),
+            identity: vec![server_id],
+        };
+        verifier
+            .verify_full_san(leaf)
+            .expect("expected identity should be accepted");
+
+        // A validly signed certificate for a different identity must still be rejected.
+        let verifier = IdentityVerifier {
+            roots: server.roots.clone(),
+            identity: vec![other_id],
+        };
+        let err = verifier
+            .verify_full_san(leaf)
+            .expect_err("mismatched identity should be rejected");
+        assert!(
+            matches!(err, rustls::Error::InvalidCertificate(_)),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[derive(Clone)]
+    struct StrictAlpnProvider(Arc<WorkloadCertificate>);
+
+    #[async_trait::async_trait]
+    impl ServerCertProvider for StrictAlpnProvider {
+        async fn fetch_cert(&mut self) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+            Ok(Arc::new(self.0.server_config(&[], 0)?))
+        }
+
+        fn require_alpn(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn require_alpn() {
+        use tls_listener::AsyncTls;
+
+        let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/server").unwrap();
+        let certs = Arc::new(generate_test_certs(
+            &id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        ));
+        let acceptor = InboundAcceptor::new(StrictAlpnProvider(certs.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        for (offered, accepted) in [
+            (vec![tls::HBONE_ALPN.to_vec()], true),
+            (vec![b"http/1.1".to_vec()], false),
+            (vec![], false),
+        ] {
+            let mut connector = certs.outbound_connector(vec![id.clone()], 0).unwrap();
+            let mut cc = (*connector.client_config).clone();
+            cc.alpn_protocols = offered.clone();
+            connector.client_config = Arc::new(cc);
+
+            let client = tokio::spawn(async move {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                // The outcome is asserted on the server side; the client may or may not see the rejection.
+                let _ = connector.connect(stream).await;
+            });
+            let (conn, _) = listener.accept().await.unwrap();
+            let res = acceptor.accept(conn).await;
+            if accepted {
+                let stream = res.unwrap();
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(tls::HBONE_ALPN));
+            } else {
+                assert!(
+                    matches!(res, Err(TlsError::UnexpectedAlpn(_))),
+                    "offered {offered:?}: {:?}",
+                    res.err()
+                );
+            }
+            client.await.unwrap();
+        }
+    }
+
+    /// resumption_handshake connects to `listener` expecting a peer with one of `expected`, and
+    /// returns how the handshake completed on the client and server.
+    async fn resumption_handshake(
+        certs: &WorkloadCertificate,
+        listener: &tokio::net::TcpListener,
+        expected: Vec<Identity>,
+        size: usize,
+    ) -> (Option<rustls::HandshakeKind>, Option<rustls::HandshakeKind>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let addr = listener.local_addr().unwrap();
+        let connector = certs.outbound_connector(expected, size).unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut tls = connector.connect(stream).await.unwrap();
+            // Read past the handshake so session tickets are processed.
+            let _ = tls.read_u8().await;
+            tls.get_ref().1.handshake_kind()
+        });
+        // The server config is rebuilt per connection, as in the inbound path.
+        let acceptor =
+            tokio_rustls::TlsAcceptor::from(Arc::new(certs.server_config(&[], size).unwrap()));
+        let (conn, _) = listener.accept().await.unwrap();
+        let mut tls = acceptor.accept(conn).await.unwrap();
+        tls.write_u8(1).await.unwrap();
+        // The client identity is still available on resumed sessions.
+        assert_eq!(
+            tls::identity_from_connection(tls.get_ref().1),
+            certs.cert.identity()
+        );
+        (client.await.unwrap(), tls.get_ref().1.handshake_kind())
+    }
+
+    #[tokio::test]
+    async fn session_resumption() {
+        use rustls::HandshakeKind;
+
+        let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/server").unwrap();
+        let other_id = Identity::from_str("spiffe://cluster.local/ns/default/sa/other").unwrap();
+        let certs = generate_test_certs(
+            &id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        for (expected, resumed) in [
+            (vec![id.clone()], false),
+            (vec![id.clone()], true),
+            // Sessions verified against a different set of identities are never reused
+            (vec![id.clone(), other_id.clone()], false),
+            (vec![other_id.clone(), id.clone()], true),
+        ] {
+            let want = if resumed {
+                HandshakeKind::Resumed
+            } else {
+                HandshakeKind::Full
+            };
+            let (client, server) =
+                resumption_handshake(&certs, &listener, expected.clone(), 16).await;
+            assert_eq!(client, Some(want), "expected {expected:?}");
+            assert_eq!(server, Some(want));
+        }
+
+        // Without a cache, every connection does a full handshake.
+        for _ in 0..2 {
+            let (client, _) = resumption_handshake(&certs, &listener, vec![id.clone()], 0).await;
+            assert_eq!(client, Some(HandshakeKind::Full));
+        }
+    }
+
+    #[tokio::test]
+    async fn session_cache_bounded() {
+        use rustls::HandshakeKind;
+
+        let id = Identity::from_str("spiffe://cluster.local/ns/default/sa/server").unwrap();
+        let other_id = Identity::from_str("spiffe://cluster.local/ns/default/sa/other").unwrap();
+        let certs = generate_test_certs(
+            &id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        // The client cache holds a single session in total, across all identities, so a session
+        // for another identity evicts the first one.
+        for (expected, want) in [
+            (vec![id.clone()], HandshakeKind::Full),
+            (vec![id.clone()], HandshakeKind::Resumed),
+            (vec![id.clone(), other_id.clone()], HandshakeKind::Full),
+            (vec![id.clone()], HandshakeKind::Full),
+        ] {
+            let (client, _) = resumption_handshake(&certs, &listener, expected.clone(), 1).await;
+            assert_eq!(client, Some(want), "expected {expected:?}");
+        }
+    }
+}
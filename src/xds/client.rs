@@ -21,6 +21,7 @@ use std::{fmt, mem};
 use prost::{DecodeError, EncodeError};
 use prost_types::value::Kind;
 use prost_types::{Struct, Value};
+use rand::Rng;
 use serde_json;
 use split_iter::Splittable;
 use thiserror::Error;
@@ -217,6 +218,7 @@ pub struct Config {
     /// alt_hostname provides an alternative accepted SAN for the control plane TLS verification
     alt_hostname: Option<String>,
     xds_headers: Vec<(AsciiMetadataKey, AsciiMetadataValue)>,
+    backoff: ReconnectBackoff,
 }
 
 pub struct State {
@@ -265,6 +267,10 @@ impl Config {
             proxy_metadata: config.proxy_metadata.clone(),
             alt_hostname: config.alt_xds_hostname.clone(),
             xds_headers: config.xds_headers.vec.clone(),
+            backoff: ReconnectBackoff::new(
+                config.xds_reconnect_backoff_base,
+                config.xds_reconnect_backoff_max,
+            ),
         }
     }
 
@@ -469,8 +475,41 @@ impl Demander {
     }
 }
 
-const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
-const MAX_BACKOFF: Duration = Duration::from_secs(15);
+/// ReconnectBackoff tracks the delay before reconnecting to the XDS server. Each consecutive failure
+/// doubles the delay, up to `max`. The delay actually slept is jittered, so that many clients
+/// disconnected by the same control plane restart do not all reconnect at the same moment.
+#[derive(Debug, Clone)]
+struct ReconnectBackoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl ReconnectBackoff {
+    fn new(base: Duration, max: Duration) -> Self {
+        ReconnectBackoff {
+            base,
+            max,
+            current: base,
+        }
+    }
+
+    /// failed records a failed connection and returns how long to wait before reconnecting.
+    fn failed(&mut self) -> Duration {
+        self.current = std::cmp::min(self.max, self.current * 2);
+        self.delay()
+    }
+
+    /// reset returns to the base delay, once a connection has made progress.
+    fn reset(&mut self) {
+        self.current = self.base;
+    }
+
+    /// delay returns the current delay, jittered to somewhere between half and all of it.
+    fn delay(&self) -> Duration {
+        self.current.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+}
 
 impl AdsClient {
     fn is_initial_request_on_demand(r: &DeltaDiscoveryRequest) -> bool {
@@ -512,23 +551,22 @@ impl AdsClient {
         }
     }
 
-    async fn run_loop(&mut self, backoff: Duration) -> Duration {
-        match self.run_internal().await {
+    async fn run_loop(&mut self) {
+        let delay = match self.run_internal().await {
             Err(e @ Error::Connection(_, _)) => {
                 // For connection errors, we add backoff
-                let backoff = std::cmp::min(MAX_BACKOFF, backoff * 2);
+                let delay = self.config.backoff.failed();
                 warn!(
                     "XDS client connection error: {}, retrying in {:?}",
-                    e, backoff
+                    e, delay
                 );
                 self.metrics
                     .increment(&ConnectionTerminationReason::ConnectionError);
-                tokio::time::sleep(backoff).await;
-                backoff
+                delay
             }
             Err(ref e @ Error::GrpcStatus(ref status)) => {
                 let err_detail = e.to_string();
-                let backoff = if status.code() == tonic::Code::Unknown
+                if status.code() == tonic::Code::Unknown
                     || status.code() == tonic::Code::Cancelled
                     || status.code() == tonic::Code::DeadlineExceeded
                     || (status.code() == tonic::Code::Unavailable
@@ -536,24 +574,22 @@ impl AdsClient {
                     || (status.code() == tonic::Code::Unavailable
                         && status.message().contains("received prior goaway"))
                 {
+                    self.config.backoff.reset();
+                    let delay = self.config.backoff.delay();
                     debug!(
                         "XDS client terminated: {}, retrying in {:?}",
-                        err_detail, backoff
+                        err_detail, delay
                     );
                     self.metrics
                         .increment(&ConnectionTerminationReason::Reconnect);
-                    INITIAL_BACKOFF
+                    delay
                 } else {
-                    warn!(
-                        "XDS client error: {}, retrying in {:?}",
-                        err_detail, backoff
-                    );
-                    self.metrics.increment(&ConnectionTerminationReason::Error);
                     // For gRPC errors, we add backoff
-                    std::cmp::min(MAX_BACKOFF, backoff * 2)
-                };
-                tokio::time::sleep(backoff).await;
-                backoff
+                    let delay = self.config.backoff.failed();
+                    warn!("XDS client error: {}, retrying in {:?}", err_detail, delay);
+                    self.metrics.increment(&ConnectionTerminationReason::Error);
+                    delay
+                }
             }
             Err(e) => {
                 // For other errors, we connect immediately
@@ -562,27 +598,30 @@ impl AdsClient {
                 warn!("XDS client error: {}, retrying", e);
                 self.metrics.increment(&ConnectionTerminationReason::Error);
                 // Reset backoff
-                INITIAL_BACKOFF
+                self.config.backoff.reset();
+                Duration::ZERO
             }
             Ok(_) => {
                 self.metrics
                     .increment(&ConnectionTerminationReason::Complete);
                 warn!("XDS client complete");
                 // Reset backoff
-                INITIAL_BACKOFF
+                self.config.backoff.reset();
+                Duration::ZERO
             }
+        };
+        self.metrics.reconnect_attempts.inc();
+        self.metrics.reconnect_backoff.set(delay.as_secs_f64());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
         }
     }
 
     pub async fn run(mut self) -> Result<(), Error> {
-        let mut backoff = INITIAL_BACKOFF;
         loop {
             self.connection_id += 1;
             let id = self.connection_id;
-            backoff = self
-                .run_loop(backoff)
-                .instrument(info_span!("xds", id))
-                .await;
+            self.run_loop().instrument(info_span!("xds", id)).await;
         }
     }
 
@@ -662,6 +701,9 @@ impl AdsClient {
                         // This could be an explicit OK response, or if the stream is reset without a gRPC status.
                         return Ok(());
                     };
+                    // The control plane is serving us, so the next failure starts from the base backoff.
+                    self.config.backoff.reset();
+                    self.metrics.reconnect_backoff.set(0.0);
                     let mut received_type = None;
                     if !self.types_to_expect.is_empty() {
                         received_type = Some(msg.type_url.clone())
@@ -1240,6 +1282,32 @@ mod tests {
         verify_address(IpAddr::V4(ip), None, &state).await;
     }
 
+    #[test]
+    fn test_reconnect_backoff() {
+        let base = Duration::from_millis(10);
+        let max = Duration::from_millis(100);
+        let mut backoff = ReconnectBackoff::new(base, max);
+
+        // Each failure doubles the delay, jittered to between half and all of it, up to the max
+        let mut want = base;
+        for _ in 0..10 {
+            want = std::cmp::min(max, want * 2);
+            let delay = backoff.failed();
+            assert!(
+                delay >= want / 2 && delay <= want,
+                "{delay:?} not near {want:?}"
+            );
+        }
+        assert_eq!(backoff.current, max);
+
+        // Success resets back to the base
+        backoff.reset();
+        assert_eq!(backoff.current, base);
+        let delay = backoff.delay();
+        assert!(delay >= base / 2 && delay <= base, "{delay:?}");
+        assert!(backoff.failed() <= base * 2);
+    }
+
     #[test]
     fn test_json_to_value() {
         use prost_types::value::Kind::*;
@@ -15,8 +15,11 @@
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
 use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::registry::{Registry, Unit};
 
+use std::sync::atomic::AtomicU64;
+
 use crate::metrics::Recorder;
 
 use super::service::discovery::v3::DeltaDiscoveryResponse;
@@ -25,6 +28,8 @@ pub struct Metrics {
     pub connection_terminations: Family<ConnectionTermination, Counter>,
     pub message_types: Family<TypeUrl, Counter>,
     pub total_messages_size: Family<TypeUrl, Counter>,
+    pub reconnect_attempts: Counter,
+    pub reconnect_backoff: Gauge<f64, AtomicU64>,
 }
 
 #[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
@@ -71,10 +76,27 @@ impl Metrics {
             total_messages_size.clone(),
         );
 
+        let reconnect_attempts = Counter::default();
+        registry.register(
+            "xds_reconnect_attempts",
+            "The total number of reconnections to the xds server (unstable)",
+            reconnect_attempts.clone(),
+        );
+
+        let reconnect_backoff = Gauge::default();
+        registry.register_with_unit(
+            "xds_reconnect_backoff",
+            "The delay before the pending reconnection to the xds server, or 0 if connected (unstable)",
+            Unit::Seconds,
+            reconnect_backoff.clone(),
+        );
+
         Self {
             connection_terminations,
             message_types: message_count,
             total_messages_size,
+            reconnect_attempts,
+            reconnect_backoff,
         }
     }
 }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -24,6 +24,7 @@ use hyper::{Method, Request};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
 use tokio::time;
 use tokio::time::timeout;
 
@@ -141,6 +142,64 @@ async fn test_shutdown_drain() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn test_shutdown_drain_ordering() {
+    helpers::initialize_telemetry();
+
+    let cert_manager = new_secret_manager(Duration::from_secs(10));
+    let app = ztunnel::app::build_with_cert(Arc::new(test_config()), cert_manager.clone())
+        .await
+        .unwrap();
+    let ta = TestApp::from((&app, cert_manager));
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let shutdown = app.shutdown.trigger().clone();
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        app.wait_termination().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+    });
+    let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+    let mut stream = ta
+        .socks5_connect(
+            DestinationAddr::Ip(dst),
+            TEST_WORKLOAD_SOURCE.parse().unwrap(),
+        )
+        .await;
+    read_write_stream(&mut stream).await;
+
+    // Simulate the SIGTERM sent on pod termination.
+    shutdown.shutdown_now().await;
+
+    // Inbound and outbound listeners stop accepting first...
+    let socks5 = ta.proxy_addresses.socks5.unwrap();
+    let outbound = ta.proxy_addresses.outbound;
+    let inbound = ta.proxy_addresses.inbound;
+    assert_eventually(
+        Duration::from_secs(1),
+        || async {
+            (
+                TcpStream::connect(socks5).await.is_err(),
+                TcpStream::connect(outbound).await.is_err(),
+                TcpStream::connect(inbound).await.is_err(),
+            )
+        },
+        (true, true, true),
+    )
+    .await;
+    // ...while established connections are still served, holding up shutdown.
+    read_write_stream(&mut stream).await;
+    assert!(shutdown_rx.try_recv().is_err());
+
+    // Once they complete, the app exits.
+    drop(stream);
+    timeout(Duration::from_secs(1), shutdown_rx)
+        .await
+        .expect("app should shutdown")
+        .unwrap();
+}
+
 #[tokio::test]
 async fn test_shutdown_forced_drain() {
     helpers::initialize_telemetry();
@@ -263,6 +322,46 @@ async fn test_vip_request() {
     run_request_test(&format!("{TEST_VIP}:80"), "").await;
 }
 
+#[tokio::test]
+async fn test_udp_hbone_request() {
+    initialize_telemetry();
+    let echo = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let echo_port = echo.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 1024];
+        loop {
+            let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..n], from).await.unwrap();
+        }
+    });
+    let cfg = config::Config {
+        outbound_udp_addr: Some("127.0.0.1:0".parse().unwrap()),
+        ..test_config_with_port(echo_port)
+    };
+    testapp::with_app(cfg, async move |app| {
+        let udp_addr = app.proxy_addresses.outbound_udp.unwrap();
+        let client = UdpSocket::bind(SocketAddr::new(TEST_WORKLOAD_SOURCE.parse().unwrap(), 0))
+            .await
+            .unwrap();
+        // A SOCKS5 UDP request header naming the destination, followed by the payload. Replies
+        // carry the same header, naming where they came from.
+        let mut datagram = vec![0, 0, 0, 0x01];
+        datagram.extend_from_slice(&TEST_WORKLOAD_HBONE.parse::<Ipv4Addr>().unwrap().octets());
+        datagram.extend_from_slice(&echo_port.to_be_bytes());
+        datagram.extend_from_slice(b"hello over HBONE");
+        for _ in 0..3 {
+            client.send_to(&datagram, udp_addr).await.unwrap();
+            let mut buf = vec![0; 1024];
+            let (n, _) = timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+                .await
+                .expect("echo reply")
+                .unwrap();
+            assert_eq!(&buf[..n], &datagram[..]);
+        }
+    })
+    .await;
+}
+
 fn on_demand_dns_assertions(metrics: ParsedMetrics) {
     {
         let metric = &("istio_on_demand_dns_total");
@@ -357,6 +456,13 @@ async fn test_stats_exist() {
             "istio_tcp_connections_closed",
             "istio_tcp_received_bytes",
             "istio_tcp_sent_bytes",
+            // Standard process metrics, named by Prometheus convention.
+            "process_cpu_seconds",
+            "process_resident_memory_bytes",
+            "process_virtual_memory_bytes",
+            "process_start_time_seconds",
+            "process_open_fds",
+            "process_max_fds",
         ]);
         {
             for (name, doc) in metric_info {
@@ -753,7 +753,23 @@ mod namespaced {
 
     #[tokio::test]
     async fn test_policy() -> anyhow::Result<()> {
-        let mut manager = setup_netns_test!(Shared);
+        let manager = setup_netns_test!(Shared);
+        test_policy_denial(manager, false, "rbac_policy").await
+    }
+
+    #[tokio::test]
+    async fn test_policy_waypoint_bypass() -> anyhow::Result<()> {
+        let manager = setup_netns_test!(Shared);
+        test_policy_denial(manager, true, "waypoint_bypass").await
+    }
+
+    /// test_policy_denial sends a request straight to a server that only allows its waypoint, and
+    /// checks the denial is counted with `reason`.
+    async fn test_policy_denial(
+        mut manager: WorkloadManager,
+        server_has_waypoint: bool,
+        reason: &str,
+    ) -> anyhow::Result<()> {
         let zt = manager.deploy_ztunnel(DEFAULT_NODE).await?;
         manager
             .add_policy(Authorization {
@@ -769,10 +785,20 @@ mod namespaced {
                 }]]],
             })
             .await?;
-        let _ = manager
-            .workload_builder("server", DEFAULT_NODE)
-            .register()
-            .await?;
+        let waypoint = match server_has_waypoint {
+            true => Some(
+                manager
+                    .register_waypoint("waypoint", DEFAULT_NODE)
+                    .await?
+                    .ip(),
+            ),
+            false => None,
+        };
+        let mut server = manager.workload_builder("server", DEFAULT_NODE);
+        if let Some(waypoint) = waypoint {
+            server = server.waypoint(waypoint);
+        }
+        let _ = server.register().await?;
         let client = manager
             .workload_builder("client", DEFAULT_NODE)
             .uncaptured()
@@ -780,6 +806,8 @@ mod namespaced {
             .await?;
 
         let srv = resolve_target(manager.resolver(), "server");
+        // zt is moved into the client below; keep a handle for checking metrics afterwards
+        let zt_metrics = zt.clone();
         client
             .run(move || async move {
                 let builder =
@@ -797,7 +825,7 @@ mod namespaced {
                     identity::Identity::from_str("spiffe://cluster.local/ns/default/sa/server")
                         .unwrap();
                 let cert = zt.cert_manager.fetch_certificate(id).await?;
-                let connector = cert.outbound_connector(vec![dst_id]).unwrap();
+                let connector = cert.outbound_connector(vec![dst_id], 0).unwrap();
                 let hbone = SocketAddr::new(srv.ip(), 15008);
                 let tcp_stream = TcpStream::connect(hbone).await.unwrap();
                 let tls_stream = connector.connect(tcp_stream).await.unwrap();
@@ -823,6 +851,15 @@ mod namespaced {
                 "connection closed due to policy rejection: allow policies exist, but none allowed",
             ),
         ]));
+        verify_metrics(
+            &zt_metrics,
+            &[(CONNECTIONS_DENIED, 1)],
+            &HashMap::from([
+                ("reporter".to_string(), "destination".to_string()),
+                ("reason".to_string(), reason.to_string()),
+            ]),
+        )
+        .await;
         Ok(())
     }
 
@@ -859,7 +896,7 @@ mod namespaced {
                     identity::Identity::from_str("spiffe://cluster.local/ns/default/sa/server")
                         .unwrap();
                 let cert = zt.cert_manager.fetch_certificate(id).await?;
-                let connector = cert.outbound_connector(vec![dst_id]).unwrap();
+                let connector = cert.outbound_connector(vec![dst_id], 0).unwrap();
                 let tcp_stream = TcpStream::connect(SocketAddr::from((srv.ip(), 15008)))
                     .await
                     .unwrap();
@@ -875,7 +912,7 @@ mod namespaced {
 
                 let response = request_sender.send_request(request).await.unwrap();
                 // We sent to server IP directly but requested client IP. Should be rejected
-                assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+                assert_eq!(response.status(), hyper::StatusCode::MISDIRECTED_REQUEST);
                 Ok(())
             })?
             .join()
@@ -941,7 +978,7 @@ mod namespaced {
                     identity::Identity::from_str("spiffe://cluster.local/ns/default/sa/server")
                         .unwrap();
                 let cert = zt.cert_manager.fetch_certificate(id).await?;
-                let connector = cert.outbound_connector(vec![dst_id]).unwrap();
+                let connector = cert.outbound_connector(vec![dst_id], 0).unwrap();
                 let tcp_stream = TcpStream::connect(SocketAddr::from((srv.ip(), 15008)))
                     .await
                     .unwrap();
@@ -1166,6 +1203,7 @@ mod namespaced {
     const UNCAPTURED_NODE: &str = "remote-node";
 
     const CONNECTIONS_OPENED: &str = "istio_tcp_connections_opened_total";
+    const CONNECTIONS_DENIED: &str = "istio_connections_denied_total";
     const CONNECTIONS_CLOSED: &str = "istio_tcp_connections_closed_total";
     const BYTES_RECV: &str = "istio_tcp_received_bytes_total";
     const BYTES_SENT: &str = "istio_tcp_sent_bytes_total";